@@ -0,0 +1,229 @@
+//! Throughput of the engine on synthetic workloads of a million transactions.
+//!
+//! Run with `cargo bench`. The `engine` group applies the mixed workload, mostly deposits and withdrawals, and
+//! workloads stressing a single path: deposits only, disputes and resolves of earlier deposits, or transactions
+//! spread over every client id. The `maps` group compares the std SipHash map with the FxHash map used by the
+//! engine on the same integer keys, the `parsing` group the serde and fast paths of the CSV rows and the
+//! `cli` group the binary on the workload written to a CSV file, with and without `--pipeline`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use csv::{ByteRecord, StringRecord};
+use rustc_hash::FxHashMap;
+use transaction_engine::csv_model::{self, COLUMNS};
+use transaction_engine::{Amount, Transaction, TransactionEngine, TxId};
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+
+// Deterministic pseudo random numbers, so that every run measures the same workload.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 33) as u32
+    }
+}
+
+// Mostly deposits and withdrawals, with a dispute for every 50 transactions, half of them resolved.
+fn workload() -> Vec<Transaction> {
+    let mut rng = Lcg(42);
+    let mut transactions = Vec::with_capacity(TRANSACTIONS as usize);
+    let mut deposits = Vec::new();
+    for tx_id in 1..=TxId::from(TRANSACTIONS) {
+        let client_id = (rng.next() % CLIENTS) as u16;
+        let amount = Amount::from_units(i64::from(rng.next() % 1_000_000) + 1);
+        let transaction = match rng.next() % 100 {
+            0..=1 if !deposits.is_empty() => {
+                let (client_id, tx_id) = deposits[rng.next() as usize % deposits.len()];
+                Transaction::Dispute { client_id, tx_id }
+            },
+            2 if !deposits.is_empty() => {
+                let (client_id, tx_id) = deposits[rng.next() as usize % deposits.len()];
+                Transaction::Resolve { client_id, tx_id }
+            },
+            3..=29 => Transaction::Withdrawal { client_id, tx_id, amount },
+            _ => {
+                deposits.push((client_id, tx_id));
+                Transaction::Deposit { client_id, tx_id, amount }
+            },
+        };
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+fn deposit_heavy() -> Vec<Transaction> {
+    let mut rng = Lcg(1);
+    (1..=TxId::from(TRANSACTIONS))
+        .map(|tx_id| {
+            let client_id = (rng.next() % CLIENTS) as u16;
+            Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(i64::from(rng.next() % 1_000_000) + 1) }
+        })
+        .collect()
+}
+
+// A deposit for every three disputes, each disputed deposit being resolved right after, so that it can be
+// disputed again.
+fn dispute_heavy() -> Vec<Transaction> {
+    let mut rng = Lcg(2);
+    let mut transactions = Vec::with_capacity(TRANSACTIONS as usize);
+    let mut deposits = Vec::new();
+    for tx_id in 1..=TxId::from(TRANSACTIONS / 4) {
+        let client_id = (rng.next() % CLIENTS) as u16;
+        deposits.push((client_id, tx_id));
+        transactions.push(Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(i64::from(rng.next() % 1_000_000) + 1) });
+    }
+    while transactions.len() < TRANSACTIONS as usize {
+        let (client_id, tx_id) = deposits[rng.next() as usize % deposits.len()];
+        transactions.push(Transaction::Dispute { client_id, tx_id });
+        transactions.push(Transaction::Resolve { client_id, tx_id });
+    }
+    transactions
+}
+
+// Deposits and withdrawals spread over every client id, so that the clients no longer fit the caches.
+fn many_clients() -> Vec<Transaction> {
+    let mut rng = Lcg(3);
+    (1..=TxId::from(TRANSACTIONS))
+        .map(|tx_id| {
+            let client_id = rng.next() as u16;
+            let amount = Amount::from_units(i64::from(rng.next() % 1_000) + 1);
+            match tx_id % 4 {
+                0 => Transaction::Withdrawal { client_id, tx_id, amount },
+                _ => Transaction::Deposit { client_id, tx_id, amount },
+            }
+        })
+        .collect()
+}
+
+fn engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.sample_size(10);
+
+    let workloads = [
+        ("1M transactions", workload()),
+        ("1M deposits", deposit_heavy()),
+        ("1M disputes and resolves", dispute_heavy()),
+        ("1M transactions of 65536 clients", many_clients()),
+    ];
+    for (name, transactions) in &workloads {
+        group.bench_function(*name, |b| b.iter_batched(
+            TransactionEngine::new,
+            |mut engine| {
+                for transaction in transactions {
+                    let _ = engine.compute_transaction(*transaction);
+                }
+                engine
+            },
+            BatchSize::PerIteration,
+        ));
+    }
+
+    let transactions = &workloads[0].1;
+    group.bench_function("1M transactions with capacity", |b| b.iter_batched(
+        || TransactionEngine::with_capacity(CLIENTS as usize, TRANSACTIONS as usize),
+        |mut engine| {
+            for transaction in transactions {
+                let _ = engine.compute_transaction(*transaction);
+            }
+            engine
+        },
+        BatchSize::PerIteration,
+    ));
+    group.finish();
+}
+
+fn maps(c: &mut Criterion) {
+    let mut rng = Lcg(7);
+    let keys: Vec<TxId> = (0..TRANSACTIONS).map(|_| TxId::from(rng.next())).collect();
+    let mut group = c.benchmark_group("maps");
+    group.sample_size(10);
+
+    group.bench_function("siphash insert and get", |b| b.iter(|| {
+        let mut map = HashMap::new();
+        for key in &keys {
+            map.insert(*key, *key);
+        }
+        keys.iter().filter(|key| map.contains_key(black_box(*key))).count()
+    }));
+    group.bench_function("fxhash insert and get", |b| b.iter(|| {
+        let mut map = FxHashMap::default();
+        for key in &keys {
+            map.insert(*key, *key);
+        }
+        keys.iter().filter(|key| map.contains_key(black_box(*key))).count()
+    }));
+    group.finish();
+}
+
+// The workload as the rows of a CSV file with the canonical header.
+fn csv_rows() -> Vec<u8> {
+    let mut rows = format!("{}\n", COLUMNS.join(","));
+    for transaction in workload() {
+        let row = match transaction {
+            Transaction::Deposit { client_id, tx_id, amount } => format!("deposit,{client_id},{tx_id},{amount}\n"),
+            Transaction::Withdrawal { client_id, tx_id, amount } => format!("withdrawal,{client_id},{tx_id},{amount}\n"),
+            Transaction::Dispute { client_id, tx_id } => format!("dispute,{client_id},{tx_id},\n"),
+            Transaction::Resolve { client_id, tx_id } => format!("resolve,{client_id},{tx_id},\n"),
+            _ => unreachable!(),
+        };
+        rows.push_str(&row);
+    }
+    rows.into_bytes()
+}
+
+fn parsing(c: &mut Criterion) {
+    let rows = csv_rows();
+    let headers = StringRecord::from(COLUMNS.to_vec());
+    let mut group = c.benchmark_group("parsing");
+    group.sample_size(10);
+
+    group.bench_function("1M rows with serde", |b| b.iter(|| {
+        let mut reader = csv::Reader::from_reader(rows.as_slice());
+        let mut row = StringRecord::new();
+        let mut parsed = 0;
+        while reader.read_record(&mut row).unwrap() {
+            parsed += usize::from(csv_model::parse_row(&row, &headers).is_ok());
+        }
+        parsed
+    }));
+    group.bench_function("1M rows with the fast path", |b| b.iter(|| {
+        let mut reader = csv::Reader::from_reader(rows.as_slice());
+        let mut row = ByteRecord::new();
+        let mut parsed = 0;
+        while reader.read_byte_record(&mut row).unwrap() {
+            parsed += usize::from(csv_model::parse_canonical_row(&row).is_some());
+        }
+        parsed
+    }));
+    group.finish();
+}
+
+fn cli(c: &mut Criterion) {
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    input.write_all(&csv_rows()).unwrap();
+    let run = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+            .arg(input.path())
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let mut group = c.benchmark_group("cli");
+    group.sample_size(10);
+
+    group.bench_function("1M rows", |b| b.iter(|| run(&[])));
+    group.bench_function("1M rows with --pipeline", |b| b.iter(|| run(&["--pipeline"])));
+    group.finish();
+}
+
+criterion_group!(benches, engine, maps, parsing, cli);
+criterion_main!(benches);
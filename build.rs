@@ -0,0 +1,11 @@
+// Generates the gRPC service from proto/engine.proto with the `grpc` feature, using a vendored protoc so that
+// the build does not need one installed.
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::compile_protos("proto/engine.proto").expect("proto/engine.proto should compile");
+    }
+    println!("cargo:rerun-if-changed=proto/engine.proto");
+}
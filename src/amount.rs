@@ -0,0 +1,385 @@
+//! Fixed-point amount with four decimal places.
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::de::{self, Visitor};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimal places an [`Amount`] can represent.
+pub const DECIMALS: u32 = 4;
+
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+/// A monetary amount stored as an integer number of 1/10000 units, so that sums
+/// of deposits and withdrawals are exact.
+///
+/// Parses from and serializes to a decimal string such as `1.2345`. When parsing, digits beyond
+/// the fourth decimal place are rounded half away from zero, see [`Amount::parse_exact`] to reject them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Builds an amount from a number of 1/10000 units.
+    pub const fn from_units(units: i64) -> Amount {
+        Amount(units)
+    }
+
+    /// Number of 1/10000 units.
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// The sum, or `None` when it is out of the range of an amount.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// The difference, or `None` when it is out of the range of an amount.
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// The sum, clamped to the range of an amount.
+    pub fn saturating_add(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+
+    /// Parses `s` like [`FromStr`], but rejects amounts with more than four decimal places
+    /// instead of rounding them.
+    pub fn parse_exact(s: &str) -> Result<Amount, ParseAmountError> {
+        parse(s, false)
+    }
+
+    /// Converts the decimal `value` × 10^-`scale`, e.g. a value of an Arrow decimal column, rounding the
+    /// digits beyond the fourth decimal place like [`FromStr`].
+    pub fn from_decimal(value: i128, scale: i8) -> Result<Amount, ParseAmountError> {
+        from_decimal(value, scale, true)
+    }
+
+    /// Converts the decimal `value` × 10^-`scale` like [`Amount::from_decimal`], but rejects the values
+    /// that have a non-zero digit beyond the fourth decimal place instead of rounding them.
+    pub fn from_decimal_exact(value: i128, scale: i8) -> Result<Amount, ParseAmountError> {
+        from_decimal(value, scale, false)
+    }
+}
+
+/// Error returned when parsing an [`Amount`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAmountError {
+    Invalid(String),
+    TooPrecise(String),
+    OutOfRange(String),
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid(s) => write!(f, "invalid amount {s:?}"),
+            ParseAmountError::TooPrecise(s) => write!(f, "amount {s} has more than {DECIMALS} decimal places"),
+            ParseAmountError::OutOfRange(s) => write!(f, "amount {s} is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s, true)
+    }
+}
+
+fn parse(s: &str, round: bool) -> Result<Amount, ParseAmountError> {
+    let invalid = || ParseAmountError::Invalid(s.to_string());
+
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(invalid())
+    }
+    if !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+        return Err(invalid())
+    }
+    let (fraction, rest) = fraction.split_at(fraction.len().min(DECIMALS as usize));
+    if !rest.is_empty() && !round {
+        return Err(ParseAmountError::TooPrecise(s.to_string()))
+    }
+    let round_up = rest.bytes().next().is_some_and(|digit| digit >= b'5');
+
+    let out_of_range = || ParseAmountError::OutOfRange(s.to_string());
+    let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| out_of_range())? };
+    let fraction: i64 = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<i64>().map_err(|_| invalid())? * 10_i64.pow(DECIMALS - fraction.len() as u32)
+    };
+
+    let units = whole.checked_mul(SCALE)
+        .and_then(|units| units.checked_add(fraction))
+        .and_then(|units| units.checked_add(round_up as i64))
+        .ok_or_else(out_of_range)?;
+
+    Ok(Amount(if negative { -units } else { units }))
+}
+
+fn from_decimal(value: i128, scale: i8, round: bool) -> Result<Amount, ParseAmountError> {
+    let out_of_range = || ParseAmountError::OutOfRange(decimal_string(value, scale));
+
+    let shift = i32::from(scale) - DECIMALS as i32;
+    let units = if shift <= 0 {
+        10_i128.checked_pow(shift.unsigned_abs()).and_then(|factor| value.checked_mul(factor)).ok_or_else(out_of_range)?
+    } else {
+        // Past 10^38 the divisor exceeds any value, which is then only made of digits beyond the fourth decimal place.
+        let divisor = 10_i128.checked_pow(shift.unsigned_abs());
+        let (units, rest) = divisor.map_or((0, value), |divisor| (value / divisor, value % divisor));
+        if rest != 0 && !round {
+            return Err(ParseAmountError::TooPrecise(decimal_string(value, scale)))
+        }
+        let round_up = divisor.is_some_and(|divisor| rest.unsigned_abs() * 2 >= divisor.unsigned_abs());
+        units + if round_up { value.signum() } else { 0 }
+    };
+
+    i64::try_from(units).map(Amount).map_err(|_| out_of_range())
+}
+
+// `value` × 10^-`scale` written out, for the error messages.
+fn decimal_string(value: i128, scale: i8) -> String {
+    if scale <= 0 {
+        return format!("{value}{}", "0".repeat(scale.unsigned_abs().into()))
+    }
+    let digits = format!("{:0>width$}", value.unsigned_abs(), width = usize::from(scale.unsigned_abs()) + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - usize::from(scale.unsigned_abs()));
+    format!("{}{whole}.{fraction}", if value < 0 { "-" } else { "" })
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        let fraction = format!("{:04}", units % scale);
+        let fraction = fraction.trim_end_matches('0');
+        let fraction = if fraction.is_empty() { "0" } else { fraction };
+
+        write!(f, "{sign}{}.{fraction}", units / scale)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        Amount(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        Amount(self.0 - rhs.0)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+// Totals over many accounts are reports, they saturate rather than overflow.
+impl std::iter::Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Amount::saturating_add)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct AmountVisitor;
+
+#[cfg(feature = "serde")]
+impl Visitor<'_> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a decimal amount with at most {DECIMALS} decimal places")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn should_parse_decimal_strings() {
+        assert_eq!(amount("1.2345"), Amount::from_units(12_345));
+        assert_eq!(amount("1"), Amount::from_units(10_000));
+        assert_eq!(amount("1."), Amount::from_units(10_000));
+        assert_eq!(amount(".5"), Amount::from_units(5_000));
+        assert_eq!(amount("0.0001"), Amount::from_units(1));
+        assert_eq!(amount("-3.50"), Amount::from_units(-35_000));
+        assert_eq!(amount("+2"), Amount::from_units(20_000));
+    }
+
+    #[test]
+    fn when_more_than_four_decimals_should_round() {
+        assert_eq!(amount("1.00001"), amount("1"));
+        assert_eq!(amount("0.12345"), amount("0.1235"));
+        assert_eq!(amount("0.00005"), amount("0.0001"));
+        assert_eq!(amount("0.000049999"), Amount::ZERO);
+        assert_eq!(amount("-2.99995"), amount("-3"));
+        assert_eq!(amount("9.99999"), amount("10"));
+    }
+
+    #[test]
+    fn when_more_than_four_decimals_parse_exact_should_reject() {
+        assert_eq!(Amount::parse_exact("1.00001"), Err(ParseAmountError::TooPrecise("1.00001".to_string())));
+        assert_eq!(Amount::parse_exact("1.10000"), Err(ParseAmountError::TooPrecise("1.10000".to_string())));
+        assert_eq!(Amount::parse_exact("1.2345"), Ok(amount("1.2345")));
+        assert!(matches!(Amount::parse_exact("1.2a"), Err(ParseAmountError::Invalid(_))));
+    }
+
+    #[test]
+    fn should_convert_decimals_of_any_scale() {
+        assert_eq!(Amount::from_decimal(12_345, 4), Ok(amount("1.2345")));
+        assert_eq!(Amount::from_decimal(15, 1), Ok(amount("1.5")));
+        assert_eq!(Amount::from_decimal(-3, 0), Ok(amount("-3")));
+        assert_eq!(Amount::from_decimal(2, -2), Ok(amount("200")));
+        assert_eq!(Amount::from_decimal(123_450_000, 8), Ok(amount("1.2345")));
+    }
+
+    #[test]
+    fn when_more_than_four_decimals_from_decimal_should_round_like_parsing() {
+        assert_eq!(Amount::from_decimal(12_345, 5), Ok(amount("0.12345")));
+        assert_eq!(Amount::from_decimal(-299_995, 5), Ok(amount("-2.99995")));
+        assert_eq!(Amount::from_decimal(49_999, 9), Ok(Amount::ZERO));
+        assert_eq!(Amount::from_decimal(1, 60), Ok(Amount::ZERO));
+
+        assert_eq!(Amount::from_decimal_exact(110_000, 5), Ok(amount("1.1")));
+        assert_eq!(Amount::from_decimal_exact(100_001, 5), Err(ParseAmountError::TooPrecise("1.00001".to_string())));
+        assert_eq!(Amount::from_decimal_exact(-5, 6), Err(ParseAmountError::TooPrecise("-0.000005".to_string())));
+    }
+
+    #[test]
+    fn when_decimal_too_large_should_reject() {
+        assert_eq!(Amount::from_decimal(i128::from(i64::MAX), 0), Err(ParseAmountError::OutOfRange("9223372036854775807".to_string())));
+        assert!(matches!(Amount::from_decimal(1, -40), Err(ParseAmountError::OutOfRange(_))));
+    }
+
+    #[test]
+    fn when_not_a_decimal_should_reject() {
+        for s in ["", "-", ".", "abc", "1.2.3", "1e5", " 1.0", "1,5", "--1", "1.-5"] {
+            assert!(matches!(s.parse::<Amount>(), Err(ParseAmountError::Invalid(_))), "{s}");
+        }
+    }
+
+    #[test]
+    fn when_too_large_should_reject() {
+        assert!(matches!("9223372036854775807".parse::<Amount>(), Err(ParseAmountError::OutOfRange(_))));
+    }
+
+    #[test]
+    fn should_display_with_at_most_four_decimals() {
+        assert_eq!(amount("2").to_string(), "2.0");
+        assert_eq!(amount("1.5000").to_string(), "1.5");
+        assert_eq!(amount("1.2345").to_string(), "1.2345");
+        assert_eq!(amount("0.0001").to_string(), "0.0001");
+        assert_eq!(amount("-40").to_string(), "-40.0");
+        assert_eq!(amount("-0.05").to_string(), "-0.05");
+    }
+
+    #[test]
+    fn summing_many_small_amounts_should_be_exact() {
+        let total: Amount = std::iter::repeat_n(amount("0.1"), 10_000).sum();
+
+        assert_eq!(total, amount("1000"));
+
+        let mut balance = Amount::ZERO;
+        for _ in 0..10_000 {
+            balance += amount("0.0001");
+        }
+        for _ in 0..5_000 {
+            balance -= amount("0.0001");
+        }
+        assert_eq!(balance, amount("0.5"));
+    }
+
+    #[test]
+    fn when_out_of_range_checked_arithmetic_should_be_none() {
+        let max = Amount::from_units(i64::MAX);
+
+        assert_eq!(max.checked_add(amount("0.0001")), None);
+        assert_eq!(Amount::from_units(i64::MIN).checked_sub(amount("0.0001")), None);
+        assert_eq!(max.checked_sub(amount("1")), Some(Amount::from_units(i64::MAX - 10_000)));
+        assert_eq!([max, max].into_iter().sum::<Amount>(), max);
+    }
+
+    #[test]
+    #[cfg(feature = "csv-io")]
+    fn should_round_trip_through_csv() {
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        writer.serialize((amount("1.2345"), amount("-7"))).unwrap();
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(data, "1.2345,-7.0\n");
+
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(data.as_bytes());
+        let parsed: (Amount, Amount) = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(parsed, (amount("1.2345"), amount("-7")));
+    }
+}
@@ -0,0 +1,461 @@
+//! Maps the record batches of an Arrow IPC file onto the engine's [`Transaction`], with the `arrow` feature.
+//!
+//! The batches have the columns of [`COLUMNS`], and optionally `to_client`, `ts` and `currency`, matched by name:
+//!
+//! - `type` and `currency`: strings, possibly dictionary encoded,
+//! - `client`, `tx` and `to_client`: integers of any width,
+//! - `amount`: decimals of any scale, rounded to four decimal places like the amounts of a CSV row,
+//! - `ts`: timestamps of any unit, or integer epoch milliseconds.
+//!
+//! Each column of a batch is decoded once into an array of its values, from which
+//! [`TransactionBatch::transaction`] builds the transaction of a row without going through strings.
+
+use std::fmt;
+use std::io::{Cursor, Read, Seek};
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, AsArray, Decimal128Array, Int64Array, RecordBatch, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Int64Type, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::{FileReader, StreamReader};
+use arrow::util::display::array_value_to_string;
+use csv::StringRecord;
+
+use crate::amount::{Amount, ParseAmountError};
+use crate::csv_model::{self, RowError, TransactionRecord, TransactionType, COLUMNS};
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Transaction, TxId};
+use crate::type_alias::TypeAliases;
+
+/// First bytes of an Arrow IPC file, a stream having none.
+const FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Columns read besides [`COLUMNS`] when the input has them.
+const OPTIONAL_COLUMNS: [&str; 3] = ["to_client", "ts", "currency"];
+
+/// Reason an Arrow input cannot be read.
+#[derive(Debug)]
+pub enum ArrowInputError {
+    Arrow(ArrowError),
+    /// One of [`COLUMNS`] is missing from the schema.
+    MissingColumn(&'static str),
+    /// The column has a type that cannot be read as its values.
+    ColumnType { column: &'static str, data_type: DataType },
+}
+
+impl fmt::Display for ArrowInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrowInputError::Arrow(e) => write!(f, "{e}"),
+            ArrowInputError::MissingColumn(column) => {
+                write!(f, "no {column} column, expected the columns {}", COLUMNS.join(","))
+            },
+            ArrowInputError::ColumnType { column, data_type } => write!(f, "the {column} column has the unexpected type {data_type}"),
+        }
+    }
+}
+
+impl std::error::Error for ArrowInputError {}
+
+impl From<ArrowError> for ArrowInputError {
+    fn from(e: ArrowError) -> ArrowInputError {
+        ArrowInputError::Arrow(e)
+    }
+}
+
+/// Iterates the record batches of an Arrow IPC file or stream as [`TransactionBatch`]es.
+pub struct ArrowReader {
+    batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>> + Send>,
+    headers: StringRecord,
+}
+
+impl ArrowReader {
+    /// Reads an IPC file, e.g. a Feather file.
+    pub fn new<R: Read + Seek + Send + 'static>(reader: R) -> Result<ArrowReader, ArrowInputError> {
+        let reader = FileReader::try_new(reader, None)?;
+        let headers = headers(&reader.schema())?;
+        Ok(ArrowReader { batches: Box::new(reader), headers })
+    }
+
+    /// Reads an IPC stream.
+    pub fn from_stream<R: Read + Send + 'static>(reader: R) -> Result<ArrowReader, ArrowInputError> {
+        let reader = StreamReader::try_new(reader, None)?;
+        let headers = headers(&reader.schema())?;
+        Ok(ArrowReader { batches: Box::new(reader), headers })
+    }
+
+    /// Reads `bytes` as an IPC file when they start like one, as an IPC stream otherwise.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<ArrowReader, ArrowInputError> {
+        match bytes.starts_with(FILE_MAGIC) {
+            true => ArrowReader::new(Cursor::new(bytes)),
+            false => ArrowReader::from_stream(Cursor::new(bytes)),
+        }
+    }
+
+    /// Names of the columns read, those of the fields of [`TransactionBatch::record`].
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+}
+
+impl Iterator for ArrowReader {
+    type Item = Result<TransactionBatch, ArrowInputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.batches.next()?;
+        Some(batch.map_err(ArrowInputError::from).and_then(|batch| TransactionBatch::new(&batch)))
+    }
+}
+
+// The columns of the schema that are read, failing when a required one is missing.
+fn headers(schema: &Schema) -> Result<StringRecord, ArrowInputError> {
+    if let Some(column) = COLUMNS.into_iter().find(|column| schema.column_with_name(column).is_none()) {
+        return Err(ArrowInputError::MissingColumn(column))
+    }
+    Ok(COLUMNS.into_iter()
+        .chain(OPTIONAL_COLUMNS.into_iter().filter(|column| schema.column_with_name(column).is_some()))
+        .collect())
+}
+
+/// The columns of a record batch, decoded into arrays of their values.
+pub struct TransactionBatch {
+    types: StringArray,
+    transaction_types: Vec<Option<TransactionType>>,
+    clients: IntColumn,
+    txs: IntColumn,
+    amounts: Decimal128Array,
+    scale: i8,
+    to_clients: Option<IntColumn>,
+    ts: Option<IntColumn>,
+    currencies: Option<StringArray>,
+    // The columns as read, in the order of the headers, to write the rows back.
+    sources: Vec<ArrayRef>,
+}
+
+impl TransactionBatch {
+    pub fn new(batch: &RecordBatch) -> Result<TransactionBatch, ArrowInputError> {
+        let column = |name: &'static str| batch.column_by_name(name).ok_or(ArrowInputError::MissingColumn(name));
+        let optional = |name: &'static str| batch.column_by_name(name);
+
+        let types = strings("type", column("type")?)?;
+        let transaction_types = types.iter().map(|name| name.and_then(TransactionType::from_name)).collect();
+
+        let amounts = column("amount")?;
+        let (amounts, scale) = match amounts.data_type() {
+            DataType::Decimal32(_, scale) | DataType::Decimal64(_, scale) | DataType::Decimal128(_, scale) => {
+                (cast(amounts, &DataType::Decimal128(38, *scale))?, *scale)
+            },
+            data_type => return Err(ArrowInputError::ColumnType { column: "amount", data_type: data_type.clone() }),
+        };
+
+        let sources = COLUMNS.into_iter().chain(OPTIONAL_COLUMNS).filter_map(optional).map(Arc::clone).collect();
+        Ok(TransactionBatch {
+            types,
+            transaction_types,
+            clients: IntColumn::new("client", column("client")?)?,
+            txs: IntColumn::new("tx", column("tx")?)?,
+            amounts: amounts.as_primitive().clone(),
+            scale,
+            to_clients: optional("to_client").map(|column| IntColumn::new("to_client", column)).transpose()?,
+            ts: optional("ts").map(IntColumn::timestamps).transpose()?,
+            currencies: optional("currency").map(|column| strings("currency", column)).transpose()?,
+            sources,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Maps the partner specific types of the `type` column onto the canonical ones.
+    pub fn resolve_aliases(&mut self, aliases: &TypeAliases) {
+        if aliases.is_empty() {
+            return
+        }
+        for (transaction_type, name) in self.transaction_types.iter_mut().zip(self.types.iter()) {
+            if let (None, Some(name)) = (*transaction_type, name) {
+                *transaction_type = TransactionType::from_name(aliases.resolve(name));
+            }
+        }
+    }
+
+    /// The transaction of the row at `index`, with its timestamp and currency. Fails like a CSV row, e.g. with
+    /// [`RowError::MissingAmount`] for a deposit whose amount is null.
+    pub fn transaction(&self, index: usize) -> Result<(Transaction, Option<Timestamp>, Option<Currency>), RowError> {
+        let transaction_type = self.transaction_types[index].ok_or_else(|| match self.types.is_null(index) {
+            true => RowError::Parse("missing type".to_string()),
+            false => RowError::UnsupportedType(self.types.value(index).to_string()),
+        })?;
+        let record = TransactionRecord {
+            transaction_type,
+            client: self.clients.required(index).and_then(client_id)?,
+            tx: self.txs.required(index).and_then(|tx| {
+                TxId::try_from(tx).map_err(|_| RowError::Parse(format!("tx id {tx} is out of range, the maximum is {}", TxId::MAX)))
+            })?,
+            amount: self.amounts.is_valid(index)
+                .then(|| Amount::from_decimal(self.amounts.value(index), self.scale))
+                .transpose()
+                .map_err(|e| RowError::Parse(e.to_string()))?,
+            to_client: self.to_clients.as_ref().map(|column| column.get(index)).transpose()?.flatten().map(client_id).transpose()?,
+            ts: self.ts.as_ref().map(|column| column.get(index)).transpose()?.flatten().map(Timestamp::from_millis),
+            currency: self.currencies.as_ref()
+                .filter(|column| column.is_valid(index) && !column.value(index).is_empty())
+                .map(|column| column.value(index).parse())
+                .transpose()
+                .map_err(|e| RowError::Parse(format!("invalid `currency`: {e}")))?,
+        };
+        Ok((record.try_into()?, record.ts, record.currency))
+    }
+
+    /// Fails when the amount of the row at `index` has a non-zero digit beyond the fourth decimal place,
+    /// which [`TransactionBatch::transaction`] rounds.
+    pub fn check_precision(&self, index: usize) -> Result<(), ParseAmountError> {
+        match self.amounts.is_valid(index) {
+            true => Amount::from_decimal_exact(self.amounts.value(index), self.scale).map(|_| ()),
+            false => Ok(()),
+        }
+    }
+
+    /// The row at `index` as written in a CSV file with the [`ArrowReader::headers`] columns, a null
+    /// being an empty field.
+    pub fn record(&self, index: usize) -> StringRecord {
+        self.sources.iter()
+            .map(|column| match column.is_valid(index) {
+                true => array_value_to_string(column, index).unwrap_or_default(),
+                false => String::new(),
+            })
+            .collect()
+    }
+}
+
+// A column of strings, possibly dictionary encoded.
+fn strings(name: &'static str, source: &ArrayRef) -> Result<StringArray, ArrowInputError> {
+    let strings = match source.data_type() {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => cast(source, &DataType::Utf8)?,
+        DataType::Dictionary(_, values) if matches!(**values, DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View) => {
+            cast(source, &DataType::Utf8)?
+        },
+        data_type => return Err(ArrowInputError::ColumnType { column: name, data_type: data_type.clone() }),
+    };
+    Ok(strings.as_string::<i32>().clone())
+}
+
+fn client_id(id: i64) -> Result<u16, RowError> {
+    u64::try_from(id).map_err(|_| RowError::Parse(format!("client id {id} is negative"))).and_then(csv_model::client_id)
+}
+
+/// An integer column widened to `i64`. A value that does not fit, e.g. a `u64` past `i64::MAX`, is null
+/// once widened while it is not in the source.
+struct IntColumn {
+    name: &'static str,
+    values: Int64Array,
+    source: ArrayRef,
+}
+
+impl IntColumn {
+    fn new(name: &'static str, source: &ArrayRef) -> Result<IntColumn, ArrowInputError> {
+        if !source.data_type().is_integer() {
+            return Err(ArrowInputError::ColumnType { column: name, data_type: source.data_type().clone() })
+        }
+        let values = cast(source, &DataType::Int64)?.as_primitive::<Int64Type>().clone();
+        Ok(IntColumn { name, values, source: Arc::clone(source) })
+    }
+
+    // Integer epoch milliseconds, or timestamps of any unit converted to milliseconds.
+    fn timestamps(source: &ArrayRef) -> Result<IntColumn, ArrowInputError> {
+        match source.data_type() {
+            DataType::Timestamp(..) => {
+                let millis = cast(&cast(source, &DataType::Timestamp(TimeUnit::Millisecond, None))?, &DataType::Int64)?;
+                Ok(IntColumn { name: "ts", values: millis.as_primitive::<Int64Type>().clone(), source: Arc::clone(source) })
+            },
+            _ => IntColumn::new("ts", source),
+        }
+    }
+
+    fn get(&self, index: usize) -> Result<Option<i64>, RowError> {
+        match (self.values.is_valid(index), self.source.is_valid(index)) {
+            (true, _) => Ok(Some(self.values.value(index))),
+            (false, false) => Ok(None),
+            (false, true) => Err(RowError::Parse(format!(
+                "{} {} is out of range",
+                self.name,
+                array_value_to_string(&self.source, index).unwrap_or_default(),
+            ))),
+        }
+    }
+
+    fn required(&self, index: usize) -> Result<i64, RowError> {
+        self.get(index)?.ok_or_else(|| RowError::Parse(format!("missing {}", self.name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{DictionaryArray, Int32Array, TimestampSecondArray, UInt16Array, UInt32Array, UInt64Array};
+    use arrow::datatypes::Int8Type;
+    use arrow::ipc::writer::{FileWriter, StreamWriter};
+
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    fn decimals(values: Vec<Option<i128>>, scale: i8) -> ArrayRef {
+        Arc::new(Decimal128Array::from(values).with_precision_and_scale(18, scale).unwrap())
+    }
+
+    // Every column is nullable, so that the batches of a file share their schema whatever their nulls.
+    fn batch(columns: Vec<(&str, ArrayRef)>) -> RecordBatch {
+        RecordBatch::try_from_iter_with_nullable(columns.into_iter().map(|(name, column)| (name, column, true))).unwrap()
+    }
+
+    fn file(batches: &[RecordBatch]) -> Vec<u8> {
+        let mut writer = FileWriter::try_new(Vec::new(), &batches[0].schema()).unwrap();
+        batches.iter().for_each(|batch| writer.write(batch).unwrap());
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn should_read_the_transactions_of_every_batch() {
+        let first = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit", "withdrawal"]))),
+            ("client", Arc::new(UInt16Array::from(vec![1, 1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2]))),
+            ("amount", decimals(vec![Some(15_000), Some(5_000)], 4)),
+        ]);
+        let second = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["dispute"]))),
+            ("client", Arc::new(UInt16Array::from(vec![1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1]))),
+            ("amount", decimals(vec![None], 4)),
+        ]);
+
+        let reader = ArrowReader::from_bytes(file(&[first, second])).unwrap();
+        assert_eq!(reader.headers(), &StringRecord::from(COLUMNS.to_vec()));
+        let transactions: Vec<_> = reader
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                (0..batch.len()).map(move |index| batch.transaction(index).unwrap().0)
+            })
+            .collect();
+
+        assert_eq!(transactions, vec![
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1.5") },
+            Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("0.5") },
+            Transaction::Dispute { client_id: 1, tx_id: 1 },
+        ]);
+    }
+
+    #[test]
+    fn should_read_a_stream() {
+        let batch = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit"]))),
+            ("client", Arc::new(UInt16Array::from(vec![1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1]))),
+            ("amount", decimals(vec![Some(1)], 0)),
+        ]);
+        let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+
+        let mut reader = ArrowReader::from_bytes(writer.into_inner().unwrap()).unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.transaction(0).unwrap().0, Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1") });
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn malformed_rows_should_fail_like_csv_rows() {
+        let batch = TransactionBatch::new(&batch(vec![
+            ("type", Arc::new(StringArray::from(vec![Some("deposit"), Some("refund"), None, Some("deposit"), Some("fee")]))),
+            ("client", Arc::new(Int32Array::from(vec![Some(1), Some(1), Some(1), Some(70_000), None]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2, 3, 4, 5]))),
+            ("amount", decimals(vec![None, Some(1), Some(1), Some(1), Some(1)], 0)),
+        ])).unwrap();
+
+        assert_eq!(batch.transaction(0), Err(RowError::MissingAmount { transaction_type: TransactionType::Deposit, client_id: 1, tx_id: 1 }));
+        assert_eq!(batch.transaction(1), Err(RowError::UnsupportedType("refund".to_string())));
+        assert_eq!(batch.transaction(2), Err(RowError::Parse("missing type".to_string())));
+        assert_eq!(
+            batch.transaction(3),
+            Err(RowError::ClientOutOfRange { client_id: 70_000 }),
+        );
+        assert_eq!(batch.transaction(4), Err(RowError::Parse("missing client".to_string())));
+        assert_eq!(batch.record(0), StringRecord::from(vec!["deposit", "1", "1", ""]));
+    }
+
+    #[test]
+    fn should_decode_dictionaries_aliases_timestamps_and_precise_amounts() {
+        let types: DictionaryArray<Int8Type> = vec!["credit", "transfer", "credit"].into_iter().collect();
+        let mut batch = TransactionBatch::new(&batch(vec![
+            ("type", Arc::new(types)),
+            ("client", Arc::new(UInt64Array::from(vec![1, 1, u64::MAX]))),
+            ("tx", Arc::new(UInt64Array::from(vec![1, 2, 3]))),
+            ("amount", decimals(vec![Some(123_456), Some(100_000), Some(1)], 5)),
+            ("to_client", Arc::new(UInt16Array::from(vec![None, Some(2), None]))),
+            ("ts", Arc::new(TimestampSecondArray::from(vec![Some(1_700_000_000), None, None]))),
+        ])).unwrap();
+        assert_eq!(batch.transaction(0), Err(RowError::UnsupportedType("credit".to_string())));
+
+        let mut aliases = TypeAliases::new();
+        aliases.add("credit=deposit").unwrap();
+        batch.resolve_aliases(&aliases);
+
+        assert_eq!(batch.transaction(0), Ok((
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1.2346") },
+            Some(Timestamp::from_millis(1_700_000_000_000)),
+            None,
+        )));
+        assert_eq!(batch.transaction(1), Ok((Transaction::Transfer { from: 1, to: 2, tx_id: 2, amount: amount("1") }, None, None)));
+        assert_eq!(batch.transaction(2), Err(RowError::Parse(format!("client {} is out of range", u64::MAX))));
+        assert_eq!(batch.check_precision(0), Err(ParseAmountError::TooPrecise("1.23456".to_string())));
+        assert_eq!(batch.check_precision(1), Ok(()));
+        assert_eq!(batch.record(1), StringRecord::from(vec!["transfer", "1", "2", "1.00000", "2", ""]));
+    }
+
+    #[test]
+    fn should_read_the_optional_currency() {
+        let currencies: DictionaryArray<Int8Type> = vec![Some("eur"), None, Some(""), Some("euro")].into_iter().collect();
+        let batch = TransactionBatch::new(&batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit", "deposit", "dispute", "deposit"]))),
+            ("client", Arc::new(UInt16Array::from(vec![1, 1, 1, 1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2, 1, 3]))),
+            ("amount", decimals(vec![Some(1), Some(1), None, Some(1)], 0)),
+            ("currency", Arc::new(currencies)),
+        ])).unwrap();
+
+        assert_eq!(batch.transaction(0).unwrap().2, Some("EUR".parse().unwrap()));
+        assert_eq!(batch.transaction(1).unwrap().2, None);
+        assert_eq!(batch.transaction(2).unwrap().2, None);
+        assert_eq!(
+            batch.transaction(3),
+            Err(RowError::Parse("invalid `currency`: invalid currency \"euro\", expected a 3-letter code".to_string()))
+        );
+        assert_eq!(batch.record(0), StringRecord::from(vec!["deposit", "1", "1", "1", "eur"]));
+    }
+
+    #[test]
+    fn unexpected_schemas_should_fail() {
+        let no_amount = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef),
+            ("client", Arc::new(UInt16Array::from(vec![1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1]))),
+        ]);
+        assert!(matches!(ArrowReader::from_bytes(file(&[no_amount])), Err(ArrowInputError::MissingColumn("amount"))));
+
+        let text_amount = batch(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef),
+            ("client", Arc::new(UInt16Array::from(vec![1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1]))),
+            ("amount", Arc::new(StringArray::from(vec!["1.0"]))),
+        ]);
+        let e = TransactionBatch::new(&text_amount).err().unwrap();
+        assert_eq!(e.to_string(), "the amount column has the unexpected type Utf8");
+    }
+}
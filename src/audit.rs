@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use csv::StringRecord;
+use serde::Deserialize;
+use serde_json::Number;
+use transaction_engine::{AuditEvent, Client, Currency, EngineEvent, Timestamp};
+
+use crate::rejection::Rejection;
+use crate::wire::{AuditEntry, JsonClient, JsonEvent};
+
+/// What `verify-audit` reads back from a line of the audit log.
+#[derive(Deserialize)]
+pub struct LoggedRow {
+    pub balance: Option<LoggedBalance>,
+    #[serde(default)]
+    pub events: Vec<JsonEvent>,
+}
+
+/// Balances of a line of the audit log.
+#[derive(Deserialize)]
+pub struct LoggedBalance {
+    client: u16,
+    available: Number,
+    held: Number,
+    total: Number,
+    locked: bool,
+    currency: Currency,
+}
+
+impl LoggedBalance {
+    pub fn client(&self) -> Result<Client, String> {
+        let amount = |amount: &Number| amount.as_str().parse().map_err(|e| format!("invalid amount {amount}: {e}"));
+        let client = Client::new(self.client, amount(&self.available)?, amount(&self.held)?, amount(&self.total)?, self.locked);
+        Ok(client.in_currency(self.currency))
+    }
+}
+
+/// JSON lines log of what was done with every input row, along with the events of the engine.
+pub struct Audit<W: Write> {
+    writer: BufWriter<W>,
+    // Filled by the observer of the engine, emptied by each row.
+    events: Arc<Mutex<Vec<EngineEvent>>>,
+    seq: u64,
+}
+
+impl Audit<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Audit<File>> {
+        Ok(Audit::from_writer(File::create(path)?))
+    }
+}
+
+impl<W: Write> Audit<W> {
+    pub fn from_writer(writer: W) -> Audit<W> {
+        Audit { writer: BufWriter::new(writer), events: Arc::default(), seq: 0 }
+    }
+
+    /// Observer to set on the engine, whose events are written with the row that caused them.
+    pub fn observer(&self) -> impl Fn(&EngineEvent) + Send + Sync + 'static {
+        let events = Arc::clone(&self.events);
+        move |event: &EngineEvent| events.lock().unwrap().push(*event)
+    }
+
+    /// A row is `applied`, `ignored` when the engine ignores it by design (e.g. a dispute on an
+    /// unknown tx), or `rejected`. The events received since the previous row are numbered and written in
+    /// `currency`, that of the row.
+    pub fn write(
+        &mut self,
+        row: &StringRecord,
+        ts: Option<Timestamp>,
+        rejection: Option<&Rejection>,
+        client: Option<&Client>,
+        currency: Currency,
+    ) -> io::Result<()> {
+        let action = match rejection {
+            None => "applied",
+            Some(Rejection::Engine(e)) if e.is_ignored() => "ignored",
+            Some(_) => "rejected",
+        };
+        let entry = AuditEntry {
+            line: row.position().map(|position| position.line()),
+            row: row.iter().collect(),
+            ts,
+            action,
+            reason: rejection.map(Rejection::code),
+            balance: client.map(|client| JsonClient::new(client, true)),
+            events: self.events.lock().unwrap().drain(..)
+                .filter_map(|event| {
+                    let event = AuditEvent { seq: self.seq + 1, currency, event };
+                    let event = JsonEvent::new(&event)?;
+                    self.seq += 1;
+                    Some(event)
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction_engine::csv_model::RowError;
+    use transaction_engine::TransactionError;
+
+    #[test]
+    fn should_write_the_row_action_and_balance() {
+        let mut output = vec![];
+        let mut audit = Audit::from_writer(&mut output);
+        let observer = audit.observer();
+        let client = Client::new(1, "3.0".parse().unwrap(), "0.0".parse().unwrap(), "3.0".parse().unwrap(), false);
+
+        observer(&EngineEvent::AccountCredited { client_id: 1, tx_id: 1, amount: "3.0".parse().unwrap() });
+        audit.write(
+            &StringRecord::from(vec!["deposit", "1", "1", "3.0", "1700000000000"]),
+            Some(Timestamp::from_millis(1_700_000_000_000)),
+            None,
+            Some(&client),
+            Currency::USD,
+        ).unwrap();
+        audit.write(
+            &StringRecord::from(vec!["dispute", "1", "9", ""]),
+            None,
+            Some(&Rejection::Engine(TransactionError::UnknownTransaction { tx_id: 9 })),
+            Some(&client),
+            Currency::USD,
+        ).unwrap();
+        observer(&EngineEvent::AccountOpened { client_id: 2, tx_id: 5 });
+        observer(&EngineEvent::TransactionRejected {
+            client_id: 2,
+            tx_id: 5,
+            error: TransactionError::InsufficientFunds { client_id: 2, requested: "1.0".parse().unwrap(), available: Default::default() },
+        });
+        audit.write(
+            &StringRecord::from(vec!["withdrawal", "2", "5", "1.0"]),
+            None,
+            Some(&Rejection::Engine(TransactionError::InsufficientFunds {
+                client_id: 2,
+                requested: "1.0".parse().unwrap(),
+                available: Default::default(),
+            })),
+            None,
+            "EUR".parse().unwrap(),
+        ).unwrap();
+        audit.write(&StringRecord::from(vec!["deposit", "x"]), None, Some(&Rejection::Row(RowError::Parse("bad".to_string()))), None, Currency::USD).unwrap();
+
+        audit.flush().unwrap();
+        drop(audit);
+
+        let written = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines, vec![
+            r#"{"line":null,"row":["deposit","1","1","3.0","1700000000000"],"ts":"2023-11-14T22:13:20.000Z","action":"applied","reason":null,"balance":{"client":1,"available":3.0,"held":0.0,"total":3.0,"locked":false,"currency":"USD"},"events":[{"seq":1,"type":"account_credited","client":1,"tx":1,"amount":3.0,"currency":"USD"}]}"#,
+            r#"{"line":null,"row":["dispute","1","9",""],"ts":null,"action":"ignored","reason":"unknown_transaction","balance":{"client":1,"available":3.0,"held":0.0,"total":3.0,"locked":false,"currency":"USD"},"events":[]}"#,
+            r#"{"line":null,"row":["withdrawal","2","5","1.0"],"ts":null,"action":"rejected","reason":"insufficient_funds","balance":null,"events":[{"seq":2,"type":"account_opened","client":2,"tx":5,"currency":"EUR"}]}"#,
+            r#"{"line":null,"row":["deposit","x"],"ts":null,"action":"rejected","reason":"parse_error","balance":null,"events":[]}"#,
+        ]);
+
+        let logged: LoggedRow = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(logged.balance.unwrap().client(), Ok(client));
+        assert_eq!(logged.events[0].event(), Ok(AuditEvent {
+            seq: 1,
+            currency: Currency::USD,
+            event: EngineEvent::AccountCredited { client_id: 1, tx_id: 1, amount: "3.0".parse().unwrap() },
+        }));
+    }
+}
@@ -0,0 +1,129 @@
+//! Outcome of applying many transactions, see [`crate::TransactionEngine::compute_transactions`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::transaction_engine::TxId;
+
+/// Counts of the transactions read, applied and rejected by reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    read: u64,
+    applied: u64,
+    rejected: BTreeMap<&'static str, u64>,
+    rejected_tx_ids: Vec<TxId>,
+}
+
+impl BatchSummary {
+    pub fn new() -> BatchSummary {
+        BatchSummary::default()
+    }
+
+    pub fn applied(&mut self) {
+        self.read += 1;
+        self.applied += 1;
+    }
+
+    /// Counts a rejection whose transaction is not known, e.g. a row that could not be parsed.
+    pub fn rejected(&mut self, reason: &'static str) {
+        self.read += 1;
+        *self.rejected.entry(reason).or_default() += 1;
+    }
+
+    /// Counts the rejection of transaction `tx_id`, which is kept in [`BatchSummary::rejected_tx_ids`].
+    pub fn rejected_tx(&mut self, tx_id: TxId, reason: &'static str) {
+        self.rejected(reason);
+        self.rejected_tx_ids.push(tx_id);
+    }
+
+    /// Adds the counts of `other`, its rejected transactions following those of this summary.
+    pub fn merge(&mut self, other: BatchSummary) {
+        self.read += other.read;
+        self.applied += other.applied;
+        for (reason, count) in other.rejected {
+            *self.rejected.entry(reason).or_default() += count;
+        }
+        self.rejected_tx_ids.extend(other.rejected_tx_ids);
+    }
+
+    pub fn read_count(&self) -> u64 {
+        self.read
+    }
+
+    pub fn applied_count(&self) -> u64 {
+        self.applied
+    }
+
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+
+    /// Rejections with `reason`, one of the [`crate::TransactionError::code`].
+    pub fn count(&self, reason: &str) -> u64 {
+        self.rejected.get(reason).copied().unwrap_or_default()
+    }
+
+    /// Rejected transactions, in the order they were applied.
+    pub fn rejected_tx_ids(&self) -> &[TxId] {
+        &self.rejected_tx_ids
+    }
+}
+
+// Reasons are listed from the most to the least frequent.
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut reasons: Vec<(&str, u64)> = self.rejected.iter().map(|(reason, count)| (*reason, *count)).collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let reasons: Vec<String> = reasons.iter().map(|(reason, count)| format!("{reason}: {count}")).collect();
+
+        writeln!(f, "read: {}, applied: {}", self.read, self.applied)?;
+        write!(f, "rejected: {} ({})", self.rejected_count(), reasons.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_rows_and_rejections_by_reason() {
+        let mut summary = BatchSummary::new();
+
+        summary.applied();
+        summary.applied();
+        summary.rejected("missing_amount");
+        summary.rejected_tx(4, "insufficient_funds");
+        summary.rejected_tx(5, "account_locked");
+        summary.rejected_tx(7, "insufficient_funds");
+
+        assert_eq!(summary.read_count(), 6);
+        assert_eq!(summary.applied_count(), 2);
+        assert_eq!(summary.rejected_count(), 4);
+        assert_eq!(summary.count("insufficient_funds"), 2);
+        assert_eq!(summary.count("parse_error"), 0);
+        assert_eq!(summary.rejected_tx_ids(), &[4, 5, 7]);
+        assert_eq!(
+            summary.to_string(),
+            "read: 6, applied: 2\nrejected: 4 (insufficient_funds: 2, account_locked: 1, missing_amount: 1)"
+        );
+    }
+
+    #[test]
+    fn merge_should_add_the_counts() {
+        let mut summary = BatchSummary::new();
+        summary.applied();
+        summary.rejected_tx(4, "insufficient_funds");
+        let mut other = BatchSummary::new();
+        other.applied();
+        other.rejected("parse_error");
+        other.rejected_tx(7, "insufficient_funds");
+
+        summary.merge(other);
+
+        assert_eq!(summary.read_count(), 5);
+        assert_eq!(summary.applied_count(), 2);
+        assert_eq!(summary.count("insufficient_funds"), 2);
+        assert_eq!(summary.count("parse_error"), 1);
+        assert_eq!(summary.rejected_tx_ids(), &[4, 7]);
+    }
+}
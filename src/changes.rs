@@ -0,0 +1,109 @@
+//! Tracking the accounts changed between two outputs of the balances, see
+//! [`TransactionEngine::track_changes`](crate::TransactionEngine::track_changes).
+
+use crate::collections::FastHashSet;
+use crate::currency::Currency;
+use crate::transaction_engine::Client;
+
+/// The accounts changed by the transactions `first_seq..=last_seq` of an engine, the transactions being numbered
+/// from 1 in the order the engine got them since it tracks changes, rejected ones included. When no transaction
+/// came, `first_seq` is `last_seq + 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changes {
+    /// Sorted by client id then currency.
+    pub clients: Vec<Client>,
+    pub first_seq: u64,
+    pub last_seq: u64,
+}
+
+// The accounts changed since the last take, and the sequence numbers of the transactions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChangeTracker {
+    pub(crate) changed: FastHashSet<(u16, Currency)>,
+    pub(crate) sequence: u64,
+    pub(crate) taken: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::amount::Amount;
+    use crate::config::EngineConfig;
+    use crate::transaction_engine::{Client, Transaction, TransactionEngine};
+
+    // Deterministic mix of every transaction type over a few clients, most references being to earlier
+    // transactions.
+    fn workload(seed: u64) -> Vec<Transaction> {
+        let mut seed = seed;
+        let mut next = move |modulo: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % modulo
+        };
+        (1..=1_000u64)
+            .map(|tx_id| {
+                let client_id = next(30) as u16;
+                let amount = Amount::from_units(next(100_000) as i64 + 1);
+                let referenced = next(tx_id) + 1;
+                match next(20) {
+                    0..=2 => Transaction::Dispute { client_id, tx_id: referenced },
+                    3 => Transaction::Resolve { client_id, tx_id: referenced },
+                    4 => Transaction::Chargeback { client_id, tx_id: referenced },
+                    5 => Transaction::Unlock { client_id, tx_id },
+                    6 => Transaction::Fee { client_id, tx_id, amount },
+                    7..=8 => Transaction::Transfer { from: client_id, to: next(30) as u16, tx_id, amount },
+                    9..=12 => Transaction::Withdrawal { client_id, tx_id, amount },
+                    _ => Transaction::Deposit { client_id, tx_id, amount },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_full_output_and_the_following_changes_should_rebuild_the_final_balances() {
+        for seed in 0..20 {
+            let mut engine = TransactionEngine::with_config(EngineConfig::default().compact_locked(true));
+            let transactions = workload(seed);
+            let (first, rest) = transactions.split_at(200);
+            engine.compute_transactions(first.iter().copied());
+            let mut rebuilt: BTreeMap<u16, Client> = engine.clients().map(|client| (client.id(), *client)).collect();
+            engine.track_changes();
+
+            let mut last_seq = 0;
+            for (index, chunk) in rest.chunks(seed as usize * 7 + 1).enumerate() {
+                engine.compute_transactions(chunk.iter().copied());
+                let changes = engine.take_changes().unwrap();
+                assert_eq!((changes.first_seq, changes.last_seq), (last_seq + 1, last_seq + chunk.len() as u64), "chunk {index}");
+                last_seq = changes.last_seq;
+                rebuilt.extend(changes.clients.into_iter().map(|client| (client.id(), client)));
+            }
+
+            let mut expected: Vec<Client> = engine.clients().copied().collect();
+            expected.sort_by_key(Client::id);
+            assert_eq!(rebuilt.into_values().collect::<Vec<_>>(), expected, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn the_changes_should_only_list_the_accounts_changed_since_the_last_take() {
+        let mut engine = TransactionEngine::new();
+        let amount = Amount::from_units(10_000);
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount }).unwrap();
+        assert_eq!(engine.take_changes(), None);
+        engine.track_changes();
+
+        engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount }).unwrap();
+        let _ = engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 3, amount: amount + amount });
+        let _ = engine.compute_transaction(Transaction::Withdrawal { client_id: 3, tx_id: 4, amount });
+        let changes = engine.take_changes().unwrap();
+
+        let zero = Amount::ZERO;
+        assert_eq!(changes.clients, vec![Client::new(2, amount, zero, amount, false), Client::new(3, zero, zero, zero, false)]);
+        assert_eq!((changes.first_seq, changes.last_seq), (1, 3));
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        let changes = engine.take_changes().unwrap();
+        assert_eq!(changes.clients, vec![Client::new(1, zero, amount, amount, false)]);
+        assert_eq!((changes.first_seq, changes.last_seq), (4, 4));
+        assert_eq!(engine.take_changes().unwrap(), crate::changes::Changes { clients: vec![], first_seq: 5, last_seq: 4 });
+    }
+}
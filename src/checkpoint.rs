@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use transaction_engine::{TransactionEngine, TransactionStore};
+
+use crate::atomic_file::AtomicFile;
+
+const PREFIX: &str = "checkpoint-";
+
+/// Where the input was when a checkpoint was taken, written as the first line of the checkpoint
+/// before the engine snapshot.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    /// Canonical path of the input.
+    pub path: PathBuf,
+    /// End of the header in the input, the rows start there.
+    pub header_end: u64,
+    /// Start of the first row not applied yet.
+    pub offset: u64,
+    /// Rows read before `offset`.
+    pub rows: u64,
+}
+
+/// Writes a checkpoint of the engine every `every` rows of a single CSV file, keeping only the latest one.
+pub struct Checkpointer {
+    dir: PathBuf,
+    every: u64,
+    path: PathBuf,
+    // Header end and offset of the checkpoint the run recovered from. The input then reads the header
+    // followed by the rows from that offset, so stream offsets are shifted from file offsets.
+    recovered: Option<(u64, u64)>,
+    header_end: Option<u64>,
+    // Rows read when the run started, there is no point in writing a checkpoint before any row is applied.
+    start: u64,
+    rows: u64,
+}
+
+impl Checkpointer {
+    pub fn new(dir: PathBuf, every: u64, path: PathBuf, recovered: Option<&Position>) -> Checkpointer {
+        Checkpointer {
+            dir,
+            every,
+            path,
+            recovered: recovered.map(|position| (position.header_end, position.offset)),
+            header_end: recovered.map(|position| position.header_end),
+            start: recovered.map_or(0, |position| position.rows),
+            rows: recovered.map_or(0, |position| position.rows),
+        }
+    }
+
+    /// Called before applying each row, `offset` being where the row starts in the input stream.
+    pub fn row<S: TransactionStore>(&mut self, engine: &TransactionEngine<S>, offset: u64) -> io::Result<()> {
+        let header_end = *self.header_end.get_or_insert(offset);
+        if self.rows > self.start && self.rows.is_multiple_of(self.every) {
+            let offset = match self.recovered {
+                Some((header_end, recovered_offset)) => offset - header_end + recovered_offset,
+                None => offset,
+            };
+            self.write(engine, Position { path: self.path.clone(), header_end, offset, rows: self.rows })?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn write<S: TransactionStore>(&mut self, engine: &TransactionEngine<S>, position: Position) -> io::Result<()> {
+        let path = self.dir.join(format!("{PREFIX}{:020}", position.rows));
+        let mut file = AtomicFile::create(&path)?;
+        {
+            let mut writer = BufWriter::new(&mut file);
+            serde_json::to_writer(&mut writer, &position)?;
+            writeln!(writer)?;
+            engine.save_snapshot(&mut writer).map_err(|e| io::Error::other(e.to_string()))?;
+            writer.flush()?;
+        }
+        file.commit()?;
+
+        // Older checkpoints, including the one the run recovered from, are no longer needed.
+        for previous in checkpoints(&self.dir)? {
+            if previous != path {
+                fs::remove_file(previous)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn checkpoints(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut checkpoints = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_checkpoint = path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(PREFIX));
+        if is_checkpoint {
+            checkpoints.push(path);
+        }
+    }
+    Ok(checkpoints)
+}
+
+/// Path of the latest checkpoint in `dir`, if any.
+// Names hold the zero padded row count, so the latest checkpoint sorts last.
+pub fn latest(dir: &Path) -> io::Result<Option<PathBuf>> {
+    Ok(checkpoints(dir)?.into_iter().max())
+}
+
+/// Reads a checkpoint written by a [`Checkpointer`].
+pub fn load(path: &Path) -> Result<(Position, TransactionEngine), String> {
+    let mut input = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut line = String::new();
+    input.read_line(&mut line).map_err(|e| e.to_string())?;
+    let position = serde_json::from_str(&line).map_err(|e| format!("corrupt checkpoint: {e}"))?;
+    let engine = TransactionEngine::load_snapshot(input).map_err(|e| e.to_string())?;
+    Ok((position, engine))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction_engine::Transaction;
+
+    fn engine() -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: "10.0".parse().unwrap() }).unwrap();
+        engine
+    }
+
+    #[test]
+    fn should_write_every_n_rows_and_keep_the_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut checkpointer = Checkpointer::new(dir.path().to_path_buf(), 2, PathBuf::from("/input.csv"), None);
+
+        for offset in [22, 30, 38, 46, 54] {
+            checkpointer.row(&engine(), offset).unwrap();
+        }
+
+        let latest = latest(dir.path()).unwrap().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let (position, engine) = load(&latest).unwrap();
+        assert_eq!(position, Position { path: PathBuf::from("/input.csv"), header_end: 22, offset: 54, rows: 4 });
+        assert_eq!(engine.get_client(1).unwrap().total(), "10.0".parse().unwrap());
+    }
+
+    #[test]
+    fn when_recovered_should_record_file_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovered = Position { path: PathBuf::from("/input.csv"), header_end: 22, offset: 54, rows: 4 };
+        let mut checkpointer = Checkpointer::new(dir.path().to_path_buf(), 2, PathBuf::from("/input.csv"), Some(&recovered));
+
+        // The recovered input reads the header followed by the rows from offset 54.
+        for offset in [22, 30, 38] {
+            checkpointer.row(&engine(), offset).unwrap();
+        }
+
+        let (position, _) = load(&latest(dir.path()).unwrap().unwrap()).unwrap();
+        assert_eq!(position, Position { path: PathBuf::from("/input.csv"), header_end: 22, offset: 70, rows: 6 });
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn when_no_checkpoint_latest_should_be_none() {
+        let dir = tempfile::tempdir().unwrap();
+        File::create(dir.path().join("other")).unwrap();
+
+        assert_eq!(latest(dir.path()).unwrap(), None);
+    }
+}
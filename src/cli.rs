@@ -0,0 +1,532 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use transaction_engine::{Amount, Currency};
+
+#[cfg(feature = "failpoints")]
+use crate::failure::InjectedFailure;
+use crate::input::InputFormat;
+use crate::logging::LogFormat;
+use crate::output::{Format, OutputMode};
+
+/// Applies deposits, withdrawals, disputes, resolves and chargebacks read from CSV files
+/// and prints the resulting client balances.
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments of `process`, so that `transaction_engine input.csv` keeps working.
+    #[command(flatten)]
+    pub process: ProcessArgs,
+
+    /// Log more to stderr: the summary of each input with -v, the decision on each transaction with -vv
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Format of the logs
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub log_format: LogFormat,
+}
+
+// Parsed once, the size of the process arguments does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Process the input files and print the client balances (default command)
+    Process(ProcessArgs),
+    /// Apply the CSV rows received over TCP, one per line without header, until interrupted.
+    /// A `REPORT` line is answered with the client balances followed by an empty line
+    Serve(ServeArgs),
+    /// Apply the transactions posted as JSON to an HTTP API until interrupted
+    ServeHttp(ServeHttpArgs),
+    /// Serve the gRPC API of proto/engine.proto until interrupted
+    #[cfg(feature = "grpc")]
+    ServeGrpc(ServeGrpcArgs),
+    /// Write a synthetic transaction file, the same for the same arguments, every row of which is applied
+    Generate(GenerateArgs),
+    /// Compare two snapshots written by --snapshot-out: the balances, the stored transactions and the totals.
+    /// Exits with code 1 when they differ, printing the number of differences and the first ones
+    Diff(DiffArgs),
+    /// Rebuild the accounts from the events of an audit log written by --audit and compare them with the
+    /// balances of the run and of each line. Exits with code 1 at the first divergence, printing its sequence number
+    VerifyAudit(VerifyAuditArgs),
+    /// Write the JSON Schemas of the JSON payloads
+    #[cfg(feature = "schema")]
+    #[command(hide = true)]
+    DumpSchemas(DumpSchemasArgs),
+}
+
+#[cfg(feature = "schema")]
+#[derive(Args, Debug)]
+pub struct DumpSchemasArgs {
+    /// Directory the `<payload>.json` files are written to, created if needed
+    #[arg(long, value_name = "DIR", default_value = "schemas")]
+    pub out: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Snapshot of the left engine
+    pub left: String,
+
+    /// Snapshot of the right engine
+    pub right: String,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyAuditArgs {
+    /// Audit log of the run
+    pub audit: String,
+
+    /// Balances written by the run as CSV, with or without the currency column
+    pub accounts: String,
+
+    /// Currency of the balances without one, that of the run
+    #[arg(long, value_name = "CODE", default_value_t = Currency::USD)]
+    pub base_currency: Currency,
+}
+
+/// Eviction of the idle accounts of a service, those without funds, held ones or lock being moved to an
+/// archive file until a transaction names them again.
+#[derive(Args, Debug)]
+pub struct EvictionArgs {
+    /// Evict the accounts without funds that no transaction touched among the last N ones
+    #[arg(long, value_name = "N", requires = "archive", conflicts_with = "evict_after_secs")]
+    pub evict_after_rows: Option<u64>,
+
+    /// Evict the accounts without funds that no transaction touched for this many seconds
+    #[arg(long, value_name = "SECS", requires = "archive")]
+    pub evict_after_secs: Option<u64>,
+
+    /// File the evicted accounts are written to as JSON lines, and read back from. Truncated on start
+    #[arg(long, value_name = "FILE")]
+    pub archive: Option<PathBuf>,
+}
+
+/// Retry queue of a service, parking the disputes, resolves and chargebacks that come before their tx.
+#[derive(Args, Debug)]
+pub struct RetryArgs {
+    /// Park up to N disputes, resolves and chargebacks of a tx not received yet, applying them once it comes.
+    /// Those coming when the queue is full are ignored like without queue
+    #[arg(long, value_name = "N")]
+    pub retry_capacity: Option<usize>,
+
+    /// Drop a parked transaction once this many transactions were applied after it
+    #[arg(long, value_name = "N", default_value_t = 10_000, requires = "retry_capacity", conflicts_with = "retry_expire_after_secs")]
+    pub retry_expire_after_rows: u64,
+
+    /// Drop a parked transaction once it waited this many seconds
+    #[arg(long, value_name = "SECS", requires = "retry_capacity")]
+    pub retry_expire_after_secs: Option<u64>,
+
+    /// Write the dropped parked transactions to this CSV file, with the rejection reason
+    #[arg(long, value_name = "PATH", requires = "retry_capacity")]
+    pub rejected: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to accept the connections on, port 0 picking a free one
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:9090")]
+    pub listen: SocketAddr,
+
+    #[command(flatten)]
+    pub eviction: EvictionArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeHttpArgs {
+    /// Address to accept the connections on, port 0 picking a free one
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    #[command(flatten)]
+    pub eviction: EvictionArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Args, Debug)]
+pub struct ServeGrpcArgs {
+    /// Address to accept the connections on, port 0 picking a free one
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:50051")]
+    pub listen: SocketAddr,
+
+    #[command(flatten)]
+    pub eviction: EvictionArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Number of rows after the header
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(..u64::from(u32::MAX)))]
+    pub rows: u32,
+
+    /// Number of clients, whose ids go from 1 to N
+    #[arg(long, value_name = "N", default_value_t = 1000, value_parser = clap::builder::RangedU64ValueParser::<u16>::new().range(1..))]
+    pub clients: u16,
+
+    /// Share of the rows disputing an earlier deposit
+    #[arg(long, value_name = "RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub dispute_rate: f64,
+
+    /// Share of the rows charging back an open dispute, the last client never being locked
+    #[arg(long, value_name = "RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub chargeback_rate: f64,
+
+    /// Seed of the pseudo random rows, the same seed writing the same file
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Write the file to this path instead of stdout
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ProcessArgs {
+    /// Input files, processed in order by the same engine. `-` reads from stdin
+    #[arg(required = true, value_name = "PATH")]
+    pub paths: Vec<String>,
+
+    /// Format of the inputs, by default NDJSON for `.ndjson` and `.jsonl` files, Arrow for `.arrow`, `.arrows` and
+    /// `.feather` files when built with the `arrow` feature, and CSV otherwise
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<InputFormat>,
+
+    /// Field delimiter of CSV inputs: `,`, `;`, `\t` or any single byte. Defaults to tab for `.tsv` files and comma otherwise
+    #[arg(long, value_parser = parse_delimiter, value_name = "DELIMITER")]
+    pub delimiter: Option<u8>,
+
+    /// Read the input files through a memory map instead of buffered reads, stdin is still read normally
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Read and parse the rows on a separate thread while the engine applies the previous ones
+    #[arg(long)]
+    pub pipeline: bool,
+
+    /// Apply the transactions on N threads, each owning a share of the clients. Rows are then no longer
+    /// applied one at a time, which the options recording each row or the engine state along the way need
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["pipeline", "audit", "rejected", "checkpoint_every", "recover", "resume", "max_stored_transactions", "daily_snapshots"],
+    )]
+    pub threads: Option<usize>,
+
+    /// Write the balances to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// Format of the balances
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    pub format: Format,
+
+    /// Field delimiter of the CSV balances
+    #[arg(long, value_parser = parse_delimiter, value_name = "DELIMITER", default_value = ",")]
+    pub output_delimiter: u8,
+
+    /// Clients listed by the balances and the --daily-snapshots. Incremental outputs are CSV headed by a
+    /// `#{"run_id":...,"first_seq":...,"last_seq":...,"count":...}` row, to be applied on top of the previous ones
+    #[arg(long, value_enum, default_value_t)]
+    pub output_mode: OutputMode,
+
+    /// Id of the run in the metadata rows of the incremental outputs, by default made of the start time and process id
+    #[arg(long, value_name = "ID")]
+    pub run_id: Option<String>,
+
+    /// Print to stderr the N clients with the largest held amount
+    #[arg(long, value_name = "N")]
+    pub top_held: Option<usize>,
+
+    /// Map a partner transaction type onto a canonical one (repeatable)
+    #[arg(long = "type-alias", value_name = "ALIAS=TYPE")]
+    pub type_aliases: Vec<String>,
+
+    /// Reject transactions from these client ids (comma separated ids, `zero` or `max`)
+    #[arg(long = "sentinel-client-ids", value_name = "IDS")]
+    pub sentinel_client_ids: Vec<String>,
+
+    /// Reject transactions with these tx ids (comma separated ids, `zero` or `max`)
+    #[arg(long = "sentinel-tx-ids", value_name = "IDS")]
+    pub sentinel_tx_ids: Vec<String>,
+
+    /// Reject amounts with more than four decimal places instead of rounding them
+    #[arg(long)]
+    pub strict_precision: bool,
+
+    /// Warn about rows whose `ts` is earlier than the previous row's, or reject them with --strict
+    #[arg(long)]
+    pub detect_out_of_order: bool,
+
+    /// Milliseconds a row may be earlier than the previous one before it is out of order
+    #[arg(long, value_name = "MS", default_value_t = 0, requires = "detect_out_of_order")]
+    pub out_of_order_tolerance: u64,
+
+    /// Apply deposits on accounts locked by a chargeback instead of rejecting them
+    #[arg(long)]
+    pub allow_locked_deposits: bool,
+
+    /// Reject adjustments on accounts locked by a chargeback instead of applying them
+    #[arg(long)]
+    pub reject_locked_adjustments: bool,
+
+    /// Reject disputes, resolves and chargebacks changing an account locked by a chargeback instead of applying them
+    #[arg(long)]
+    pub reject_locked_disputes: bool,
+
+    /// Reject disputes holding more than the available funds instead of letting them turn negative
+    #[arg(long)]
+    pub reject_disputes_exceeding_available: bool,
+
+    /// Reject disputes of a transaction already disputed this many times, its resolves notwithstanding
+    #[arg(long, value_name = "N")]
+    pub max_disputes_per_tx: Option<u32>,
+
+    /// Drop the stored transactions of accounts locked by a chargeback, which can then no longer be disputed, to save memory
+    #[arg(long)]
+    pub compact: bool,
+
+    /// Stop storing transactions for later disputes once this many are stored, bounding the memory used
+    #[arg(long, value_name = "N")]
+    pub max_stored_transactions: Option<usize>,
+
+    /// Currency of the rows without one, whose balances the totals and reports on stderr add up
+    #[arg(long, value_name = "CODE", default_value_t = Currency::USD)]
+    pub base_currency: Currency,
+
+    /// Let withdrawals take each account in the base currency this far below zero available
+    #[arg(long, value_name = "AMOUNT", default_value_t = Amount::ZERO, value_parser = parse_credit_limit)]
+    pub credit_limit: Amount,
+
+    /// Keep about N MiB of stored transactions in memory, the oldest ones moving to a temporary file past it
+    /// and read back when disputed. Snapshots and checkpoints would miss the moved ones
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["threads", "resume", "recover", "snapshot_out", "checkpoint_every"],
+    )]
+    pub max_memory_mb: Option<usize>,
+
+    /// Stop at the first malformed row (parse error or missing amount) and exit with code 3 without printing the balances
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Print nothing to stderr but the errors failing the run: neither the rejected rows nor the summary at the end
+    #[arg(short, long, conflicts_with_all = ["top_held", "stats", "progress"])]
+    pub quiet: bool,
+
+    /// Load the engine state from this snapshot before processing, to continue a previous run
+    #[arg(long, value_name = "PATH")]
+    pub resume: Option<String>,
+
+    /// Write the engine state to this snapshot once processed, to be continued with --resume
+    #[arg(long, value_name = "PATH")]
+    pub snapshot_out: Option<String>,
+
+    /// Write the balances at the end of each day to DIR/accounts-YYYY-MM-DD.csv, the rows needing a timestamp
+    /// and to be sorted by time
+    #[arg(long, value_name = "DIR")]
+    pub daily_snapshots: Option<PathBuf>,
+
+    /// Write a checkpoint of the engine every N rows, to recover from with --recover
+    #[arg(long, value_name = "N", requires = "checkpoint_dir", value_parser = clap::value_parser!(u64).range(1..))]
+    pub checkpoint_every: Option<u64>,
+
+    /// Directory of the checkpoints, only the latest one is kept
+    #[arg(long, value_name = "DIR", requires = "checkpoint_every")]
+    pub checkpoint_dir: Option<PathBuf>,
+
+    /// Continue from the latest checkpoint in this directory, reading the input from where the checkpoint was taken
+    #[arg(long, value_name = "DIR", conflicts_with = "resume")]
+    pub recover: Option<PathBuf>,
+
+    /// Keep the engine state in this SQLite database, created on the first run: the transactions are written as
+    /// they are applied and read back when disputed, the balances are saved once processed
+    #[cfg(feature = "sqlite")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["resume", "recover", "threads", "max_memory_mb", "snapshot_out", "checkpoint_every"],
+    )]
+    pub state_db: Option<PathBuf>,
+
+    /// Write a JSON line per input row to this file, with the action taken and the resulting client balances
+    #[arg(long, value_name = "PATH")]
+    pub audit: Option<String>,
+
+    /// Write the rows that were not applied to this CSV file, with the rejection reason
+    #[arg(long, value_name = "PATH")]
+    pub rejected: Option<String>,
+
+    /// Write the transactions still disputed at the end of the run to this CSV file, as tx, client and amount
+    #[arg(long, value_name = "PATH")]
+    pub disputes: Option<String>,
+
+    /// Write the clients flagged for fraud review to this CSV file, as client, disputes, chargebacks and charged back amount
+    #[arg(long, value_name = "PATH")]
+    pub flag_report: Option<String>,
+
+    /// Chargebacks on the funds of a client after which it is flagged for fraud review
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "flag_report")]
+    pub flag_min_chargebacks: u32,
+
+    /// Check that every client has available + held = total and no negative held funds once processed, exit with code 4 otherwise
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Print the engine counters and the throughput to stderr as JSON after the balances
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Show the bytes and rows processed on stderr while reading, when stderr is a terminal
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Abort the run at a point, as if killed: after-rows=N before applying row N + 1, during-output halfway
+    /// through the balances, before-flush once the balances are written but not flushed nor committed
+    #[cfg(feature = "failpoints")]
+    #[arg(long, value_name = "POINT", hide = true)]
+    pub inject_failure: Option<InjectedFailure>,
+}
+
+/// Parses a delimiter given as a single byte, `\t` standing for a tab.
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [byte] => Ok(*byte),
+        _ => Err(format!("the delimiter should be a single byte, got {value:?}")),
+    }
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("the rate should be a number between 0 and 1, got {value:?}")),
+    }
+}
+
+fn parse_credit_limit(value: &str) -> Result<Amount, String> {
+    match value.parse::<Amount>() {
+        Ok(limit) if !limit.is_negative() => Ok(limit),
+        _ => Err(format!("the credit limit should be a non-negative amount, got {value:?}")),
+    }
+}
+
+impl Cli {
+    /// Command to run, `process` when none is named.
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Process(self.process))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ProcessArgs, clap::Error> {
+        Cli::try_parse_from(std::iter::once("transaction_engine").chain(args.iter().copied()))
+            .map(|cli| match cli.command() {
+                Command::Process(args) => args,
+                command => panic!("expected the process command, got {command:?}"),
+            })
+    }
+
+    #[test]
+    fn when_no_command_should_default_to_process() {
+        let args = parse(&["input.csv", "--top-held", "3"]).unwrap();
+
+        assert_eq!(args.paths, vec!["input.csv"]);
+        assert_eq!(args.top_held, Some(3));
+        assert_eq!(args.format, Format::Csv);
+    }
+
+    #[test]
+    fn credit_limit_should_be_a_non_negative_amount() {
+        assert_eq!(parse(&["input.csv"]).unwrap().credit_limit, Amount::ZERO);
+        assert_eq!(parse(&["input.csv", "--credit-limit", "50.5"]).unwrap().credit_limit, "50.5".parse().unwrap());
+        assert!(parse(&["input.csv", "--credit-limit=-1.0"]).is_err());
+        assert!(parse(&["input.csv", "--credit-limit", "a lot"]).is_err());
+    }
+
+    #[test]
+    fn quiet_should_conflict_with_the_reports_on_stderr() {
+        assert!(parse(&["input.csv", "-q"]).unwrap().quiet);
+        for report in [&["--stats"][..], &["--top-held", "3"], &["--progress"]] {
+            assert!(parse(&[&["input.csv", "--quiet"][..], report].concat()).is_err(), "{report:?}");
+        }
+    }
+
+    #[test]
+    fn when_process_command_should_parse_its_arguments() {
+        let args = parse(&["process", "day1.csv", "day2.csv", "-o", "out.csv", "--format", "ndjson"]).unwrap();
+
+        assert_eq!(args.paths, vec!["day1.csv", "day2.csv"]);
+        assert_eq!(args.output.as_deref(), Some("out.csv"));
+        assert_eq!(args.format, Format::Ndjson);
+    }
+
+    #[test]
+    fn when_serve_command_should_parse_the_address() {
+        let cli = Cli::try_parse_from(["transaction_engine", "serve", "--listen", "0.0.0.0:9090"]).unwrap();
+
+        match cli.command() {
+            Command::Serve(args) => assert_eq!(args.listen, "0.0.0.0:9090".parse().unwrap()),
+            command => panic!("expected the serve command, got {command:?}"),
+        }
+    }
+
+    #[test]
+    fn when_serve_http_command_should_default_the_address() {
+        let cli = Cli::try_parse_from(["transaction_engine", "serve-http"]).unwrap();
+
+        match cli.command() {
+            Command::ServeHttp(args) => assert_eq!(args.listen, "127.0.0.1:8080".parse().unwrap()),
+            command => panic!("expected the serve-http command, got {command:?}"),
+        }
+    }
+
+    #[test]
+    fn should_accept_stdin_and_repeated_options() {
+        let args = parse(&["-", "--type-alias", "a=deposit", "--type-alias", "b=withdrawal"]).unwrap();
+
+        assert_eq!(args.paths, vec!["-"]);
+        assert_eq!(args.input_format, None);
+        assert_eq!(args.type_aliases, vec!["a=deposit", "b=withdrawal"]);
+    }
+
+    #[test]
+    fn should_parse_delimiters() {
+        assert_eq!(parse_delimiter(","), Ok(b','));
+        assert_eq!(parse_delimiter(";"), Ok(b';'));
+        assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+        assert_eq!(parse_delimiter("\t"), Ok(b'\t'));
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("é").is_err());
+
+        let args = parse(&["input.csv", "--delimiter", ";"]).unwrap();
+        assert_eq!(args.delimiter, Some(b';'));
+        assert_eq!(args.output_delimiter, b',');
+    }
+
+    #[test]
+    fn when_no_path_should_fail() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["process"]).is_err());
+    }
+}
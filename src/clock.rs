@@ -0,0 +1,98 @@
+//! The time read by the decisions an engine takes over time, see
+//! [`TransactionEngine::set_clock`](crate::TransactionEngine::set_clock).
+
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::timestamp::Timestamp;
+
+/// Tells the current time: when an account became idle for [`crate::EvictAfter::Duration`], or a transaction was
+/// parked for [`crate::ExpireAfter::Duration`].
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+/// The time of the system, the clock of an engine unless set otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        let millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+        Timestamp::from_millis(i64::try_from(millis).unwrap_or(i64::MAX))
+    }
+}
+
+/// A clock that only moves when told to, e.g. to test the deadlines of an engine. Its clones tell the same time:
+/// one is given to the engine, another kept to move it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicI64>);
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock(Arc::new(AtomicI64::new(now.millis())))
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.0.store(now.millis(), Ordering::Relaxed);
+    }
+
+    /// Moves the clock `by` forward, to the millisecond.
+    pub fn advance(&self, by: Duration) {
+        let millis = i64::try_from(by.as_millis()).unwrap_or(i64::MAX);
+        self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| Some(now.saturating_add(millis)))
+            .expect("the update always gives a time");
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+// Shared by the clones of an engine.
+#[derive(Clone)]
+pub(crate) struct SharedClock(pub(crate) Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn now(&self) -> Timestamp {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(Arc::new(SystemClock))
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Clock({})", self.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_manual_clock_should_move_only_when_told_for_all_its_clones() {
+        let clock = ManualClock::new(Timestamp::from_millis(1_000));
+        let engine_side = clock.clone();
+
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(engine_side.now(), Timestamp::from_millis(2_500));
+        clock.set(Timestamp::from_millis(0));
+        assert_eq!(engine_side.now(), Timestamp::from_millis(0));
+    }
+
+    #[test]
+    fn the_system_clock_should_tell_the_time_since_the_epoch() {
+        // 2020-01-01T00:00:00Z, well before any run of the tests.
+        assert!(SystemClock.now() > Timestamp::from_millis(1_577_836_800_000));
+    }
+}
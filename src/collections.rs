@@ -0,0 +1,134 @@
+//! The maps of the engine state, keyed by client or tx ids, or by accounts.
+//!
+//! With the `std-collections` feature, on by default, they are the hash maps of std hashed with FxHash. These small
+//! keys hash much faster with FxHash than with SipHash; its weaker resistance to crafted collisions is of little
+//! concern with keys bounded to 16 and 64 bits. Without the feature, they are the B-trees of `alloc`, which need no
+//! hasher, wrapped to offer the few methods of the hash maps the engine calls.
+
+#[cfg(feature = "std-collections")]
+pub(crate) type FastHashMap<K, V> = rustc_hash::FxHashMap<K, V>;
+#[cfg(feature = "std-collections")]
+pub(crate) type FastHashSet<T> = rustc_hash::FxHashSet<T>;
+
+#[cfg(not(feature = "std-collections"))]
+pub(crate) use ordered::{FastHashMap, FastHashSet};
+
+#[cfg(not(feature = "std-collections"))]
+mod ordered {
+    use std::collections::{btree_map, btree_set, BTreeMap, BTreeSet};
+    use std::mem;
+    use std::ops::{Deref, DerefMut};
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(crate) struct FastHashMap<K, V>(BTreeMap<K, V>);
+
+    impl<K, V> Default for FastHashMap<K, V> {
+        fn default() -> Self {
+            FastHashMap(BTreeMap::new())
+        }
+    }
+
+    impl<K, V> Deref for FastHashMap<K, V> {
+        type Target = BTreeMap<K, V>;
+
+        fn deref(&self) -> &BTreeMap<K, V> {
+            &self.0
+        }
+    }
+
+    impl<K, V> DerefMut for FastHashMap<K, V> {
+        fn deref_mut(&mut self) -> &mut BTreeMap<K, V> {
+            &mut self.0
+        }
+    }
+
+    // A B-tree allocates node by node, there is no room to reserve ahead.
+    impl<K, V> FastHashMap<K, V> {
+        pub(crate) fn reserve(&mut self, _additional: usize) {}
+
+        pub(crate) fn capacity(&self) -> usize {
+            self.0.len()
+        }
+
+        pub(crate) fn drain(&mut self) -> btree_map::IntoIter<K, V> {
+            mem::take(&mut self.0).into_iter()
+        }
+    }
+
+    impl<K: Ord, V> FromIterator<(K, V)> for FastHashMap<K, V> {
+        fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+            FastHashMap(iter.into_iter().collect())
+        }
+    }
+
+    impl<K, V> IntoIterator for FastHashMap<K, V> {
+        type Item = (K, V);
+        type IntoIter = btree_map::IntoIter<K, V>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.0.into_iter()
+        }
+    }
+
+    impl<'a, K, V> IntoIterator for &'a FastHashMap<K, V> {
+        type Item = (&'a K, &'a V);
+        type IntoIter = btree_map::Iter<'a, K, V>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.0.iter()
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(crate) struct FastHashSet<T>(BTreeSet<T>);
+
+    impl<T> Default for FastHashSet<T> {
+        fn default() -> Self {
+            FastHashSet(BTreeSet::new())
+        }
+    }
+
+    impl<T> Deref for FastHashSet<T> {
+        type Target = BTreeSet<T>;
+
+        fn deref(&self) -> &BTreeSet<T> {
+            &self.0
+        }
+    }
+
+    impl<T> DerefMut for FastHashSet<T> {
+        fn deref_mut(&mut self) -> &mut BTreeSet<T> {
+            &mut self.0
+        }
+    }
+
+    impl<T> FastHashSet<T> {
+        pub(crate) fn drain(&mut self) -> btree_set::IntoIter<T> {
+            mem::take(&mut self.0).into_iter()
+        }
+    }
+
+    impl<T: Ord> FromIterator<T> for FastHashSet<T> {
+        fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+            FastHashSet(iter.into_iter().collect())
+        }
+    }
+
+    impl<T> IntoIterator for FastHashSet<T> {
+        type Item = T;
+        type IntoIter = btree_set::IntoIter<T>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.0.into_iter()
+        }
+    }
+
+    impl<'a, T> IntoIterator for &'a FastHashSet<T> {
+        type Item = &'a T;
+        type IntoIter = btree_set::Iter<'a, T>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.0.iter()
+        }
+    }
+}
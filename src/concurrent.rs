@@ -0,0 +1,252 @@
+//! Sharing an engine between threads, see [`ConcurrentTransactionEngine`].
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::batch::BatchSummary;
+use crate::currency::Currency;
+use crate::metrics::Metrics;
+use crate::retry::ParkedTransaction;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Client, Transaction, TransactionEngine, TransactionError};
+
+/// A [`TransactionEngine`] that many threads can feed and read at once, e.g. the handlers of a service.
+///
+/// The engine is behind a mutex held for one transaction at a time, or for a whole batch with
+/// [`ConcurrentTransactionEngine::submit_all`]. [`ConcurrentTransactionEngine::submit`] returns once the
+/// transaction is applied, so the transactions submitted by one thread are applied in the order it submitted
+/// them. Those of different threads are interleaved in the order the threads take the lock.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+/// use transaction_engine::{Amount, ConcurrentTransactionEngine, Transaction};
+///
+/// let engine = Arc::new(ConcurrentTransactionEngine::default());
+/// let amount: Amount = "1.0".parse().unwrap();
+/// let handlers: Vec<_> = (0..4u16).map(|client_id| {
+///     let engine = Arc::clone(&engine);
+///     thread::spawn(move || engine.submit(Transaction::Deposit { client_id, tx_id: client_id.into(), amount }))
+/// }).collect();
+/// for handler in handlers {
+///     handler.join().unwrap().unwrap();
+/// }
+///
+/// assert_eq!(engine.get_client_list().len(), 4);
+/// ```
+#[derive(Debug, Default)]
+pub struct ConcurrentTransactionEngine {
+    engine: Mutex<TransactionEngine>,
+}
+
+impl From<TransactionEngine> for ConcurrentTransactionEngine {
+    fn from(engine: TransactionEngine) -> Self {
+        ConcurrentTransactionEngine::new(engine)
+    }
+}
+
+impl ConcurrentTransactionEngine {
+    pub fn new(engine: TransactionEngine) -> ConcurrentTransactionEngine {
+        ConcurrentTransactionEngine { engine: Mutex::new(engine) }
+    }
+
+    // A thread only panics with the lock held when an observer or an invariant check panics, the
+    // transaction it applied may then be half done.
+    fn lock(&self) -> MutexGuard<'_, TransactionEngine> {
+        self.engine.lock().expect("a thread panicked while applying a transaction")
+    }
+
+    /// Applies a transaction, see [`TransactionEngine::compute_transaction`].
+    pub fn submit(&self, transaction: Transaction) -> Result<(), TransactionError> {
+        self.lock().compute_transaction(transaction)
+    }
+
+    /// Applies a transaction read at `ts`, see [`TransactionEngine::compute_transaction_at`].
+    pub fn submit_at(&self, transaction: Transaction, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        self.lock().compute_transaction_at(transaction, ts)
+    }
+
+    /// Applies a transaction read at `ts` to the accounts in `currency`, see
+    /// [`TransactionEngine::compute_transaction_in`].
+    pub fn submit_in(&self, transaction: Transaction, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        self.lock().compute_transaction_in(transaction, currency, ts)
+    }
+
+    /// Applies every transaction in turn under a single lock, so that no other thread's transaction is
+    /// interleaved with them. See [`TransactionEngine::compute_transactions`].
+    pub fn submit_all<I: IntoIterator<Item = Transaction>>(&self, transactions: I) -> BatchSummary {
+        self.lock().compute_transactions(transactions)
+    }
+
+    /// Returns a copy of the client with this id, without creating its account when it has none.
+    pub fn get_client(&self, id: u16) -> Option<Client> {
+        self.lock().get_client(id).copied()
+    }
+
+    /// Returns a copy of every client, in no particular order.
+    pub fn get_client_list(&self) -> Vec<Client> {
+        self.lock().get_client_list()
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.lock().metrics()
+    }
+
+    /// Evicts the idle accounts, see [`TransactionEngine::evict_idle`].
+    pub fn evict_idle(&self) -> usize {
+        self.lock().evict_idle()
+    }
+
+    /// Removes the expired parked transactions, see [`TransactionEngine::expire_parked`].
+    pub fn expire_parked(&self) -> Vec<ParkedTransaction> {
+        self.lock().expire_parked()
+    }
+
+    /// Runs `read` on the engine, for the reads without a method of their own. The other threads wait
+    /// until it returns.
+    pub fn read<T>(&self, read: impl FnOnce(&TransactionEngine) -> T) -> T {
+        read(&self.lock())
+    }
+
+    /// Returns the engine once no other thread uses it.
+    pub fn into_inner(self) -> TransactionEngine {
+        self.engine.into_inner().expect("a thread panicked while applying a transaction")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction_engine::TxId;
+
+    const THREADS: u32 = 8;
+    const ROUNDS: u32 = 1_000;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    // The transactions a thread submits, in order. Each thread has its own client and shares client 1 with the others.
+    fn thread_transactions(thread: u32) -> Vec<Transaction> {
+        let own = 100 + thread as u16;
+        let mut tx_id = TxId::from(thread * 10 * ROUNDS);
+        let mut next_tx_id = || {
+            tx_id += 1;
+            tx_id
+        };
+        let mut transactions = vec![];
+        for _ in 0..ROUNDS {
+            transactions.push(Transaction::Deposit { client_id: own, tx_id: next_tx_id(), amount: amount("3.0") });
+            transactions.push(Transaction::Withdrawal { client_id: own, tx_id: next_tx_id(), amount: amount("1.0") });
+            // Submitted after its own deposit, the withdrawal always finds the funds.
+            transactions.push(Transaction::Deposit { client_id: 1, tx_id: next_tx_id(), amount: amount("2.0") });
+            transactions.push(Transaction::Withdrawal { client_id: 1, tx_id: next_tx_id(), amount: amount("1.5") });
+        }
+        // The dispute of its first deposit, applied after every transaction the thread submitted.
+        transactions.push(Transaction::Dispute { client_id: own, tx_id: TxId::from(thread * 10 * ROUNDS) + 1 });
+        transactions
+    }
+
+    #[test]
+    fn concurrent_submissions_should_all_be_applied() {
+        let engine = Arc::new(ConcurrentTransactionEngine::default());
+
+        let producers: Vec<_> = (0..THREADS).map(|thread| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for transaction in thread_transactions(thread) {
+                    engine.submit(transaction).unwrap();
+                }
+            })
+        }).collect();
+        let readers: Vec<_> = (0..2).map(|_| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for _ in 0..ROUNDS {
+                    if let Some(client) = engine.get_client(1) {
+                        assert!(client.available() >= Amount::ZERO);
+                        assert_eq!(client.available() + client.held(), client.total());
+                    }
+                }
+            })
+        }).collect();
+        producers.into_iter().chain(readers).for_each(|handle| handle.join().unwrap());
+
+        let shared = engine.get_client(1).unwrap();
+        assert_eq!(shared.total(), Amount::from_units(i64::from(THREADS * ROUNDS) * 5_000));
+        for thread in 0..THREADS {
+            let own = engine.get_client(100 + thread as u16).unwrap();
+            assert_eq!(own.total(), Amount::from_units(i64::from(ROUNDS) * 20_000));
+            assert_eq!(own.held(), amount("3.0"));
+        }
+        let metrics = engine.metrics();
+        assert_eq!(metrics.applied(), u64::from(THREADS * (4 * ROUNDS + 1)));
+        assert_eq!(metrics.rejected(), 0);
+        assert_eq!(metrics.clients, u64::from(THREADS) + 1);
+
+        // However the threads interleaved, the state is the one of the threads applied one after the other.
+        let mut sequential = TransactionEngine::new();
+        for thread in 0..THREADS {
+            sequential.compute_transactions(thread_transactions(thread));
+        }
+        let diff = engine.read(|engine| engine.diff_states(&sequential));
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    #[test]
+    fn submit_all_should_not_interleave_other_threads() {
+        let engine = Arc::new(ConcurrentTransactionEngine::default());
+
+        // Deposits immediately withdrawn, which a withdrawal of another thread in between would make fail.
+        let batch = |thread: u32| (0..ROUNDS).flat_map(move |round| {
+            let tx_id = TxId::from((thread * ROUNDS + round) * 2);
+            [
+                Transaction::Deposit { client_id: 1, tx_id, amount: amount("1.0") },
+                Transaction::Withdrawal { client_id: 1, tx_id: tx_id + 1, amount: amount("1.0") },
+            ]
+        });
+        let batches: Vec<_> = (0..THREADS).map(|thread| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || engine.submit_all(batch(thread)))
+        }).collect();
+        let thief = {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                (0..ROUNDS).filter(|round| {
+                    let withdrawal = Transaction::Withdrawal { client_id: 1, tx_id: TxId::from(10 * THREADS * ROUNDS + round), amount: amount("1.0") };
+                    engine.submit(withdrawal).is_ok()
+                }).count()
+            })
+        };
+        for batch in batches {
+            assert_eq!(batch.join().unwrap().rejected_count(), 0);
+        }
+        assert_eq!(thief.join().unwrap(), 0);
+
+        let engine = Arc::into_inner(engine).unwrap().into_inner();
+        assert_eq!(engine.get_client(1).unwrap().total(), Amount::ZERO);
+        assert_eq!(engine.metrics().withdrawal.applied, u64::from(THREADS * ROUNDS));
+
+        // The thief's withdrawals all being rejected, the state is the one of the batches alone.
+        let mut sequential = TransactionEngine::new();
+        for thread in 0..THREADS {
+            sequential.compute_transactions(batch(thread));
+        }
+        let diff = engine.diff_states(&sequential);
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    #[test]
+    fn read_should_see_the_engine() {
+        let engine = ConcurrentTransactionEngine::new(TransactionEngine::new());
+        engine.submit(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+        engine.submit(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+
+        assert_eq!(engine.read(TransactionEngine::held_total), amount("10.0"));
+        assert_eq!(engine.read(|engine| engine.disputed_transactions().count()), 1);
+        assert_eq!(engine.get_client(2), None);
+    }
+}
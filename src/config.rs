@@ -0,0 +1,113 @@
+//! Policies of the engine that partners disagree on.
+
+use crate::{Amount, Currency};
+
+/// Settings of a [`crate::TransactionEngine`], given to [`crate::TransactionEngine::with_config`].
+///
+/// The default is the behavior of [`crate::TransactionEngine::new`]. Settings are changed with the
+/// builder methods:
+///
+/// ```
+/// use transaction_engine::{EngineConfig, TransactionEngine};
+///
+/// let config = EngineConfig::default()
+///     .allow_dispute_on_locked(false)
+///     .max_stored_transactions(Some(1_000_000));
+/// let engine = TransactionEngine::with_config(config);
+/// assert!(!engine.config().allow_dispute_on_locked);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EngineConfig {
+    /// Deposits on an account locked by a chargeback are rejected. Defaults to true.
+    pub reject_deposits_when_locked: bool,
+    /// A dispute may hold more than the available funds of the account, leaving them negative.
+    /// Otherwise such a dispute is rejected. Defaults to true.
+    pub allow_negative_available_on_dispute: bool,
+    /// Disputes, resolves and chargebacks apply to locked accounts, so that the past transactions of a
+    /// frozen account can still be investigated. Otherwise they are rejected as soon as an account whose
+    /// balances they would change is locked. Defaults to true.
+    pub allow_dispute_on_locked: bool,
+    /// Adjustments apply to locked accounts. Defaults to true.
+    pub allow_adjustment_on_locked: bool,
+    /// Number of disputes a transaction can go through, the count surviving its resolves, after which
+    /// further disputes are rejected. Defaults to no limit.
+    pub max_disputes_per_tx: Option<u32>,
+    /// Once a chargeback locks an account, its stored transactions that can no longer be meaningfully
+    /// disputed are dropped, as [`crate::TransactionEngine::compact`] does. Defaults to false.
+    pub compact_locked: bool,
+    /// Number of transactions stored for later disputes. Once reached, transactions are still applied
+    /// but no longer stored, so they cannot be disputed. Their tx ids are kept, a transaction reusing one being
+    /// rejected as a duplicate. Defaults to no limit.
+    pub max_stored_transactions: Option<usize>,
+    /// Currency of the transactions giving none, and of the accounts read by
+    /// [`crate::TransactionEngine::get_client`]. Defaults to USD.
+    pub base_currency: Currency,
+    /// Amount a withdrawal can take beyond the available funds of an account in the base currency, leaving
+    /// them negative, unless [`crate::TransactionEngine::set_credit_limit`] gives the client another one.
+    /// Disputes are not affected. Defaults to zero.
+    pub credit_limit: Amount,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            reject_deposits_when_locked: true,
+            allow_negative_available_on_dispute: true,
+            allow_dispute_on_locked: true,
+            allow_adjustment_on_locked: true,
+            max_disputes_per_tx: None,
+            compact_locked: false,
+            max_stored_transactions: None,
+            base_currency: Currency::USD,
+            credit_limit: Amount::ZERO,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn reject_deposits_when_locked(mut self, reject: bool) -> Self {
+        self.reject_deposits_when_locked = reject;
+        self
+    }
+
+    pub fn allow_negative_available_on_dispute(mut self, allow: bool) -> Self {
+        self.allow_negative_available_on_dispute = allow;
+        self
+    }
+
+    pub fn allow_dispute_on_locked(mut self, allow: bool) -> Self {
+        self.allow_dispute_on_locked = allow;
+        self
+    }
+
+    pub fn allow_adjustment_on_locked(mut self, allow: bool) -> Self {
+        self.allow_adjustment_on_locked = allow;
+        self
+    }
+
+    pub fn max_disputes_per_tx(mut self, max: Option<u32>) -> Self {
+        self.max_disputes_per_tx = max;
+        self
+    }
+
+    pub fn compact_locked(mut self, compact: bool) -> Self {
+        self.compact_locked = compact;
+        self
+    }
+
+    pub fn max_stored_transactions(mut self, max: Option<usize>) -> Self {
+        self.max_stored_transactions = max;
+        self
+    }
+
+    pub fn base_currency(mut self, currency: Currency) -> Self {
+        self.base_currency = currency;
+        self
+    }
+
+    pub fn credit_limit(mut self, limit: Amount) -> Self {
+        self.credit_limit = limit;
+        self
+    }
+}
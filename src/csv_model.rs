@@ -0,0 +1,915 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use csv::{ByteRecord, StringRecord};
+use serde::de::{self, DeserializeSeed, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{self, TxId};
+use crate::type_alias::TRANSACTION_TYPES;
+
+/// Columns of the input CSV. They are matched by name and can be in any order.
+pub const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// First column of [`COLUMNS`] missing from `headers`, if any.
+pub fn missing_column(headers: &StringRecord) -> Option<&'static str> {
+    COLUMNS.into_iter().find(|column| !headers.iter().any(|header| header == *column))
+}
+
+/// Reason a row could not be turned into a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowError {
+    /// The row could not be deserialized.
+    Parse(String),
+    /// A deposit, withdrawal, transfer, adjustment or fee without amount.
+    MissingAmount { transaction_type: TransactionType, client_id: u16, tx_id: TxId },
+    /// A transfer without `to_client`.
+    MissingToClient { client_id: u16, tx_id: TxId },
+    /// A `client` or `to_client` id past `u16::MAX`, which no account can have.
+    ClientOutOfRange { client_id: u64 },
+    /// A type that is neither a transaction type nor one of its aliases, e.g. one the engine does not support yet.
+    UnsupportedType(String),
+}
+
+impl RowError {
+    /// Snake case name of the error, like [`transaction_engine::TransactionError::code`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            RowError::Parse(_) => "parse_error",
+            RowError::MissingAmount { .. } => "missing_amount",
+            RowError::MissingToClient { .. } => "missing_to_client",
+            RowError::ClientOutOfRange { .. } => "client_out_of_range",
+            RowError::UnsupportedType(_) => "unsupported_type",
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::Parse(e) => write!(f, "parse error: {e}"),
+            RowError::MissingAmount { transaction_type, client_id, tx_id } => {
+                write!(f, "missing amount on {} tx {tx_id} of client {client_id}", transaction_type.name())
+            },
+            RowError::MissingToClient { client_id, tx_id } => write!(f, "missing to_client on transfer tx {tx_id} of client {client_id}"),
+            RowError::ClientOutOfRange { client_id } => {
+                write!(f, "client id {client_id} is out of range, the maximum is {}", u16::MAX)
+            },
+            RowError::UnsupportedType(name) => write!(f, "unsupported transaction type {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Client id of a row, failing with [`RowError::ClientOutOfRange`] rather than being truncated.
+pub fn client_id(id: u64) -> Result<u16, RowError> {
+    u16::try_from(id).map_err(|_| RowError::ClientOutOfRange { client_id: id })
+}
+
+/// Transaction of a row whose columns are named by `headers`, with the row's timestamp and currency if any.
+pub fn parse_row(
+    row: &StringRecord,
+    headers: &StringRecord,
+) -> Result<(transaction_engine::Transaction, Option<Timestamp>, Option<Currency>), RowError> {
+    // Readers are flexible so that a dispute may leave out its amount, a row longer than the header is still wrong.
+    if row.len() > headers.len() {
+        let line = row.position().map(|position| format!(" (line: {})", position.line())).unwrap_or_default();
+        return Err(RowError::Parse(format!("found a row with {} fields, but the header has {}{line}", row.len(), headers.len())))
+    }
+    // The type is read as a string first, so that a type the engine does not know is told apart from a malformed
+    // row. An empty one is left to the deserialization.
+    let type_column = headers.iter().position(|header| header == "type");
+    if let Some(name) = type_column.and_then(|column| row.get(column)).filter(|name| !name.is_empty()) {
+        if TransactionType::from_name(name).is_none() {
+            return Err(RowError::UnsupportedType(name.to_string()))
+        }
+    }
+    // Likewise, a client id too large for an account is told apart from one that is not a number.
+    for column in ["client", "to_client"] {
+        let field = headers.iter().position(|header| header == column).and_then(|column| row.get(column));
+        if let Some(id) = field.and_then(|id| id.trim().parse::<u64>().ok()) {
+            client_id(id)?;
+        }
+    }
+    let record = row.deserialize::<TransactionRecord>(Some(headers)).map_err(|e| RowError::Parse(e.to_string()))?;
+    Ok((record.try_into()?, record.ts, record.currency))
+}
+
+/// Whether `headers` are exactly the [`COLUMNS`] in this order, for which [`parse_canonical_row`] applies.
+pub fn is_canonical(headers: &StringRecord) -> bool {
+    headers.iter().eq(COLUMNS)
+}
+
+/// Fast path of [`parse_row`] for a row under the canonical header: the fields are matched and parsed
+/// from their bytes, without deserializing a [`TransactionRecord`]. Returns `None` for anything but a
+/// well-formed row, which [`parse_row`] then handles so that errors are reported the same way.
+pub fn parse_canonical_row(row: &ByteRecord) -> Option<transaction_engine::Transaction> {
+    use transaction_engine::Transaction;
+
+    if row.len() != COLUMNS.len() {
+        return None
+    }
+    let client_id = u16::try_from(parse_digits(&row[1])?).ok()?;
+    let tx_id = parse_digits(&row[2])?;
+    // Like the serde path, an amount is parsed whatever the type, an invalid one failing the row.
+    let amount = match &row[3] {
+        b"" => None,
+        amount => Some(std::str::from_utf8(amount).ok()?.parse::<Amount>().ok()?),
+    };
+
+    let transaction = match &row[0] {
+        b"deposit" => Transaction::Deposit { client_id, tx_id, amount: amount? },
+        b"withdrawal" => Transaction::Withdrawal { client_id, tx_id, amount: amount? },
+        b"dispute" => Transaction::Dispute { client_id, tx_id },
+        b"resolve" => Transaction::Resolve { client_id, tx_id },
+        b"chargeback" => Transaction::Chargeback { client_id, tx_id },
+        b"unlock" => Transaction::Unlock { client_id, tx_id },
+        b"adjustment" => Transaction::Adjustment { client_id, tx_id, amount: amount? },
+        b"fee" => Transaction::Fee { client_id, tx_id, amount: amount? },
+        // Transfers need the to_client column, which the canonical header lacks.
+        _ => return None,
+    };
+    Some(transaction)
+}
+
+// Unsigned decimal integer made of ASCII digits only.
+fn parse_digits(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() {
+        return None
+    }
+    bytes.iter().try_fold(0u64, |value, byte| match byte {
+        b'0'..=b'9' => value.checked_mul(10)?.checked_add(u64::from(byte - b'0')),
+        _ => None,
+    })
+}
+
+/// Parses the rows of an input whose columns are named by `headers`, taking the fast path of
+/// [`parse_canonical_row`] when the header is canonical and falling back on [`parse_row`] otherwise.
+#[derive(Debug, Clone)]
+pub struct RowParser {
+    headers: StringRecord,
+    canonical: bool,
+}
+
+impl RowParser {
+    pub fn new(headers: &StringRecord) -> RowParser {
+        RowParser { headers: headers.clone(), canonical: is_canonical(headers) }
+    }
+
+    pub fn parse(&self, row: &StringRecord) -> Result<(transaction_engine::Transaction, Option<Timestamp>, Option<Currency>), RowError> {
+        match self.canonical.then(|| parse_canonical_row(row.as_byte_record())).flatten() {
+            Some(transaction) => Ok((transaction, None, None)),
+            None => parse_row(row, &self.headers),
+        }
+    }
+
+    /// Like [`RowParser::parse`] on a row read as bytes, which only needs to be valid UTF-8 when it
+    /// falls back on [`parse_row`].
+    pub fn parse_bytes(&self, row: &ByteRecord) -> Result<(transaction_engine::Transaction, Option<Timestamp>, Option<Currency>), RowError> {
+        if let Some(transaction) = self.canonical.then(|| parse_canonical_row(row)).flatten() {
+            return Ok((transaction, None, None))
+        }
+        let row = StringRecord::from_byte_record(row.clone()).map_err(|e| RowError::Parse(e.to_string()))?;
+        parse_row(&row, &self.headers)
+    }
+}
+
+/// Value of the `type` column of an input row, matched whatever its case. The [`BUILTIN_TYPE_ALIASES`] name a
+/// type as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Unlock,
+    Transfer,
+    Adjustment,
+    Fee,
+}
+
+/// Other names of the types found in partner files, matched whatever their case like the type names.
+pub const BUILTIN_TYPE_ALIASES: [(&str, TransactionType); 3] = [
+    ("withdraw", TransactionType::Withdrawal),
+    ("charge_back", TransactionType::Chargeback),
+    ("charge-back", TransactionType::Chargeback),
+];
+
+impl TransactionType {
+    pub const ALL: [TransactionType; 9] = [
+        TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Dispute, TransactionType::Resolve,
+        TransactionType::Chargeback, TransactionType::Unlock, TransactionType::Transfer, TransactionType::Adjustment,
+        TransactionType::Fee,
+    ];
+
+    /// Name of the type in the `type` column.
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Fee => "fee",
+        }
+    }
+
+    /// Type named `name` in the `type` column, e.g. `deposit`, `Deposit` or `DEPOSIT`, or by one of the
+    /// [`BUILTIN_TYPE_ALIASES`].
+    pub fn from_name(name: &str) -> Option<TransactionType> {
+        TransactionType::ALL.into_iter()
+            .find(|transaction_type| transaction_type.name().eq_ignore_ascii_case(name))
+            .or_else(|| {
+                BUILTIN_TYPE_ALIASES.into_iter()
+                    .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+                    .map(|(_, transaction_type)| transaction_type)
+            })
+    }
+}
+
+struct TypeVisitor;
+
+impl Visitor<'_> for TypeVisitor {
+    type Value = TransactionType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a transaction type")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<TransactionType, E> {
+        TransactionType::from_name(name).ok_or_else(|| E::unknown_variant(name, &TRANSACTION_TYPES))
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TypeVisitor)
+    }
+}
+
+/// One row of the input CSV, with the `type,client,tx,amount` columns, the optional
+/// `to_client` column naming the recipient of a transfer and the optional `ts` and `currency` columns.
+///
+/// Converting it into a [`transaction_engine::Transaction`] fails for a
+/// deposit, withdrawal, transfer, adjustment or fee without amount, or a transfer without recipient. A client id that
+/// does not fit in a `u16` fails deserialization instead of being truncated, [`parse_row`] reports it as
+/// [`RowError::ClientOutOfRange`]. Deserialization errors name the
+/// offending column, e.g. ``invalid `tx`: invalid digit found in string``.
+#[derive(Debug, Clone,Copy)]
+pub struct TransactionRecord {
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: TxId,
+    pub amount: Option<Amount>,
+    pub to_client: Option<u16>,
+    pub ts: Option<Timestamp>,
+    /// Currency of the accounts, the base currency of the engine when none.
+    pub currency: Option<Currency>,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    ToClient,
+    Ts,
+    Currency,
+    #[serde(other)]
+    Other,
+}
+
+// Deserializes the value of the `name` column, naming the column in the error.
+struct Named<T>(&'static str, PhantomData<T>);
+
+impl<T> Named<T> {
+    fn column(name: &'static str) -> Named<T> {
+        Named(name, PhantomData)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for Named<T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        T::deserialize(deserializer).map_err(|e| de::Error::custom(format!("invalid `{}`: {e}", self.0)))
+    }
+}
+
+// An id given as an unsigned integer, or as a string of digits so that the error of a CSV field is ours rather
+// than one naming the field by its index.
+struct Id(u64);
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor)
+    }
+}
+
+struct IdVisitor;
+
+impl Visitor<'_> for IdVisitor {
+    type Value = Id;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an unsigned integer")
+    }
+
+    fn visit_u64<E: de::Error>(self, id: u64) -> Result<Id, E> {
+        Ok(Id(id))
+    }
+
+    fn visit_i64<E: de::Error>(self, id: i64) -> Result<Id, E> {
+        u64::try_from(id).map(Id).map_err(|_| E::invalid_value(de::Unexpected::Signed(id), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, id: &str) -> Result<Id, E> {
+        id.parse().map(Id).map_err(E::custom)
+    }
+}
+
+// An amount, an empty one being none like a missing or null one, whatever the deserializer does with empty strings.
+struct OptionalAmount(Option<Amount>);
+
+impl<'de> Deserialize<'de> for OptionalAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_option(OptionalAmountVisitor)
+    }
+}
+
+struct OptionalAmountVisitor;
+
+impl<'de> Visitor<'de> for OptionalAmountVisitor {
+    type Value = OptionalAmount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount or nothing")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<OptionalAmount, E> {
+        Ok(OptionalAmount(None))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<OptionalAmount, E> {
+        Ok(OptionalAmount(None))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<OptionalAmount, D::Error> {
+        deserializer.deserialize_str(OptionalAmountVisitor)
+    }
+
+    fn visit_str<E: de::Error>(self, amount: &str) -> Result<OptionalAmount, E> {
+        match amount.trim() {
+            "" => Ok(OptionalAmount(None)),
+            amount => amount.parse().map(|amount| OptionalAmount(Some(amount))).map_err(E::custom),
+        }
+    }
+}
+
+// An id that does not fit in its type fails instead of being truncated.
+fn in_range<T: TryFrom<u64>, E: de::Error>(id: Option<Id>, kind: &str, max: u64) -> Result<Option<T>, E> {
+    id.map(|Id(id)| T::try_from(id).map_err(|_| E::custom(format!("{kind} id {id} is out of range, the maximum is {max}"))))
+        .transpose()
+}
+
+struct RecordVisitor;
+
+impl<'de> Visitor<'de> for RecordVisitor {
+    type Value = TransactionRecord;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a row with the {} columns", COLUMNS.join(", "))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TransactionRecord, A::Error> {
+        let (mut transaction_type, mut client, mut tx, mut amount, mut to_client, mut ts, mut currency) =
+            (None, None, None, None, None, None, None);
+        while let Some(column) = map.next_key()? {
+            match column {
+                Column::Type => transaction_type = Some(map.next_value_seed(Named::column("type"))?),
+                Column::Client => client = Some(map.next_value_seed(Named::column("client"))?),
+                Column::Tx => tx = Some(map.next_value_seed(Named::column("tx"))?),
+                Column::Amount => amount = map.next_value_seed(Named::<OptionalAmount>::column("amount"))?.0,
+                Column::ToClient => to_client = map.next_value_seed(Named::column("to_client"))?,
+                Column::Ts => ts = map.next_value_seed(Named::column("ts"))?,
+                Column::Currency => currency = map.next_value_seed(Named::column("currency"))?,
+                Column::Other => {
+                    map.next_value::<de::IgnoredAny>()?;
+                },
+            }
+        }
+        Ok(TransactionRecord {
+            transaction_type: transaction_type.ok_or_else(|| de::Error::missing_field("type"))?,
+            client: in_range(client, "client", u16::MAX.into())?.ok_or_else(|| de::Error::missing_field("client"))?,
+            tx: in_range(tx, "tx", TxId::MAX)?.ok_or_else(|| de::Error::missing_field("tx"))?,
+            amount,
+            to_client: in_range(to_client, "client", u16::MAX.into())?,
+            ts,
+            currency,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionRecord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("TransactionRecord", &["type", "client", "tx", "amount", "to_client", "ts", "currency"], RecordVisitor)
+    }
+}
+
+/// Deserializes a row with the columns of a [`TransactionRecord`] straight into the transaction, the `ts`
+/// and `currency` columns being ignored. The amount of a dispute, resolve, chargeback or unlock is ignored as well.
+///
+/// ```
+/// use transaction_engine::Transaction;
+///
+/// let mut reader = csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,\n".as_bytes());
+/// let mut rows = reader.deserialize::<Transaction>();
+/// assert_eq!(rows.next().unwrap().unwrap(), Transaction::Deposit { client_id: 1, tx_id: 1, amount: "2.5".parse().unwrap() });
+/// assert!(rows.next().unwrap().unwrap_err().to_string().contains("a withdrawal needs an `amount`"));
+/// ```
+impl<'de> Deserialize<'de> for transaction_engine::Transaction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = TransactionRecord::deserialize(deserializer)?;
+        let name = record.transaction_type.name();
+        record.try_into().map_err(|e| match e {
+            RowError::MissingAmount { .. } => de::Error::custom(format!("a {name} needs an `amount`")),
+            RowError::MissingToClient { .. } => de::Error::custom(format!("a {name} needs a `to_client`")),
+            e @ (RowError::Parse(_) | RowError::ClientOutOfRange { .. } | RowError::UnsupportedType(_)) => de::Error::custom(e),
+        })
+    }
+}
+
+impl TryInto<transaction_engine::Transaction> for TransactionRecord {
+    type Error = RowError;
+
+    fn try_into(self) -> Result<transaction_engine::Transaction, Self::Error> {
+        let missing_amount = RowError::MissingAmount { transaction_type: self.transaction_type, client_id: self.client, tx_id: self.tx };
+        match self.transaction_type {
+            TransactionType::Deposit => {
+                if let Some(amount) = self.amount  {
+                    Ok(transaction_engine::Transaction::Deposit { 
+                        client_id: self.client, 
+                        tx_id: self.tx, 
+                        amount 
+                    })
+                } else {
+                    Err(missing_amount)
+                }
+            },
+            TransactionType::Withdrawal => {
+                if let Some(amount) = self.amount  {
+                    Ok(transaction_engine::Transaction::Withdrawal { 
+                        client_id: self.client, 
+                        tx_id: self.tx, 
+                        amount 
+                    })
+                } else {
+                    Err(missing_amount)
+                } 
+            },
+            TransactionType::Dispute => {
+                Ok(transaction_engine::Transaction::Dispute { 
+                    client_id: self.client, 
+                    tx_id: self.tx 
+                })
+            },
+            TransactionType::Resolve => {
+                Ok(transaction_engine::Transaction::Resolve { 
+                    client_id: self.client, 
+                    tx_id: self.tx 
+                })
+            },
+            TransactionType::Chargeback => {
+                Ok(transaction_engine::Transaction::Chargeback{ 
+                    client_id: self.client, 
+                    tx_id: self.tx 
+                })
+            },
+            TransactionType::Unlock => {
+                Ok(transaction_engine::Transaction::Unlock {
+                    client_id: self.client,
+                    tx_id: self.tx
+                })
+            },
+            TransactionType::Transfer => {
+                Ok(transaction_engine::Transaction::Transfer {
+                    from: self.client,
+                    to: self.to_client.ok_or(RowError::MissingToClient { client_id: self.client, tx_id: self.tx })?,
+                    tx_id: self.tx,
+                    amount: self.amount.ok_or(missing_amount)?
+                })
+            },
+            TransactionType::Adjustment => {
+                Ok(transaction_engine::Transaction::Adjustment {
+                    client_id: self.client,
+                    tx_id: self.tx,
+                    amount: self.amount.ok_or(missing_amount)?
+                })
+            },
+            TransactionType::Fee => {
+                Ok(transaction_engine::Transaction::Fee {
+                    client_id: self.client,
+                    tx_id: self.tx,
+                    amount: self.amount.ok_or(missing_amount)?
+                })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &str) -> Result<TransactionRecord, csv::Error> {
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        rdr.deserialize().next().unwrap()
+    }
+
+    #[test]
+    fn when_client_id_fits_should_parse() {
+        let record = parse("type,client,tx,amount\ndeposit,65535,1,1.0\n").unwrap();
+
+        assert_eq!(record.client, u16::MAX);
+    }
+
+    #[test]
+    fn when_client_id_out_of_range_should_reject_instead_of_truncating() {
+        let err = parse("type,client,tx,amount\ndeposit,70000,1,1.0\n").unwrap_err();
+
+        assert!(err.to_string().contains("client id 70000 is out of range"), "{err}");
+    }
+
+    #[test]
+    fn amount_should_be_converted_without_precision_loss() {
+        let record = parse("type,client,tx,amount\ndeposit,1,1,123456789.1234\n").unwrap();
+        assert_eq!(record.amount, Some(Amount::from_units(1_234_567_891_234)));
+
+        match record.try_into() {
+            Ok(transaction_engine::Transaction::Deposit { amount, .. }) => {
+                assert_eq!(amount, Amount::from_units(1_234_567_891_234))
+            },
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn when_amount_has_more_than_four_decimals_should_round() {
+        let record = parse("type,client,tx,amount\ndeposit,1,1,1.00005\n").unwrap();
+
+        assert_eq!(record.amount, Some(Amount::from_units(10_001)));
+    }
+
+    #[test]
+    fn when_amount_empty_should_be_none() {
+        let record = parse("type,client,tx,amount\ndispute,1,1,\n").unwrap();
+
+        assert_eq!(record.amount, None);
+    }
+
+    #[test]
+    fn when_columns_are_reordered_should_parse_by_name() {
+        let record = parse("amount,tx,client,type\n2.5,7,3,withdrawal\n").unwrap();
+
+        assert!(matches!(record.transaction_type, TransactionType::Withdrawal));
+        assert_eq!((record.client, record.tx), (3, 7));
+        assert_eq!(record.amount, Some(Amount::from_units(25_000)));
+    }
+
+    #[test]
+    fn missing_column_should_name_the_first_missing_column() {
+        assert_eq!(missing_column(&StringRecord::from(vec!["type", "client", "tx", "amount"])), None);
+        assert_eq!(missing_column(&StringRecord::from(vec!["tx", "amount", "type", "client", "note"])), None);
+        assert_eq!(missing_column(&StringRecord::from(vec!["type", "cliente", "tx", "amount"])), Some("client"));
+        assert_eq!(missing_column(&StringRecord::from(vec!["type", "client", "tx"])), Some("amount"));
+    }
+
+    #[test]
+    fn parse_row_should_convert_or_explain_the_row() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["deposit", "1", "2", "3.0"]), &headers),
+            Ok((transaction_engine::Transaction::Deposit { client_id: 1, tx_id: 2, amount: Amount::from_units(30_000) }, None, None))
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["withdrawal", "1", "2", ""]), &headers),
+            Err(RowError::MissingAmount { transaction_type: TransactionType::Withdrawal, client_id: 1, tx_id: 2 })
+        );
+        assert_eq!(
+            RowError::MissingAmount { transaction_type: TransactionType::Withdrawal, client_id: 1, tx_id: 2 }.to_string(),
+            "missing amount on withdrawal tx 2 of client 1"
+        );
+        assert_eq!(parse_row(&StringRecord::from(vec!["deposit", "x", "2", "3.0"]), &headers).unwrap_err().code(), "parse_error");
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["refund", "x", "2", "3.0"]), &headers),
+            Err(RowError::UnsupportedType("refund".to_string()))
+        );
+        assert_eq!(parse_row(&StringRecord::from(vec!["", "1", "2", "3.0"]), &headers).unwrap_err().code(), "parse_error");
+    }
+
+    #[test]
+    fn transfer_should_need_a_recipient_and_an_amount() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "to_client"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["transfer", "1", "100", "25.0", "2"]), &headers),
+            Ok((transaction_engine::Transaction::Transfer { from: 1, to: 2, tx_id: 100, amount: Amount::from_units(250_000) }, None, None))
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["transfer", "1", "100", "25.0", ""]), &headers),
+            Err(RowError::MissingToClient { client_id: 1, tx_id: 100 })
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["transfer", "1", "100", "", "2"]), &headers),
+            Err(RowError::MissingAmount { transaction_type: TransactionType::Transfer, client_id: 1, tx_id: 100 })
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["transfer", "1", "100", "25.0"]), &StringRecord::from(vec!["type", "client", "tx", "amount"])),
+            Err(RowError::MissingToClient { client_id: 1, tx_id: 100 })
+        );
+    }
+
+    #[test]
+    fn client_id_past_u16_should_be_out_of_range() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "to_client"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["deposit", "100000", "1", "1.0", ""]), &headers),
+            Err(RowError::ClientOutOfRange { client_id: 100_000 })
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["transfer", "1", "2", "1.0", "65536"]), &headers),
+            Err(RowError::ClientOutOfRange { client_id: 65_536 })
+        );
+        assert_eq!(RowError::ClientOutOfRange { client_id: 65_536 }.code(), "client_out_of_range");
+        assert!(parse_row(&StringRecord::from(vec!["deposit", "65535", "1", "1.0", ""]), &headers).is_ok());
+        assert_eq!(parse_row(&StringRecord::from(vec!["deposit", "-1", "1", "1.0", ""]), &headers).unwrap_err().code(), "parse_error");
+    }
+
+    #[test]
+    fn adjustment_should_keep_its_sign_and_need_an_amount() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["adjustment", "5", "900", "-3.50"]), &headers),
+            Ok((transaction_engine::Transaction::Adjustment { client_id: 5, tx_id: 900, amount: Amount::from_units(-35_000) }, None, None))
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["adjustment", "5", "900", ""]), &headers),
+            Err(RowError::MissingAmount { transaction_type: TransactionType::Adjustment, client_id: 5, tx_id: 900 })
+        );
+    }
+
+    #[test]
+    fn fee_should_need_an_amount() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["fee", "1", "7", "0.25"]), &headers),
+            Ok((transaction_engine::Transaction::Fee { client_id: 1, tx_id: 7, amount: Amount::from_units(2_500) }, None, None))
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["fee", "1", "7", ""]), &headers),
+            Err(RowError::MissingAmount { transaction_type: TransactionType::Fee, client_id: 1, tx_id: 7 })
+        );
+    }
+
+    #[test]
+    fn canonical_header_should_be_the_columns_in_order() {
+        assert!(is_canonical(&StringRecord::from(vec!["type", "client", "tx", "amount"])));
+        assert!(!is_canonical(&StringRecord::from(vec!["amount", "tx", "client", "type"])));
+        assert!(!is_canonical(&StringRecord::from(vec!["type", "client", "tx", "amount", "ts"])));
+    }
+
+    // Both paths must agree on every row: the fast one either gives the same transaction or defers.
+    fn assert_paths_agree(row: &StringRecord, headers: &StringRecord) -> bool {
+        let slow = parse_row(row, headers);
+        match parse_canonical_row(row.as_byte_record()) {
+            Some(transaction) => {
+                assert_eq!(slow, Ok((transaction, None, None)), "{row:?}");
+                true
+            },
+            None => {
+                assert_eq!(RowParser::new(headers).parse(row), slow, "{row:?}");
+                false
+            },
+        }
+    }
+
+    #[test]
+    fn fast_path_should_match_the_serde_path() {
+        let headers = StringRecord::from(COLUMNS.to_vec());
+        let fast = [
+            vec!["deposit", "1", "2", "3.0"], vec!["withdrawal", "65535", "18446744073709551615", "1.00005"],
+            vec!["dispute", "1", "2", ""], vec!["resolve", "1", "2", "5"], vec!["chargeback", "0", "0", ""],
+            vec!["unlock", "1", "2", ""], vec!["adjustment", "5", "900", "-3.50"], vec!["fee", "1", "7", "+0.25"],
+        ];
+        for row in fast {
+            assert!(assert_paths_agree(&StringRecord::from(row), &headers));
+        }
+        let deferred = [
+            vec!["deposit", "1", "2", ""], vec!["Deposit", "1", "2", "3.0"], vec!["deposit", "70000", "2", "3.0"],
+            vec!["deposit", "1", "18446744073709551616", "3.0"], vec!["deposit", "+1", "2", "3.0"], vec!["deposit", "x", "2", "3.0"],
+            vec!["dispute", "1", "2", "abc"], vec!["transfer", "1", "2", "3.0"], vec!["payout", "1", "2", "3.0"],
+            vec!["deposit", "", "2", "3.0"], vec!["deposit", "1", "99999999999999999999999", "3.0"],
+        ];
+        for row in deferred {
+            assert!(!assert_paths_agree(&StringRecord::from(row), &headers));
+        }
+    }
+
+    #[test]
+    fn fast_path_should_match_the_serde_path_on_the_fixtures() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/tests");
+        let mut fast_rows = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "csv") {
+                continue
+            }
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_path(&path).unwrap();
+            let headers = reader.headers().unwrap().clone();
+            if !is_canonical(&headers) {
+                continue
+            }
+            for row in reader.records().filter_map(Result::ok) {
+                fast_rows += usize::from(assert_paths_agree(&row, &headers));
+            }
+        }
+        assert!(fast_rows > 100, "{fast_rows}");
+    }
+
+    #[test]
+    fn ts_column_should_be_optional() {
+        let record = parse("type,client,tx,amount,ts\ndeposit,1,1,1.0,2023-11-14T22:13:20Z\n").unwrap();
+        assert_eq!(record.ts, Some(Timestamp::from_millis(1_700_000_000_000)));
+
+        let record = parse("type,client,tx,amount,ts\ndispute,1,1,,\n").unwrap();
+        assert_eq!(record.ts, None);
+
+        let record = parse("type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        assert_eq!(record.ts, None);
+
+        assert!(parse("type,client,tx,amount,ts\ndeposit,1,1,1.0,yesterday\n").is_err());
+    }
+
+    #[test]
+    fn currency_column_should_be_optional() {
+        let record = parse("type,client,tx,amount,currency\ndeposit,1,1,1.0,eur\n").unwrap();
+        assert_eq!(record.currency, Some("EUR".parse().unwrap()));
+
+        let record = parse("type,client,tx,amount,currency\ndispute,1,1,,\n").unwrap();
+        assert_eq!(record.currency, None);
+
+        let record = parse("type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        assert_eq!(record.currency, None);
+
+        let error = parse("type,client,tx,amount,currency\ndeposit,1,1,1.0,euro\n").unwrap_err();
+        assert!(error.to_string().contains("invalid `currency`: invalid currency \"euro\", expected a 3-letter code"), "{error}");
+    }
+
+    #[test]
+    fn parse_row_should_give_the_currency_of_the_row() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "currency"]);
+
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["deposit", "1", "2", "3.0", "GBP"]), &headers),
+            Ok((transaction_engine::Transaction::Deposit { client_id: 1, tx_id: 2, amount: Amount::from_units(30_000) }, None, Some("GBP".parse().unwrap())))
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["dispute", "1", "2", "", ""]), &headers),
+            Ok((transaction_engine::Transaction::Dispute { client_id: 1, tx_id: 2 }, None, None))
+        );
+        assert_eq!(parse_row(&StringRecord::from(vec!["deposit", "1", "2", "3.0", "G8P"]), &headers).unwrap_err().code(), "parse_error");
+    }
+
+    #[test]
+    fn when_client_id_negative_should_reject() {
+        assert!(parse("type,client,tx,amount\ndeposit,-1,1,1.0\n").is_err());
+    }
+
+    fn transaction(data: &str) -> Result<transaction_engine::Transaction, String> {
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let transaction: Result<transaction_engine::Transaction, csv::Error> = rdr.deserialize().next().unwrap();
+        transaction.map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn type_should_match_whatever_its_case_and_the_builtin_aliases() {
+        let types = [
+            ("Deposit", TransactionType::Deposit), ("WITHDRAWAL", TransactionType::Withdrawal),
+            ("withdraw", TransactionType::Withdrawal), ("Withdraw", TransactionType::Withdrawal),
+            ("charge_back", TransactionType::Chargeback), ("Charge-Back", TransactionType::Chargeback),
+            ("chargeBack", TransactionType::Chargeback),
+        ];
+        for (name, transaction_type) in types {
+            assert_eq!(TransactionType::from_name(name), Some(transaction_type), "{name}");
+            let record = parse(&format!("type,client,tx,amount\n{name},1,1,1.0\n")).unwrap();
+            assert_eq!(record.transaction_type, transaction_type, "{name}");
+        }
+        for name in ["refund", "withdrawals", "charge back", ""] {
+            assert_eq!(TransactionType::from_name(name), None, "{name}");
+        }
+        assert!(parse("type,client,tx,amount\nRefund,1,1,1.0\n").unwrap_err().to_string().contains("unknown variant `Refund`"));
+    }
+
+    #[test]
+    fn dispute_should_parse_with_or_without_an_amount() {
+        let headers = StringRecord::from(COLUMNS.to_vec());
+        let dispute = Ok((transaction_engine::Transaction::Dispute { client_id: 1, tx_id: 2 }, None, None));
+        for row in ["dispute,1,2\n", "dispute,1,2,\n", "dispute,1,2,\"\"\n", "dispute,1,2, \n"] {
+            let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(row.as_bytes());
+            let record = reader.records().next().unwrap().unwrap();
+            assert_eq!(RowParser::new(&headers).parse(&record), dispute, "{row}");
+        }
+        assert_eq!(parse_row(&StringRecord::from(vec!["deposit", "1", "2", "\"\""]), &headers).unwrap_err().code(), "parse_error");
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["deposit", "1", "2"]), &headers),
+            Err(RowError::MissingAmount { transaction_type: TransactionType::Deposit, client_id: 1, tx_id: 2 })
+        );
+        assert_eq!(
+            parse_row(&StringRecord::from(vec!["dispute", "1", "2", "", ""]), &headers),
+            Err(RowError::Parse("found a row with 5 fields, but the header has 4".to_string()))
+        );
+        // An empty string is no amount whatever the format.
+        assert_eq!(
+            serde_json::from_str::<transaction_engine::Transaction>(r#"{"type": "resolve", "client": 1, "tx": 2, "amount": ""}"#).unwrap(),
+            transaction_engine::Transaction::Resolve { client_id: 1, tx_id: 2 }
+        );
+    }
+
+    #[test]
+    fn transaction_should_deserialize_straight_from_a_row() {
+        assert_eq!(
+            transaction("type,client,tx,amount,to_client,ts\ntransfer,1,7,2.5,2,1700000000000\n"),
+            Ok(transaction_engine::Transaction::Transfer { from: 1, to: 2, tx_id: 7, amount: Amount::from_units(25_000) })
+        );
+        assert_eq!(
+            transaction("tx,note,client,type,amount\n3,late,1,dispute,9.0\n"),
+            Ok(transaction_engine::Transaction::Dispute { client_id: 1, tx_id: 3 })
+        );
+    }
+
+    #[test]
+    fn transaction_errors_should_name_the_offending_field() {
+        let error = |row: &str| transaction(&format!("type,client,tx,amount\n{row}\n")).unwrap_err();
+        let errors = [
+            ("refund,1,1,1.0", "invalid `type`: unknown variant `refund`, expected one of `deposit`, `withdrawal`"),
+            (",1,1,1.0", "invalid `type`: unknown variant ``"),
+            ("deposit,x,1,1.0", "invalid `client`: invalid digit found in string"),
+            ("deposit,,1,1.0", "invalid `client`: cannot parse integer from empty string"),
+            ("deposit,70000,1,1.0", "client id 70000 is out of range, the maximum is 65535"),
+            ("deposit,1,-1,1.0", "invalid `tx`: invalid value: integer `-1`, expected an unsigned integer"),
+            ("deposit,1,1.5,1.0", "invalid `tx`: invalid type: floating point `1.5`, expected an unsigned integer"),
+            ("deposit,1,18446744073709551616,1.0", "invalid `tx`: invalid type: integer `18446744073709551616` as u128"),
+            ("deposit,1,1,abc", "invalid `amount`: invalid amount \"abc\""),
+            ("dispute,1,1,abc", "invalid `amount`: invalid amount \"abc\""),
+            ("deposit,1,1,", "a deposit needs an `amount`"),
+            ("withdrawal,1,1,", "a withdrawal needs an `amount`"),
+            ("transfer,1,1,1.0", "a transfer needs a `to_client`"),
+        ];
+        for (row, message) in errors {
+            assert!(error(row).contains(message), "{row}: {}", error(row));
+        }
+        assert!(transaction("type,tx,amount\ndeposit,1,1.0\n").unwrap_err().contains("missing field `client`"));
+        assert!(transaction("type,client,tx,amount,to_client\ntransfer,1,1,1.0,70000\n").unwrap_err()
+            .contains("client id 70000 is out of range"));
+    }
+
+    #[test]
+    fn a_serialized_transaction_should_deserialize_back() {
+        let amount = Amount::from_units(25_000);
+        let transactions = [
+            transaction_engine::Transaction::Deposit { client_id: 1, tx_id: 1, amount },
+            transaction_engine::Transaction::Dispute { client_id: 1, tx_id: 1 },
+            transaction_engine::Transaction::Unlock { client_id: 1, tx_id: 2 },
+            transaction_engine::Transaction::Transfer { from: 1, to: 2, tx_id: 3, amount },
+            transaction_engine::Transaction::Adjustment { client_id: 2, tx_id: 4, amount: -amount },
+        ];
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for transaction in transactions {
+            writer.serialize(transaction).unwrap();
+        }
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(data.lines().take(2).collect::<Vec<_>>(), ["type,client,tx,amount,to_client", "deposit,1,1,2.5,"]);
+
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let read: Vec<transaction_engine::Transaction> = reader.deserialize().collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, transactions);
+        for transaction in transactions {
+            let json = serde_json::to_string(&transaction).unwrap();
+            assert_eq!(serde_json::from_str::<transaction_engine::Transaction>(&json).unwrap(), transaction, "{json}");
+        }
+    }
+}
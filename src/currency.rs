@@ -0,0 +1,133 @@
+//! Currency of an account, from the optional `currency` column of an input.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::de::{self, Visitor};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A 3-letter currency code such as `USD` or `EUR`, stored in upper case.
+///
+/// Parses from three ASCII letters of any case. The default is `USD`, the currency of the accounts and
+/// transactions saved before currencies were supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    /// The code, e.g. `"EUR"`.
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("a currency code is ASCII")
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+/// Error returned when parsing a [`Currency`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid currency {:?}, expected a 3-letter code", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if [a, b, c].iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency([a, b, c].map(|letter| letter.to_ascii_uppercase())))
+            },
+            _ => Err(ParseCurrencyError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Currency {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Currency".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({ "type": "string", "pattern": "^[A-Z]{3}$" })
+    }
+}
+
+#[cfg(feature = "serde")]
+struct CurrencyVisitor;
+
+#[cfg(feature = "serde")]
+impl Visitor<'_> for CurrencyVisitor {
+    type Value = Currency;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a 3-letter currency code")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Currency, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_three_letters_in_upper_case() {
+        assert_eq!("EUR".parse::<Currency>().unwrap().code(), "EUR");
+        assert_eq!("gbp".parse::<Currency>().unwrap().to_string(), "GBP");
+        assert_eq!("Usd".parse::<Currency>(), Ok(Currency::USD));
+        assert_eq!(Currency::default(), Currency::USD);
+    }
+
+    #[test]
+    fn when_not_three_letters_should_reject() {
+        for s in ["", "EU", "EURO", "E1R", " EUR", "€UR", "ÉUR"] {
+            assert!(s.parse::<Currency>().is_err(), "{s}");
+        }
+        assert_eq!("EU".parse::<Currency>().unwrap_err().to_string(), "invalid currency \"EU\", expected a 3-letter code");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn should_serialize_as_its_code() {
+        let eur: Currency = "eur".parse().unwrap();
+        assert_eq!(serde_json::to_string(&eur).unwrap(), "\"EUR\"");
+        assert_eq!(serde_json::from_str::<Currency>("\"chf\"").unwrap().code(), "CHF");
+        assert!(serde_json::from_str::<Currency>("\"CH\"").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! `--daily-snapshots`: the balances at the end of each day of an input sorted by time.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use transaction_engine::{Timestamp, TransactionEngine, TransactionStore};
+
+use crate::atomic_file::AtomicFile;
+use crate::out_of_order::OutOfOrder;
+use crate::output::{self, Format};
+
+/// Writes the balances of the engine to `accounts-YYYY-MM-DD.csv` in a directory whenever the date of the rows
+/// rolls over, the rows being checked to come in time order. Nothing is kept but the time of the previous row.
+/// With a run id, only the accounts changed since the previous output are written, after a metadata row, the
+/// engine tracking its changes.
+pub struct DailySnapshots {
+    dir: PathBuf,
+    delimiter: u8,
+    run_id: Option<String>,
+    previous: Option<Timestamp>,
+}
+
+impl DailySnapshots {
+    /// Creates `dir` if needed.
+    pub fn create<P: AsRef<Path>>(dir: P, delimiter: u8, run_id: Option<String>) -> io::Result<DailySnapshots> {
+        fs::create_dir_all(&dir)?;
+        Ok(DailySnapshots { dir: dir.as_ref().to_path_buf(), delimiter, run_id, previous: None })
+    }
+
+    /// To be called before a row of time `ts` is applied: writes the balances of the day of the previous row
+    /// when `ts` is on a later date. Fails on a row without time or earlier than the previous one.
+    pub fn row<S: TransactionStore>(&mut self, ts: Option<Timestamp>, engine: &mut TransactionEngine<S>) -> Result<(), String> {
+        let ts = ts.ok_or("--daily-snapshots needs a timestamp on every row")?;
+        match self.previous {
+            Some(previous) if ts < previous => {
+                return Err(format!("--daily-snapshots needs rows sorted by time: {}", OutOfOrder { ts, previous }))
+            },
+            Some(previous) if ts.day() > previous.day() => self.write(previous, engine)?,
+            _ => {},
+        }
+        self.previous = Some(ts);
+        Ok(())
+    }
+
+    /// Writes the balances of the last day, once every row is applied.
+    pub fn finish<S: TransactionStore>(&self, engine: &mut TransactionEngine<S>) -> Result<(), String> {
+        self.previous.map_or(Ok(()), |previous| self.write(previous, engine))
+    }
+
+    fn write<S: TransactionStore>(&self, day: Timestamp, engine: &mut TransactionEngine<S>) -> Result<(), String> {
+        let path = self.dir.join(format!("accounts-{}.csv", day.date()));
+        let with_currency = engine.is_multi_currency();
+        AtomicFile::create(&path)
+            .and_then(|file| match &self.run_id {
+                Some(run_id) => {
+                    let changes = engine.take_changes().expect("changes tracked for the incremental outputs");
+                    output::write_changes(file, run_id, &changes, self.delimiter, with_currency)
+                },
+                None => output::write_clients(file, engine.clients(), Format::Csv, self.delimiter, with_currency),
+            })
+            .and_then(AtomicFile::commit)
+            .map_err(|e| format!("cannot write {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction_engine::{Amount, Transaction};
+
+    fn ts(s: &str) -> Option<Timestamp> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn should_write_the_balances_when_the_date_rolls_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daily = DailySnapshots::create(dir.path().join("days"), b',', None).unwrap();
+        let mut engine = TransactionEngine::new();
+        let deposit = |tx_id| Transaction::Deposit { client_id: 1, tx_id, amount: Amount::from_units(10_000) };
+
+        daily.row(ts("2024-01-01T08:00:00Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(1)).unwrap();
+        daily.row(ts("2024-01-01T23:59:59Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(2)).unwrap();
+        daily.row(ts("2024-01-03T00:00:00Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(3)).unwrap();
+        daily.finish(&mut engine).unwrap();
+
+        let read = |date: &str| fs::read_to_string(dir.path().join(format!("days/accounts-{date}.csv"))).unwrap();
+        assert_eq!(read("2024-01-01"), "client,available,held,total,locked\n1,2.0,0.0,2.0,false\n");
+        assert_eq!(read("2024-01-03"), "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n");
+        assert_eq!(fs::read_dir(dir.path().join("days")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn should_write_the_changed_balances_with_a_run_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daily = DailySnapshots::create(dir.path(), b',', Some("run".to_string())).unwrap();
+        let mut engine = TransactionEngine::new();
+        engine.track_changes();
+        let deposit = |client_id, tx_id| Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(10_000) };
+
+        daily.row(ts("2024-01-01T08:00:00Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(1, 1)).unwrap();
+        daily.row(ts("2024-01-01T09:00:00Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(2, 2)).unwrap();
+        daily.row(ts("2024-01-02T00:00:00Z"), &mut engine).unwrap();
+        engine.compute_transaction(deposit(2, 3)).unwrap();
+        daily.finish(&mut engine).unwrap();
+
+        let read = |date: &str| fs::read_to_string(dir.path().join(format!("accounts-{date}.csv"))).unwrap();
+        assert_eq!(
+            read("2024-01-01"),
+            "#{\"run_id\":\"run\",\"first_seq\":1,\"last_seq\":2,\"count\":2}\n\
+             client,available,held,total,locked\n1,1.0,0.0,1.0,false\n2,1.0,0.0,1.0,false\n",
+        );
+        assert_eq!(
+            read("2024-01-02"),
+            "#{\"run_id\":\"run\",\"first_seq\":3,\"last_seq\":3,\"count\":1}\nclient,available,held,total,locked\n2,2.0,0.0,2.0,false\n",
+        );
+    }
+
+    #[test]
+    fn should_fail_on_rows_out_of_order_or_without_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daily = DailySnapshots::create(dir.path(), b',', None).unwrap();
+        let mut engine = TransactionEngine::new();
+
+        daily.row(ts("2024-01-02T00:00:00Z"), &mut engine).unwrap();
+        assert_eq!(
+            daily.row(ts("2024-01-01T23:59:59.999Z"), &mut engine),
+            Err("--daily-snapshots needs rows sorted by time: timestamp 2024-01-01T23:59:59.999Z is 1 ms earlier \
+                 than the previous row's 2024-01-02T00:00:00.000Z".to_string()),
+        );
+        assert_eq!(daily.row(None, &mut engine), Err("--daily-snapshots needs a timestamp on every row".to_string()));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
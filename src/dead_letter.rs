@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use csv::{StringRecord, Writer, WriterBuilder};
+
+pub struct DeadLetter<W: Write> {
+    writer: Writer<W>,
+    width: usize,
+}
+
+impl DeadLetter<File> {
+    pub fn create<P: AsRef<Path>>(path: P, headers: &StringRecord) -> csv::Result<DeadLetter<File>> {
+        DeadLetter::from_writer(File::create(path)?, headers)
+    }
+}
+
+impl<W: Write> DeadLetter<W> {
+    pub fn from_writer(writer: W, headers: &StringRecord) -> csv::Result<DeadLetter<W>> {
+        let mut writer = WriterBuilder::new().flexible(true).from_writer(writer);
+        writer.write_record(headers.iter().chain(["reason"]))?;
+
+        Ok(DeadLetter { writer, width: headers.len() })
+    }
+
+    // Short rows are padded so that `reason` lines up with its header, longer rows are
+    // written as is and keep `reason` as their last field.
+    pub fn write(&mut self, record: &StringRecord, reason: &str) -> csv::Result<()> {
+        let padding = self.width.saturating_sub(record.len());
+        self.writer.write_record(
+            record.iter()
+                .chain(std::iter::repeat_n("", padding))
+                .chain([reason])
+        )
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(rows: &[(&[&str], &str)]) -> String {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let mut dead_letter = DeadLetter::from_writer(vec![], &headers).unwrap();
+        for (fields, reason) in rows {
+            dead_letter.write(&StringRecord::from(fields.to_vec()), reason).unwrap();
+        }
+        String::from_utf8(dead_letter.writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_write_original_columns_and_reason() {
+        let data = written(&[(&["withdrawal", "1", "2", "60.0"], "insufficient funds")]);
+
+        assert_eq!(data, "type,client,tx,amount,reason\nwithdrawal,1,2,60.0,insufficient funds\n");
+    }
+
+    #[test]
+    fn when_row_is_short_should_pad_before_reason() {
+        let data = written(&[(&["dispute", "1", "2"], "tx 2 is unknown")]);
+
+        assert_eq!(data, "type,client,tx,amount,reason\ndispute,1,2,,tx 2 is unknown\n");
+    }
+
+    #[test]
+    fn when_row_is_long_should_keep_every_field() {
+        let data = written(&[(&["deposit", "1", "2", "3.0", "extra"], "parse error")]);
+
+        assert_eq!(data, "type,client,tx,amount,reason\ndeposit,1,2,3.0,extra,parse error\n");
+    }
+}
@@ -0,0 +1,34 @@
+//! The `diff` command: the differences between the engines of two snapshots.
+
+use std::fs::File;
+
+use transaction_engine::{SnapshotError, TransactionEngine};
+
+use crate::cli::DiffArgs;
+
+/// Exit code of two snapshots that differ, as with diff(1).
+pub const EXIT_DIFFERENT: i32 = 1;
+/// Exit code of a snapshot that cannot be loaded.
+pub const EXIT_TROUBLE: i32 = 2;
+
+/// Prints the differences of the snapshots, nothing when they have none. Returns the exit code.
+pub fn run(args: DiffArgs) -> i32 {
+    let load = |path: &str| {
+        File::open(path).map_err(SnapshotError::Io).and_then(TransactionEngine::load_snapshot)
+            .map_err(|e| format!("cannot load {path}: {e}"))
+    };
+    let (left, right) = match load(&args.left).and_then(|left| Ok((left, load(&args.right)?))) {
+        Ok(engines) => engines,
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            return EXIT_TROUBLE
+        },
+    };
+
+    let diff = left.diff_states(&right);
+    if diff.is_empty() {
+        return 0
+    }
+    println!("{diff}");
+    EXIT_DIFFERENT
+}
@@ -0,0 +1,25 @@
+//! The hidden `dump-schemas` command: the JSON Schemas of the payloads of the `wire` module.
+
+use std::fs;
+
+use crate::cli::DumpSchemasArgs;
+use crate::wire;
+
+/// Writes each schema to `<out>/<payload>.json`, pretty-printed. Returns the exit code.
+pub fn run(args: DumpSchemasArgs) -> i32 {
+    let written = fs::create_dir_all(&args.out).map_err(|e| format!("cannot create {}: {e}", args.out.display()))
+        .and_then(|()| {
+            wire::schemas().into_iter().try_for_each(|(name, schema)| {
+                let path = args.out.join(format!("{name}.json"));
+                let json = serde_json::to_string_pretty(&schema).expect("a schema is JSON");
+                fs::write(&path, json + "\n").map_err(|e| format!("cannot write {}: {e}", path.display()))
+            })
+        });
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            1
+        },
+    }
+}
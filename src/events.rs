@@ -0,0 +1,66 @@
+//! Notifications of what the engine changes, see [`crate::TransactionEngine::set_observer`].
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::amount::Amount;
+use crate::transaction_engine::{TransactionError, TxId};
+
+/// A change made by the engine to a client account. `tx_id` is the transaction that caused it,
+/// i.e. the disputed transaction for disputes, resolves and chargebacks.
+///
+/// Every change of the balances and locks is in exactly one event, so that the events of a run rebuild its
+/// accounts, see [`replay_audit`](crate::replay::replay_audit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineEvent {
+    /// A rejected transaction opened the account of its client, with zero balances. The other accounts are
+    /// opened by their first credit or debit.
+    AccountOpened { client_id: u16, tx_id: TxId },
+    /// Funds added to the available ones and the total: a deposit, a received transfer, a positive adjustment,
+    /// or the refund of a sent transfer charged back.
+    AccountCredited { client_id: u16, tx_id: TxId, amount: Amount },
+    /// Funds removed from the available ones and the total: a withdrawal, a sent transfer, a negative
+    /// adjustment or a fee. The amount is positive.
+    AccountDebited { client_id: u16, tx_id: TxId, amount: Amount },
+    /// Available funds held by the dispute of a deposit or of a received transfer.
+    FundsHeld { client_id: u16, tx_id: TxId, amount: Amount },
+    /// Held funds made available again: the resolve of a disputed deposit or received transfer, or the
+    /// chargeback of a disputed withdrawal or fee.
+    FundsReleased { client_id: u16, tx_id: TxId, amount: Amount },
+    /// Funds added to the held ones and the total by the dispute of a withdrawal or a fee, its amount being
+    /// held until the dispute is settled.
+    HeldCredited { client_id: u16, tx_id: TxId, amount: Amount },
+    /// Held funds removed from the total: the chargeback of a deposit or of a received transfer, or the
+    /// resolve of a disputed withdrawal or fee.
+    HeldDebited { client_id: u16, tx_id: TxId, amount: Amount },
+    /// The first chargeback on the account locked it.
+    AccountLocked { client_id: u16, tx_id: TxId },
+    /// An unlock reinstated the locked account.
+    AccountUnlocked { client_id: u16, tx_id: TxId },
+    /// The transaction was rejected and changed nothing. Not sent for those ignored, see
+    /// [`TransactionError::is_ignored`].
+    TransactionRejected { client_id: u16, tx_id: TxId, error: TransactionError },
+}
+
+/// Receives the events of an engine as transactions are applied.
+///
+/// Any `Fn(&EngineEvent)` closure is an observer.
+pub trait EngineObserver: Send + Sync {
+    fn on_event(&self, event: &EngineEvent);
+}
+
+impl<F: Fn(&EngineEvent) + Send + Sync> EngineObserver for F {
+    fn on_event(&self, event: &EngineEvent) {
+        self(event)
+    }
+}
+
+// Shared by the clones of an engine.
+#[derive(Clone)]
+pub(crate) struct Observer(pub(crate) Arc<dyn EngineObserver>);
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
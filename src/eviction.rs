@@ -0,0 +1,335 @@
+//! Moving the idle accounts of a long-running engine out of memory, see
+//! [`TransactionEngine::set_eviction`](crate::TransactionEngine::set_eviction).
+
+use std::fmt;
+#[cfg(feature = "json-io")]
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(feature = "json-io")]
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+#[cfg(feature = "json-io")]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::collections::FastHashMap;
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Client, TxId};
+
+/// How long an account without funds must go without transactions before it is evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictAfter {
+    /// Transactions applied by the engine since the last one touching the account, rejected ones included.
+    Sequences(u64),
+    /// Time since the last transaction touching the account, as told by the clock of the engine.
+    Duration(Duration),
+}
+
+/// An evicted account, with the tx ids of its stored transactions so that it is restored as it was.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ArchivedClient {
+    pub client: Client,
+    pub history: Vec<TxId>,
+}
+
+/// Where the evicted accounts are written, and read back from when a transaction names them again.
+pub trait ClientArchive: Send {
+    fn archive(&mut self, account: &ArchivedClient) -> io::Result<()>;
+
+    /// Removes the account of `client_id` in `currency` from the archive and returns it, if it is there.
+    fn restore(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<ArchivedClient>>;
+}
+
+/// An archive made of two callbacks, e.g. to keep the evicted accounts in a database.
+pub struct CallbackArchive<A, R> {
+    archive: A,
+    restore: R,
+}
+
+impl<A, R> CallbackArchive<A, R>
+where
+    A: FnMut(&ArchivedClient) -> io::Result<()> + Send,
+    R: FnMut(u16, Currency) -> io::Result<Option<ArchivedClient>> + Send,
+{
+    pub fn new(archive: A, restore: R) -> CallbackArchive<A, R> {
+        CallbackArchive { archive, restore }
+    }
+}
+
+impl<A, R> ClientArchive for CallbackArchive<A, R>
+where
+    A: FnMut(&ArchivedClient) -> io::Result<()> + Send,
+    R: FnMut(u16, Currency) -> io::Result<Option<ArchivedClient>> + Send,
+{
+    fn archive(&mut self, account: &ArchivedClient) -> io::Result<()> {
+        (self.archive)(account)
+    }
+
+    fn restore(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<ArchivedClient>> {
+        (self.restore)(client_id, currency)
+    }
+}
+
+/// An archive appending the evicted accounts to a file as JSON lines, with the offset of the line of each
+/// account still archived kept in memory. The lines of restored accounts stay in the file, which is truncated
+/// when the archive is created.
+#[cfg(feature = "json-io")]
+pub struct FileArchive {
+    file: File,
+    end: u64,
+    offsets: FastHashMap<(u16, Currency), u64>,
+}
+
+#[cfg(feature = "json-io")]
+impl FileArchive {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<FileArchive> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        Ok(FileArchive { file, end: 0, offsets: FastHashMap::default() })
+    }
+
+    /// Number of accounts archived and not restored.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+}
+
+#[cfg(feature = "json-io")]
+impl ClientArchive for FileArchive {
+    fn archive(&mut self, account: &ArchivedClient) -> io::Result<()> {
+        let mut line = serde_json::to_vec(account)?;
+        line.push(b'\n');
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&line)?;
+        let key = (account.client.id(), account.client.currency());
+        self.offsets.insert(key, self.end);
+        self.end += line.len() as u64;
+        Ok(())
+    }
+
+    fn restore(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<ArchivedClient>> {
+        let Some(&offset) = self.offsets.get(&(client_id, currency)) else {
+            return Ok(None)
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        BufReader::new(&self.file).read_line(&mut line)?;
+        let account = serde_json::from_str(&line)?;
+        self.offsets.remove(&(client_id, currency));
+        Ok(Some(account))
+    }
+}
+
+// The policy and archive of an engine, with the last activity of its accounts. Shared by the clones of the
+// engine like its observer, the activity being theirs.
+#[derive(Clone)]
+pub(crate) struct Eviction {
+    pub(crate) after: EvictAfter,
+    pub(crate) archive: Arc<Mutex<dyn ClientArchive>>,
+    // Transactions applied since the eviction was set.
+    pub(crate) sequence: u64,
+    // Sequence number and time of the last transaction of each account, those without one counting as active
+    // when the eviction was set.
+    pub(crate) activity: FastHashMap<(u16, Currency), (u64, Timestamp)>,
+    pub(crate) started: Timestamp,
+}
+
+impl Eviction {
+    pub(crate) fn new(after: EvictAfter, archive: Arc<Mutex<dyn ClientArchive>>, now: Timestamp) -> Eviction {
+        Eviction { after, archive, sequence: 0, activity: FastHashMap::default(), started: now }
+    }
+
+    pub(crate) fn touch(&mut self, key: (u16, Currency), now: Timestamp) {
+        self.activity.insert(key, (self.sequence, now));
+    }
+
+    pub(crate) fn is_idle(&self, key: &(u16, Currency), now: Timestamp) -> bool {
+        let (sequence, time) = self.activity.get(key).copied().unwrap_or((0, self.started));
+        match self.after {
+            EvictAfter::Sequences(sequences) => self.sequence - sequence >= sequences,
+            EvictAfter::Duration(duration) => now.duration_since(time) >= duration,
+        }
+    }
+}
+
+impl fmt::Debug for Eviction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Eviction").field("after", &self.after).field("sequence", &self.sequence).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::clock::ManualClock;
+    #[cfg(feature = "json-io")]
+    use crate::transaction_engine::TxStatus;
+    use crate::transaction_engine::{Transaction, TransactionEngine};
+
+    fn deposit(client_id: u16, tx_id: TxId, units: i64) -> Transaction {
+        Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(units) }
+    }
+
+    fn withdrawal(client_id: u16, tx_id: TxId, units: i64) -> Transaction {
+        Transaction::Withdrawal { client_id, tx_id, amount: Amount::from_units(units) }
+    }
+
+    // An engine evicting after 2 transactions into a file archive, with client 1 emptied by tx 2 and client 2
+    // keeping its funds.
+    #[cfg(feature = "json-io")]
+    fn engine(dir: &tempfile::TempDir) -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        engine.set_eviction(EvictAfter::Sequences(2), FileArchive::create(dir.path().join("archive.jsonl")).unwrap());
+        for transaction in [deposit(1, 1, 10_000), withdrawal(1, 2, 10_000), deposit(2, 3, 10_000), deposit(2, 4, 10_000)] {
+            engine.compute_transaction(transaction).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn should_evict_the_idle_accounts_without_funds() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = engine(&dir);
+
+        assert_eq!(engine.evict_idle(), 1);
+
+        assert_eq!(engine.get_client(1), None);
+        assert_eq!(engine.get_client(2).unwrap().total(), Amount::from_units(20_000));
+        assert_eq!(engine.metrics().evicted_clients, 1);
+        assert_eq!(engine.metrics().clients, 1);
+        assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+    }
+
+    #[test]
+    fn should_keep_the_accounts_active_held_or_locked() {
+        let mut engine = TransactionEngine::new();
+        engine.set_eviction(EvictAfter::Sequences(3), CallbackArchive::new(|_: &ArchivedClient| Ok(()), |_, _| Ok(None)));
+        for transaction in [
+            deposit(1, 1, 10_000), Transaction::Dispute { client_id: 1, tx_id: 1 },
+            deposit(2, 2, 10_000), Transaction::Dispute { client_id: 2, tx_id: 2 },
+            Transaction::Chargeback { client_id: 2, tx_id: 2 },
+            deposit(3, 3, 10_000), withdrawal(3, 4, 10_000),
+        ] {
+            engine.compute_transaction(transaction).unwrap();
+        }
+
+        assert_eq!(engine.evict_idle(), 0);
+        assert_eq!(engine.client_count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn should_restore_an_evicted_account_on_a_new_deposit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = engine(&dir);
+        engine.evict_idle();
+
+        engine.compute_transaction(deposit(1, 5, 30_000)).unwrap();
+
+        assert_eq!(engine.get_client(1).unwrap().available(), Amount::from_units(30_000));
+        let history: Vec<TxId> = engine.client_transactions(1).map(|transaction| transaction.tx_id()).collect();
+        assert_eq!(history, vec![1, 2, 5]);
+        assert_eq!(engine.metrics().restored_clients, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn should_restore_an_evicted_account_on_the_dispute_of_its_deposit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = engine(&dir);
+        engine.evict_idle();
+
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+
+        let client = engine.get_client(1).unwrap();
+        assert_eq!((client.available(), client.held(), client.total()), (Amount::from_units(-10_000), Amount::ZERO, Amount::from_units(-10_000)));
+        assert!(client.is_locked());
+        assert_eq!(engine.metrics().restored_clients, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn should_restore_the_recipient_of_a_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = engine(&dir);
+        engine.evict_idle();
+
+        engine.compute_transaction(Transaction::Transfer { from: 2, to: 1, tx_id: 5, amount: Amount::from_units(5_000) }).unwrap();
+
+        assert_eq!(engine.client_transactions(1).count(), 2);
+        assert_eq!(engine.get_client(1).unwrap().total(), Amount::from_units(5_000));
+    }
+
+    #[test]
+    fn should_evict_after_a_duration() {
+        let mut engine = TransactionEngine::new();
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&archived);
+        engine.set_eviction(
+            EvictAfter::Duration(Duration::ZERO),
+            CallbackArchive::new(
+                move |account: &ArchivedClient| {
+                    sink.lock().unwrap().push(account.clone());
+                    Ok(())
+                },
+                |_, _| Ok(None),
+            ),
+        );
+        engine.compute_transaction(withdrawal(7, 1, 10_000)).unwrap_err();
+
+        assert_eq!(engine.evict_idle(), 1);
+        assert_eq!(archived.lock().unwrap()[0].client, Client::new(7, Amount::ZERO, Amount::ZERO, Amount::ZERO, false));
+    }
+
+    #[test]
+    fn should_evict_once_the_clock_reaches_the_duration() {
+        let clock = ManualClock::new(Timestamp::from_millis(1_000));
+        let mut engine = TransactionEngine::new();
+        engine.set_clock(clock.clone());
+        engine.set_eviction(EvictAfter::Duration(Duration::from_secs(60)), CallbackArchive::new(|_: &ArchivedClient| Ok(()), |_, _| Ok(None)));
+        engine.compute_transaction(withdrawal(7, 1, 10_000)).unwrap_err();
+
+        clock.advance(Duration::from_millis(59_999));
+        assert_eq!(engine.evict_idle(), 0);
+        // A new transaction of the client starts the duration over.
+        engine.compute_transaction(withdrawal(7, 2, 10_000)).unwrap_err();
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(engine.evict_idle(), 0);
+        clock.advance(Duration::from_millis(59_999));
+        assert_eq!(engine.evict_idle(), 1);
+        assert_eq!(engine.client_count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn file_archive_should_give_back_what_it_archived_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = FileArchive::create(dir.path().join("archive.jsonl")).unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let account = |id| ArchivedClient {
+            client: Client::new(id, Amount::ZERO, Amount::ZERO, Amount::ZERO, false).in_currency(eur),
+            history: vec![u64::from(id), 10],
+        };
+        archive.archive(&account(1)).unwrap();
+        archive.archive(&account(2)).unwrap();
+
+        assert_eq!(archive.restore(1, eur).unwrap(), Some(account(1)));
+        assert_eq!(archive.restore(1, eur).unwrap(), None);
+        assert_eq!(archive.restore(2, Currency::default()).unwrap(), None);
+        archive.archive(&account(1)).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.restore(2, eur).unwrap(), Some(account(2)));
+        assert_eq!(archive.restore(1, eur).unwrap(), Some(account(1)));
+    }
+}
@@ -0,0 +1,100 @@
+//! A tiny registry of failure points, compiled with the `failpoints` feature. Code calls [`hit`] at a named point
+//! and fails there, the way it chooses, once a test armed the point with [`arm`]. The registry is shared by the
+//! threads of the process, so tests running side by side should arm points of their own names.
+
+use std::sync::Mutex;
+
+// The armed points, with the hits each lets through before triggering.
+static ARMED: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+
+/// Makes the point `name` trigger once, after letting `skip` hits through. Arming an armed point starts over.
+pub fn arm(name: &str, skip: u64) {
+    let mut armed = ARMED.lock().unwrap();
+    armed.retain(|(armed, _)| armed != name);
+    armed.push((name.to_string(), skip));
+}
+
+pub fn disarm(name: &str) {
+    ARMED.lock().unwrap().retain(|(armed, _)| armed != name);
+}
+
+/// Whether the point `name` triggers now, which disarms it.
+pub fn hit(name: &str) -> bool {
+    let mut armed = ARMED.lock().unwrap();
+    let Some(index) = armed.iter().position(|(armed, _)| armed == name) else {
+        return false
+    };
+    match &mut armed[index].1 {
+        0 => {
+            armed.remove(index);
+            true
+        },
+        skip => {
+            *skip -= 1;
+            false
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::amount::Amount;
+    use crate::eviction::{ArchivedClient, CallbackArchive, EvictAfter};
+    use crate::transaction_engine::{Transaction, TransactionEngine};
+
+    #[test]
+    fn a_point_should_trigger_once_after_the_skipped_hits() {
+        arm("registry-skip", 2);
+
+        let hits: Vec<bool> = (0..5).map(|_| hit("registry-skip")).collect();
+
+        assert_eq!(hits, vec![false, false, true, false, false]);
+        assert!(!hit("registry-unknown"));
+    }
+
+    #[test]
+    fn a_disarmed_or_rearmed_point_should_start_over() {
+        arm("registry-rearm", 0);
+        disarm("registry-rearm");
+        assert!(!hit("registry-rearm"));
+
+        arm("registry-rearm", 5);
+        arm("registry-rearm", 1);
+        assert_eq!([hit("registry-rearm"), hit("registry-rearm")], [false, true]);
+    }
+
+    #[test]
+    fn an_account_the_archive_fails_to_take_should_be_evicted_on_the_next_run() {
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&archived);
+        let mut engine = TransactionEngine::new();
+        engine.set_eviction(
+            EvictAfter::Sequences(1),
+            CallbackArchive::new(
+                move |account: &ArchivedClient| {
+                    if hit("eviction-archive") {
+                        return Err(io::Error::other("injected failure"))
+                    }
+                    sink.lock().unwrap().push(account.client.id());
+                    Ok(())
+                },
+                |_, _| Ok(None),
+            ),
+        );
+        let amount = Amount::from_units(10_000);
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount }).unwrap();
+        engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 3, amount }).unwrap();
+        arm("eviction-archive", 0);
+
+        assert_eq!(engine.evict_idle(), 0);
+        assert_eq!(engine.get_client(1).map(|client| client.total()), Some(Amount::ZERO));
+        assert_eq!(engine.evict_idle(), 1);
+        assert_eq!(*archived.lock().unwrap(), vec![1]);
+        assert_eq!(engine.get_client(1), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! The hidden `--inject-failure` option of the `failpoints` feature: the run aborts at a point of the
+//! [`failpoint`] registry, as if killed, leaving the files it wrote so far as they are.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use transaction_engine::failpoint;
+
+/// Where `process` aborts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedFailure {
+    /// `after-rows=N`: before applying row N + 1, the rows being counted across the inputs.
+    AfterRows(u64),
+    /// `during-output`: halfway through writing the balances.
+    DuringOutput,
+    /// `before-flush`: once the balances are written, before they are flushed and their file is committed.
+    BeforeFlush,
+}
+
+impl InjectedFailure {
+    pub fn arm(self) {
+        match self {
+            InjectedFailure::AfterRows(rows) => failpoint::arm("after-rows", rows),
+            InjectedFailure::DuringOutput => failpoint::arm("during-output", 0),
+            InjectedFailure::BeforeFlush => failpoint::arm("before-flush", 0),
+        }
+    }
+}
+
+impl FromStr for InjectedFailure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("after-rows", rows)) => rows.parse().map(InjectedFailure::AfterRows)
+                .map_err(|_| format!("invalid row count {rows:?}")),
+            None if s == "during-output" => Ok(InjectedFailure::DuringOutput),
+            None if s == "before-flush" => Ok(InjectedFailure::BeforeFlush),
+            _ => Err(format!("unknown failure point {s:?}, expected after-rows=N, during-output or before-flush")),
+        }
+    }
+}
+
+/// Aborts the process when `point` triggers.
+pub fn fail_point(point: &str) {
+    if failpoint::hit(point) {
+        abort(point)
+    }
+}
+
+/// When `during-output` triggers, writes the first half of the bytes of `output` to `writer` and aborts.
+pub fn fail_during_output<W: Write>(writer: &mut W, output: impl FnOnce() -> io::Result<Vec<u8>>) -> io::Result<()> {
+    if failpoint::hit("during-output") {
+        let output = output()?;
+        writer.write_all(&output[..output.len() / 2])?;
+        writer.flush()?;
+        abort("during-output")
+    }
+    Ok(())
+}
+
+/// Aborts the process, without running destructors or flushing buffers.
+fn abort(point: &str) -> ! {
+    eprintln!("Application error: failure injected at {point}");
+    std::process::abort()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_the_failure_points() {
+        assert_eq!("after-rows=12".parse(), Ok(InjectedFailure::AfterRows(12)));
+        assert_eq!("during-output".parse(), Ok(InjectedFailure::DuringOutput));
+        assert_eq!("before-flush".parse(), Ok(InjectedFailure::BeforeFlush));
+        assert_eq!("after-rows=x".parse::<InjectedFailure>(), Err("invalid row count \"x\"".to_string()));
+        assert!("during-input".parse::<InjectedFailure>().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! The `generate` command: a synthetic transaction file, the same for the same arguments.
+//!
+//! Every row of the file is applied by an engine with the default settings: withdrawals never exceed the
+//! available funds, disputes reference an earlier deposit of the same client whose funds are still available,
+//! chargebacks reference an open dispute, and locked clients receive no further transactions.
+
+use std::io::{self, BufWriter, Write};
+
+use transaction_engine::Amount;
+
+use crate::atomic_file::AtomicFile;
+use crate::cli::GenerateArgs;
+
+/// Largest amount of a deposit, in units of 0.0001.
+const MAX_DEPOSIT_UNITS: i64 = 1_000_000;
+
+/// Share of the deposits and withdrawals being withdrawals.
+const WITHDRAWAL_RATE: f64 = 0.3;
+
+/// Writes the file to `--out` or stdout. Returns the exit code.
+pub fn run(args: GenerateArgs) -> i32 {
+    let result = match &args.out {
+        Some(out) => AtomicFile::create(out)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot create {out}: {e}")))
+            .and_then(|file| write(&args, BufWriter::new(file))?.into_inner().map_err(io::IntoInnerError::into_error))
+            .and_then(AtomicFile::commit),
+        None => write(&args, BufWriter::new(io::stdout().lock())).map(drop),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            1
+        },
+    }
+}
+
+fn write<W: Write>(args: &GenerateArgs, mut output: W) -> io::Result<W> {
+    writeln!(output, "type,client,tx,amount")?;
+    let mut generator = Generator::new(args);
+    for _ in 0..args.rows {
+        match generator.next_row() {
+            Row::Deposit { client_id, tx_id, amount } => writeln!(output, "deposit,{client_id},{tx_id},{amount}")?,
+            Row::Withdrawal { client_id, tx_id, amount } => writeln!(output, "withdrawal,{client_id},{tx_id},{amount}")?,
+            Row::Dispute { client_id, tx_id } => writeln!(output, "dispute,{client_id},{tx_id},")?,
+            Row::Chargeback { client_id, tx_id } => writeln!(output, "chargeback,{client_id},{tx_id},")?,
+        }
+    }
+    output.flush()?;
+    Ok(output)
+}
+
+#[derive(Debug, PartialEq)]
+enum Row {
+    Deposit { client_id: u16, tx_id: u32, amount: Amount },
+    Withdrawal { client_id: u16, tx_id: u32, amount: Amount },
+    Dispute { client_id: u16, tx_id: u32 },
+    Chargeback { client_id: u16, tx_id: u32 },
+}
+
+// SplitMix64, written out so that a seed keeps giving the same file whatever the dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+struct Generator {
+    rng: Rng,
+    dispute_rate: f64,
+    chargeback_rate: f64,
+    next_tx_id: u32,
+    // Available funds of each client, in units, by client id - 1.
+    available: Vec<i64>,
+    locked: Vec<bool>,
+    // Client ids not locked by a chargeback.
+    active: Vec<u16>,
+    // Deposits that may be disputed, and the open disputes, as (client id, tx id, units).
+    deposits: Vec<(u16, u32, i64)>,
+    disputes: Vec<(u16, u32, i64)>,
+}
+
+impl Generator {
+    fn new(args: &GenerateArgs) -> Generator {
+        Generator {
+            rng: Rng(args.seed),
+            dispute_rate: args.dispute_rate,
+            chargeback_rate: args.chargeback_rate,
+            next_tx_id: 1,
+            available: vec![0; usize::from(args.clients)],
+            locked: vec![false; usize::from(args.clients)],
+            active: (1..=args.clients).collect(),
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    fn next_row(&mut self) -> Row {
+        let draw = self.rng.unit();
+        if draw < self.chargeback_rate {
+            if let Some(row) = self.chargeback() {
+                return row
+            }
+        } else if draw < self.chargeback_rate + self.dispute_rate {
+            if let Some(row) = self.dispute() {
+                return row
+            }
+        }
+        self.deposit_or_withdrawal()
+    }
+
+    // Charges back an open dispute, unless it would lock the last active client.
+    fn chargeback(&mut self) -> Option<Row> {
+        if self.disputes.is_empty() || self.active.len() < 2 {
+            return None
+        }
+        let (client_id, tx_id, _) = self.disputes.swap_remove(self.rng.below(self.disputes.len()));
+        if self.locked[usize::from(client_id) - 1] {
+            return None
+        }
+        self.locked[usize::from(client_id) - 1] = true;
+        self.active.retain(|id| *id != client_id);
+        Some(Row::Chargeback { client_id, tx_id })
+    }
+
+    // Disputes a deposit whose funds are still available. Deposits of locked clients are dropped on the way.
+    fn dispute(&mut self) -> Option<Row> {
+        if self.deposits.is_empty() {
+            return None
+        }
+        let index = self.rng.below(self.deposits.len());
+        let (client_id, tx_id, units) = self.deposits[index];
+        let client = usize::from(client_id) - 1;
+        if self.locked[client] {
+            self.deposits.swap_remove(index);
+            return None
+        }
+        if self.available[client] < units {
+            return None
+        }
+        self.deposits.swap_remove(index);
+        self.available[client] -= units;
+        self.disputes.push((client_id, tx_id, units));
+        Some(Row::Dispute { client_id, tx_id })
+    }
+
+    fn deposit_or_withdrawal(&mut self) -> Row {
+        let client_id = self.active[self.rng.below(self.active.len())];
+        let client = usize::from(client_id) - 1;
+        let tx_id = self.next_tx_id;
+        self.next_tx_id += 1;
+
+        let available = self.available[client];
+        if available > 0 && self.rng.unit() < WITHDRAWAL_RATE {
+            let units = self.rng.below(available.min(MAX_DEPOSIT_UNITS) as usize) as i64 + 1;
+            self.available[client] -= units;
+            return Row::Withdrawal { client_id, tx_id, amount: Amount::from_units(units) }
+        }
+        let units = self.rng.below(MAX_DEPOSIT_UNITS as usize) as i64 + 1;
+        self.available[client] += units;
+        self.deposits.push((client_id, tx_id, units));
+        Row::Deposit { client_id, tx_id, amount: Amount::from_units(units) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(rows: u32, clients: u16, dispute_rate: f64, chargeback_rate: f64, seed: u64) -> GenerateArgs {
+        GenerateArgs { rows, clients, dispute_rate, chargeback_rate, seed, out: None }
+    }
+
+    fn written(args: &GenerateArgs) -> String {
+        String::from_utf8(write(args, Vec::new()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn same_seed_should_write_the_same_rows() {
+        let args = args(1000, 10, 0.1, 0.02, 42);
+        assert_eq!(written(&args), written(&args));
+        assert_ne!(written(&args), written(&GenerateArgs { seed: 43, ..args }));
+    }
+
+    #[test]
+    fn disputes_should_reference_earlier_deposits_of_the_same_client() {
+        let mut generator = Generator::new(&args(10_000, 5, 0.2, 0.05, 7));
+        let mut deposits = std::collections::HashMap::new();
+        let mut disputed = std::collections::HashSet::new();
+        let (mut dispute_count, mut chargeback_count) = (0, 0);
+        for _ in 0..10_000 {
+            match generator.next_row() {
+                Row::Deposit { client_id, tx_id, .. } => assert!(deposits.insert(tx_id, client_id).is_none()),
+                Row::Withdrawal { .. } => {},
+                Row::Dispute { client_id, tx_id } => {
+                    assert_eq!(deposits.get(&tx_id), Some(&client_id));
+                    assert!(disputed.insert(tx_id));
+                    dispute_count += 1;
+                },
+                Row::Chargeback { client_id, tx_id } => {
+                    assert_eq!(deposits.get(&tx_id), Some(&client_id));
+                    assert!(disputed.remove(&tx_id));
+                    chargeback_count += 1;
+                },
+            }
+        }
+        assert!(dispute_count > 0 && chargeback_count > 0);
+        // The last active client is never locked.
+        assert_eq!(generator.active.len(), 1);
+    }
+}
@@ -0,0 +1,179 @@
+//! gRPC service of an engine, generated from `proto/engine.proto` with the `grpc` feature.
+//!
+//! [`EngineService`] serves a [`ConcurrentTransactionEngine`], its transactions having the fields of a CSV row
+//! and the rejections the codes of [`TransactionError::code`] and [`RowError::code`].
+
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::amount::Amount;
+use crate::concurrent::ConcurrentTransactionEngine;
+use crate::csv_model::{self, RowError, TransactionRecord, TransactionType};
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Client, TransactionError};
+
+/// The messages and the client and server generated from the proto.
+pub mod proto {
+    tonic::include_proto!("transaction_engine");
+}
+
+use proto::engine_server::{Engine, EngineServer};
+
+/// Serves the engine to the `Engine` service of the proto.
+///
+/// ```no_run
+/// # async fn serve() -> Result<(), tonic::transport::Error> {
+/// use std::sync::Arc;
+/// use transaction_engine::grpc::EngineService;
+/// use transaction_engine::ConcurrentTransactionEngine;
+///
+/// let service = EngineService::new(Arc::new(ConcurrentTransactionEngine::default()));
+/// tonic::transport::Server::builder()
+///     .add_service(service.into_server())
+///     .serve("127.0.0.1:50051".parse().unwrap())
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EngineService {
+    engine: Arc<ConcurrentTransactionEngine>,
+}
+
+impl EngineService {
+    pub fn new(engine: Arc<ConcurrentTransactionEngine>) -> EngineService {
+        EngineService { engine }
+    }
+
+    /// The service to add to a [`tonic::transport::Server`].
+    pub fn into_server(self) -> EngineServer<EngineService> {
+        EngineServer::new(self)
+    }
+
+    fn submit(&self, transaction: proto::Transaction) -> proto::Outcome {
+        let rejection = match record(transaction).and_then(|record| Ok((record.try_into()?, record.ts, record.currency))) {
+            Ok((transaction, ts, currency)) => {
+                let currency = currency.unwrap_or_else(|| self.base_currency());
+                self.engine.submit_in(transaction, currency, ts).err().map(proto::Rejection::from)
+            },
+            Err(e) => Some(e.into()),
+        };
+        proto::Outcome { rejection }
+    }
+
+    fn base_currency(&self) -> Currency {
+        self.engine.read(|engine| engine.config().base_currency)
+    }
+}
+
+#[tonic::async_trait]
+impl Engine for EngineService {
+    async fn submit_transaction(&self, request: Request<proto::Transaction>) -> Result<Response<proto::Outcome>, Status> {
+        Ok(Response::new(self.submit(request.into_inner())))
+    }
+
+    async fn submit_batch(&self, request: Request<Streaming<proto::Transaction>>) -> Result<Response<proto::BatchOutcome>, Status> {
+        let mut transactions = request.into_inner();
+        let mut batch = proto::BatchOutcome::default();
+        while let Some(transaction) = transactions.message().await? {
+            let outcome = self.submit(transaction);
+            match outcome.rejection {
+                None => batch.accepted += 1,
+                Some(_) => batch.rejected += 1,
+            }
+            batch.outcomes.push(outcome);
+        }
+        Ok(Response::new(batch))
+    }
+
+    async fn get_client(&self, request: Request<proto::GetClientRequest>) -> Result<Response<proto::Client>, Status> {
+        let request = request.into_inner();
+        let id = client_id(request.client).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let currency = currency(request.currency).map_err(|e| Status::invalid_argument(e.to_string()))?
+            .unwrap_or_else(|| self.base_currency());
+        match self.engine.read(|engine| engine.get_account(id, currency).copied()) {
+            Some(client) => Ok(Response::new((&client).into())),
+            None => Err(Status::not_found(TransactionError::UnknownClient { client_id: id }.to_string())),
+        }
+    }
+
+    async fn list_clients(&self, _: Request<proto::ListClientsRequest>) -> Result<Response<proto::ListClientsResponse>, Status> {
+        let mut clients = self.engine.get_client_list();
+        clients.sort_unstable_by_key(Client::id);
+        Ok(Response::new(proto::ListClientsResponse { clients: clients.iter().map(proto::Client::from).collect() }))
+    }
+}
+
+// Read as the matching CSV row: an empty amount is no amount and the ids out of range fail.
+fn record(transaction: proto::Transaction) -> Result<TransactionRecord, RowError> {
+    let transaction_type = match proto::TransactionType::try_from(transaction.r#type) {
+        Ok(proto::TransactionType::Deposit) => TransactionType::Deposit,
+        Ok(proto::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+        Ok(proto::TransactionType::Dispute) => TransactionType::Dispute,
+        Ok(proto::TransactionType::Resolve) => TransactionType::Resolve,
+        Ok(proto::TransactionType::Chargeback) => TransactionType::Chargeback,
+        Ok(proto::TransactionType::Unlock) => TransactionType::Unlock,
+        Ok(proto::TransactionType::Transfer) => TransactionType::Transfer,
+        Ok(proto::TransactionType::Adjustment) => TransactionType::Adjustment,
+        Ok(proto::TransactionType::Fee) => TransactionType::Fee,
+        Ok(proto::TransactionType::Unspecified) | Err(_) => {
+            return Err(RowError::Parse(format!("unknown transaction type {}", transaction.r#type)))
+        },
+    };
+    Ok(TransactionRecord {
+        transaction_type,
+        client: client_id(transaction.client)?,
+        tx: transaction.tx,
+        amount: non_empty(transaction.amount)
+            .map(|amount| amount.parse::<Amount>())
+            .transpose()
+            .map_err(|e| RowError::Parse(e.to_string()))?,
+        to_client: transaction.to_client.map(client_id).transpose()?,
+        ts: non_empty(transaction.ts)
+            .map(|ts| ts.parse::<Timestamp>())
+            .transpose()
+            .map_err(|e| RowError::Parse(e.to_string()))?,
+        currency: currency(transaction.currency)?,
+    })
+}
+
+fn currency(currency: Option<String>) -> Result<Option<Currency>, RowError> {
+    non_empty(currency)
+        .map(|currency| currency.trim().parse::<Currency>())
+        .transpose()
+        .map_err(|e| RowError::Parse(e.to_string()))
+}
+
+fn non_empty(field: Option<String>) -> Option<String> {
+    field.filter(|field| !field.trim().is_empty())
+}
+
+fn client_id(id: u32) -> Result<u16, RowError> {
+    csv_model::client_id(u64::from(id))
+}
+
+impl From<RowError> for proto::Rejection {
+    fn from(e: RowError) -> proto::Rejection {
+        proto::Rejection { code: e.code().to_string(), message: e.to_string() }
+    }
+}
+
+impl From<TransactionError> for proto::Rejection {
+    fn from(e: TransactionError) -> proto::Rejection {
+        proto::Rejection { code: e.code().to_string(), message: e.to_string() }
+    }
+}
+
+impl From<&Client> for proto::Client {
+    fn from(client: &Client) -> proto::Client {
+        proto::Client {
+            client: client.id().into(),
+            available: client.available().to_string(),
+            held: client.held().to_string(),
+            total: client.total().to_string(),
+            locked: client.is_locked(),
+            currency: client.currency().to_string(),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use clap::ValueEnum;
+use csv::StringRecord;
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use serde_json::Value;
+use transaction_engine::csv_model::COLUMNS;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wraps `reader` in a gzip or zstd decoder when its first bytes are the magic number of
+/// one of these formats, so compressed files and streams are read transparently.
+pub fn decompress<'a>(reader: Box<dyn Read + Send + 'a>) -> io::Result<Box<dyn Read + Send + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Reads `file` through a memory map, sparing a read syscall per buffer. Falls back to reading the file
+/// when it is empty, as an empty map cannot be created on every platform, or when it cannot be mapped.
+pub fn map(file: File) -> Box<dyn Read + Send> {
+    if file.metadata().map_or(true, |metadata| metadata.len() == 0) {
+        return Box::new(file)
+    }
+    // SAFETY: the map is only read. A file truncated or modified by another process while mapped
+    // gives wrong rows or a SIGBUS, the same risk as any tool mapping its input.
+    match unsafe { Mmap::map(&file) } {
+        Ok(map) => Box::new(Cursor::new(map)),
+        Err(_) => Box::new(file),
+    }
+}
+
+/// `path` without its `.gz` or `.zst` extension, giving access to the extension of the content.
+fn uncompressed_path(path: &str) -> &Path {
+    let stripped = path.strip_suffix(".gz").or_else(|| path.strip_suffix(".zst")).unwrap_or(path);
+    Path::new(stripped)
+}
+
+/// Whether the extension of `path` marks it as compressed.
+pub fn is_compressed(path: &str) -> bool {
+    uncompressed_path(path) != Path::new(path)
+}
+
+/// Error reading an input. A `Record` error only affects the faulty row, while the input
+/// cannot be read any further after an `Io` error (e.g. a corrupt compressed stream).
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadError {
+    Io(String),
+    Record(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) | ReadError::Record(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Format of an input file.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    /// One JSON transaction object per line
+    Ndjson,
+    /// Arrow IPC file or stream
+    #[cfg(feature = "arrow")]
+    Arrow,
+}
+
+impl InputFormat {
+    /// Format given by the extension of `path`: NDJSON for `.ndjson` and `.jsonl`, Arrow for `.arrow`,
+    /// `.arrows` and `.feather` with the `arrow` feature, CSV otherwise. A `.gz` or `.zst` extension is skipped.
+    pub fn from_path(path: &str) -> InputFormat {
+        match uncompressed_path(path).extension().and_then(|extension| extension.to_str()) {
+            Some("ndjson") | Some("jsonl") => InputFormat::Ndjson,
+            #[cfg(feature = "arrow")]
+            Some("arrow") | Some("arrows") | Some("feather") => InputFormat::Arrow,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// Delimiter of a CSV input when none is given: tab for `.tsv` files, comma otherwise.
+pub fn default_delimiter(path: &str) -> u8 {
+    match uncompressed_path(path).extension().and_then(|extension| extension.to_str()) {
+        Some("tsv") => b'\t',
+        _ => b',',
+    }
+}
+
+/// Source of records, whatever the format of the input, so that every format goes
+/// through the same CSV record mapping.
+pub enum Input<R: Read> {
+    Csv(csv::Reader<R>),
+    Ndjson(NdjsonReader<R>),
+}
+
+impl<R: Read> Input<R> {
+    /// `delimiter` only applies to CSV inputs.
+    pub fn new(reader: R, format: InputFormat, delimiter: u8) -> Input<R> {
+        match format {
+            InputFormat::Csv => Input::Csv(csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .trim(csv::Trim::All)
+                // Dispute rows may leave out the amount column.
+                .flexible(true)
+                .from_reader(reader)),
+            InputFormat::Ndjson => Input::Ndjson(NdjsonReader::new(reader)),
+            #[cfg(feature = "arrow")]
+            InputFormat::Arrow => unreachable!("Arrow inputs are read in record batches, not records"),
+        }
+    }
+
+    pub fn headers(&mut self) -> Result<StringRecord, String> {
+        match self {
+            Input::Csv(rdr) => rdr.headers().cloned().map_err(|e| e.to_string()),
+            Input::Ndjson(_) => Ok(StringRecord::from(COLUMNS.to_vec())),
+        }
+    }
+
+    /// Reads the next record into `record`, returning `false` at the end of the input.
+    /// On error `record` holds what could be read of the faulty row.
+    pub fn read_record(&mut self, record: &mut StringRecord) -> Result<bool, ReadError> {
+        match self {
+            Input::Csv(rdr) => rdr.read_record(record).map_err(|e| match e.is_io_error() {
+                true => ReadError::Io(e.to_string()),
+                false => ReadError::Record(e.to_string()),
+            }),
+            Input::Ndjson(rdr) => rdr.read_record(record),
+        }
+    }
+}
+
+pub struct NdjsonReader<R> {
+    lines: io::Lines<BufReader<R>>,
+    line: u64,
+}
+
+impl<R: Read> NdjsonReader<R> {
+    pub fn new(reader: R) -> NdjsonReader<R> {
+        NdjsonReader { lines: BufReader::new(reader).lines(), line: 0 }
+    }
+
+    pub fn read_record(&mut self, record: &mut StringRecord) -> Result<bool, ReadError> {
+        record.clear();
+
+        let text = loop {
+            self.line += 1;
+            match self.lines.next() {
+                None => return Ok(false),
+                // The invalid line has been consumed, the next ones can still be read.
+                Some(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                    return Err(ReadError::Record(format!("NDJSON error: line {}: {e}", self.line)))
+                },
+                Some(Err(e)) => return Err(ReadError::Io(format!("NDJSON error: line {}: {e}", self.line))),
+                Some(Ok(text)) if text.trim().is_empty() => continue,
+                Some(Ok(text)) => break text,
+            }
+        };
+
+        let mut position = csv::Position::new();
+        position.set_line(self.line);
+
+        let result = match fields(&text) {
+            Ok(fields) => {
+                fields.iter().for_each(|field| record.push_field(field));
+                Ok(true)
+            },
+            Err(e) => {
+                record.push_field(&text);
+                Err(ReadError::Record(format!("NDJSON error: line {}: {e}", self.line)))
+            },
+        };
+        record.set_position(Some(position));
+        result
+    }
+}
+
+// The fields of an object are read in the order of the CSV columns. Numbers are kept as written (serde_json is built with arbitrary_precision), so amounts
+// are parsed from their exact decimal representation.
+fn fields(text: &str) -> Result<Vec<String>, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| {
+        let message = e.to_string();
+        let position = format!(" at line {} column {}", e.line(), e.column());
+        format!("column {}: {}", e.column(), message.strip_suffix(&position).unwrap_or(&message))
+    })?;
+    object_fields(&value)
+}
+
+/// The fields of a JSON object in the order of [`COLUMNS`], as a line of an NDJSON input would be read.
+pub fn object_fields(value: &Value) -> Result<Vec<String>, String> {
+    let object = value.as_object().ok_or("expected a JSON object")?;
+
+    COLUMNS.iter()
+        .map(|name| match object.get(*name) {
+            None | Some(Value::Null) => Ok(String::new()),
+            Some(Value::String(s)) => Ok(s.trim().to_string()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            Some(_) => Err(format!("field {name} should be a string or a number")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &str) -> Vec<Result<StringRecord, ReadError>> {
+        let mut input = Input::new(data.as_bytes(), InputFormat::Ndjson, b',');
+        let mut results = vec![];
+        let mut record = StringRecord::new();
+        loop {
+            match input.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => results.push(Ok(record.clone())),
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn should_detect_format_from_extension() {
+        assert_eq!(InputFormat::from_path("day1.ndjson"), InputFormat::Ndjson);
+        assert_eq!(InputFormat::from_path("data/day1.jsonl"), InputFormat::Ndjson);
+        assert_eq!(InputFormat::from_path("day1.csv"), InputFormat::Csv);
+        assert_eq!(InputFormat::from_path("-"), InputFormat::Csv);
+        assert_eq!(InputFormat::from_path("day1.ndjson.gz"), InputFormat::Ndjson);
+        assert_eq!(InputFormat::from_path("day1.jsonl.zst"), InputFormat::Ndjson);
+        assert_eq!(InputFormat::from_path("day1.csv.gz"), InputFormat::Csv);
+    }
+
+    #[test]
+    fn should_default_to_tab_for_tsv_files() {
+        assert_eq!(default_delimiter("day1.tsv"), b'\t');
+        assert_eq!(default_delimiter("day1.csv"), b',');
+        assert_eq!(default_delimiter("-"), b',');
+        assert_eq!(default_delimiter("day1.tsv.gz"), b'\t');
+    }
+
+    fn decompressed(data: Vec<u8>) -> io::Result<String> {
+        let mut text = String::new();
+        decompress(Box::new(io::Cursor::new(data)))?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn should_decompress_gzip_and_zstd() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut gzip, data.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(data.as_bytes(), 0).unwrap();
+
+        assert_eq!(decompressed(gzip).unwrap(), data);
+        assert_eq!(decompressed(zstd).unwrap(), data);
+        assert_eq!(decompressed(data.as_bytes().to_vec()).unwrap(), data);
+        assert_eq!(decompressed(vec![]).unwrap(), "");
+    }
+
+    #[test]
+    fn when_compressed_stream_is_corrupt_should_fail() {
+        let mut data = zstd::encode_all("deposit,1,1,1.0\n".repeat(100).as_bytes(), 0).unwrap();
+        let len = data.len();
+        data[8..len - 4].iter_mut().for_each(|byte| *byte ^= 0x5a);
+
+        assert!(decompressed(data).is_err());
+    }
+
+    #[test]
+    fn csv_input_should_split_on_delimiter() {
+        let mut input = Input::new("type;client\ndeposit;1\n".as_bytes(), InputFormat::Csv, b';');
+        let mut record = StringRecord::new();
+
+        assert_eq!(input.headers(), Ok(StringRecord::from(vec!["type", "client"])));
+        assert_eq!(input.read_record(&mut record), Ok(true));
+        assert_eq!(record, StringRecord::from(vec!["deposit", "1"]));
+    }
+
+    #[test]
+    fn csv_input_should_trim_fields() {
+        let mut input = Input::new("type, client ,amount\n  deposit , 1,\t\n".as_bytes(), InputFormat::Csv, b',');
+        let mut record = StringRecord::new();
+
+        assert_eq!(input.headers(), Ok(StringRecord::from(vec!["type", "client", "amount"])));
+        assert_eq!(input.read_record(&mut record), Ok(true));
+        assert_eq!(record, StringRecord::from(vec!["deposit", "1", ""]));
+    }
+
+    #[test]
+    fn ndjson_lines_should_map_onto_csv_fields() {
+        let records = read_all(concat!(
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.2345}\n",
+            "\n",
+            "{\"type\":\"dispute\",\"client\":1,\"tx\":1}\n",
+            "{\"tx\":2,\"amount\":\" 3.0 \",\"client\":2,\"type\":\"withdrawal\",\"extra\":true}\n",
+        ));
+
+        assert_eq!(records, vec![
+            Ok(StringRecord::from(vec!["deposit", "1", "1", "1.2345"])),
+            Ok(StringRecord::from(vec!["dispute", "1", "1", ""])),
+            Ok(StringRecord::from(vec!["withdrawal", "2", "2", "3.0"])),
+        ]);
+    }
+
+    #[test]
+    fn when_line_is_malformed_should_report_line_and_continue() {
+        let records = read_all(concat!(
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.0}\n",
+            "{\"type\":\"deposit\",\n",
+            "[1, 2]\n",
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":[1]}\n",
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":2.0}\n",
+        ));
+
+        assert_eq!(records.len(), 5);
+        assert!(records[1].as_ref().unwrap_err().to_string().starts_with("NDJSON error: line 2: column"), "{:?}", records[1]);
+        assert_eq!(records[2], Err(ReadError::Record("NDJSON error: line 3: expected a JSON object".to_string())));
+        assert_eq!(records[3], Err(ReadError::Record("NDJSON error: line 4: field amount should be a string or a number".to_string())));
+        assert_eq!(records[4], Ok(StringRecord::from(vec!["deposit", "1", "3", "2.0"])));
+    }
+
+    #[test]
+    fn number_amounts_should_keep_their_precision() {
+        let records = read_all("{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":12345.6789}\n");
+
+        assert_eq!(records, vec![Ok(StringRecord::from(vec!["deposit", "1", "1", "12345.6789"]))]);
+    }
+
+    #[test]
+    fn mapped_file_should_read_its_content() {
+        let mut file = tempfile::tempfile().unwrap();
+        io::Write::write_all(&mut file, b"type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let mut content = String::new();
+        map(file).read_to_string(&mut content).unwrap();
+
+        assert_eq!(content, "type,client,tx,amount\ndeposit,1,1,1.0\n");
+    }
+
+    #[test]
+    fn mapped_empty_file_should_read_nothing() {
+        let file = tempfile::tempfile().unwrap();
+
+        let mut content = vec![];
+        map(file).read_to_end(&mut content).unwrap();
+
+        assert!(content.is_empty());
+    }
+}
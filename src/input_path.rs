@@ -0,0 +1,98 @@
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PathKind {
+    File,
+    Stdin,
+    Directory,
+    Empty,
+    UnexpandedGlob,
+}
+
+pub fn classify(path: &str) -> PathKind {
+    if path.trim().is_empty() {
+        return PathKind::Empty
+    }
+
+    if path == "-" {
+        return PathKind::Stdin
+    }
+
+    if Path::new(path).is_dir() {
+        return PathKind::Directory
+    }
+
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    if file_name.contains(['*', '?']) && !Path::new(path).exists() {
+        return PathKind::UnexpandedGlob
+    }
+
+    PathKind::File
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resources_dir() -> String {
+        env!("CARGO_MANIFEST_DIR").to_string() + "/resources/tests"
+    }
+
+    #[test]
+    fn when_path_is_empty_should_be_empty() {
+        assert_eq!(classify(""), PathKind::Empty);
+        assert_eq!(classify("   "), PathKind::Empty);
+    }
+
+    #[test]
+    fn when_path_is_dash_should_be_stdin() {
+        assert_eq!(classify("-"), PathKind::Stdin);
+        assert_eq!(classify("./-"), PathKind::File);
+    }
+
+    #[test]
+    fn when_path_is_a_directory_should_be_directory() {
+        assert_eq!(classify(&resources_dir()), PathKind::Directory);
+        assert_eq!(classify(&(resources_dir() + "/")), PathKind::Directory);
+        assert_eq!(classify("."), PathKind::Directory);
+    }
+
+    #[test]
+    fn when_path_is_a_file_should_be_file() {
+        assert_eq!(classify(&(resources_dir() + "/deposit.csv")), PathKind::File);
+    }
+
+    #[test]
+    fn when_path_does_not_exist_should_be_file() {
+        assert_eq!(classify("missing.csv"), PathKind::File);
+        assert_eq!(classify("data/missing.csv"), PathKind::File);
+    }
+
+    #[test]
+    fn when_path_ends_with_unexpanded_glob_should_be_glob() {
+        assert_eq!(classify("*"), PathKind::UnexpandedGlob);
+        assert_eq!(classify("data/*"), PathKind::UnexpandedGlob);
+        assert_eq!(classify("data/*.csv"), PathKind::UnexpandedGlob);
+        assert_eq!(classify("data/day?.csv"), PathKind::UnexpandedGlob);
+    }
+
+    #[test]
+    fn when_windows_path_ends_with_glob_should_be_glob() {
+        assert_eq!(classify("data\\*"), PathKind::UnexpandedGlob);
+        assert_eq!(classify("C:\\data\\*.csv"), PathKind::UnexpandedGlob);
+    }
+
+    #[test]
+    fn when_glob_is_only_in_a_parent_component_should_be_file() {
+        assert_eq!(classify("data/*/missing.csv"), PathKind::File);
+        assert_eq!(classify("C:\\data*\\missing.csv"), PathKind::File);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn when_windows_path_is_a_directory_should_be_directory() {
+        let path = resources_dir().replace('/', "\\");
+        assert_eq!(classify(&path), PathKind::Directory);
+        assert_eq!(classify(&(path + "\\")), PathKind::Directory);
+    }
+}
@@ -0,0 +1,83 @@
+//! Payment transaction engine.
+//!
+//! [`TransactionEngine`] applies deposits, withdrawals, disputes, resolves and
+//! chargebacks to client accounts and reports the resulting [`Client`] balances.
+//! The `csv_model` module maps rows of a `type,client,tx,amount` CSV file onto
+//! the engine's [`Transaction`].
+//!
+//! The engine and its types build without any optional dependency. The cargo features add to them:
+//!
+//! - `csv-io`: the `csv_model`, `process` and `type_alias` modules reading CSV inputs.
+//! - `json-io`: the JSON snapshots of the engine, the `FileArchive` of evicted clients and the memory limit of
+//!   `InMemoryStore::with_memory_limit`, spilling transactions to a file.
+//! - `serde`: `Serialize` and `Deserialize` impls of the engine types.
+//! - `std-collections`: the engine state in std hash maps rather than B-trees.
+//! - `cli`: the `transaction_engine` binary, reading and writing CSV and JSON.
+//!
+//! `cli` and `std-collections` are on by default. An embedded engine depends on the crate with
+//! `default-features = false` and the features it needs, `serde` alone building the engine and its types with
+//! their impls.
+
+pub mod amount;
+#[cfg(feature = "arrow")]
+pub mod arrow_model;
+pub mod batch;
+pub mod changes;
+pub mod clock;
+mod collections;
+pub mod concurrent;
+pub mod config;
+#[cfg(feature = "csv-io")]
+pub mod csv_model;
+pub mod currency;
+pub mod events;
+pub mod eviction;
+#[cfg(feature = "failpoints")]
+pub mod failpoint;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod memory;
+pub mod metrics;
+#[cfg(feature = "csv-io")]
+pub mod process;
+pub mod replay;
+pub mod retry;
+pub mod sentinel;
+pub mod shard;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod state_diff;
+pub mod store;
+pub mod timestamp;
+pub mod transaction_engine;
+#[cfg(feature = "csv-io")]
+pub mod type_alias;
+
+pub use amount::Amount;
+pub use batch::BatchSummary;
+pub use changes::Changes;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use concurrent::ConcurrentTransactionEngine;
+pub use config::EngineConfig;
+#[cfg(feature = "csv-io")]
+pub use csv_model::{TransactionRecord, TransactionType};
+pub use currency::Currency;
+pub use events::{EngineEvent, EngineObserver};
+pub use eviction::{ArchivedClient, CallbackArchive, ClientArchive, EvictAfter};
+#[cfg(feature = "json-io")]
+pub use eviction::FileArchive;
+pub use memory::MemoryEstimate;
+pub use metrics::{DisputeCounters, Metrics};
+pub use state_diff::StateDiff;
+pub use timestamp::Timestamp;
+#[cfg(feature = "csv-io")]
+pub use process::ProcessError;
+pub use replay::{AuditEvent, AuditReplay};
+pub use retry::{ExpireAfter, ParkedTransaction, RetryQueueConfig};
+pub use store::{BTreeStore, InMemoryStore, StoredTransaction, TransactionState, TransactionStore};
+pub use transaction_engine::{
+    Client, HeldExposure, InvariantViolation, PersistedTransaction, Transaction, TransactionEngine, TransactionError,
+    TxId, TxStatus,
+};
+#[cfg(feature = "json-io")]
+pub use transaction_engine::SnapshotError;
@@ -1,120 +1,1053 @@
-use csv::Writer;
-use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{ErrorKind, IsTerminal, Read, Seek, SeekFrom, Stderr, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-mod transaction_engine;
+use clap::Parser;
+use csv::StringRecord;
+#[cfg(feature = "arrow")]
+use transaction_engine::arrow_model::ArrowReader;
+use transaction_engine::csv_model;
+use transaction_engine::sentinel::SentinelIds;
+use transaction_engine::shard::ShardedEngine;
+#[cfg(feature = "sqlite")]
+use transaction_engine::sqlite_store::SqliteStore;
+use transaction_engine::type_alias::TypeAliases;
+use transaction_engine::amount::ParseAmountError;
+use transaction_engine::csv_model::{RowError, RowParser};
+use transaction_engine::{
+    Amount, BatchSummary, Changes, Currency, EngineConfig, InMemoryStore, InvariantViolation, SnapshotError, Timestamp, Transaction,
+    TransactionEngine, TransactionStore,
+};
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let path = match args.get(1) {
-        Some(path) => path,
-        None => {
-            println!("Missing argument");
-            std::process::exit(1);
+mod atomic_file;
+mod audit;
+mod checkpoint;
+mod cli;
+mod daily;
+mod dead_letter;
+mod diff;
+#[cfg(feature = "schema")]
+mod dump_schemas;
+#[cfg(feature = "failpoints")]
+mod failure;
+mod generate;
+mod input;
+mod input_path;
+mod logging;
+mod out_of_order;
+mod output;
+mod progress;
+mod rejection;
+mod serve;
+#[cfg(feature = "grpc")]
+mod serve_grpc;
+mod serve_http;
+mod verify_audit;
+mod wire;
+
+use atomic_file::AtomicFile;
+use audit::Audit;
+use checkpoint::{Checkpointer, Position};
+use cli::{Cli, Command, ProcessArgs};
+use daily::DailySnapshots;
+use dead_letter::DeadLetter;
+use input::{Input, InputFormat, ReadError};
+use input_path::PathKind;
+use out_of_order::OrderCheck;
+use output::{Format, OutputMode};
+use progress::Progress;
+use rejection::Rejection;
+use wire::Stats;
+
+const EXIT_INVALID_PATH: i32 = 2;
+const EXIT_MALFORMED_ROW: i32 = 3;
+const EXIT_INVARIANT_VIOLATED: i32 = 4;
+const EXIT_INTERRUPTED: i32 = 130;
+/// 128 + SIGPIPE, the status of a process killed when writing to a closed pipe.
+const EXIT_BROKEN_PIPE: i32 = 141;
+
+/// Rows the reader thread of `--pipeline` sends at once, so that the channel is not synchronized for every row.
+const PIPELINE_BATCH: usize = 1024;
+/// Batches the reader thread of `--pipeline` can get ahead of the engine before waiting for it.
+const PIPELINE_BOUND: usize = 64;
+
+/// Set on SIGINT or SIGTERM, the inputs are then no longer read and the balances so far are written.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Options of a `process` run, once validated.
+struct Args {
+    paths: Vec<String>,
+    top_held: Option<usize>,
+    type_aliases: TypeAliases,
+    sentinels: SentinelIds,
+    rejected: Option<String>,
+    audit: Option<String>,
+    resume: Option<String>,
+    snapshot_out: Option<String>,
+    daily_snapshots: Option<PathBuf>,
+    checkpoint: Option<(PathBuf, u64)>,
+    recover: Option<PathBuf>,
+    // In bytes.
+    max_memory: Option<usize>,
+    #[cfg(feature = "sqlite")]
+    state_db: Option<PathBuf>,
+    output: Option<String>,
+    format: Format,
+    input_format: Option<InputFormat>,
+    delimiter: Option<u8>,
+    mmap: bool,
+    pipeline: bool,
+    threads: Option<usize>,
+    output_delimiter: u8,
+    /// Set with `--output-mode incremental`, the outputs then only list the clients changed since the previous one.
+    run_id: Option<String>,
+    strict_precision: bool,
+    config: EngineConfig,
+    out_of_order_tolerance: Option<u64>,
+    strict: bool,
+    quiet: bool,
+    disputes: Option<String>,
+    flag_report: Option<(String, u32)>,
+    verify: bool,
+    stats: bool,
+    progress: bool,
+    #[cfg(feature = "failpoints")]
+    inject_failure: Option<failure::InjectedFailure>,
+}
+
+fn build_args(cli: ProcessArgs) -> Result<Args, String> {
+    let mut type_aliases = TypeAliases::new();
+    for alias in &cli.type_aliases {
+        type_aliases.add(alias)?;
+    }
+
+    let mut sentinels = SentinelIds::new();
+    for ids in &cli.sentinel_client_ids {
+        sentinels.add_client_ids(ids)?;
+    }
+    for ids in &cli.sentinel_tx_ids {
+        sentinels.add_tx_ids(ids)?;
+    }
+
+    if cli.checkpoint_every.is_some() || cli.recover.is_some() {
+        // Recovering seeks the input to the offset of the checkpoint, which needs a single plain CSV file.
+        let format = cli.paths.first().map(|path| cli.input_format.unwrap_or_else(|| InputFormat::from_path(path)));
+        match cli.paths.as_slice() {
+            [path] if path != "-" && !input::is_compressed(path) && format == Some(InputFormat::Csv) => {},
+            _ => return Err("checkpoints need a single uncompressed CSV file as input".to_string()),
         }
+    }
+
+    let max_memory = cli.max_memory_mb
+        .map(|mb| mb.checked_mul(1 << 20).ok_or(format!("--max-memory-mb {mb} is more than can be addressed")))
+        .transpose()?;
+
+    let run_id = match cli.output_mode {
+        OutputMode::Full => None,
+        // The changes of the shards are only known once they are merged, in no order.
+        OutputMode::Incremental if cli.threads.is_some() => return Err("--output-mode incremental cannot be used with --threads".to_string()),
+        OutputMode::Incremental if cli.format != Format::Csv => return Err("--output-mode incremental writes CSV balances".to_string()),
+        OutputMode::Incremental => Some(cli.run_id.unwrap_or_else(default_run_id)),
     };
 
-    let mut engine = transaction_engine::TransactionEngine::new();
+    Ok(Args {
+        checkpoint: cli.checkpoint_dir.zip(cli.checkpoint_every),
+        recover: cli.recover,
+        max_memory,
+        #[cfg(feature = "sqlite")]
+        state_db: cli.state_db,
+        paths: cli.paths,
+        top_held: cli.top_held,
+        type_aliases,
+        sentinels,
+        rejected: cli.rejected,
+        audit: cli.audit,
+        resume: cli.resume,
+        snapshot_out: cli.snapshot_out,
+        daily_snapshots: cli.daily_snapshots,
+        output: cli.output,
+        format: cli.format,
+        input_format: cli.input_format,
+        delimiter: cli.delimiter,
+        mmap: cli.mmap,
+        pipeline: cli.pipeline,
+        threads: cli.threads,
+        output_delimiter: cli.output_delimiter,
+        run_id,
+        strict_precision: cli.strict_precision,
+        config: EngineConfig::default()
+            .reject_deposits_when_locked(!cli.allow_locked_deposits)
+            .allow_negative_available_on_dispute(!cli.reject_disputes_exceeding_available)
+            .allow_dispute_on_locked(!cli.reject_locked_disputes)
+            .allow_adjustment_on_locked(!cli.reject_locked_adjustments)
+            .max_disputes_per_tx(cli.max_disputes_per_tx)
+            .compact_locked(cli.compact)
+            .max_stored_transactions(cli.max_stored_transactions)
+            .base_currency(cli.base_currency)
+            .credit_limit(cli.credit_limit),
+        out_of_order_tolerance: cli.detect_out_of_order.then_some(cli.out_of_order_tolerance),
+        strict: cli.strict,
+        quiet: cli.quiet,
+        disputes: cli.disputes,
+        flag_report: cli.flag_report.map(|path| (path, cli.flag_min_chargebacks)),
+        verify: cli.verify,
+        stats: cli.stats,
+        // The progress line is meant for a terminal, it would only clutter a redirected stderr.
+        progress: cli.progress && std::io::stderr().is_terminal(),
+        #[cfg(feature = "failpoints")]
+        inject_failure: cli.inject_failure,
+    })
+}
 
-    let mut rdr = match csv::Reader::from_path(path){
-        Ok(rdr) => rdr,
-        Err(e) => {
-            eprintln!("Application error: {e}");
-            std::process::exit(1);
-        },
+/// Writes the balances of `engine`, or only its `changes` in incremental mode.
+fn write_balances<W: Write, S: TransactionStore>(mut output: W, args: &Args, engine: &TransactionEngine<S>, changes: Option<&Changes>) -> std::io::Result<W> {
+    fail_during_output(&mut output, || write_balances(Vec::new(), args, engine, changes))?;
+    match (&args.run_id, changes) {
+        (Some(run_id), Some(changes)) => output::write_changes(output, run_id, changes, args.output_delimiter, engine.is_multi_currency()),
+        _ => output::write_clients(output, engine.clients(), args.format, args.output_delimiter, engine.is_multi_currency()),
+    }
+}
+
+/// With `--inject-failure`, aborts the run when `point` triggers.
+#[cfg(feature = "failpoints")]
+use failure::{fail_during_output, fail_point};
+#[cfg(not(feature = "failpoints"))]
+fn fail_point(_: &str) {}
+#[cfg(not(feature = "failpoints"))]
+fn fail_during_output<W: Write>(_: &mut W, _: impl FnOnce() -> std::io::Result<Vec<u8>>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Start time in milliseconds and process id, in hexadecimal.
+fn default_run_id() -> String {
+    let millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+    format!("{millis:x}-{:x}", std::process::id())
+}
+
+// With --strict-precision, rejects the amounts of deposits, withdrawals, transfers, adjustments and fees with
+// more than four decimal places. The deserialized amount is already rounded, so the check is done on the text of the row.
+fn check_precision(
+    args: &Args,
+    transaction: &Transaction,
+    row: &StringRecord,
+    amount_column: Option<usize>,
+) -> Result<(), ParseAmountError> {
+    let has_amount = matches!(
+        transaction,
+        Transaction::Deposit { .. }
+            | Transaction::Withdrawal { .. }
+            | Transaction::Transfer { .. }
+            | Transaction::Adjustment { .. }
+            | Transaction::Fee { .. }
+    );
+    if !args.strict_precision || !has_amount {
+        return Ok(())
+    }
+    match amount_column.and_then(|column| row.get(column)) {
+        Some(amount) if !amount.is_empty() => Amount::parse_exact(amount).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// Error stopping the run before any balance is written.
+struct Fatal {
+    message: String,
+    code: i32,
+}
+
+impl Fatal {
+    fn new(message: String) -> Fatal {
+        Fatal { message, code: 1 }
+    }
+}
+
+/// Error ending a `process` run, reported on stderr by `main` before exiting with its code.
+enum AppError {
+    /// An invalid option or input path, before anything is read.
+    InvalidArgument { message: String, code: i32 },
+    Fatal(Fatal),
+    /// The reader of stdout closed it, e.g. `head`. Like other command line tools, the run ends without a message.
+    BrokenPipe,
+    /// Found by `--verify` once the balances are written.
+    InvariantsViolated(Vec<InvariantViolation>),
+    /// SIGINT or SIGTERM after `rows` rows, the balances written only cover them.
+    Interrupted { rows: u64 },
+}
+
+impl AppError {
+    /// Prints the error to stderr and returns the exit code.
+    fn report(&self) -> i32 {
+        match self {
+            AppError::InvalidArgument { message, code } => {
+                eprintln!("Invalid argument: {message}");
+                *code
+            },
+            AppError::Fatal(fatal) => {
+                eprintln!("Application error: {}", fatal.message);
+                fatal.code
+            },
+            AppError::BrokenPipe => EXIT_BROKEN_PIPE,
+            AppError::InvariantsViolated(violations) => {
+                let mut client_ids: Vec<String> = violations.iter().map(|violation| violation.client_id().to_string()).collect();
+                client_ids.dedup();
+                for violation in violations {
+                    eprintln!("Invariant violated: {violation}");
+                }
+                eprintln!("Invariants violated by clients {}", client_ids.join(","));
+                EXIT_INVARIANT_VIOLATED
+            },
+            AppError::Interrupted { rows } => {
+                eprintln!("Interrupted after {rows} rows, the balances only cover these rows");
+                EXIT_INTERRUPTED
+            },
+        }
+    }
+}
+
+impl From<Fatal> for AppError {
+    fn from(fatal: Fatal) -> AppError {
+        AppError::Fatal(fatal)
+    }
+}
+
+/// Transaction of a row, with its timestamp and the currency of its accounts.
+type Parsed = (Transaction, Option<Timestamp>, Currency);
+
+/// Row read from an input: where it starts, unknown when it could not be read, and its transaction once parsed.
+type ReadRow = (Option<u64>, Result<Parsed, Rejection>);
+
+/// Columns of an input the handling of its rows looks up.
+struct Columns {
+    type_: Option<usize>,
+    client: Option<usize>,
+    ts: Option<usize>,
+    amount: Option<usize>,
+    currency: Option<usize>,
+}
+
+impl Columns {
+    fn new(headers: &StringRecord) -> Columns {
+        let position = |column| headers.iter().position(|header| header == column);
+        Columns {
+            type_: position("type"),
+            client: position("client"),
+            ts: position("ts"),
+            amount: position("amount"),
+            currency: position("currency"),
+        }
+    }
+}
+
+/// Parses `row` and runs the checks that do not depend on the engine, so that they can run on the reader thread.
+fn parse_row(row: &StringRecord, columns: &Columns, parser: &RowParser, args: &Args) -> Result<Parsed, Rejection> {
+    let aliased = match columns.type_ {
+        Some(column) if !args.type_aliases.is_empty() => args.type_aliases.apply(row, column),
+        _ => None,
     };
-    rdr.deserialize()
-        .for_each(|res: Result<Transaction, csv::Error>|{
-            match res {
-                Ok(transaction) => {
-                    match transaction.try_into() {
-                        Ok(model) => engine.compute_transaction(model),
-                        Err(_) => {}
-                    }
-                },
-                Err(e) => eprintln!("Application error: {e}")
+    let row = aliased.as_ref().unwrap_or(row);
+    let (transaction, ts, currency) = parser.parse(row).map_err(Rejection::Row)?;
+    check_precision(args, &transaction, row, columns.amount)
+        .map_err(|error| Rejection::Precision { tx_id: transaction.tx_id(), error })?;
+    args.sentinels.check(&transaction).map_err(Rejection::Sentinel)?;
+    Ok((transaction, ts, currency.unwrap_or(args.config.base_currency)))
+}
+
+/// Reads the next row of `input` into `record`, `None` at the end of the input.
+fn read_row<R: Read>(
+    path: &str,
+    input: &mut Input<R>,
+    record: &mut StringRecord,
+    columns: &Columns,
+    parser: &RowParser,
+    args: &Args,
+) -> Result<Option<ReadRow>, Fatal> {
+    match input.read_record(record) {
+        Ok(false) => Ok(None),
+        Ok(true) => Ok(Some((record.position().map(|position| position.byte()), parse_row(record, columns, parser, args)))),
+        Err(ReadError::Io(e)) => Err(Fatal::new(format!("cannot read {path}: {e}"))),
+        Err(ReadError::Record(e)) => Ok(Some((None, Err(Rejection::Row(RowError::Parse(e)))))),
+    }
+}
+
+fn apply_row<S: TransactionStore>(
+    (transaction, ts, currency): Parsed,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    order: Option<&mut OrderCheck>,
+) -> Result<(), Rejection> {
+    check_order(ts, args, order)?;
+    engine.compute_transaction_in(transaction, currency, ts).map_err(Rejection::Engine)
+}
+
+// With --detect-out-of-order, warns about a row earlier than the previous one or rejects it with --strict.
+fn check_order(ts: Option<Timestamp>, args: &Args, order: Option<&mut OrderCheck>) -> Result<(), Rejection> {
+    if let (Some(order), Some(ts)) = (order, ts) {
+        if let Err(e) = order.check(ts) {
+            if args.strict {
+                return Err(Rejection::OutOfOrder(e))
+            }
+            if !args.quiet {
+                eprintln!("Application warning: {e}");
+            }
+            order.accept(ts);
+        }
+    }
+    Ok(())
+}
+
+/// What a run records about its rows besides the balances.
+struct Tally {
+    summary: BatchSummary,
+    dead_letter: Option<DeadLetter<File>>,
+    audit: Option<Audit<File>>,
+    checkpointer: Option<Checkpointer>,
+    order: Option<OrderCheck>,
+    daily: Option<DailySnapshots>,
+    // Types already reported as unsupported, each being reported once.
+    unsupported_types: HashSet<String>,
+}
+
+impl Tally {
+    fn flush(&mut self) {
+        if let Some(dead_letter) = self.dead_letter.as_mut() {
+            if let Err(e) = dead_letter.flush() {
+                eprintln!("Application error: {e}");
             }
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            if let Err(e) = audit.flush() {
+                eprintln!("Application error: cannot write the audit log: {e}");
+            }
+        }
+    }
+}
+
+/// Like [`process`], with the rows sent to the shards of `sharded`. Only the rows rejected before reaching
+/// the engine are counted in `tally`, those of the engine are in the summary of the shards.
+fn process_sharded<R: Read>(
+    path: &str,
+    input: &mut Input<R>,
+    headers: &StringRecord,
+    args: &Args,
+    sharded: &mut ShardedEngine,
+    tally: &mut Tally,
+    mut progress: Option<&mut Progress<Stderr>>,
+) -> Result<(), Fatal> {
+    let columns = Columns::new(headers);
+    let parser = RowParser::new(headers);
+
+    let mut record = StringRecord::new();
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        let Some((_, parsed)) = read_row(path, input, &mut record, &columns, &parser, args)? else {
+            break
+        };
+        let result = parsed.and_then(|(transaction, ts, currency)| {
+            check_order(ts, args, tally.order.as_mut()).map(|()| sharded.compute_transaction_in(transaction, currency, ts))
         });
+        // The shards are on their own threads, their state is only known once they finish.
+        if let Some(progress) = progress.as_mut() {
+            progress.row(|| None);
+        }
+        if let Err(rejection) = result {
+            reject_row(path, &record, rejection, args, tally)?;
+        }
+    }
 
-    let client = engine.get_client_list();
-    
-    let mut writer = Writer::from_writer(std::io::stdout());
-    client.into_iter().for_each(|client| {
-        writer.serialize(client);
-    });
+    Ok(())
+}
+
+/// Applies every row of `input` to `engine`, counting the applied and rejected rows in `tally`.
+/// Fails when the input cannot be read any further, or on the first malformed row with `--strict`.
+fn process<R: Read + Send, S: TransactionStore>(
+    path: &str,
+    input: &mut Input<R>,
+    headers: &StringRecord,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    tally: &mut Tally,
+    mut progress: Option<&mut Progress<Stderr>>,
+) -> Result<(), Fatal> {
+    let columns = Columns::new(headers);
+    let parser = RowParser::new(headers);
+    if args.pipeline {
+        return process_pipelined(path, input, &columns, &parser, args, engine, tally, progress)
+    }
+
+    let mut record = StringRecord::new();
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        let Some(row) = read_row(path, input, &mut record, &columns, &parser, args)? else {
+            break
+        };
+        handle_row(path, &record, row, &columns, args, engine, tally, progress.as_deref_mut())?;
+    }
+
+    Ok(())
+}
 
-    writer.flush();
+/// Like [`process`], with the rows read and parsed on another thread while the engine applies the previous ones.
+/// The rows go through a bounded channel in order, so the reader waits when the engine falls behind.
+#[allow(clippy::too_many_arguments)]
+fn process_pipelined<R: Read + Send, S: TransactionStore>(
+    path: &str,
+    input: &mut Input<R>,
+    columns: &Columns,
+    parser: &RowParser,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    tally: &mut Tally,
+    mut progress: Option<&mut Progress<Stderr>>,
+) -> Result<(), Fatal> {
+    let (sender, receiver) = mpsc::sync_channel::<Result<Vec<(StringRecord, ReadRow)>, Fatal>>(PIPELINE_BOUND);
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut batch = Vec::with_capacity(PIPELINE_BATCH);
+            while !INTERRUPTED.load(Ordering::Relaxed) {
+                let mut record = StringRecord::new();
+                match read_row(path, input, &mut record, columns, parser, args) {
+                    Ok(Some(row)) => batch.push((record, row)),
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = sender.send(Ok(batch)).and_then(|()| sender.send(Err(e)));
+                        return
+                    },
+                }
+                // Sending fails once the engine stopped early, the rest of the input is then not needed.
+                if batch.len() == PIPELINE_BATCH
+                    && sender.send(Ok(std::mem::replace(&mut batch, Vec::with_capacity(PIPELINE_BATCH)))).is_err() {
+                    return
+                }
+            }
+            let _ = sender.send(Ok(batch));
+        });
 
+        // The receiver is dropped on an early return, which stops the reader.
+        for batch in receiver {
+            for (record, row) in batch? {
+                if INTERRUPTED.load(Ordering::Relaxed) {
+                    return Ok(())
+                }
+                handle_row(path, &record, row, columns, args, engine, tally, progress.as_deref_mut())?;
+            }
+        }
+        Ok(())
+    })
 }
 
+/// Applies a row read from the input at `path`, recording the outcome in `tally`.
+#[allow(clippy::too_many_arguments)]
+fn handle_row<S: TransactionStore>(
+    path: &str,
+    record: &StringRecord,
+    (offset, parsed): ReadRow,
+    columns: &Columns,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    tally: &mut Tally,
+    progress: Option<&mut Progress<Stderr>>,
+) -> Result<(), Fatal> {
+    fail_point("after-rows");
+    if let (Some(checkpointer), Some(offset)) = (tally.checkpointer.as_mut(), offset) {
+        checkpointer.row(engine, offset)
+            .map_err(|e| Fatal::new(format!("cannot write a checkpoint: {e}")))?;
+    }
+    if let (Some(daily), Ok((_, ts, _))) = (tally.daily.as_mut(), &parsed) {
+        daily.row(*ts, engine).map_err(|e| Fatal::new(format!("{e}, at line {} of {path}", line(record))))?;
+    }
+    let result = parsed.and_then(|parsed| apply_row(parsed, args, engine, tally.order.as_mut()));
+
+    if let Some(progress) = progress {
+        progress.row(|| Some(engine.memory_estimate()));
+    }
+
+    if let Some(audit) = tally.audit.as_mut() {
+        audit_row(audit, record, columns, result.as_ref().err(), engine)?;
+    }
 
-#[derive(Debug, Deserialize,Clone,Copy)]
-pub enum TransactionType {
-    #[serde(rename = "deposit")]
-    Deposit,
-    #[serde(rename = "withdrawal")]
-    Withdrawal,
-    #[serde(rename = "dispute")]
-    Dispute,
-    #[serde(rename = "resolve")]
-    Resolve,
-    #[serde(rename = "chargeback")]
-    Chargeback,
+    match result {
+        Ok(()) => {
+            tally.summary.applied();
+            Ok(())
+        },
+        Err(rejection) => reject_row(path, record, rejection, args, tally),
+    }
 }
 
-#[derive(Debug, Deserialize, Clone,Copy)]
-pub struct Transaction {
-    #[serde(rename = "type")]
-    transaction_type: TransactionType,
-    client: u16,
-    tx: u32,
-    amount: Option<f64>
+fn audit_row<S: TransactionStore>(
+    audit: &mut Audit<File>,
+    record: &StringRecord,
+    columns: &Columns,
+    rejection: Option<&Rejection>,
+    engine: &TransactionEngine<S>,
+) -> Result<(), Fatal> {
+    let currency = columns.currency.and_then(|column| record.get(column))
+        .and_then(|currency| currency.parse().ok())
+        .unwrap_or(engine.config().base_currency);
+    let client = columns.client.and_then(|column| record.get(column))
+        .and_then(|client_id| client_id.parse().ok())
+        .and_then(|client_id| engine.get_account(client_id, currency));
+    let ts = columns.ts.and_then(|column| record.get(column)).and_then(|ts| ts.parse().ok());
+    audit.write(record, ts, rejection, client, currency)
+        .map_err(|e| Fatal::new(format!("cannot write the audit log: {e}")))
 }
 
-impl TryInto<transaction_engine::Transaction> for Transaction {
-    type Error = ();
+/// Like [`process`] for an Arrow input, whose record batches are decoded column by column. The input is read
+/// whole first, as reading an Arrow file starts from its end. The rows are numbered from 1 in the messages, and
+/// only written as CSV records for the audit log and the rejected rows.
+#[cfg(feature = "arrow")]
+fn process_arrow<S: TransactionStore>(
+    path: &str,
+    mut input: Box<dyn Read + Send + '_>,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    mut sharded: Option<&mut ShardedEngine>,
+    tally: &mut Tally,
+    mut progress: Option<&mut Progress<Stderr>>,
+) -> Result<(), Fatal> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes).map_err(|e| Fatal::new(format!("cannot read {path}: {e}")))?;
+    let reader = ArrowReader::from_bytes(bytes).map_err(|e| Fatal::new(format!("cannot read {path}: {e}")))?;
+    let headers = reader.headers().clone();
+    create_dead_letter(args, tally, &headers)?;
+    let columns = Columns::new(&headers);
 
-    fn try_into(self) -> Result<transaction_engine::Transaction, Self::Error> {
-        match self.transaction_type {
-            TransactionType::Deposit => {
-                if let Some(amount) = self.amount  {
-                    Ok(transaction_engine::Transaction::Deposit { 
-                        client_id: self.client, 
-                        tx_id: self.tx, 
-                        amount 
-                    })
-                } else {
-                    Err(())
+    let mut line = 0;
+    for batch in reader {
+        let mut batch = batch.map_err(|e| Fatal::new(format!("cannot read {path}: {e}")))?;
+        batch.resolve_aliases(&args.type_aliases);
+        for index in 0..batch.len() {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return Ok(())
+            }
+            line += 1;
+            let parsed = batch.transaction(index).map_err(Rejection::Row).and_then(|(transaction, ts, currency)| {
+                if args.strict_precision {
+                    batch.check_precision(index).map_err(|error| Rejection::Precision { tx_id: transaction.tx_id(), error })?;
+                }
+                args.sentinels.check(&transaction).map_err(Rejection::Sentinel)?;
+                Ok((transaction, ts, currency.unwrap_or(args.config.base_currency)))
+            });
+            if let (Some(daily), Ok((_, ts, _))) = (tally.daily.as_mut(), &parsed) {
+                daily.row(*ts, engine).map_err(|e| Fatal::new(format!("{e}, at line {line} of {path}")))?;
+            }
+            let result = match (parsed, sharded.as_deref_mut()) {
+                (Ok((transaction, ts, currency)), Some(sharded)) => {
+                    check_order(ts, args, tally.order.as_mut()).map(|()| sharded.compute_transaction_in(transaction, currency, ts))
+                },
+                (Ok(parsed), None) => apply_row(parsed, args, engine, tally.order.as_mut()).map(|()| tally.summary.applied()),
+                (Err(rejection), _) => Err(rejection),
+            };
+
+            if let Some(progress) = progress.as_mut() {
+                progress.row(|| sharded.is_none().then(|| engine.memory_estimate()));
+            }
+            let record = || {
+                let mut record = batch.record(index);
+                let mut position = csv::Position::new();
+                position.set_line(line);
+                record.set_position(Some(position));
+                record
+            };
+            if let Some(audit) = tally.audit.as_mut() {
+                audit_row(audit, &record(), &columns, result.as_ref().err(), engine)?;
+            }
+            if let Err(rejection) = result {
+                reject_row(path, &record(), rejection, args, tally)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records the rejection of a row read from the input at `path`, failing on a malformed row with `--strict`.
+fn reject_row(path: &str, record: &StringRecord, rejection: Rejection, args: &Args, tally: &mut Tally) -> Result<(), Fatal> {
+    let line = line(record);
+    if args.strict && rejection.is_malformed() {
+        let text: Vec<&str> = record.iter().collect();
+        return Err(Fatal {
+            message: format!("malformed row at line {line} of {path}: {rejection}\n{}", text.join(",")),
+            code: EXIT_MALFORMED_ROW,
+        })
+    }
+
+    // Rejected transactions are ignored, the balances only report what was applied.
+    tally.summary.rejected(rejection.code());
+    match &rejection {
+        _ if args.quiet => {},
+        Rejection::Engine(_) => {},
+        Rejection::Row(RowError::Parse(e)) => eprintln!("Application error: {e}"),
+        Rejection::Row(
+            e @ (RowError::MissingAmount { .. } | RowError::MissingToClient { .. } | RowError::ClientOutOfRange { .. }),
+        ) => {
+            eprintln!("Application error: {e} at line {line} of {path}")
+        },
+        Rejection::Row(RowError::UnsupportedType(name)) => {
+            if tally.unsupported_types.insert(name.clone()) {
+                eprintln!("Application warning: unsupported transaction type {name:?} at line {line} of {path}, its rows are skipped");
+            }
+        },
+        Rejection::Precision { tx_id, error } => eprintln!("Application error: {error} on tx {tx_id} at line {line} of {path}"),
+        Rejection::Sentinel(e) => eprintln!("Application error: {e}"),
+        Rejection::OutOfOrder(e) => eprintln!("Application error: {e}"),
+    }
+
+    if let Some(dead_letter) = tally.dead_letter.as_mut() {
+        if let Err(e) = dead_letter.write(record, &rejection.to_string()) {
+            eprintln!("Application error: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+// Line of a row in its input, for the messages.
+fn line(record: &StringRecord) -> String {
+    record.position().map(|position| position.line().to_string()).unwrap_or("?".to_string())
+}
+
+/// Applies every input to `engine` in order, returning the counts of applied and rejected rows.
+fn process_inputs<S: TransactionStore>(
+    inputs: Vec<(&String, Box<dyn Read + Send>)>,
+    args: &Args,
+    engine: &mut TransactionEngine<S>,
+    checkpointer: Option<Checkpointer>,
+) -> Result<BatchSummary, Fatal> {
+    let audit = args.audit.as_ref()
+        .map(|audit| Audit::create(audit).map_err(|e| Fatal::new(format!("cannot create {audit}: {e}"))))
+        .transpose()?;
+    if let Some(audit) = &audit {
+        engine.set_observer(audit.observer());
+    }
+    let order = args.out_of_order_tolerance.map(OrderCheck::new);
+    let daily = args.daily_snapshots.as_ref()
+        .map(|dir| DailySnapshots::create(dir, args.output_delimiter, args.run_id.clone()).map_err(|e| Fatal::new(format!("cannot create {}: {e}", dir.display()))))
+        .transpose()?;
+    let mut tally = Tally { summary: BatchSummary::new(), dead_letter: None, audit, checkpointer, order, daily, unsupported_types: HashSet::new() };
+
+    // What was written so far is kept even when the run fails.
+    let result = apply_inputs(inputs, args, engine, &mut tally);
+    tally.flush();
+    result?;
+    // The last day of an interrupted run is not over.
+    if let (Some(daily), false) = (&tally.daily, INTERRUPTED.load(Ordering::Relaxed)) {
+        daily.finish(engine).map_err(Fatal::new)?;
+    }
+    Ok(tally.summary)
+}
+
+fn apply_inputs<S: TransactionStore>(inputs: Vec<(&String, Box<dyn Read + Send>)>, args: &Args, engine: &mut TransactionEngine<S>, tally: &mut Tally) -> Result<(), Fatal> {
+    let mut sharded = args.threads.map(|threads| ShardedEngine::new(threads, args.config));
+    for (path, input) in inputs {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            break
+        }
+        let format = args.input_format.unwrap_or_else(|| InputFormat::from_path(path));
+        let delimiter = args.delimiter.unwrap_or_else(|| input::default_delimiter(path));
+        // The size is that of the file on disk, the progress of a compressed input is measured on its compressed bytes.
+        let mut progress = args.progress.then(|| {
+            let size = std::fs::metadata(path).ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len());
+            Progress::new(std::io::stderr(), size)
+        });
+        let input: Box<dyn Read + Send> = match &progress {
+            Some(progress) => Box::new(progress.counting(input)),
+            None => input,
+        };
+        let input = input::decompress(input).map_err(|e| Fatal::new(format!("cannot read {path}: {e}")))?;
+        let (started, before) = (Instant::now(), tally.summary.clone());
+        match format {
+            InputFormat::Csv | InputFormat::Ndjson => {
+                let mut input = Input::new(input, format, delimiter);
+                let headers = input.headers().map_err(|e| Fatal::new(format!("cannot read {path}: {e}")))?;
+                if let Some(column) = csv_model::missing_column(&headers) {
+                    return Err(Fatal::new(format!(
+                        "{path} has no {column} column, expected the columns {}", csv_model::COLUMNS.join(",")
+                    )))
+                }
+                create_dead_letter(args, tally, &headers)?;
+
+                match sharded.as_mut() {
+                    Some(sharded) => process_sharded(path, &mut input, &headers, args, sharded, tally, progress.as_mut())?,
+                    None => process(path, &mut input, &headers, args, engine, tally, progress.as_mut())?,
                 }
             },
-            TransactionType::Withdrawal => {
-                if let Some(amount) = self.amount  {
-                    Ok(transaction_engine::Transaction::Withdrawal { 
-                        client_id: self.client, 
-                        tx_id: self.tx, 
-                        amount 
-                    })
-                } else {
-                    Err(())
-                } 
-            },
-            TransactionType::Dispute => {
-                Ok(transaction_engine::Transaction::Dispute { 
-                    client_id: self.client, 
-                    tx_id: self.tx 
-                })
-            },
-            TransactionType::Resolve => {
-                Ok(transaction_engine::Transaction::Resolve { 
-                    client_id: self.client, 
-                    tx_id: self.tx 
+            #[cfg(feature = "arrow")]
+            InputFormat::Arrow => process_arrow(path, input, args, engine, sharded.as_mut(), tally, progress.as_mut())?,
+        }
+        if let Some(progress) = progress {
+            progress.finish();
+        }
+        log_input(path, &before, &tally.summary, sharded.is_some(), started.elapsed());
+        // Between inputs, also drops the transfers received by locked accounts.
+        if args.config.compact_locked {
+            match sharded.as_mut() {
+                Some(sharded) => sharded.compact(),
+                None => {
+                    engine.compact();
+                },
+            }
+        }
+    }
+
+    if let Some(sharded) = sharded {
+        let (merged, summary) = sharded.finish();
+        tracing::info!(read = summary.read_count(), applied = summary.applied_count(), rejected = summary.rejected_count(), "shards finished");
+        engine.merge(merged);
+        tally.summary.merge(summary);
+    }
+    Ok(())
+}
+
+/// Logs the rows of the input at `path`, the difference between the summaries `before` and `after` it. With
+/// `--threads`, the rows handled by the shards are only counted once they finish.
+fn log_input(path: &str, before: &BatchSummary, after: &BatchSummary, sharded: bool, elapsed: Duration) {
+    let read = after.read_count() - before.read_count();
+    let rejected = after.rejected_count() - before.rejected_count();
+    let elapsed_ms = elapsed.as_millis() as u64;
+    match sharded {
+        true => tracing::info!(path, rejected, elapsed_ms, "input sent to the shards"),
+        false => tracing::info!(path, read, applied = after.applied_count() - before.applied_count(), rejected, elapsed_ms, "input processed"),
+    }
+}
+
+// The dead letter is created with the header of the first input.
+fn create_dead_letter(args: &Args, tally: &mut Tally, headers: &StringRecord) -> Result<(), Fatal> {
+    if let (Some(rejected), None) = (&args.rejected, &tally.dead_letter) {
+        let created = DeadLetter::create(rejected, headers)
+            .map_err(|e| Fatal::new(format!("cannot create {rejected}: {e}")))?;
+        tally.dead_letter = Some(created);
+    }
+    Ok(())
+}
+
+/// Loads the latest checkpoint of `dir`, which must have been taken on `path`.
+fn recover(dir: &Path, path: &str) -> Result<(Position, TransactionEngine), String> {
+    let latest = checkpoint::latest(dir)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "no checkpoint found".to_string())?;
+    let (position, engine) = checkpoint::load(&latest)?;
+    let canonical = std::fs::canonicalize(path).map_err(|e| format!("cannot open {path}: {e}"))?;
+    if canonical != position.path {
+        return Err(format!("the checkpoint was taken on {}, not {path}", position.path.display()))
+    }
+    Ok((position, engine))
+}
+
+/// Opens the input so that it reads its header followed by the rows from the checkpoint offset.
+fn open_at(path: &str, position: &Position) -> std::io::Result<Box<dyn Read + Send>> {
+    let header = File::open(path)?.take(position.header_end);
+    let mut rows = File::open(path)?;
+    rows.seek(SeekFrom::Start(position.offset))?;
+    Ok(Box::new(header.chain(rows)))
+}
+
+fn main() {
+    if let Err(e) = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed)) {
+        eprintln!("Application error: cannot install the interrupt handler: {e}");
+    }
+
+    let cli = Cli::parse();
+    logging::init(logging::level(cli.verbose), cli.log_format);
+
+    let code = match cli.command() {
+        Command::Process(args) => match run(args) {
+            Ok(()) => 0,
+            Err(e) => e.report(),
+        },
+        Command::Serve(args) => serve::run(args),
+        Command::ServeHttp(args) => serve_http::run(args),
+        #[cfg(feature = "grpc")]
+        Command::ServeGrpc(args) => serve_grpc::run(args),
+        Command::Generate(args) => generate::run(args),
+        Command::Diff(args) => diff::run(args),
+        Command::VerifyAudit(args) => verify_audit::run(args),
+        #[cfg(feature = "schema")]
+        Command::DumpSchemas(args) => dump_schemas::run(args),
+    };
+    std::process::exit(code);
+}
+
+/// Runs the `process` command. Every file it creates is removed on error, before `main` exits.
+fn run(args: ProcessArgs) -> Result<(), AppError> {
+    let args = build_args(args).map_err(|message| AppError::InvalidArgument { message, code: 1 })?;
+    #[cfg(feature = "failpoints")]
+    if let Some(failure) = args.inject_failure {
+        failure.arm();
+    }
+
+    for path in &args.paths {
+        let message = match input_path::classify(path) {
+            PathKind::File | PathKind::Stdin => continue,
+            PathKind::Empty => "the input path is empty".to_string(),
+            PathKind::Directory => format!("{path} is a directory, expected a CSV file"),
+            PathKind::UnexpandedGlob => format!("{path} looks like a glob pattern that matched no file, the shell did not expand it"),
+        };
+        return Err(AppError::InvalidArgument { message, code: EXIT_INVALID_PATH })
+    }
+
+    let recovered = args.recover.as_ref()
+        .map(|dir| recover(dir, &args.paths[0]).map_err(|e| Fatal::new(format!("cannot recover from {}: {e}", dir.display()))))
+        .transpose()?;
+
+    // Every input is opened before the first transaction is applied, so a missing file
+    // fails the run instead of producing balances for part of the inputs.
+    let inputs = args.paths.iter()
+        .map(|path| -> Result<(&String, Box<dyn Read + Send>), Fatal> {
+            if input_path::classify(path) == PathKind::Stdin {
+                // Not locked, so that the pipeline can read it on its own thread. Inputs are buffered anyway.
+                return Ok((path, Box::new(std::io::stdin())))
+            }
+            let input = match &recovered {
+                Some((position, _)) => open_at(path, position),
+                None => File::open(path).map(|file| -> Box<dyn Read + Send> {
+                    match args.mmap {
+                        true => input::map(file),
+                        false => Box::new(file),
+                    }
+                }),
+            };
+            input.map(|input| (path, input)).map_err(|e| Fatal::new(format!("cannot open {path}: {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Created upfront so that an unwritable output fails the run before processing the inputs.
+    let output = args.output.as_ref()
+        .map(|output| AtomicFile::create(output).map_err(|e| Fatal::new(format!("cannot create {output}: {e}"))))
+        .transpose()?;
+
+    let snapshot_out = args.snapshot_out.as_ref()
+        .map(|snapshot_out| AtomicFile::create(snapshot_out).map_err(|e| Fatal::new(format!("cannot create {snapshot_out}: {e}"))))
+        .transpose()?;
+
+    let checkpointer = args.checkpoint.as_ref().map(|(dir, every)| {
+        let path = std::fs::canonicalize(&args.paths[0]).unwrap_or_else(|_| PathBuf::from(&args.paths[0]));
+        Checkpointer::new(dir.clone(), *every, path, recovered.as_ref().map(|(position, _)| position))
+    });
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.state_db {
+        let store = SqliteStore::open(path).map_err(|e| Fatal::new(format!("cannot open {}: {e}", path.display())))?;
+        let engine = store.load(args.config).map_err(|e| Fatal::new(format!("cannot load {}: {e}", path.display())))?;
+        return run_engine(&args, engine, inputs, checkpointer, output, snapshot_out, |engine| {
+            engine.commit().map_err(|e| Fatal::new(format!("cannot save {}: {e}", path.display())))
+        })
+    }
+
+    let engine = match (&args.resume, recovered) {
+        (_, Some((_, engine))) => engine,
+        (Some(resume), None) => File::open(resume).map_err(SnapshotError::Io).and_then(TransactionEngine::load_snapshot)
+            .map_err(|e| Fatal::new(format!("cannot resume from {resume}: {e}")))?,
+        (None, None) => match args.max_memory {
+            Some(max_memory) => InMemoryStore::with_memory_limit(max_memory)
+                .map(|store| TransactionEngine::with_store(store, args.config))
+                .map_err(|e| Fatal::new(format!("cannot create the spill file: {e}")))?,
+            None => TransactionEngine::new(),
+        },
+    };
+    run_engine(&args, engine, inputs, checkpointer, output, snapshot_out, |_| Ok(()))
+}
+
+/// Applies the inputs to `engine` and writes its balances and reports, `save` persisting the engine of a run
+/// that was not interrupted before the balances are written.
+fn run_engine<S: TransactionStore>(
+    args: &Args,
+    mut engine: TransactionEngine<S>,
+    inputs: Vec<(&String, Box<dyn Read + Send>)>,
+    checkpointer: Option<Checkpointer>,
+    output: Option<AtomicFile>,
+    snapshot_out: Option<AtomicFile>,
+    save: impl FnOnce(&mut TransactionEngine<S>) -> Result<(), Fatal>,
+) -> Result<(), AppError> {
+    engine.set_config(args.config);
+    if args.run_id.is_some() {
+        engine.track_changes();
+    }
+
+    let started = Instant::now();
+    let summary = process_inputs(inputs, args, &mut engine, checkpointer)?;
+
+    // An interrupted run is not saved, resuming from it would silently skip the rows left unread.
+    if let (Some(mut file), false) = (snapshot_out, INTERRUPTED.load(Ordering::Relaxed)) {
+        let snapshot_out = args.snapshot_out.as_deref().unwrap_or_default();
+        engine.save_snapshot(&mut file).and_then(|()| file.commit().map_err(SnapshotError::Io))
+            .map_err(|e| Fatal::new(format!("cannot write {snapshot_out}: {e}")))?;
+    }
+
+    if !INTERRUPTED.load(Ordering::Relaxed) {
+        save(&mut engine)?;
+    }
+
+    let changes = args.run_id.is_some().then(|| engine.take_changes().expect("changes tracked for the incremental outputs"));
+    match output {
+        Some(file) => {
+            let output = args.output.as_deref().unwrap_or_default();
+            write_balances(file, args, &engine, changes.as_ref())
+                .and_then(|file| {
+                    fail_point("before-flush");
+                    file.commit()
                 })
-            },
-            TransactionType::Chargeback => {
-                Ok(transaction_engine::Transaction::Chargeback{ 
-                    client_id: self.client, 
-                    tx_id: self.tx 
+                .map_err(|e| Fatal::new(format!("cannot write {output}: {e}")))?;
+        },
+        None => {
+            write_balances(std::io::stdout(), args, &engine, changes.as_ref())
+                .and_then(|mut stdout| {
+                    fail_point("before-flush");
+                    stdout.flush()
                 })
-            },
-        }
+                .map_err(|e| match e.kind() {
+                    ErrorKind::BrokenPipe => AppError::BrokenPipe,
+                    _ => AppError::Fatal(Fatal::new(format!("cannot write the balances to stdout: {e}"))),
+                })?;
+        },
+    }
+
+    if let Some(path) = &args.disputes {
+        let mut disputes: Vec<_> = engine.disputed_transactions().collect();
+        disputes.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        File::create(path).and_then(|file| output::write_disputes(file, disputes))
+            .map_err(|e| Fatal::new(format!("cannot write {path}: {e}")))?;
+    }
+
+    if let Some((path, min_chargebacks)) = &args.flag_report {
+        let flagged = engine.flagged_clients(*min_chargebacks)
+            .into_iter()
+            .filter_map(|client_id| engine.dispute_counters(client_id).map(|counters| (client_id, *counters)));
+        File::create(path).and_then(|file| output::write_flagged(file, flagged))
+            .map_err(|e| Fatal::new(format!("cannot write {path}: {e}")))?;
     }
-}
\ No newline at end of file
+
+    let sentinel_rejections = summary.count("sentinel_client_id") + summary.count("sentinel_tx_id");
+    if sentinel_rejections > 0 && !args.quiet {
+        eprintln!("Rejected {sentinel_rejections} transactions with sentinel ids");
+    }
+
+    if summary.rejected_count() > 0 && !args.quiet {
+        eprintln!("{summary}");
+    }
+
+    if let Some(n) = args.top_held {
+        eprintln!("client,held,disputed_tx");
+        engine.top_by_held(n).into_iter().for_each(|exposure| {
+            let disputed_tx: Vec<String> = exposure.disputed_tx.iter()
+                .map(|tx_id| tx_id.to_string())
+                .collect();
+            eprintln!("{},{},{}", exposure.client.id(), exposure.client.held(), disputed_tx.join(" "));
+        });
+    }
+
+    if args.stats {
+        eprintln!("{}", Stats::new(&engine, summary.read_count(), started.elapsed()));
+    }
+
+    if args.verify {
+        engine.check_invariants().map_err(AppError::InvariantsViolated)?;
+    }
+
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(AppError::Interrupted { rows: summary.read_count() })
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Estimate of the memory taken by the state of an engine, as returned by
+//! [`TransactionEngine::memory_estimate`](crate::TransactionEngine::memory_estimate).
+//!
+//! The estimate is the number of entries of each map times the bytes of an entry, a hash map entry taking one
+//! more control byte. The allocator overhead and the spare capacity of the maps are left out.
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Bytes of an account in the map of clients, the tx ids of its history aside.
+pub const CLIENT_BYTES: usize = 65;
+/// Bytes of a transaction in the map of an [`InMemoryStore`](crate::InMemoryStore).
+pub const TRANSACTION_BYTES: usize = 57;
+/// Bytes of a tx id in the history of a client or the queue of a store.
+pub const TX_ID_BYTES: usize = 8;
+/// Bytes of a transaction moved to the spill file, in the index of the file.
+pub const SPILLED_BYTES: usize = 25;
+/// Bytes of a tx id in the set of the compacted ones, or of those applied without being stored.
+pub const COMPACTED_BYTES: usize = 9;
+/// Bytes of the dispute counters of a client.
+pub const DISPUTE_COUNTERS_BYTES: usize = 25;
+/// Bytes of the credit limit of a client.
+pub const CREDIT_LIMIT_BYTES: usize = 17;
+
+/// Rough size of the state of an engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MemoryEstimate {
+    pub clients: u64,
+    pub client_bytes: u64,
+    /// Transactions stored, the ones moved out of memory by the store included.
+    pub transactions: u64,
+    /// Bytes of the transactions the store keeps in memory, along with what it keeps of the others.
+    pub transaction_bytes: u64,
+    /// Bytes of the histories of the clients, the compacted and unstored tx ids, the dispute counters and the credit
+    /// limits.
+    pub aux_bytes: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.client_bytes + self.transaction_bytes + self.aux_bytes
+    }
+}
+
+// Bytes of a store spilling to a file, with `in_memory` transactions in its map and its queue and `spilled` ones
+// in the index of the file. The budget of `InMemoryStore::with_memory_limit` is checked against it.
+#[cfg_attr(not(feature = "json-io"), allow(dead_code))]
+pub(crate) fn spilling_store_bytes(in_memory: usize, spilled: usize) -> usize {
+    in_memory * (TRANSACTION_BYTES + TX_ID_BYTES) + spilled * SPILLED_BYTES
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::metrics::DisputeCounters;
+    use crate::store::StoredTransaction;
+    use crate::transaction_engine::{Account, Transaction, TransactionEngine, TxId};
+
+    // A struct growing fails the build of the tests until its constant is updated.
+    #[cfg(target_pointer_width = "64")]
+    const _: () = {
+        assert!(CLIENT_BYTES == size_of::<((u16, Currency), Account)>() + 1);
+        assert!(TRANSACTION_BYTES == size_of::<(TxId, StoredTransaction)>() + 1);
+        assert!(TX_ID_BYTES == size_of::<TxId>());
+        assert!(SPILLED_BYTES == size_of::<(TxId, (u64, u32))>() + 1);
+        assert!(COMPACTED_BYTES == size_of::<TxId>() + 1);
+        assert!(DISPUTE_COUNTERS_BYTES == size_of::<(u16, DisputeCounters)>() + 1);
+        assert!(CREDIT_LIMIT_BYTES == size_of::<(u16, Amount)>() + 1);
+    };
+
+    fn engine_with_deposits(count: TxId) -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        for tx_id in 1..=count {
+            let client_id = (tx_id % 10) as u16;
+            engine.compute_transaction(Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(10_000) }).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn should_match_the_sizes_of_the_entries() {
+        let mut engine = engine_with_deposits(20);
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compact();
+        engine.set_credit_limit(2, Amount::from_units(10_000));
+
+        assert_eq!(engine.memory_estimate(), MemoryEstimate {
+            clients: 10,
+            client_bytes: 10 * CLIENT_BYTES as u64,
+            transactions: 18,
+            transaction_bytes: 18 * TRANSACTION_BYTES as u64,
+            aux_bytes: (18 * TX_ID_BYTES + 2 * COMPACTED_BYTES + DISPUTE_COUNTERS_BYTES + CREDIT_LIMIT_BYTES) as u64,
+        });
+    }
+
+    #[test]
+    fn should_scale_linearly_with_the_transactions() {
+        let small = engine_with_deposits(1_000).memory_estimate();
+        let large = engine_with_deposits(3_000).memory_estimate();
+
+        assert_eq!(large.clients, small.clients);
+        assert_eq!(large.transactions, 3 * small.transactions);
+        assert_eq!(large.transaction_bytes, 3 * small.transaction_bytes);
+        assert_eq!(large.aux_bytes, 3 * small.aux_bytes);
+        assert_eq!(large.total_bytes() - small.total_bytes(), 2_000 * (TRANSACTION_BYTES + TX_ID_BYTES) as u64);
+    }
+
+    #[test]
+    fn when_spilling_should_count_the_spilled_transactions_in_the_index() {
+        assert_eq!(spilling_store_bytes(2, 3), 2 * (TRANSACTION_BYTES + TX_ID_BYTES) + 3 * SPILLED_BYTES);
+    }
+}
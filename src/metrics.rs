@@ -0,0 +1,115 @@
+//! Counters kept by the engine while applying transactions.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::amount::Amount;
+
+/// Number of applied and rejected transactions of one type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TypeCounters {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+impl TypeCounters {
+    fn add(&mut self, other: TypeCounters) {
+        self.applied += other.applied;
+        self.rejected += other.rejected;
+    }
+
+    pub(crate) fn record<T, E>(&mut self, result: &Result<T, E>) {
+        match result {
+            Ok(_) => self.applied += 1,
+            Err(_) => self.rejected += 1,
+        }
+    }
+}
+
+/// Snapshot of the engine counters, as returned by [`crate::TransactionEngine::metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Metrics {
+    pub deposit: TypeCounters,
+    pub withdrawal: TypeCounters,
+    pub dispute: TypeCounters,
+    pub resolve: TypeCounters,
+    pub chargeback: TypeCounters,
+    pub unlock: TypeCounters,
+    pub transfer: TypeCounters,
+    pub adjustment: TypeCounters,
+    pub fee: TypeCounters,
+    /// Disputes neither resolved nor charged back yet.
+    pub open_disputes: u64,
+    /// Deposits and withdrawals kept so they can be disputed.
+    pub stored_transactions: u64,
+    /// Clients with an account, i.e. touched by at least one transaction.
+    pub clients: u64,
+    /// Accounts moved to the archive by [`crate::TransactionEngine::evict_idle`].
+    pub evicted_clients: u64,
+    /// Evicted accounts brought back by a transaction.
+    pub restored_clients: u64,
+    /// Disputes, resolves and chargebacks waiting in the retry queue for the tx they reference.
+    pub parked_transactions: u64,
+    /// Parked transactions applied once their tx came, whether accepted or rejected then.
+    pub parked_retried: u64,
+    /// Parked transactions removed by [`crate::TransactionEngine::expire_parked`].
+    pub parked_expired: u64,
+    /// Transactions rejected for their unknown tx because the retry queue was full.
+    pub parked_overflow: u64,
+}
+
+/// Disputes opened and chargebacks executed on the funds of one client, as returned by
+/// [`crate::TransactionEngine::dispute_counters`]. A transfer counts for its recipient.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct DisputeCounters {
+    pub disputes: u32,
+    pub chargebacks: u32,
+    /// Sum of the charged back amounts in the base currency, those in other currencies being left out.
+    pub charged_back: Amount,
+}
+
+impl Metrics {
+    /// Applied transactions of every type.
+    pub fn applied(&self) -> u64 {
+        self.counters().iter().map(|counters| counters.applied).sum()
+    }
+
+    /// Rejected transactions of every type.
+    pub fn rejected(&self) -> u64 {
+        self.counters().iter().map(|counters| counters.rejected).sum()
+    }
+
+    // Adds the counters of `other`, the engines having been fed different transactions.
+    pub(crate) fn add(&mut self, other: &Metrics) {
+        self.deposit.add(other.deposit);
+        self.withdrawal.add(other.withdrawal);
+        self.dispute.add(other.dispute);
+        self.resolve.add(other.resolve);
+        self.chargeback.add(other.chargeback);
+        self.unlock.add(other.unlock);
+        self.transfer.add(other.transfer);
+        self.adjustment.add(other.adjustment);
+        self.fee.add(other.fee);
+        self.open_disputes += other.open_disputes;
+        self.stored_transactions += other.stored_transactions;
+        self.clients += other.clients;
+        self.evicted_clients += other.evicted_clients;
+        self.restored_clients += other.restored_clients;
+        self.parked_transactions += other.parked_transactions;
+        self.parked_retried += other.parked_retried;
+        self.parked_expired += other.parked_expired;
+        self.parked_overflow += other.parked_overflow;
+    }
+
+    fn counters(&self) -> [TypeCounters; 9] {
+        [
+            self.deposit, self.withdrawal, self.dispute, self.resolve, self.chargeback, self.unlock, self.transfer,
+            self.adjustment, self.fee,
+        ]
+    }
+}
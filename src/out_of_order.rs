@@ -0,0 +1,71 @@
+use std::fmt;
+
+use transaction_engine::Timestamp;
+
+/// A row whose timestamp is earlier than the previous one by more than the tolerance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutOfOrder {
+    pub ts: Timestamp,
+    pub previous: Timestamp,
+}
+
+impl fmt::Display for OutOfOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let late = self.previous.millis() - self.ts.millis();
+        write!(f, "timestamp {} is {late} ms earlier than the previous row's {}", self.ts, self.previous)
+    }
+}
+
+/// Compares the timestamp of each row with the one of the previous row, for `--detect-out-of-order`.
+/// Rows without timestamp are not compared.
+#[derive(Debug)]
+pub struct OrderCheck {
+    tolerance: i64,
+    previous: Option<Timestamp>,
+}
+
+impl OrderCheck {
+    pub fn new(tolerance_ms: u64) -> OrderCheck {
+        OrderCheck { tolerance: i64::try_from(tolerance_ms).unwrap_or(i64::MAX), previous: None }
+    }
+
+    /// Fails when `ts` is out of order, in which case it is not kept as the previous timestamp unless
+    /// [`OrderCheck::accept`] is called.
+    pub fn check(&mut self, ts: Timestamp) -> Result<(), OutOfOrder> {
+        match self.previous {
+            Some(previous) if previous.millis().saturating_sub(ts.millis()) > self.tolerance => {
+                Err(OutOfOrder { ts, previous })
+            },
+            _ => {
+                self.accept(ts);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn accept(&mut self, ts: Timestamp) {
+        self.previous = Some(ts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(millis: i64) -> Timestamp {
+        Timestamp::from_millis(millis)
+    }
+
+    #[test]
+    fn should_fail_beyond_the_tolerance() {
+        let mut check = OrderCheck::new(100);
+
+        assert_eq!(check.check(ts(1_000)), Ok(()));
+        assert_eq!(check.check(ts(1_000)), Ok(()));
+        assert_eq!(check.check(ts(900)), Ok(()));
+        assert_eq!(check.check(ts(799)), Err(OutOfOrder { ts: ts(799), previous: ts(900) }));
+        assert_eq!(check.check(ts(850)), Ok(()));
+        check.accept(ts(10));
+        assert_eq!(check.check(ts(20)), Ok(()));
+    }
+}
@@ -0,0 +1,235 @@
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use csv::WriterBuilder;
+use serde::{Serialize, Serializer};
+use serde_json::Number;
+use transaction_engine::{Amount, Changes, Client, Currency, DisputeCounters, PersistedTransaction, TxId};
+
+use crate::wire::{JsonClient, Manifest};
+
+/// Format of the client balances report.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Csv,
+    /// A JSON array of client objects
+    Json,
+    /// One JSON client object per line
+    Ndjson,
+}
+
+/// Which clients an output of the balances lists.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Every client
+    #[default]
+    Full,
+    /// The clients changed since the previous output of the run, after a metadata row
+    Incremental,
+}
+
+// A row of the CSV report, the currency column being left out unless an account is in another currency than
+// the base one.
+#[derive(Serialize)]
+struct CsvClient {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+}
+
+impl CsvClient {
+    fn new(client: &Client, with_currency: bool) -> CsvClient {
+        CsvClient {
+            client: client.id(),
+            available: client.available(),
+            held: client.held(),
+            total: client.total(),
+            locked: client.is_locked(),
+            currency: with_currency.then(|| client.currency()),
+        }
+    }
+}
+
+// The decimal string of an amount is a valid JSON number, keeping it as is avoids going
+// through a float and never produces scientific notation.
+pub fn number(amount: Amount) -> Number {
+    Number::from_str(&amount.to_string()).expect("an amount is a valid JSON number")
+}
+
+/// `delimiter` only applies to the CSV format. Clients are written as they are iterated, without collecting them.
+/// With `with_currency`, each row ends with the currency of the account.
+pub fn write_clients<'a, W, I>(output: W, clients: I, format: Format, delimiter: u8, with_currency: bool) -> io::Result<W>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Client>,
+{
+    match format {
+        Format::Csv => {
+            let mut writer = WriterBuilder::new().delimiter(delimiter).from_writer(output);
+            for client in clients {
+                writer.serialize(CsvClient::new(client, with_currency)).map_err(io_error)?;
+            }
+            writer.flush()?;
+            writer.into_inner().map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))
+        },
+        Format::Json => {
+            let mut writer = BufWriter::new(output);
+            serde_json::Serializer::new(&mut writer).collect_seq(clients.into_iter().map(|client| JsonClient::new(client, with_currency)))?;
+            writeln!(writer)?;
+            writer.into_inner().map_err(|e| e.into_error())
+        },
+        Format::Ndjson => {
+            let mut writer = BufWriter::new(output);
+            for client in clients {
+                serde_json::to_writer(&mut writer, &JsonClient::new(client, with_currency))?;
+                writeln!(writer)?;
+            }
+            writer.into_inner().map_err(|e| e.into_error())
+        },
+    }
+}
+
+/// Writes the CSV balances of the accounts in `changes`, after a metadata row: `#` and a JSON object with the
+/// `run_id`, the `first_seq` and `last_seq` of the transactions covered, and the `count` of rows.
+pub fn write_changes<W: Write>(mut output: W, run_id: &str, changes: &Changes, delimiter: u8, with_currency: bool) -> io::Result<W> {
+    let manifest = Manifest { run_id, first_seq: changes.first_seq, last_seq: changes.last_seq, count: changes.clients.len() };
+    writeln!(output, "#{}", serde_json::to_string(&manifest)?)?;
+    write_clients(output, &changes.clients, Format::Csv, delimiter, with_currency)
+}
+
+// Keeps the kind of an I/O error, which the conversion of csv makes `Other`, so that a closed pipe is told apart.
+fn io_error(e: csv::Error) -> io::Error {
+    let kind = match e.kind() {
+        csv::ErrorKind::Io(e) => e.kind(),
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+/// Writes the disputed transactions as `tx,client,amount` CSV rows, in the given order.
+pub fn write_disputes<'a, W, I>(output: W, disputes: I) -> io::Result<W>
+where
+    W: Write,
+    I: IntoIterator<Item = (TxId, &'a PersistedTransaction)>,
+{
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(["tx", "client", "amount"])?;
+    for (tx_id, transaction) in disputes {
+        writer.write_record([tx_id.to_string(), transaction.client_id().to_string(), transaction.amount().to_string()])?;
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))
+}
+
+/// Writes the flagged clients as `client,disputes,chargebacks,charged_back` CSV rows, in the given order.
+pub fn write_flagged<W, I>(output: W, flagged: I) -> io::Result<W>
+where
+    W: Write,
+    I: IntoIterator<Item = (u16, DisputeCounters)>,
+{
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(["client", "disputes", "chargebacks", "charged_back"])?;
+    for (client_id, counters) in flagged {
+        writer.write_record([
+            client_id.to_string(), counters.disputes.to_string(), counters.chargebacks.to_string(), counters.charged_back.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(format: Format) -> String {
+        written_with(format, b',')
+    }
+
+    fn written_with(format: Format, delimiter: u8) -> String {
+        let clients = vec![
+            Client::new(1, "1.5".parse().unwrap(), "0.0001".parse().unwrap(), "1.5001".parse().unwrap(), false),
+            Client::new(2, "-40".parse().unwrap(), "50".parse().unwrap(), "10".parse().unwrap(), true),
+        ];
+        String::from_utf8(write_clients(vec![], &clients, format, delimiter, false).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_should_write_header_and_rows() {
+        assert_eq!(written(Format::Csv), "client,available,held,total,locked\n1,1.5,0.0001,1.5001,false\n2,-40.0,50.0,10.0,true\n");
+    }
+
+    #[test]
+    fn csv_should_use_the_given_delimiter() {
+        assert!(written_with(Format::Csv, b';').starts_with("client;available;held;total;locked\n1;1.5;0.0001;1.5001;false\n"));
+    }
+
+    #[test]
+    fn json_should_write_an_array_with_plain_numbers() {
+        assert_eq!(
+            written(Format::Json),
+            "[{\"client\":1,\"available\":1.5,\"held\":0.0001,\"total\":1.5001,\"locked\":false},\
+              {\"client\":2,\"available\":-40.0,\"held\":50.0,\"total\":10.0,\"locked\":true}]\n"
+        );
+    }
+
+    #[test]
+    fn with_currency_should_end_each_row_with_the_currency() {
+        let eur = "EUR".parse().unwrap();
+        let clients = vec![
+            Client::new(1, "1.5".parse().unwrap(), Amount::ZERO, "1.5".parse().unwrap(), false),
+            Client::new(1, "2".parse().unwrap(), Amount::ZERO, "2".parse().unwrap(), true).in_currency(eur),
+        ];
+        let written = |format| String::from_utf8(write_clients(vec![], &clients, format, b',', true).unwrap()).unwrap();
+
+        assert_eq!(written(Format::Csv), "client,available,held,total,locked,currency\n1,1.5,0.0,1.5,false,USD\n1,2.0,0.0,2.0,true,EUR\n");
+        assert_eq!(
+            written(Format::Ndjson).lines().nth(1),
+            Some("{\"client\":1,\"available\":2.0,\"held\":0.0,\"total\":2.0,\"locked\":true,\"currency\":\"EUR\"}")
+        );
+    }
+
+    #[test]
+    fn disputes_should_write_tx_client_and_amount() {
+        let deposit = PersistedTransaction::Deposit { client_id: 2, tx_id: 4, amount: "1.5".parse().unwrap(), ts: None, currency: Currency::USD };
+        let withdrawal = PersistedTransaction::Withdrawal { client_id: 1, tx_id: 7, amount: "0.25".parse().unwrap(), ts: None, currency: Currency::USD };
+
+        let written = write_disputes(vec![], [(4, &deposit), (7, &withdrawal)]).unwrap();
+
+        assert_eq!(String::from_utf8(written).unwrap(), "tx,client,amount\n4,2,1.5\n7,1,0.25\n");
+    }
+
+    #[test]
+    fn csv_should_keep_the_kind_of_a_write_error() {
+        struct ClosedPipe;
+        impl Write for ClosedPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let clients: Vec<Client> = (1..=1000).map(|id| Client::new(id, Amount::ZERO, Amount::ZERO, Amount::ZERO, false)).collect();
+
+        for format in [Format::Csv, Format::Json, Format::Ndjson] {
+            let e = write_clients(ClosedPipe, &clients, format, b',', false).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::BrokenPipe, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn ndjson_should_write_one_object_per_line() {
+        let data = written(Format::Ndjson);
+        let lines: Vec<&str> = data.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "{\"client\":1,\"available\":1.5,\"held\":0.0001,\"total\":1.5001,\"locked\":false}");
+    }
+}
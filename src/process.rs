@@ -0,0 +1,109 @@
+//! Reading transactions from a CSV source, see [`TransactionEngine::process_csv`].
+
+use std::fmt;
+use std::io::Read;
+
+use csv::ByteRecord;
+
+use crate::batch::BatchSummary;
+use crate::csv_model;
+use crate::transaction_engine::TransactionEngine;
+
+/// Error stopping [`TransactionEngine::process_csv`] before the end of its input.
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The input could not be read.
+    Read(csv::Error),
+    /// The header has no column of this name, see [`csv_model::COLUMNS`].
+    MissingColumn(&'static str),
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::Read(e) => write!(f, "cannot read the input: {e}"),
+            ProcessError::MissingColumn(column) => {
+                write!(f, "no {column} column, expected the columns {}", csv_model::COLUMNS.join(","))
+            },
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+impl TransactionEngine {
+    /// Applies the rows of a `type,client,tx,amount` CSV source. Malformed rows and rejected
+    /// transactions are counted in the summary and skipped, only a header without the expected
+    /// columns or a failing reader stops the processing.
+    pub fn process_csv<R: Read>(&mut self, reader: R) -> Result<BatchSummary, ProcessError> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(reader);
+        let headers = reader.headers().map_err(ProcessError::Read)?.clone();
+        if let Some(column) = csv_model::missing_column(&headers) {
+            return Err(ProcessError::MissingColumn(column))
+        }
+
+        // Rows are read as bytes, only those the fast path of a canonical header defers are decoded.
+        let parser = csv_model::RowParser::new(&headers);
+        let mut summary = BatchSummary::new();
+        let mut row = ByteRecord::new();
+        loop {
+            match reader.read_byte_record(&mut row) {
+                Ok(false) => break,
+                Ok(true) => match parser.parse_bytes(&row) {
+                    Ok((transaction, ts, currency)) => match self.compute_transaction_in(transaction, currency.unwrap_or(self.config().base_currency), ts) {
+                        Ok(()) => summary.applied(),
+                        Err(e) => summary.rejected_tx(transaction.tx_id(), e.code()),
+                    },
+                    Err(e) => summary.rejected(e.code()),
+                },
+                Err(e) if e.is_io_error() => return Err(ProcessError::Read(e)),
+                Err(_) => summary.rejected("parse_error"),
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_apply_the_rows_and_skip_the_malformed_ones() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 10.0
+withdrawal, 1, 2, 4.0
+deposit, 2, 3, 2.0
+withdrawal, 2, 4, 5.0
+deposit, x, 5, 1.0
+withdrawal, 1, 6,
+dispute, 1, 1
+dispute, 2, 3,
+";
+        let mut engine = TransactionEngine::new();
+
+        let summary = engine.process_csv(input.as_bytes()).unwrap();
+
+        assert_eq!(summary.read_count(), 8);
+        assert_eq!(summary.applied_count(), 5);
+        assert_eq!(summary.count("insufficient_funds"), 1);
+        assert_eq!(summary.count("parse_error"), 1);
+        assert_eq!(summary.count("missing_amount"), 1);
+        assert_eq!(summary.rejected_tx_ids(), &[4]);
+        let client = engine.get_client(1).unwrap();
+        // The dispute without amount column is applied like the one with an empty amount.
+        assert_eq!((client.available(), client.held()), ("-4.0".parse().unwrap(), "10.0".parse().unwrap()));
+        let client = engine.get_client(2).unwrap();
+        assert_eq!((client.available(), client.held()), ("0.0".parse().unwrap(), "2.0".parse().unwrap()));
+    }
+
+    #[test]
+    fn when_a_column_is_missing_should_fail() {
+        let mut engine = TransactionEngine::new();
+
+        let result = engine.process_csv("type,client,amount\ndeposit,1,1.0\n".as_bytes());
+
+        assert!(matches!(result, Err(ProcessError::MissingColumn("tx"))));
+        assert_eq!(engine.client_count(), 0);
+    }
+}
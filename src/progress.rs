@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use transaction_engine::{Clock, MemoryEstimate, SystemClock, Timestamp};
+
+/// Rows between two updates of the progress line.
+const EVERY: u64 = 100_000;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Single progress line of an input, rewritten in place every [`EVERY`] rows.
+pub struct Progress<W: Write> {
+    output: W,
+    bytes: Arc<AtomicU64>,
+    size: Option<u64>,
+    rows: u64,
+    clock: Box<dyn Clock>,
+    started: Timestamp,
+    // Estimated bytes of the engine state when the line was last written.
+    memory: Option<u64>,
+}
+
+impl<W: Write> Progress<W> {
+    /// `size` is the size of the input when it is a regular file, the line then shows how much of it was read.
+    pub fn new(output: W, size: Option<u64>) -> Progress<W> {
+        Progress::with_clock(output, size, SystemClock)
+    }
+
+    /// Like [`Progress::new`], the rate being measured on the time of `clock`.
+    pub fn with_clock<C: Clock + 'static>(output: W, size: Option<u64>, clock: C) -> Progress<W> {
+        let started = clock.now();
+        Progress { output, bytes: Arc::new(AtomicU64::new(0)), size, rows: 0, clock: Box::new(clock), started, memory: None }
+    }
+
+    /// Wraps the input so that the bytes read from it are counted, possibly on another thread.
+    pub fn counting<R: Read>(&self, input: R) -> CountingReader<R> {
+        CountingReader { input, bytes: Arc::clone(&self.bytes) }
+    }
+
+    /// Counts a row, `memory` estimating the bytes of the engine state when the line is due, `None` when unknown.
+    pub fn row(&mut self, memory: impl FnOnce() -> Option<MemoryEstimate>) {
+        self.rows += 1;
+        if self.rows.is_multiple_of(EVERY) {
+            self.memory = memory().map(|estimate| estimate.total_bytes());
+            // Progress is best effort, a failing stderr must not stop the run.
+            let _ = self.write_line();
+        }
+    }
+
+    /// Writes the final state of the line and ends it.
+    pub fn finish(mut self) {
+        let _ = self.write_line().and_then(|()| writeln!(self.output));
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let elapsed = self.clock.now().duration_since(self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 { self.rows as f64 / elapsed } else { 0.0 };
+        match self.size {
+            Some(size) => {
+                let bytes = self.bytes.load(Ordering::Relaxed);
+                let percent = if size > 0 { bytes as f64 * 100.0 / size as f64 } else { 100.0 };
+                write!(
+                    self.output,
+                    "\r{:.1}/{:.1} MiB ({percent:.0}%), {} rows, {rate:.0} rows/s",
+                    bytes as f64 / MIB, size as f64 / MIB, self.rows
+                )?;
+            },
+            None => write!(self.output, "\r{} rows, {rate:.0} rows/s", self.rows)?,
+        }
+        if let Some(memory) = self.memory {
+            write!(self.output, ", state ~{:.1} MiB", memory as f64 / MIB)?;
+        }
+        self.output.flush()
+    }
+}
+
+pub struct CountingReader<R> {
+    input: R,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use transaction_engine::ManualClock;
+
+    use super::*;
+
+    #[test]
+    fn should_count_the_bytes_read() {
+        let progress = Progress::new(vec![], Some(11));
+        let mut read = String::new();
+
+        progress.counting("type,client".as_bytes()).read_to_string(&mut read).unwrap();
+
+        assert_eq!(progress.bytes.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn when_size_is_known_should_show_bytes_and_rows() {
+        let mut output = vec![];
+        let mut progress = Progress::new(&mut output, Some(2 * 1024 * 1024));
+        progress.bytes.store(1024 * 1024, Ordering::Relaxed);
+        progress.row(|| None);
+        progress.finish();
+
+        let line = String::from_utf8(output).unwrap();
+        assert!(line.starts_with("\r1.0/2.0 MiB (50%), 1 rows, "), "{line}");
+        assert!(line.ends_with(" rows/s\n"), "{line}");
+    }
+
+    #[test]
+    fn when_size_is_unknown_should_only_show_rows() {
+        let mut output = vec![];
+        let mut progress = Progress::new(&mut output, None);
+        for _ in 0..EVERY {
+            progress.row(|| None);
+        }
+
+        let line = String::from_utf8(output).unwrap();
+        assert!(line.starts_with("\r100000 rows, "), "{line}");
+    }
+
+    #[test]
+    fn should_show_the_rate_over_the_time_of_the_clock() {
+        let mut output = vec![];
+        let clock = ManualClock::new(Timestamp::from_millis(0));
+        let mut progress = Progress::with_clock(&mut output, None, clock.clone());
+        clock.advance(Duration::from_secs(4));
+        for _ in 0..EVERY {
+            progress.row(|| None);
+        }
+
+        let line = String::from_utf8(output).unwrap();
+        assert_eq!(line, "\r100000 rows, 25000 rows/s");
+    }
+
+    #[test]
+    fn when_memory_is_estimated_should_show_the_state_size() {
+        let mut output = vec![];
+        let mut progress = Progress::new(&mut output, None);
+        let estimate = MemoryEstimate { client_bytes: 1024 * 1024, transaction_bytes: 2 * 1024 * 1024, ..MemoryEstimate::default() };
+        for _ in 0..EVERY {
+            progress.row(|| Some(estimate));
+        }
+        progress.finish();
+
+        let line = String::from_utf8(output).unwrap();
+        assert!(line.ends_with(" rows/s, state ~3.0 MiB\n"), "{line}");
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+
+use transaction_engine::amount::ParseAmountError;
+use transaction_engine::csv_model::RowError;
+use transaction_engine::sentinel::SentinelRejection;
+use transaction_engine::{TransactionError, TxId};
+
+use crate::out_of_order::OutOfOrder;
+
+/// Reason a row of the input was not applied.
+#[derive(Debug)]
+pub enum Rejection {
+    /// The row could not be read or deserialized, is a deposit or withdrawal without amount, or has an unsupported type.
+    Row(RowError),
+    /// An amount with more than four decimal places under `--strict-precision`.
+    Precision { tx_id: TxId, error: ParseAmountError },
+    Sentinel(SentinelRejection),
+    /// A row earlier than the previous one under `--detect-out-of-order --strict`.
+    OutOfOrder(OutOfOrder),
+    Engine(TransactionError),
+}
+
+impl Rejection {
+    /// Whether the row itself is malformed, as opposed to a well formed transaction that was refused. A row of an
+    /// unsupported type is skipped rather than malformed.
+    pub fn is_malformed(&self) -> bool {
+        matches!(self, Rejection::Row(e) if !matches!(e, RowError::UnsupportedType(_)))
+    }
+
+    /// Snake case name of the reason, used to count rejections.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::Row(e) => e.code(),
+            Rejection::Precision { .. } => "too_precise",
+            Rejection::Sentinel(SentinelRejection::SentinelClientId(_)) => "sentinel_client_id",
+            Rejection::Sentinel(SentinelRejection::SentinelTxId(_)) => "sentinel_tx_id",
+            Rejection::OutOfOrder(_) => "out_of_order",
+            Rejection::Engine(e) => e.code(),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Row(e) => write!(f, "{e}"),
+            Rejection::Precision { tx_id, error } => write!(f, "precision: {error} on tx {tx_id}"),
+            Rejection::Sentinel(e) => write!(f, "{e}"),
+            Rejection::OutOfOrder(e) => write!(f, "{e}"),
+            Rejection::Engine(e) => write!(f, "{e}"),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Rebuilding the accounts of a run from the events of its engine, see [`replay_audit`].
+
+use std::collections::BTreeMap;
+
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::events::EngineEvent;
+use crate::transaction_engine::Client;
+
+/// An event of an engine, numbered from 1 in the order it was sent, with the currency of the transaction
+/// that caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub seq: u64,
+    pub currency: Currency,
+    pub event: EngineEvent,
+}
+
+/// Accounts rebuilt from the events applied so far, along with the last event of each.
+#[derive(Debug, Default)]
+pub struct AuditReplay {
+    accounts: BTreeMap<(u16, Currency), (Client, u64)>,
+}
+
+impl AuditReplay {
+    pub fn new() -> AuditReplay {
+        AuditReplay::default()
+    }
+
+    /// Applies the change described by `event` to the account it names, opening it if needed.
+    pub fn apply(&mut self, event: &AuditEvent) {
+        let (client_id, available, held, locked) = match event.event {
+            EngineEvent::AccountOpened { client_id, .. } => (client_id, Amount::ZERO, Amount::ZERO, None),
+            EngineEvent::AccountCredited { client_id, amount, .. } => (client_id, amount, Amount::ZERO, None),
+            EngineEvent::AccountDebited { client_id, amount, .. } => (client_id, -amount, Amount::ZERO, None),
+            EngineEvent::FundsHeld { client_id, amount, .. } => (client_id, -amount, amount, None),
+            EngineEvent::FundsReleased { client_id, amount, .. } => (client_id, amount, -amount, None),
+            EngineEvent::HeldCredited { client_id, amount, .. } => (client_id, Amount::ZERO, amount, None),
+            EngineEvent::HeldDebited { client_id, amount, .. } => (client_id, Amount::ZERO, -amount, None),
+            EngineEvent::AccountLocked { client_id, .. } => (client_id, Amount::ZERO, Amount::ZERO, Some(true)),
+            EngineEvent::AccountUnlocked { client_id, .. } => (client_id, Amount::ZERO, Amount::ZERO, Some(false)),
+            EngineEvent::TransactionRejected { .. } => return,
+        };
+        let (client, seq) = self.accounts.entry((client_id, event.currency)).or_insert_with(|| {
+            (Client::new(client_id, Amount::ZERO, Amount::ZERO, Amount::ZERO, false).in_currency(event.currency), 0)
+        });
+        *client = Client::new(
+            client_id,
+            client.available() + available,
+            client.held() + held,
+            client.total() + available + held,
+            locked.unwrap_or(client.is_locked()),
+        ).in_currency(event.currency);
+        *seq = event.seq;
+    }
+
+    /// The rebuilt account of `client_id` in `currency`, if an event named it.
+    pub fn get(&self, client_id: u16, currency: Currency) -> Option<&Client> {
+        self.accounts.get(&(client_id, currency)).map(|(client, _)| client)
+    }
+
+    /// Sequence number of the last event of the account of `client_id` in `currency`.
+    pub fn last_seq(&self, client_id: u16, currency: Currency) -> Option<u64> {
+        self.accounts.get(&(client_id, currency)).map(|(_, seq)| *seq)
+    }
+
+    /// The rebuilt accounts, by client id then currency.
+    pub fn clients(&self) -> impl Iterator<Item = &Client> + '_ {
+        self.accounts.values().map(|(client, _)| client)
+    }
+}
+
+/// Rebuilds the accounts of a run from the events its engine sent, starting from an engine without accounts.
+/// The accounts come by client id then currency, and match those of the engine once every event is applied.
+pub fn replay_audit(events: impl Iterator<Item = AuditEvent>) -> Vec<Client> {
+    let mut replay = AuditReplay::new();
+    events.for_each(|event| replay.apply(&event));
+    replay.clients().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::transaction_engine::{Transaction, TransactionEngine};
+
+    // Runs `transactions` through an engine built with `config`, returning its accounts sorted as the replay
+    // ones, and its events numbered with the currency of their transaction.
+    fn run(transactions: &[(Transaction, Currency)], config: EngineConfig) -> (Vec<Client>, Vec<AuditEvent>) {
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = TransactionEngine::with_config(config);
+        let recorded = Arc::clone(&emitted);
+        engine.set_observer(move |event: &EngineEvent| recorded.lock().unwrap().push(*event));
+
+        let mut events = vec![];
+        for (transaction, currency) in transactions {
+            let _ = engine.compute_transaction_in(*transaction, *currency, None);
+            for event in emitted.lock().unwrap().drain(..) {
+                events.push(AuditEvent { seq: events.len() as u64 + 1, currency: *currency, event });
+            }
+        }
+        let mut clients: Vec<Client> = engine.clients().copied().collect();
+        clients.sort_by_key(|client| (client.id(), client.currency()));
+        (clients, events)
+    }
+
+    // Deterministic mix of every transaction type in two currencies, most references being to earlier
+    // transactions, of the same client or not.
+    fn workload(seed: u64) -> Vec<(Transaction, Currency)> {
+        let mut seed = seed;
+        let mut next = move |modulo: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % modulo
+        };
+        let currencies = [Currency::default(), "EUR".parse().unwrap()];
+        (1..=2_000u64)
+            .map(|tx_id| {
+                let client_id = next(20) as u16;
+                let amount = Amount::from_units(next(100_000) as i64 + 1);
+                let referenced = next(tx_id) + 1;
+                let transaction = match next(24) {
+                    0..=2 => Transaction::Dispute { client_id, tx_id: referenced },
+                    3 => Transaction::Resolve { client_id, tx_id: referenced },
+                    4 => Transaction::Chargeback { client_id, tx_id: referenced },
+                    5 => Transaction::Unlock { client_id, tx_id },
+                    6 => Transaction::Fee { client_id, tx_id, amount },
+                    7 => Transaction::Adjustment { client_id, tx_id, amount: -amount },
+                    8 => Transaction::Adjustment { client_id, tx_id, amount },
+                    9..=11 => Transaction::Transfer { from: client_id, to: next(20) as u16, tx_id, amount },
+                    12..=16 => Transaction::Withdrawal { client_id, tx_id, amount },
+                    _ => Transaction::Deposit { client_id, tx_id, amount },
+                };
+                (transaction, currencies[next(4).min(1) as usize])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay_should_rebuild_the_accounts_of_any_run() {
+        let configs = [
+            EngineConfig::default(),
+            EngineConfig::default().compact_locked(true).max_disputes_per_tx(Some(1)).max_stored_transactions(Some(100)),
+            EngineConfig::default().reject_deposits_when_locked(false).credit_limit(Amount::from_units(50_000)),
+            EngineConfig::default().allow_dispute_on_locked(false).allow_negative_available_on_dispute(false),
+        ];
+        for seed in 0..20 {
+            for config in configs {
+                let (clients, events) = run(&workload(seed), config);
+
+                assert_eq!(replay_audit(events.into_iter()), clients, "seed {seed}, config {config:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn replay_should_open_the_accounts_of_rejected_transactions() {
+        let usd = Currency::default();
+        let (clients, events) = run(&[
+            (Transaction::Withdrawal { client_id: 1, tx_id: 1, amount: Amount::from_units(10_000) }, usd),
+            (Transaction::Dispute { client_id: 2, tx_id: 1 }, usd),
+        ], EngineConfig::default());
+
+        assert_eq!(clients, vec![Client::new(1, Amount::ZERO, Amount::ZERO, Amount::ZERO, false)]);
+        assert_eq!(replay_audit(events.into_iter()), clients);
+    }
+
+    #[test]
+    fn replay_should_keep_the_last_event_of_each_account() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut replay = AuditReplay::new();
+        let amount = Amount::from_units(10_000);
+        replay.apply(&AuditEvent { seq: 1, currency: eur, event: EngineEvent::AccountCredited { client_id: 1, tx_id: 1, amount } });
+        replay.apply(&AuditEvent { seq: 2, currency: eur, event: EngineEvent::HeldCredited { client_id: 1, tx_id: 2, amount } });
+        replay.apply(&AuditEvent { seq: 3, currency: eur, event: EngineEvent::AccountLocked { client_id: 1, tx_id: 2 } });
+
+        assert_eq!(replay.get(1, eur), Some(&Client::new(1, amount, amount, amount + amount, true).in_currency(eur)));
+        assert_eq!(replay.last_seq(1, eur), Some(3));
+        assert_eq!(replay.get(1, Currency::default()), None);
+    }
+}
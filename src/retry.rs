@@ -0,0 +1,298 @@
+//! Parking the disputes, resolves and chargebacks that come before the transaction they reference, see
+//! [`TransactionEngine::set_retry_queue`](crate::TransactionEngine::set_retry_queue).
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::collections::FastHashMap;
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Transaction, TxId};
+
+/// How long a parked transaction waits for the tx it references.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireAfter {
+    /// Transactions applied by the engine since it was parked, rejected ones included.
+    Sequences(u64),
+    /// Time since it was parked, as told by the clock of the engine.
+    Duration(Duration),
+}
+
+/// Settings of the retry queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryQueueConfig {
+    /// Parked transactions at most, those coming once the queue is full being rejected and counted as overflow.
+    pub capacity: usize,
+    pub expire_after: ExpireAfter,
+}
+
+/// Type of a parked transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Reference {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/// A dispute, resolve or chargeback waiting for the tx it references, with the currency and time it came with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ParkedTransaction {
+    pub r#type: Reference,
+    pub client_id: u16,
+    pub tx_id: TxId,
+    pub currency: Currency,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ts: Option<Timestamp>,
+}
+
+impl ParkedTransaction {
+    /// The transaction to park, if it references a tx.
+    pub fn new(transaction: Transaction, currency: Currency, ts: Option<Timestamp>) -> Option<ParkedTransaction> {
+        let (r#type, client_id, tx_id) = match transaction {
+            Transaction::Dispute { client_id, tx_id } => (Reference::Dispute, client_id, tx_id),
+            Transaction::Resolve { client_id, tx_id } => (Reference::Resolve, client_id, tx_id),
+            Transaction::Chargeback { client_id, tx_id } => (Reference::Chargeback, client_id, tx_id),
+            _ => return None,
+        };
+        Some(ParkedTransaction { r#type, client_id, tx_id, currency, ts })
+    }
+
+    pub fn transaction(&self) -> Transaction {
+        let (client_id, tx_id) = (self.client_id, self.tx_id);
+        match self.r#type {
+            Reference::Dispute => Transaction::Dispute { client_id, tx_id },
+            Reference::Resolve => Transaction::Resolve { client_id, tx_id },
+            Reference::Chargeback => Transaction::Chargeback { client_id, tx_id },
+        }
+    }
+}
+
+// The parked transactions in the order they came, with the sequence number and time they were parked at, and
+// how many reference each tx id.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RetryQueue {
+    pub(crate) config: Option<RetryQueueConfig>,
+    parked: VecDeque<(ParkedTransaction, u64, Timestamp)>,
+    waiting: FastHashMap<TxId, usize>,
+    // Transactions applied since the queue was set.
+    sequence: u64,
+}
+
+impl RetryQueue {
+    pub(crate) fn len(&self) -> usize {
+        self.parked.len()
+    }
+
+    pub(crate) fn tick(&mut self) {
+        self.sequence += 1;
+    }
+
+    pub(crate) fn is_waiting_for(&self, tx_id: TxId) -> bool {
+        self.waiting.contains_key(&tx_id)
+    }
+
+    // Parks `parked` unless the queue is disabled or full.
+    pub(crate) fn park(&mut self, parked: ParkedTransaction, now: Timestamp) -> bool {
+        if self.config.is_none_or(|config| self.parked.len() >= config.capacity) {
+            return false
+        }
+        self.push(parked, now);
+        true
+    }
+
+    fn push(&mut self, parked: ParkedTransaction, now: Timestamp) {
+        *self.waiting.entry(parked.tx_id).or_default() += 1;
+        self.parked.push_back((parked, self.sequence, now));
+    }
+
+    // Whether a transaction parked at `sequence` and `time` waited longer than the config allows, as of `now`.
+    fn is_expired(&self, now: Timestamp) -> impl Fn(u64, Timestamp) -> bool {
+        let (expire_after, current) = (self.config.map(|config| config.expire_after), self.sequence);
+        move |sequence, time| match expire_after {
+            Some(ExpireAfter::Sequences(sequences)) => current - sequence >= sequences,
+            Some(ExpireAfter::Duration(duration)) => now.duration_since(time) >= duration,
+            None => false,
+        }
+    }
+
+    // Removes the transactions waiting for `tx_id`, in the order they came. The expired ones are left to
+    // `expire`, even when it has not run since they expired.
+    pub(crate) fn take(&mut self, tx_id: TxId, now: Timestamp) -> Vec<ParkedTransaction> {
+        if !self.waiting.contains_key(&tx_id) {
+            return Vec::new()
+        }
+        let is_expired = self.is_expired(now);
+        let taken: Vec<ParkedTransaction> = self.parked.iter()
+            .filter(|(parked, sequence, time)| parked.tx_id == tx_id && !is_expired(*sequence, *time))
+            .map(|(parked, _, _)| *parked)
+            .collect();
+        if !taken.is_empty() {
+            let expired = self.waiting[&tx_id] - taken.len();
+            self.parked.retain(|(parked, sequence, time)| parked.tx_id != tx_id || is_expired(*sequence, *time));
+            match expired {
+                0 => self.waiting.remove(&tx_id),
+                expired => self.waiting.insert(tx_id, expired),
+            };
+        }
+        taken
+    }
+
+    // Removes the transactions parked for longer than the config allows, the oldest first.
+    pub(crate) fn expire(&mut self, now: Timestamp) -> Vec<ParkedTransaction> {
+        let is_expired = self.is_expired(now);
+        let mut expired = Vec::new();
+        while let Some(&(parked, sequence, time)) = self.parked.front() {
+            if !is_expired(sequence, time) {
+                break
+            }
+            self.parked.pop_front();
+            if let Some(waiting) = self.waiting.get_mut(&parked.tx_id) {
+                *waiting -= 1;
+                if *waiting == 0 {
+                    self.waiting.remove(&parked.tx_id);
+                }
+            }
+            expired.push(parked);
+        }
+        expired
+    }
+
+    pub(crate) fn parked(&self) -> impl Iterator<Item = &ParkedTransaction> + '_ {
+        self.parked.iter().map(|(parked, _, _)| parked)
+    }
+
+    // Parks transactions of a snapshot or of another engine, as if they came `now`.
+    pub(crate) fn extend(&mut self, parked: impl IntoIterator<Item = ParkedTransaction>, now: Timestamp) {
+        for parked in parked {
+            self.push(parked, now);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.parked.clear();
+        self.waiting.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::clock::ManualClock;
+    use crate::transaction_engine::{TransactionEngine, TransactionError, TxStatus};
+
+    fn deposit(client_id: u16, tx_id: TxId) -> Transaction {
+        Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(10_000) }
+    }
+
+    fn engine(capacity: usize, expire_after: ExpireAfter) -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        engine.set_retry_queue(RetryQueueConfig { capacity, expire_after });
+        engine
+    }
+
+    #[test]
+    fn a_dispute_before_its_deposit_should_be_applied_once_the_deposit_comes() {
+        let mut engine = engine(10, ExpireAfter::Sequences(100));
+
+        assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }), Err(TransactionError::Parked { tx_id: 1 }));
+        assert_eq!(engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }), Err(TransactionError::Parked { tx_id: 1 }));
+        engine.compute_transaction(deposit(2, 2)).unwrap();
+        assert_eq!(engine.metrics().parked_transactions, 2);
+        engine.compute_transaction(deposit(1, 1)).unwrap();
+
+        assert_eq!(engine.transaction_state(1), TxStatus::ChargedBack);
+        let client = engine.get_client(1).unwrap();
+        assert_eq!((client.total(), client.is_locked()), (Amount::ZERO, true));
+        let metrics = engine.metrics();
+        assert_eq!((metrics.parked_transactions, metrics.parked_retried), (0, 2));
+        assert_eq!((metrics.dispute.applied, metrics.chargeback.applied, metrics.dispute.rejected), (1, 1, 0));
+    }
+
+    #[test]
+    fn a_parked_transaction_should_expire_after_the_configured_sequences() {
+        let mut engine = engine(10, ExpireAfter::Sequences(2));
+        let _ = engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 });
+        engine.compute_transaction(deposit(2, 2)).unwrap();
+        assert_eq!(engine.expire_parked(), vec![]);
+        engine.compute_transaction(deposit(2, 3)).unwrap();
+        // Expired, if not removed yet.
+        engine.compute_transaction(deposit(1, 1)).unwrap();
+
+        let expired = engine.expire_parked();
+
+        assert_eq!(expired, vec![ParkedTransaction::new(Transaction::Dispute { client_id: 1, tx_id: 1 }, Currency::default(), None).unwrap()]);
+        assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+        assert_eq!((engine.metrics().parked_expired, engine.metrics().parked_retried), (1, 0));
+    }
+
+    #[test]
+    fn a_parked_transaction_should_expire_after_the_configured_duration() {
+        let mut engine = engine(10, ExpireAfter::Duration(Duration::ZERO));
+        let _ = engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 });
+
+        assert_eq!(engine.expire_parked().len(), 1);
+        assert_eq!(engine.metrics().parked_transactions, 0);
+    }
+
+    #[test]
+    fn a_parked_transaction_should_expire_once_the_clock_reaches_the_duration() {
+        let clock = ManualClock::new("2024-01-01T00:00:00Z".parse().unwrap());
+        let mut engine = TransactionEngine::new();
+        engine.set_clock(clock.clone());
+        engine.set_retry_queue(RetryQueueConfig { capacity: 10, expire_after: ExpireAfter::Duration(Duration::from_secs(30)) });
+        let _ = engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 });
+        let _ = engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 });
+
+        clock.advance(Duration::from_millis(29_999));
+        assert_eq!(engine.expire_parked(), vec![]);
+        engine.compute_transaction(deposit(1, 1)).unwrap();
+        assert_eq!(engine.transaction_state(1), TxStatus::Disputed);
+        clock.advance(Duration::from_millis(1));
+        // Expired, if not removed yet.
+        engine.compute_transaction(deposit(1, 2)).unwrap();
+        assert_eq!(engine.transaction_state(2), TxStatus::Settled);
+
+        assert_eq!(engine.expire_parked().len(), 1);
+        assert_eq!((engine.metrics().parked_expired, engine.metrics().parked_retried), (1, 1));
+    }
+
+    #[test]
+    fn a_full_queue_should_reject_and_count_the_overflow() {
+        let mut engine = engine(2, ExpireAfter::Sequences(100));
+        for tx_id in 1..=2 {
+            assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id }), Err(TransactionError::Parked { tx_id }));
+        }
+
+        assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 3 }), Err(TransactionError::UnknownTransaction { tx_id: 3 }));
+
+        let metrics = engine.metrics();
+        assert_eq!((metrics.parked_transactions, metrics.parked_overflow, metrics.dispute.rejected), (2, 1, 1));
+        engine.compute_transaction(deposit(1, 3)).unwrap();
+        assert_eq!(engine.transaction_state(3), TxStatus::Settled);
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn the_parked_transactions_should_be_saved_in_snapshots() {
+        let mut engine = engine(10, ExpireAfter::Sequences(100));
+        let eur: Currency = "EUR".parse().unwrap();
+        let ts = Some("2024-01-01T00:00:00Z".parse().unwrap());
+        let _ = engine.compute_transaction_in(Transaction::Dispute { client_id: 1, tx_id: 1 }, eur, ts);
+        let mut saved = Vec::new();
+        engine.save_snapshot(&mut saved).unwrap();
+
+        let mut loaded = TransactionEngine::load_snapshot(saved.as_slice()).unwrap();
+
+        assert_eq!(loaded.metrics().parked_transactions, 1);
+        loaded.compute_transaction_in(deposit(1, 1), eur, None).unwrap();
+        assert_eq!(loaded.transaction_state(1), TxStatus::Disputed);
+        assert_eq!(loaded.get_account(1, eur).unwrap().held(), Amount::from_units(10_000));
+    }
+}
@@ -0,0 +1,158 @@
+//! Rejection of client and tx ids that partners use as "unknown" sentinels.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::transaction_engine::{Transaction, TxId};
+
+/// Reason a transaction was rejected by [`SentinelIds::check`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum SentinelRejection {
+    SentinelClientId(u16),
+    SentinelTxId(TxId),
+}
+
+impl fmt::Display for SentinelRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SentinelRejection::SentinelClientId(id) => write!(f, "sentinel client id {id}"),
+            SentinelRejection::SentinelTxId(id) => write!(f, "sentinel tx id {id}"),
+        }
+    }
+}
+
+/// Set of client and tx ids to reject. Empty by default.
+#[derive(Debug, Default)]
+pub struct SentinelIds {
+    client_ids: HashSet<u16>,
+    tx_ids: HashSet<TxId>,
+}
+
+fn parse_ids<T>(spec: &str, max: T) -> Result<Vec<T>, String>
+where
+    T: FromStr + From<u8> + Copy,
+{
+    spec.split(',')
+        .map(str::trim)
+        .map(|id| match id {
+            "zero" => Ok(T::from(0)),
+            "max" => Ok(max),
+            _ => id.parse().map_err(|_| format!("invalid sentinel id {id}, expected a number, zero or max")),
+        })
+        .collect()
+}
+
+impl SentinelIds {
+    pub fn new() -> SentinelIds {
+        SentinelIds::default()
+    }
+
+    /// Adds comma separated client ids, accepting the `zero` and `max` keywords.
+    pub fn add_client_ids(&mut self, spec: &str) -> Result<(), String> {
+        self.client_ids.extend(parse_ids(spec, u16::MAX)?);
+        Ok(())
+    }
+
+    /// Adds comma separated tx ids, accepting the `zero` and `max` keywords.
+    pub fn add_tx_ids(&mut self, spec: &str) -> Result<(), String> {
+        self.tx_ids.extend(parse_ids(spec, TxId::MAX)?);
+        Ok(())
+    }
+
+    /// Rejects a transaction whose client id, recipient of a transfer included, or tx id is a sentinel.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), SentinelRejection> {
+        if self.client_ids.contains(&transaction.client_id()) {
+            return Err(SentinelRejection::SentinelClientId(transaction.client_id()))
+        }
+        if let Transaction::Transfer { to, .. } = transaction {
+            if self.client_ids.contains(to) {
+                return Err(SentinelRejection::SentinelClientId(*to))
+            }
+        }
+        if self.tx_ids.contains(&transaction.tx_id()) {
+            return Err(SentinelRejection::SentinelTxId(transaction.tx_id()))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+
+    fn deposit(client_id: u16, tx_id: TxId) -> Transaction {
+        Transaction::Deposit { client_id, tx_id, amount: Amount::from_units(10_000) }
+    }
+
+    #[test]
+    fn when_no_sentinel_configured_should_accept_everything() {
+        let sentinels = SentinelIds::new();
+
+        assert!(sentinels.check(&deposit(0, 0)).is_ok());
+        assert!(sentinels.check(&deposit(u16::MAX, TxId::MAX)).is_ok());
+    }
+
+    #[test]
+    fn when_keywords_configured_should_reject_zero_and_max_client_ids() {
+        let mut sentinels = SentinelIds::new();
+        sentinels.add_client_ids("zero,max").unwrap();
+
+        assert_eq!(sentinels.check(&deposit(0, 1)), Err(SentinelRejection::SentinelClientId(0)));
+        assert_eq!(sentinels.check(&deposit(u16::MAX, 1)), Err(SentinelRejection::SentinelClientId(u16::MAX)));
+        assert!(sentinels.check(&deposit(1, 1)).is_ok());
+        assert!(sentinels.check(&deposit(u16::MAX - 1, 1)).is_ok());
+    }
+
+    #[test]
+    fn when_explicit_ids_configured_should_reject_only_those() {
+        let mut sentinels = SentinelIds::new();
+        sentinels.add_client_ids("42, 7").unwrap();
+
+        assert!(sentinels.check(&deposit(42, 1)).is_err());
+        assert!(sentinels.check(&deposit(7, 1)).is_err());
+        assert!(sentinels.check(&deposit(41, 1)).is_ok());
+        assert!(sentinels.check(&deposit(43, 1)).is_ok());
+    }
+
+    #[test]
+    fn when_client_sentinel_configured_should_reject_transfers_to_it() {
+        let mut sentinels = SentinelIds::new();
+        sentinels.add_client_ids("zero").unwrap();
+
+        let transfer = |from, to| Transaction::Transfer { from, to, tx_id: 1, amount: Amount::from_units(10_000) };
+        assert_eq!(sentinels.check(&transfer(1, 0)), Err(SentinelRejection::SentinelClientId(0)));
+        assert_eq!(sentinels.check(&transfer(0, 1)), Err(SentinelRejection::SentinelClientId(0)));
+        assert!(sentinels.check(&transfer(1, 2)).is_ok());
+    }
+
+    #[test]
+    fn when_tx_sentinel_configured_should_reject_reference_rows() {
+        let mut sentinels = SentinelIds::new();
+        sentinels.add_tx_ids("zero").unwrap();
+
+        let dispute = Transaction::Dispute { client_id: 1, tx_id: 0 };
+        assert_eq!(sentinels.check(&dispute), Err(SentinelRejection::SentinelTxId(0)));
+        assert!(sentinels.check(&deposit(1, 1)).is_ok());
+        assert!(sentinels.check(&deposit(0, 1)).is_ok());
+    }
+
+    #[test]
+    fn when_max_tx_sentinel_configured_should_reject_the_largest_64_bit_id() {
+        let mut sentinels = SentinelIds::new();
+        sentinels.add_tx_ids("max").unwrap();
+
+        assert_eq!(sentinels.check(&deposit(1, TxId::MAX)), Err(SentinelRejection::SentinelTxId(TxId::MAX)));
+        assert!(sentinels.check(&deposit(1, TxId::from(u32::MAX))).is_ok());
+    }
+
+    #[test]
+    fn when_id_invalid_should_fail_to_parse() {
+        let mut sentinels = SentinelIds::new();
+
+        assert!(sentinels.add_client_ids("minus").is_err());
+        assert!(sentinels.add_client_ids("70000").is_err());
+        assert!(sentinels.add_tx_ids("-1").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! The `serve` command: CSV rows received over TCP applied to a single engine.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use csv::StringRecord;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use transaction_engine::csv_model::{RowError, RowParser, COLUMNS};
+use transaction_engine::{
+    ConcurrentTransactionEngine, EvictAfter, ExpireAfter, FileArchive, ParkedTransaction, RetryQueueConfig, TransactionEngine,
+    TransactionError,
+};
+
+use crate::cli::{EvictionArgs, RetryArgs, ServeArgs};
+use crate::dead_letter::DeadLetter;
+use crate::output::{self, Format};
+use crate::INTERRUPTED;
+
+/// Line asking for the balances, answered with the client CSV followed by an empty line.
+const REPORT: &[u8] = b"REPORT";
+
+/// Bytes of the longest line, without its end. A longer one is answered with `ERROR line too long` and its
+/// connection closed.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Every how often a service checks whether the process was interrupted.
+const INTERRUPT_CHECK: Duration = Duration::from_millis(100);
+
+/// Every how often a service evicts the idle accounts and drops the expired parked transactions.
+const MAINTENANCE: Duration = Duration::from_secs(1);
+
+/// Serves until interrupted, then writes the balances to stdout. Returns the exit code.
+pub fn run(args: ServeArgs) -> i32 {
+    run_until_interrupted(serve(args))
+}
+
+/// Runs a service returning its engine once interrupted, then writes the balances to stdout. Returns the
+/// exit code.
+pub fn run_until_interrupted(service: impl Future<Output = io::Result<Arc<ConcurrentTransactionEngine>>>) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Application error: cannot start the runtime: {e}");
+            return 1
+        },
+    };
+    let engine = match runtime.block_on(service) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            return 1
+        },
+    };
+    // The connections still open are dropped with the runtime.
+    drop(runtime);
+
+    let metrics = engine.metrics();
+    if metrics.evicted_clients > 0 {
+        eprintln!("Evicted {} accounts, restored {}", metrics.evicted_clients, metrics.restored_clients);
+    }
+    if metrics.parked_retried + metrics.parked_expired + metrics.parked_overflow + metrics.parked_transactions > 0 {
+        eprintln!(
+            "Parked transactions: {} applied once their tx came, {} expired, {} over capacity, {} still waiting",
+            metrics.parked_retried, metrics.parked_expired, metrics.parked_overflow, metrics.parked_transactions,
+        );
+    }
+
+    let written = engine.read(|engine| {
+        output::write_clients(io::stdout(), engine.clients(), Format::Csv, b',', engine.is_multi_currency())
+    });
+    if let Err(e) = written {
+        eprintln!("Application error: {e}");
+    }
+    0
+}
+
+pub async fn bind(address: SocketAddr) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(address).await
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot listen on {address}: {e}")))?;
+    // Tells the address when the port was picked by the system.
+    eprintln!("Listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// Creates the engine of a service with its eviction and retry queue, evicting the idle accounts and dropping
+/// the expired parked transactions every [`MAINTENANCE`]. The evicted accounts are left out of the balances
+/// written once interrupted, having none.
+pub fn engine(eviction: &EvictionArgs, retry: &RetryArgs) -> io::Result<Arc<ConcurrentTransactionEngine>> {
+    let mut engine = TransactionEngine::new();
+    let evict_after = match (eviction.evict_after_rows, eviction.evict_after_secs) {
+        (Some(rows), _) => Some(EvictAfter::Sequences(rows)),
+        (None, Some(secs)) => Some(EvictAfter::Duration(Duration::from_secs(secs))),
+        (None, None) => None,
+    };
+    if let Some(after) = evict_after {
+        let path = eviction.archive.as_ref().expect("--archive required by the eviction");
+        engine.set_eviction(after, FileArchive::create(path).map_err(|e| cannot_create(path, e))?);
+    }
+    if let Some(capacity) = retry.retry_capacity {
+        let expire_after = match retry.retry_expire_after_secs {
+            Some(secs) => ExpireAfter::Duration(Duration::from_secs(secs)),
+            None => ExpireAfter::Sequences(retry.retry_expire_after_rows),
+        };
+        engine.set_retry_queue(RetryQueueConfig { capacity, expire_after });
+    }
+    let mut rejected = match &retry.rejected {
+        Some(path) => {
+            let headers = StringRecord::from(COLUMNS.to_vec());
+            Some(DeadLetter::create(path, &headers).map_err(|e| cannot_create(path, e.into()))?)
+        },
+        None => None,
+    };
+
+    let engine = Arc::new(ConcurrentTransactionEngine::new(engine));
+    if evict_after.is_some() || retry.retry_capacity.is_some() {
+        let maintained = Arc::clone(&engine);
+        tokio::spawn(async move {
+            let mut maintenance = tokio::time::interval(MAINTENANCE);
+            loop {
+                maintenance.tick().await;
+                maintained.evict_idle();
+                let expired = maintained.expire_parked();
+                if let Some(rejected) = rejected.as_mut() {
+                    if let Err(e) = write_expired(rejected, &expired) {
+                        eprintln!("Application error: cannot write the expired parked transactions: {e}");
+                    }
+                }
+            }
+        });
+    }
+    Ok(engine)
+}
+
+fn cannot_create(path: &std::path::Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("cannot create {}: {e}", path.display()))
+}
+
+// Written as the row of the transaction, without amount, rejected for its unknown tx.
+fn write_expired(rejected: &mut DeadLetter<std::fs::File>, expired: &[ParkedTransaction]) -> csv::Result<()> {
+    for parked in expired {
+        let transaction = parked.transaction();
+        let (client, tx) = (parked.client_id.to_string(), parked.tx_id.to_string());
+        let reason = TransactionError::UnknownTransaction { tx_id: parked.tx_id }.to_string();
+        rejected.write(&StringRecord::from(vec![transaction.name(), &client, &tx, ""]), &reason)?;
+    }
+    rejected.flush()?;
+    Ok(())
+}
+
+/// Completes once the process is interrupted.
+pub async fn interrupted() {
+    let mut interrupt_check = tokio::time::interval(INTERRUPT_CHECK);
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        interrupt_check.tick().await;
+    }
+}
+
+async fn serve(args: ServeArgs) -> io::Result<Arc<ConcurrentTransactionEngine>> {
+    let listener = bind(args.listen).await?;
+    let engine = engine(&args.eviction, &args.retry)?;
+    let parser = Arc::new(RowParser::new(&StringRecord::from(COLUMNS.to_vec())));
+    let interrupted = interrupted();
+    tokio::pin!(interrupted);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle(stream, Arc::clone(&engine), Arc::clone(&parser)));
+                },
+                // E.g. too many open files, the connections already open are still served.
+                Err(e) => eprintln!("Application error: cannot accept a connection: {e}"),
+            },
+            _ = &mut interrupted => return Ok(engine),
+        }
+    }
+}
+
+/// Applies the rows of a connection in order until it is closed. A malformed row is answered with an
+/// `ERROR` line, rejected transactions are ignored as in a batch run. A line longer than [`MAX_LINE`] closes the
+/// connection, so that a client sending no line end cannot have it buffered without bound.
+async fn handle(stream: TcpStream, engine: Arc<ConcurrentTransactionEngine>, parser: Arc<RowParser>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_LINE as u64 + 1).read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => line_number += 1,
+        }
+        if line.len() > MAX_LINE && line.last() != Some(&b'\n') {
+            let _ = writer.write_all(b"ERROR line too long\n").await;
+            return
+        }
+        let response = match line.trim_ascii() {
+            [] => continue,
+            REPORT => report(&engine),
+            row => match apply(row, &parser, &engine) {
+                Ok(()) => continue,
+                Err(e) => format!("ERROR line {line_number}: {e}\n").into_bytes(),
+            },
+        };
+        if writer.write_all(&response).await.is_err() {
+            return
+        }
+    }
+}
+
+fn apply(row: &[u8], parser: &RowParser, engine: &ConcurrentTransactionEngine) -> Result<(), RowError> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).trim(csv::Trim::All).from_reader(row);
+    let mut record = StringRecord::new();
+    reader.read_record(&mut record).map_err(|e| RowError::Parse(e.to_string()))?;
+    // Rows have the canonical columns, without currency.
+    let (transaction, ts, _) = parser.parse(&record)?;
+    let _ = engine.submit_at(transaction, ts);
+    Ok(())
+}
+
+fn report(engine: &ConcurrentTransactionEngine) -> Vec<u8> {
+    let mut report = engine.read(|engine| output::write_clients(Vec::new(), engine.clients(), Format::Csv, b',', engine.is_multi_currency()))
+        .unwrap_or_default();
+    report.push(b'\n');
+    report
+}
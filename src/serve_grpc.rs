@@ -0,0 +1,28 @@
+//! The `serve-grpc` command: the gRPC service of [`transaction_engine::grpc`] applied to a single engine.
+
+use std::io;
+use std::sync::Arc;
+
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use transaction_engine::grpc::EngineService;
+use transaction_engine::ConcurrentTransactionEngine;
+
+use crate::cli::ServeGrpcArgs;
+use crate::serve;
+
+/// Serves until interrupted, then writes the balances to stdout. Returns the exit code.
+pub fn run(args: ServeGrpcArgs) -> i32 {
+    serve::run_until_interrupted(serve(args))
+}
+
+async fn serve(args: ServeGrpcArgs) -> io::Result<Arc<ConcurrentTransactionEngine>> {
+    let listener = serve::bind(args.listen).await?;
+    let engine = serve::engine(&args.eviction, &args.retry)?;
+    Server::builder()
+        .add_service(EngineService::new(Arc::clone(&engine)).into_server())
+        .serve_with_incoming_shutdown(TcpIncoming::from(listener), serve::interrupted())
+        .await
+        .map_err(io::Error::other)?;
+    Ok(engine)
+}
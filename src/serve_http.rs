@@ -0,0 +1,107 @@
+//! The `serve-http` command: transactions posted as JSON to an HTTP API applied to a single engine.
+//!
+//! - `POST /transactions` takes a transaction object, with the fields of a CSV row, or an array of them
+//!   applied in order. It answers the outcome of each, an object or an array matching the body.
+//! - `GET /clients` answers every client sorted by id, `GET /clients/{id}` a single one.
+//! - `GET /stats` answers the counters of the engine.
+//! - `GET /healthz` answers `ok` while the service runs.
+
+use std::io;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use csv::StringRecord;
+use serde_json::Value;
+use transaction_engine::csv_model::{RowError, RowParser, COLUMNS};
+use transaction_engine::{ConcurrentTransactionEngine, TransactionError};
+
+use crate::cli::ServeHttpArgs;
+use crate::input;
+use crate::serve;
+use crate::wire::{JsonClient, Outcome, Reason};
+
+#[derive(Clone)]
+struct Service {
+    engine: Arc<ConcurrentTransactionEngine>,
+    parser: Arc<RowParser>,
+}
+
+/// Serves until interrupted, then writes the balances to stdout. Returns the exit code.
+pub fn run(args: ServeHttpArgs) -> i32 {
+    serve::run_until_interrupted(serve(args))
+}
+
+async fn serve(args: ServeHttpArgs) -> io::Result<Arc<ConcurrentTransactionEngine>> {
+    let listener = serve::bind(args.listen).await?;
+    let service = Service {
+        engine: serve::engine(&args.eviction, &args.retry)?,
+        parser: Arc::new(RowParser::new(&StringRecord::from(COLUMNS.to_vec()))),
+    };
+    let router = Router::new()
+        .route("/transactions", post(post_transactions))
+        .route("/clients", get(get_clients))
+        .route("/clients/{id}", get(get_client))
+        .route("/stats", get(|State(service): State<Service>| async move { Json(service.engine.metrics()) }))
+        .route("/healthz", get(|| async { "ok" }))
+        .with_state(service.clone());
+    axum::serve(listener, router).with_graceful_shutdown(serve::interrupted()).await?;
+    Ok(service.engine)
+}
+
+/// Answers 200 when every transaction is accepted, otherwise the status of the first rejection. The
+/// transactions of an array after a rejected one are still applied.
+async fn post_transactions(State(service): State<Service>, body: Bytes) -> Response {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            let outcome = Outcome::from(RowError::Parse(format!("invalid JSON body: {e}")));
+            return (outcome.status(), Json(outcome)).into_response()
+        },
+    };
+    match body {
+        Value::Array(items) => {
+            let outcomes: Vec<Outcome> = items.iter().map(|item| apply(item, &service)).collect();
+            let status = outcomes.iter().map(Outcome::status).find(|status| *status != StatusCode::OK).unwrap_or(StatusCode::OK);
+            (status, Json(outcomes)).into_response()
+        },
+        item => {
+            let outcome = apply(&item, &service);
+            (outcome.status(), Json(outcome)).into_response()
+        },
+    }
+}
+
+fn apply(item: &Value, service: &Service) -> Outcome {
+    let parsed = input::object_fields(item)
+        .map_err(RowError::Parse)
+        .and_then(|fields| service.parser.parse(&StringRecord::from(fields)));
+    match parsed {
+        Ok((transaction, ts, _)) => match service.engine.submit_at(transaction, ts) {
+            Ok(()) => Outcome::Accepted,
+            Err(TransactionError::Parked { .. }) => Outcome::Parked,
+            Err(e) => e.into(),
+        },
+        Err(e) => e.into(),
+    }
+}
+
+async fn get_clients(State(service): State<Service>) -> Json<Vec<JsonClient>> {
+    let mut clients = service.engine.get_client_list();
+    clients.sort_unstable_by_key(|client| client.id());
+    Json(clients.iter().map(JsonClient::from).collect())
+}
+
+async fn get_client(State(service): State<Service>, Path(id): Path<u16>) -> Response {
+    match service.engine.get_client(id) {
+        Some(client) => Json(JsonClient::from(&client)).into_response(),
+        None => {
+            let e = TransactionError::UnknownClient { client_id: id };
+            (StatusCode::NOT_FOUND, Json(Reason::from(e))).into_response()
+        },
+    }
+}
@@ -0,0 +1,404 @@
+//! Applying transactions on several threads, see [`ShardedEngine`].
+
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::batch::BatchSummary;
+use crate::collections::FastHashMap;
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Transaction, TransactionEngine, TxId};
+
+/// Messages sent to a shard at once, so that its channel is not synchronized for every transaction.
+const BATCH: usize = 256;
+/// Batches a shard can be behind before [`ShardedEngine::compute_transaction_at`] waits for it.
+const BOUND: usize = 16;
+
+enum Message {
+    Apply(Transaction, Currency, Option<Timestamp>),
+    /// Answers whether the shard has used the tx id, once the previous transactions are applied.
+    IsTxIdUsed(TxId, SyncSender<bool>),
+    Compact,
+}
+
+struct Shard {
+    sender: SyncSender<Vec<Message>>,
+    pending: Vec<Message>,
+    worker: JoinHandle<(TransactionEngine, BatchSummary)>,
+}
+
+impl Shard {
+    fn spawn(config: EngineConfig) -> Shard {
+        let (sender, receiver) = mpsc::sync_channel(BOUND);
+        let worker = thread::spawn(move || work(receiver, TransactionEngine::with_config(config)));
+        Shard { sender, pending: Vec::with_capacity(BATCH), worker }
+    }
+
+    fn send(&mut self, message: Message) {
+        self.pending.push(message);
+        if self.pending.len() == BATCH {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return
+        }
+        let batch = mem::replace(&mut self.pending, Vec::with_capacity(BATCH));
+        // The worker only stops once the sender is dropped, unless it panicked, which `join` then reports.
+        let _ = self.sender.send(batch);
+    }
+
+    fn is_tx_id_used(&mut self, tx_id: TxId) -> bool {
+        let (reply, answer) = mpsc::sync_channel(1);
+        self.send(Message::IsTxIdUsed(tx_id, reply));
+        self.flush();
+        answer.recv().unwrap_or(false)
+    }
+
+    fn join(mut self) -> (TransactionEngine, BatchSummary) {
+        self.flush();
+        drop(self.sender);
+        self.worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+fn work(receiver: Receiver<Vec<Message>>, mut engine: TransactionEngine) -> (TransactionEngine, BatchSummary) {
+    let mut summary = BatchSummary::new();
+    for batch in receiver {
+        for message in batch {
+            match message {
+                Message::Apply(transaction, currency, ts) => match engine.compute_transaction_in(transaction, currency, ts) {
+                    Ok(()) => summary.applied(),
+                    Err(e) => summary.rejected_tx(transaction.tx_id(), e.code()),
+                },
+                Message::IsTxIdUsed(tx_id, reply) => {
+                    let _ = reply.send(engine.is_tx_id_used(tx_id));
+                },
+                Message::Compact => {
+                    engine.compact();
+                },
+            }
+        }
+    }
+    (engine, summary)
+}
+
+// Config of the `shard`th of `count` shards, the first ones storing one transaction more when the limit is not a
+// multiple of the count.
+fn shard_config(config: EngineConfig, shard: usize, count: usize) -> EngineConfig {
+    let share = config.max_stored_transactions.map(|max| max / count + usize::from(shard < max % count));
+    config.max_stored_transactions(share)
+}
+
+/// Applies transactions on one thread per shard, each shard being a [`TransactionEngine`] owning the
+/// clients whose id falls into it. The balances, stored transactions and counters once
+/// [`ShardedEngine::finish`]ed are those of a single engine fed the same transactions.
+///
+/// The transactions of a client are applied in order by its shard. Disputes, resolves and chargebacks go
+/// to the shard of the referenced transaction, whose tx id is tracked when it is routed. A tx id reused
+/// by a client of another shard is checked with the shard holding it first, as the engine rejects
+/// duplicates across clients.
+///
+/// A transfer between clients of different shards cannot be applied by either of them: the shards are
+/// then merged into one engine, which applies the following transactions on the calling thread.
+///
+/// [`EngineConfig::max_stored_transactions`] is split between the shards, each storing its share, so that
+/// they store no more transactions together than the limit. A shard whose share is full no longer stores
+/// transactions even though others still have room: the stored transactions then differ from those of a
+/// single engine.
+///
+/// ```
+/// use transaction_engine::shard::ShardedEngine;
+/// use transaction_engine::{Amount, EngineConfig, Transaction};
+///
+/// let mut sharded = ShardedEngine::new(4, EngineConfig::default());
+/// let amount: Amount = "10.0".parse().unwrap();
+/// sharded.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount });
+/// sharded.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount });
+/// sharded.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 });
+///
+/// let (engine, summary) = sharded.finish();
+/// assert_eq!(engine.get_client(1).unwrap().held(), amount);
+/// assert_eq!(summary.applied_count(), 3);
+/// ```
+pub struct ShardedEngine {
+    shards: Vec<Shard>,
+    // Shard each tx id was last routed to as a new transaction.
+    owners: FastHashMap<TxId, usize>,
+    // Set once the shards are merged, which then applies the transactions.
+    merged: Option<(TransactionEngine, BatchSummary)>,
+    config: EngineConfig,
+}
+
+impl ShardedEngine {
+    /// Starts `shards` worker threads, at least one, with engines using `config`, the stored transactions
+    /// limit split between them.
+    pub fn new(shards: usize, config: EngineConfig) -> ShardedEngine {
+        let count = shards.max(1);
+        ShardedEngine {
+            shards: (0..count).map(|shard| Shard::spawn(shard_config(config, shard, count))).collect(),
+            owners: FastHashMap::default(),
+            merged: None,
+            config,
+        }
+    }
+
+    pub fn compute_transaction(&mut self, transaction: Transaction) {
+        self.compute_transaction_at(transaction, None)
+    }
+
+    /// Sends a transaction to the shard of its client. Whether it was applied or rejected is only known
+    /// from the summary returned by [`ShardedEngine::finish`].
+    pub fn compute_transaction_at(&mut self, transaction: Transaction, ts: Option<Timestamp>) {
+        self.compute_transaction_in(transaction, self.config.base_currency, ts)
+    }
+
+    /// Like [`ShardedEngine::compute_transaction_at`], on the accounts in `currency`, see
+    /// [`TransactionEngine::compute_transaction_in`].
+    pub fn compute_transaction_in(&mut self, transaction: Transaction, currency: Currency, ts: Option<Timestamp>) {
+        if let Some((engine, summary)) = self.merged.as_mut() {
+            match engine.compute_transaction_in(transaction, currency, ts) {
+                Ok(()) => summary.applied(),
+                Err(e) => summary.rejected_tx(transaction.tx_id(), e.code()),
+            }
+            return
+        }
+
+        let tx_id = transaction.tx_id();
+        let shard = match transaction {
+            Transaction::Dispute { client_id, .. }
+            | Transaction::Resolve { client_id, .. }
+            | Transaction::Chargeback { client_id, .. } => {
+                self.owners.get(&tx_id).copied().unwrap_or_else(|| self.shard_of(client_id))
+            },
+            Transaction::Unlock { client_id, .. } => self.shard_of(client_id),
+            Transaction::Transfer { from, to, .. } if self.shard_of(from) != self.shard_of(to) => {
+                self.merged = Some(self.merge_shards());
+                return self.compute_transaction_in(transaction, currency, ts)
+            },
+            Transaction::Deposit { .. }
+            | Transaction::Withdrawal { .. }
+            | Transaction::Transfer { .. }
+            | Transaction::Adjustment { .. }
+            | Transaction::Fee { .. } => self.route_new(transaction.client_id(), tx_id),
+        };
+        self.shards[shard].send(Message::Apply(transaction, currency, ts));
+    }
+
+    // Shard of a transaction creating `tx_id` for `client_id`. When another shard already used the tx id,
+    // the transaction goes there to be rejected as a duplicate.
+    fn route_new(&mut self, client_id: u16, tx_id: TxId) -> usize {
+        let shard = self.shard_of(client_id);
+        match self.owners.get(&tx_id).copied() {
+            Some(owner) if owner != shard && self.shards[owner].is_tx_id_used(tx_id) => owner,
+            _ => {
+                self.owners.insert(tx_id, shard);
+                shard
+            },
+        }
+    }
+
+    fn shard_of(&self, client_id: u16) -> usize {
+        usize::from(client_id) % self.shards.len()
+    }
+
+    /// Drops the stored transactions that can no longer be meaningfully disputed, as
+    /// [`TransactionEngine::compact`] does, once the transactions sent so far are applied.
+    pub fn compact(&mut self) {
+        match self.merged.as_mut() {
+            Some((engine, _)) => {
+                engine.compact();
+            },
+            None => self.shards.iter_mut().for_each(|shard| shard.send(Message::Compact)),
+        }
+    }
+
+    fn merge_shards(&mut self) -> (TransactionEngine, BatchSummary) {
+        let mut engine = TransactionEngine::with_config(self.config);
+        let mut summary = BatchSummary::new();
+        for shard in self.shards.drain(..) {
+            let (shard_engine, shard_summary) = shard.join();
+            engine.merge(shard_engine);
+            summary.merge(shard_summary);
+        }
+        self.owners = FastHashMap::default();
+        (engine, summary)
+    }
+
+    /// Waits for the shards to apply every transaction and returns them merged into one engine, with
+    /// the counts of applied and rejected transactions.
+    pub fn finish(mut self) -> (TransactionEngine, BatchSummary) {
+        match self.merged.take() {
+            Some(merged) => merged,
+            None => self.merge_shards(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction_engine::Client;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    fn sorted_clients(engine: &TransactionEngine) -> Vec<Client> {
+        let mut clients = engine.get_client_list();
+        clients.sort_by_key(Client::id);
+        clients
+    }
+
+    fn assert_same_as_sequential(transactions: &[Transaction], shards: usize, config: EngineConfig) {
+        let mut sequential = TransactionEngine::with_config(config);
+        let expected = sequential.compute_transactions(transactions.iter().copied());
+        let mut sharded = ShardedEngine::new(shards, config);
+        for transaction in transactions {
+            sharded.compute_transaction(*transaction);
+        }
+
+        let (engine, summary) = sharded.finish();
+
+        assert_eq!(sorted_clients(&engine), sorted_clients(&sequential));
+        assert_eq!(engine.metrics(), sequential.metrics());
+        assert_eq!(summary.applied_count(), expected.applied_count());
+        assert_eq!(summary.to_string(), expected.to_string());
+        let mut disputed: Vec<TxId> = engine.disputed_transactions().map(|(tx_id, _)| tx_id).collect();
+        let mut expected_disputed: Vec<TxId> = sequential.disputed_transactions().map(|(tx_id, _)| tx_id).collect();
+        disputed.sort_unstable();
+        expected_disputed.sort_unstable();
+        assert_eq!(disputed, expected_disputed);
+        // The stored transactions too, with their dispute state.
+        let diff = engine.diff_states(&sequential);
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    // Deterministic mix of deposits, withdrawals and disputes, some of them of another client's transaction.
+    fn workload() -> Vec<Transaction> {
+        let mut transactions = vec![];
+        let mut seed: u64 = 7;
+        let mut next = move |modulo: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % modulo
+        };
+        for tx_id in 1..=5_000u64 {
+            let client_id = next(50) as u16;
+            let amount = Amount::from_units(next(100_000) as i64 + 1);
+            let referenced = next(tx_id) + 1;
+            let transaction = match next(20) {
+                0 => Transaction::Dispute { client_id, tx_id: referenced },
+                1 => Transaction::Resolve { client_id, tx_id: referenced },
+                2 => Transaction::Chargeback { client_id, tx_id: referenced },
+                3 => Transaction::Deposit { client_id, tx_id: referenced, amount },
+                4..=8 => Transaction::Withdrawal { client_id, tx_id, amount },
+                _ => Transaction::Deposit { client_id, tx_id, amount },
+            };
+            transactions.push(transaction);
+        }
+        transactions
+    }
+
+    #[test]
+    fn should_match_a_single_engine() {
+        for shards in [1, 2, 3, 8] {
+            assert_same_as_sequential(&workload(), shards, EngineConfig::default());
+        }
+        assert_same_as_sequential(&workload(), 4, EngineConfig::default().compact_locked(true).max_disputes_per_tx(Some(1)));
+    }
+
+    #[test]
+    fn dispute_of_another_client_transaction_should_be_rejected_by_its_shard() {
+        let transactions = [
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+            Transaction::Dispute { client_id: 2, tx_id: 1 },
+            Transaction::Dispute { client_id: 1, tx_id: 1 },
+            Transaction::Chargeback { client_id: 2, tx_id: 1 },
+        ];
+
+        assert_same_as_sequential(&transactions, 2, EngineConfig::default());
+    }
+
+    #[test]
+    fn tx_id_reused_by_another_shard_should_be_a_duplicate_only_when_stored() {
+        let transactions = [
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+            // Stored by the shard of client 1.
+            Transaction::Deposit { client_id: 2, tx_id: 1, amount: amount("5.0") },
+            // Rejected for lack of funds, so its tx id can be used by client 2.
+            Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("50.0") },
+            Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") },
+            Transaction::Dispute { client_id: 2, tx_id: 2 },
+        ];
+
+        assert_same_as_sequential(&transactions, 2, EngineConfig::default());
+        let mut sharded = ShardedEngine::new(2, EngineConfig::default());
+        transactions.iter().for_each(|transaction| sharded.compute_transaction(*transaction));
+        let (engine, summary) = sharded.finish();
+        assert_eq!(summary.count("duplicate_transaction"), 1);
+        assert_eq!(engine.get_client(2).unwrap().held(), amount("5.0"));
+    }
+
+    #[test]
+    fn max_stored_transactions_should_be_split_between_the_shards() {
+        let transactions = [
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1.0") },
+            Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("1.0") },
+            Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("1.0") },
+            Transaction::Deposit { client_id: 2, tx_id: 4, amount: amount("1.0") },
+            Transaction::Deposit { client_id: 2, tx_id: 5, amount: amount("1.0") },
+            Transaction::Deposit { client_id: 2, tx_id: 6, amount: amount("1.0") },
+        ];
+        let mut sharded = ShardedEngine::new(2, EngineConfig::default().max_stored_transactions(Some(3)));
+        transactions.iter().for_each(|transaction| sharded.compute_transaction(*transaction));
+        // Client 1 is in the shard storing one transaction, client 2 in the one storing two.
+        sharded.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 });
+        sharded.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 5 });
+        sharded.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 3, amount: amount("1.0") });
+        let (engine, summary) = sharded.finish();
+
+        assert_eq!(engine.metrics().stored_transactions, 3);
+        assert_eq!(summary.applied_count(), 7);
+        assert_eq!(summary.count("duplicate_transaction"), 1);
+        assert_eq!(engine.get_client(2).unwrap().held(), amount("1.0"));
+    }
+
+    #[test]
+    fn transfer_between_shards_should_merge_them() {
+        let transactions = [
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+            Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("3.0") },
+            Transaction::Transfer { from: 1, to: 2, tx_id: 3, amount: amount("4.0") },
+            Transaction::Dispute { client_id: 1, tx_id: 3 },
+            Transaction::Chargeback { client_id: 1, tx_id: 3 },
+            Transaction::Deposit { client_id: 3, tx_id: 4, amount: amount("1.0") },
+        ];
+
+        assert_same_as_sequential(&transactions, 2, EngineConfig::default());
+    }
+
+    #[test]
+    fn compact_should_compact_every_shard() {
+        let transactions = [
+            Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+            Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") },
+            Transaction::Dispute { client_id: 1, tx_id: 1 },
+            Transaction::Chargeback { client_id: 1, tx_id: 1 },
+            Transaction::Deposit { client_id: 2, tx_id: 3, amount: amount("5.0") },
+        ];
+        let mut sharded = ShardedEngine::new(2, EngineConfig::default());
+        transactions.iter().for_each(|transaction| sharded.compute_transaction(*transaction));
+
+        sharded.compact();
+        sharded.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 });
+        let (engine, summary) = sharded.finish();
+
+        assert_eq!(summary.count("compacted"), 1);
+        assert_eq!(engine.metrics().stored_transactions, 1);
+    }
+}
@@ -0,0 +1,692 @@
+//! Engine state kept in a SQLite database, with the `sqlite` feature.
+//!
+//! [`SqliteStore`] is a [`TransactionStore`] writing the transactions to the database as the engine stores them,
+//! and their dispute state as it changes, so that a later run disputes the transactions of the previous ones
+//! without loading them all. Only the latest transactions and the open disputes stay in memory, the others are
+//! read back when referenced. The client balances, the dispute counters, the compacted tx ids and those of the
+//! transactions applied past [`EngineConfig::max_stored_transactions`] without being stored are written by
+//! [`TransactionEngine::commit`], along with which the changes to the transactions are committed: the database
+//! holds the state of the last commit. Amounts are kept as integer units, exactly.
+//!
+//! A database of a previous version of the tables is migrated when opened, the accounts and transactions of
+//! version 1 being in USD.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::amount::Amount;
+use crate::collections::FastHashMap;
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::memory::{TRANSACTION_BYTES, TX_ID_BYTES};
+use crate::metrics::DisputeCounters;
+use crate::store::{StoredTransaction, TransactionState, TransactionStore};
+use crate::timestamp::Timestamp;
+use crate::transaction_engine::{Client, PersistedTransaction, Snapshot, TransactionEngine, TxId};
+
+/// Number of transactions kept in memory by default.
+pub const DEFAULT_CACHE_SIZE: usize = 100_000;
+
+const COLUMNS: &str = "type, client, to_client, tx, amount, ts, state, disputes, currency";
+
+/// Version of the tables, kept in the `user_version` of the database. It changes whenever the tables do.
+const SCHEMA_VERSION: i32 = 3;
+
+const SCHEMA: &str = "
+    CREATE TABLE clients (
+        client INTEGER NOT NULL,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        locked INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE transactions (
+        seq INTEGER PRIMARY KEY,
+        tx INTEGER NOT NULL UNIQUE,
+        type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        to_client INTEGER,
+        amount INTEGER NOT NULL,
+        ts INTEGER,
+        state TEXT NOT NULL,
+        disputes INTEGER NOT NULL,
+        currency TEXT NOT NULL
+    );
+    CREATE TABLE dispute_counters (
+        client INTEGER PRIMARY KEY,
+        disputes INTEGER NOT NULL,
+        chargebacks INTEGER NOT NULL,
+        charged_back INTEGER NOT NULL
+    );
+    CREATE TABLE compacted (
+        tx INTEGER PRIMARY KEY
+    );
+    CREATE TABLE unstored (
+        tx INTEGER PRIMARY KEY
+    );
+";
+
+/// Turns the tables of version 1, from before the currencies, into those of version 2.
+const MIGRATION_FROM_1: &str = "
+    ALTER TABLE clients RENAME TO clients_1;
+    CREATE TABLE clients (
+        client INTEGER NOT NULL,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        locked INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    INSERT INTO clients SELECT client, available, held, total, locked, 'USD' FROM clients_1;
+    DROP TABLE clients_1;
+    ALTER TABLE transactions ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+";
+
+/// Turns the tables of version 2 into those of version 3, which keep the tx ids of the transactions not stored.
+const MIGRATION_FROM_2: &str = "
+    CREATE TABLE unstored (
+        tx INTEGER PRIMARY KEY
+    );
+";
+
+/// Reason the state could not be loaded from or saved to the database by [`SqliteStore`].
+#[derive(Debug)]
+pub enum StoreError {
+    Sqlite(rusqlite::Error),
+    /// The database was written by another version of the tables.
+    Incompatible { version: i32 },
+    /// A row does not hold a valid transaction.
+    Corrupt(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Sqlite(e) => write!(f, "{e}"),
+            StoreError::Incompatible { version } => {
+                write!(f, "not a compatible state database, expected version {SCHEMA_VERSION} but found {version}")
+            },
+            StoreError::Corrupt(message) => write!(f, "corrupt state database: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> StoreError {
+        StoreError::Sqlite(e)
+    }
+}
+
+/// Transactions of an engine in a SQLite database, along with the rest of its state once committed.
+///
+/// ```
+/// use transaction_engine::sqlite_store::SqliteStore;
+/// use transaction_engine::{Amount, EngineConfig, Transaction};
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("state.sqlite");
+/// let amount: Amount = "10".parse().unwrap();
+/// let mut engine = SqliteStore::open(&path).unwrap().load(EngineConfig::default()).unwrap();
+/// engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount }).unwrap();
+/// engine.commit().unwrap();
+///
+/// let mut engine = SqliteStore::open(&path).unwrap().load(EngineConfig::default()).unwrap();
+/// engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+/// assert_eq!(engine.get_client(1).unwrap().held(), amount);
+/// ```
+///
+/// Like the spilled transactions of [`InMemoryStore::with_memory_limit`](crate::InMemoryStore::with_memory_limit),
+/// the transactions out of memory are missed by `iter`, so by
+/// [`TransactionEngine::transaction`](crate::TransactionEngine::transaction) and `compact`. The store keeps the
+/// first failed statement, which the trait has no way to report, and fails the next commit with it.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Connection,
+    cache_size: usize,
+    cached: FastHashMap<TxId, StoredTransaction>,
+    // Tx ids of the cached transactions, oldest first, possibly along with ids removed since.
+    queue: VecDeque<TxId>,
+    len: usize,
+    error: Option<StoreError>,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables when it does not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, StoreError> {
+        SqliteStore::init(Connection::open(path)?)
+    }
+
+    /// Opens a database living in memory only, gone once the store is dropped.
+    pub fn open_in_memory() -> Result<SqliteStore, StoreError> {
+        SqliteStore::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<SqliteStore, StoreError> {
+        let version: i32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        match version {
+            0 => connection.execute_batch(&format!("BEGIN; {SCHEMA} PRAGMA user_version = {SCHEMA_VERSION}; COMMIT;"))?,
+            1 => connection.execute_batch(&format!(
+                "BEGIN; {MIGRATION_FROM_1} {MIGRATION_FROM_2} PRAGMA user_version = {SCHEMA_VERSION}; COMMIT;"
+            ))?,
+            2 => connection.execute_batch(&format!("BEGIN; {MIGRATION_FROM_2} PRAGMA user_version = {SCHEMA_VERSION}; COMMIT;"))?,
+            SCHEMA_VERSION => {},
+            version => return Err(StoreError::Incompatible { version }),
+        }
+        let len: i64 = connection.query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get(0))?;
+        // Everything written until the next commit is rolled back when the store is dropped without it.
+        connection.execute_batch("BEGIN")?;
+        Ok(SqliteStore {
+            connection,
+            cache_size: DEFAULT_CACHE_SIZE,
+            cached: FastHashMap::default(),
+            queue: VecDeque::new(),
+            len: len as usize,
+            error: None,
+        })
+    }
+
+    /// Keeps at most `transactions` transactions in memory, at least one, the open disputes staying in memory even
+    /// past it.
+    pub fn cache_size(mut self, transactions: usize) -> SqliteStore {
+        self.cache_size = transactions.max(1);
+        self
+    }
+
+    /// Restores the engine last committed, a new engine when nothing was committed yet, reading the clients and
+    /// counters along with the latest transactions and the open disputes only. The metrics counters start over.
+    pub fn load(mut self, config: EngineConfig) -> Result<TransactionEngine<SqliteStore>, StoreError> {
+        let clients = self.connection
+            .prepare("SELECT client, available, held, total, locked, currency FROM clients")?
+            .query_map([], |row| {
+                let client = Client::new(row.get(0)?, amount(row, 1)?, amount(row, 2)?, amount(row, 3)?, row.get(4)?);
+                Ok(client.in_currency(currency(row, 5)?))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut transactions = Vec::new();
+        {
+            let mut statement = self.connection.prepare(&format!(
+                "SELECT {COLUMNS} FROM transactions \
+                 WHERE state = 'disputed' OR seq IN (SELECT seq FROM transactions ORDER BY seq DESC LIMIT ?1) ORDER BY seq"
+            ))?;
+            let mut rows = statement.query([self.cache_size as i64])?;
+            while let Some(row) = rows.next()? {
+                transactions.push(stored_transaction(row)?);
+            }
+        }
+        for stored in &transactions {
+            self.cache(stored.clone());
+        }
+
+        let dispute_counters = self.connection
+            .prepare("SELECT client, disputes, chargebacks, charged_back FROM dispute_counters")?
+            .query_map([], |row| {
+                Ok((row.get(0)?, DisputeCounters { disputes: row.get(1)?, chargebacks: row.get(2)?, charged_back: amount(row, 3)? }))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let compacted = self.connection
+            .prepare("SELECT tx FROM compacted")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let unstored = self.connection
+            .prepare("SELECT tx FROM unstored")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        // The retry queue of the services is not kept in the database.
+        let parked = Vec::new();
+        Ok(TransactionEngine::restore(self, config, Snapshot { clients, transactions, dispute_counters, compacted, unstored, parked }))
+    }
+
+    // Writes the state of `snapshot` but its transactions, already written, and commits. On a failure,
+    // everything since the previous commit is rolled back.
+    fn commit(&mut self, snapshot: &Snapshot) -> Result<(), StoreError> {
+        let result = match self.error.take() {
+            Some(error) => Err(error),
+            None => self.write(snapshot),
+        };
+        let end = if result.is_ok() { "COMMIT; BEGIN" } else { "ROLLBACK; BEGIN" };
+        self.connection.execute_batch(end)?;
+        result
+    }
+
+    fn write(&self, snapshot: &Snapshot) -> Result<(), StoreError> {
+        let mut upsert = self.connection.prepare_cached(
+            "INSERT OR REPLACE INTO clients (client, available, held, total, locked, currency) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for client in &snapshot.clients {
+            upsert.execute(params![
+                client.id(),
+                client.available().units(),
+                client.held().units(),
+                client.total().units(),
+                client.is_locked(),
+                client.currency().code(),
+            ])?;
+        }
+
+        let mut upsert = self.connection.prepare_cached("INSERT OR REPLACE INTO dispute_counters VALUES (?1, ?2, ?3, ?4)")?;
+        for (client_id, counters) in &snapshot.dispute_counters {
+            upsert.execute(params![client_id, counters.disputes, counters.chargebacks, counters.charged_back.units()])?;
+        }
+
+        let mut insert = self.connection.prepare_cached("INSERT OR IGNORE INTO compacted VALUES (?1)")?;
+        for tx_id in &snapshot.compacted {
+            insert.execute(params![tx_id])?;
+        }
+        let mut insert = self.connection.prepare_cached("INSERT OR IGNORE INTO unstored VALUES (?1)")?;
+        for tx_id in &snapshot.unstored {
+            insert.execute(params![tx_id])?;
+        }
+        Ok(())
+    }
+
+    // Keeps the first error for the next commit.
+    fn checked<T: Default>(&mut self, result: Result<T, StoreError>) -> T {
+        result.unwrap_or_else(|e| {
+            self.error.get_or_insert(e);
+            T::default()
+        })
+    }
+
+    fn select(&self, tx_id: TxId) -> Result<Option<StoredTransaction>, StoreError> {
+        let mut statement = self.connection.prepare_cached(&format!("SELECT {COLUMNS} FROM transactions WHERE tx = ?1"))?;
+        let mut rows = statement.query([tx_id])?;
+        rows.next()?.map(stored_transaction).transpose()
+    }
+
+    fn write_transaction(&self, stored: &StoredTransaction) -> Result<(), StoreError> {
+        let StoredTransaction { transaction, state, disputes } = stored;
+        let (transaction_type, to_client) = match transaction {
+            PersistedTransaction::Deposit { .. } => ("deposit", None),
+            PersistedTransaction::Withdrawal { .. } => ("withdrawal", None),
+            PersistedTransaction::Transfer { to, .. } => ("transfer", Some(*to)),
+            PersistedTransaction::Adjustment { .. } => ("adjustment", None),
+            PersistedTransaction::Fee { .. } => ("fee", None),
+        };
+        self.connection.prepare_cached(&format!("INSERT OR REPLACE INTO transactions ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"))?
+            .execute(params![
+                transaction_type,
+                transaction.client_id(),
+                to_client,
+                transaction.tx_id(),
+                transaction.amount().units(),
+                transaction.ts().map(Timestamp::millis),
+                state_name(*state),
+                disputes,
+                transaction.currency().code(),
+            ])?;
+        Ok(())
+    }
+
+    // Past the cache size, forgets the oldest transactions but the open disputes, which are still in the database.
+    fn cache(&mut self, stored: StoredTransaction) {
+        let tx_id = stored.tx_id();
+        if self.cached.insert(tx_id, stored).is_none() {
+            self.queue.push_back(tx_id);
+        }
+        for _ in 0..self.queue.len() {
+            if self.cached.len() <= self.cache_size {
+                break
+            }
+            let Some(oldest) = self.queue.pop_front() else {
+                break
+            };
+            match self.cached.get(&oldest).map(|stored| stored.state) {
+                Some(TransactionState::Disputed) => self.queue.push_back(oldest),
+                Some(_) => {
+                    self.cached.remove(&oldest);
+                },
+                None => {},
+            }
+        }
+    }
+
+    /// Number of transactions in memory.
+    pub fn cached(&self) -> usize {
+        self.cached.len()
+    }
+}
+
+impl TransactionStore for SqliteStore {
+    fn insert(&mut self, transaction: StoredTransaction) {
+        if !self.contains(transaction.tx_id()) {
+            self.len += 1;
+        }
+        let result = self.write_transaction(&transaction);
+        self.checked(result);
+        self.cache(transaction);
+    }
+
+    fn get(&self, tx_id: TxId) -> Option<&StoredTransaction> {
+        self.cached.get(&tx_id)
+    }
+
+    fn set_state(&mut self, tx_id: TxId, state: TransactionState, disputes: u32) {
+        let result = self.connection.prepare_cached("UPDATE transactions SET state = ?1, disputes = ?2 WHERE tx = ?3")
+            .and_then(|mut statement| statement.execute(params![state_name(state), disputes, tx_id]))
+            .map(drop)
+            .map_err(StoreError::from);
+        self.checked(result);
+        if let Some(stored) = self.cached.get_mut(&tx_id) {
+            stored.state = state;
+            stored.disputes = disputes;
+        }
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> Option<StoredTransaction> {
+        let result = self.select(tx_id).and_then(|stored| {
+            self.connection.prepare_cached("DELETE FROM transactions WHERE tx = ?1")?.execute([tx_id])?;
+            Ok(stored)
+        });
+        self.cached.remove(&tx_id);
+        let removed = self.checked(result);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn contains(&self, tx_id: TxId) -> bool {
+        if self.cached.contains_key(&tx_id) {
+            return true
+        }
+        let found = self.connection.prepare_cached("SELECT 1 FROM transactions WHERE tx = ?1")
+            .and_then(|mut statement| statement.query_row([tx_id], |_| Ok(())).optional());
+        matches!(found, Ok(Some(())))
+    }
+
+    fn fetch(&mut self, tx_id: TxId) {
+        if self.cached.contains_key(&tx_id) {
+            return
+        }
+        let result = self.select(tx_id);
+        if let Some(stored) = self.checked(result) {
+            self.cache(stored);
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &StoredTransaction> + '_ {
+        self.cached.values()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = StoredTransaction> + '_ {
+        let result = (|| {
+            let mut statement = self.connection.prepare_cached(&format!("SELECT {COLUMNS} FROM transactions ORDER BY seq"))?;
+            let mut rows = statement.query([])?;
+            let mut transactions = Vec::new();
+            while let Some(row) = rows.next()? {
+                transactions.push(stored_transaction(row)?);
+            }
+            Ok(transactions)
+        })();
+        let transactions = self.checked(result);
+        self.clear();
+        transactions.into_iter()
+    }
+
+    fn clear(&mut self) {
+        let result = self.connection.execute("DELETE FROM transactions", []).map(drop).map_err(StoreError::from);
+        self.checked(result);
+        self.cached.clear();
+        self.queue.clear();
+        self.len = 0;
+    }
+
+    // Only the cached transactions are in memory, with their tx id in the queue.
+    fn memory_bytes(&self) -> usize {
+        self.cached.len() * (TRANSACTION_BYTES + TX_ID_BYTES)
+    }
+}
+
+impl TransactionEngine<SqliteStore> {
+    /// Writes the client balances, the dispute counters and the compacted and unstored tx ids to the database, and
+    /// commits them along with the transactions stored since the previous commit, all at once. An engine dropped
+    /// without committing leaves the state of the previous commit, as does a failed commit.
+    pub fn commit(&mut self) -> Result<(), StoreError> {
+        let snapshot = self.account_snapshot();
+        self.store_mut().commit(&snapshot)
+    }
+}
+
+fn state_name(state: TransactionState) -> &'static str {
+    match state {
+        TransactionState::None => "settled",
+        TransactionState::Disputed => "disputed",
+        TransactionState::ChargedBack => "charged_back",
+    }
+}
+
+fn amount(row: &Row, column: usize) -> rusqlite::Result<Amount> {
+    row.get(column).map(Amount::from_units)
+}
+
+fn currency(row: &Row, column: usize) -> rusqlite::Result<Currency> {
+    row.get::<_, String>(column)?.parse().map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+fn stored_transaction(row: &Row) -> Result<StoredTransaction, StoreError> {
+    let (client_id, to, tx_id): (u16, Option<u16>, TxId) = (row.get("client")?, row.get("to_client")?, row.get("tx")?);
+    let (amount, ts) = (amount(row, 4)?, row.get::<_, Option<i64>>("ts")?.map(Timestamp::from_millis));
+    let currency = currency(row, 8)?;
+    let transaction_type: String = row.get("type")?;
+    let transaction = match (transaction_type.as_str(), to) {
+        ("deposit", None) => PersistedTransaction::Deposit { client_id, tx_id, amount, ts, currency },
+        ("withdrawal", None) => PersistedTransaction::Withdrawal { client_id, tx_id, amount, ts, currency },
+        ("transfer", Some(to)) => PersistedTransaction::Transfer { from: client_id, to, tx_id, amount, ts, currency },
+        ("adjustment", None) => PersistedTransaction::Adjustment { client_id, tx_id, amount, ts, currency },
+        ("fee", None) => PersistedTransaction::Fee { client_id, tx_id, amount, ts, currency },
+        (transaction_type, _) => return Err(StoreError::Corrupt(format!("tx {tx_id} is not a valid {transaction_type}"))),
+    };
+    let state = match row.get::<_, String>("state")?.as_str() {
+        "settled" => TransactionState::None,
+        "disputed" => TransactionState::Disputed,
+        "charged_back" => TransactionState::ChargedBack,
+        state => return Err(StoreError::Corrupt(format!("tx {tx_id} has the unknown state {state:?}"))),
+    };
+    Ok(StoredTransaction { transaction, state, disputes: row.get("disputes")? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction_engine::{Transaction, TransactionError, TxStatus};
+
+    fn amount(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
+
+    fn load(path: &Path) -> TransactionEngine<SqliteStore> {
+        SqliteStore::open(path).unwrap().load(EngineConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn when_reopened_should_dispute_a_deposit_of_a_previous_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        {
+            let mut engine = load(&path);
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.5") }).unwrap();
+            engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("3") }).unwrap();
+            engine.compute_transaction(Transaction::Transfer { from: 2, to: 1, tx_id: 3, amount: amount("1") }).unwrap();
+            engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 3 }).unwrap();
+            engine.commit().unwrap();
+        }
+
+        let mut engine = load(&path);
+        assert_eq!(engine.transaction_state(3), TxStatus::Disputed);
+        assert_eq!(engine.metrics().open_disputes, 1);
+        assert_eq!(engine.get_client(1).copied(), Some(Client::new(1, amount("10.5"), amount("1"), amount("11.5"), false)));
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        assert_eq!(engine.get_client(1).copied(), Some(Client::new(1, Amount::ZERO, amount("11.5"), amount("11.5"), false)));
+        assert_eq!(
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("1") }),
+            Err(TransactionError::DuplicateTransaction { tx_id: 2 })
+        );
+    }
+
+    #[test]
+    fn should_write_the_transactions_and_their_state_as_they_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        let mut engine = load(&path);
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2") }).unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+
+        let connection = &engine.store_mut().connection;
+        let row: (String, u32) = connection.query_row("SELECT state, disputes FROM transactions WHERE tx = 1", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        }).unwrap();
+        assert_eq!(row, ("disputed".to_string(), 1));
+    }
+
+    #[test]
+    fn when_not_committed_should_keep_the_state_of_the_previous_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        {
+            let mut engine = load(&path);
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2") }).unwrap();
+            engine.commit().unwrap();
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3") }).unwrap();
+            engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        }
+
+        let engine = load(&path);
+        assert_eq!(engine.get_client(1).copied(), Some(Client::new(1, amount("2"), Amount::ZERO, amount("2"), false)));
+        assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+        assert!(!engine.is_tx_id_used(2));
+    }
+
+    #[test]
+    fn when_past_the_cache_size_should_read_the_transactions_back_from_the_database() {
+        let mut engine = SqliteStore::open_in_memory().unwrap().cache_size(2).load(EngineConfig::default()).unwrap();
+        for tx_id in 1..=5 {
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount: amount("1") }).unwrap();
+        }
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 5 }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 6, amount: amount("1") }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 7, amount: amount("1") }).unwrap();
+
+        assert_eq!(engine.store_mut().cached(), 2);
+        assert_eq!(engine.metrics().stored_transactions, 7);
+        assert_eq!(engine.transaction_state(1), TxStatus::Unknown);
+        assert_eq!(engine.transaction_state(5), TxStatus::Disputed);
+        assert_eq!(
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1") }),
+            Err(TransactionError::DuplicateTransaction { tx_id: 1 })
+        );
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+        assert_eq!(engine.get_client(1).copied(), Some(Client::new(1, amount("5"), amount("1"), amount("6"), true)));
+    }
+
+    #[test]
+    fn when_committed_twice_should_keep_the_latest_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        let mut engine = load(&path);
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2") }).unwrap();
+        engine.commit().unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compact();
+        engine.commit().unwrap();
+
+        let mut loaded = load(&path);
+        assert_eq!(loaded.get_client_list(), engine.get_client_list());
+        assert_eq!(loaded.dispute_counters(1).map(|counters| counters.chargebacks), Some(1));
+        assert_eq!(loaded.metrics().stored_transactions, 0);
+        assert_eq!(
+            loaded.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }),
+            Err(TransactionError::Compacted { tx_id: 1 })
+        );
+    }
+
+    #[test]
+    fn should_keep_the_tx_ids_applied_past_the_max_stored_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        let config = EngineConfig::default().max_stored_transactions(Some(1));
+        let mut engine = SqliteStore::open(&path).unwrap().load(config).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2") }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3") }).unwrap();
+        engine.commit().unwrap();
+
+        let mut loaded = SqliteStore::open(&path).unwrap().load(config).unwrap();
+        assert_eq!(
+            loaded.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3") }),
+            Err(TransactionError::DuplicateTransaction { tx_id: 2 })
+        );
+        assert_eq!(loaded.get_client(1).map(Client::total), Some(amount("5")));
+    }
+
+    #[test]
+    fn should_keep_the_accounts_of_every_currency() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        let mut engine = load(&path);
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2") }).unwrap();
+        engine.compute_transaction_in(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3") }, eur, None).unwrap();
+        engine.commit().unwrap();
+
+        let mut loaded = load(&path);
+        assert_eq!(loaded.get_account(1, eur).copied(), Some(Client::new(1, amount("3"), Amount::ZERO, amount("3"), false).in_currency(eur)));
+        assert_eq!(
+            loaded.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }),
+            Err(TransactionError::CurrencyMismatch { tx_id: 2, currency: Currency::USD, expected: eur })
+        );
+        loaded.compute_transaction_in(Transaction::Dispute { client_id: 1, tx_id: 2 }, eur, None).unwrap();
+        assert_eq!(loaded.get_account(1, eur).map(Client::held), Some(amount("3")));
+    }
+
+    #[test]
+    fn when_the_tables_are_of_version_1_should_migrate_them_to_usd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        Connection::open(&path).unwrap().execute_batch("
+            CREATE TABLE clients (client INTEGER PRIMARY KEY, available INTEGER NOT NULL, held INTEGER NOT NULL,
+                total INTEGER NOT NULL, locked INTEGER NOT NULL);
+            CREATE TABLE transactions (seq INTEGER PRIMARY KEY, tx INTEGER NOT NULL UNIQUE, type TEXT NOT NULL,
+                client INTEGER NOT NULL, to_client INTEGER, amount INTEGER NOT NULL, ts INTEGER, state TEXT NOT NULL,
+                disputes INTEGER NOT NULL);
+            CREATE TABLE dispute_counters (client INTEGER PRIMARY KEY, disputes INTEGER NOT NULL,
+                chargebacks INTEGER NOT NULL, charged_back INTEGER NOT NULL);
+            CREATE TABLE compacted (tx INTEGER PRIMARY KEY);
+            INSERT INTO clients VALUES (1, 50000, 0, 50000, 0);
+            INSERT INTO transactions VALUES (1, 1, 'deposit', 1, NULL, 50000, NULL, 'settled', 0);
+            PRAGMA user_version = 1;
+        ").unwrap();
+
+        let mut engine = load(&path);
+        assert_eq!(engine.get_client(1).copied(), Some(Client::new(1, amount("5"), Amount::ZERO, amount("5"), false)));
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        assert_eq!(engine.get_client(1).map(Client::held), Some(amount("5")));
+        engine.commit().unwrap();
+        assert_eq!(load(&path).get_client_list(), engine.get_client_list());
+    }
+
+    #[test]
+    fn when_the_version_differs_should_not_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.sqlite");
+        Connection::open(&path).unwrap().execute_batch("PRAGMA user_version = 7;").unwrap();
+
+        assert!(matches!(SqliteStore::open(&path), Err(StoreError::Incompatible { version: 7 })));
+    }
+}
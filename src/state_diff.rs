@@ -0,0 +1,121 @@
+//! Comparison of the state of two engines, see [`TransactionEngine::diff_states`].
+
+use std::fmt;
+
+use crate::currency::Currency;
+use crate::transaction_engine::TxId;
+
+/// Differences kept as examples by a [`StateDiff`], the others being only counted.
+pub const MAX_EXAMPLES: usize = 10;
+
+/// The engine a client or transaction is missing from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The engine `diff_states` is called on.
+    Left,
+    /// The engine given to `diff_states`.
+    Right,
+}
+
+/// One difference between two engines, values being written as in the balances and the snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The account of a client in a currency is missing from one engine.
+    MissingClient { client_id: u16, currency: Currency, missing_from: Side },
+    /// A balance or the lock of an account differs.
+    Client { client_id: u16, currency: Currency, field: &'static str, left: String, right: String },
+    /// A stored transaction is missing from one engine.
+    MissingTransaction { tx_id: TxId, missing_from: Side },
+    /// The type, client, amount, currency, dispute state or number of disputes of a stored transaction differs.
+    Transaction { tx_id: TxId, field: &'static str, left: String, right: String },
+    /// A sum over the whole engine differs: funds, held funds, clients, stored transactions or open disputes.
+    Total { field: &'static str, left: String, right: String },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::MissingClient { client_id, currency, missing_from } => {
+                write!(f, "client {client_id} in {currency}: missing from the {}", missing_from.name())
+            },
+            Difference::Client { client_id, currency, field, left, right } => {
+                write!(f, "client {client_id} in {currency}: {field} {left} != {right}")
+            },
+            Difference::MissingTransaction { tx_id, missing_from } => {
+                write!(f, "tx {tx_id}: missing from the {}", missing_from.name())
+            },
+            Difference::Transaction { tx_id, field, left, right } => write!(f, "tx {tx_id}: {field} {left} != {right}"),
+            Difference::Total { field, left, right } => write!(f, "{field}: {left} != {right}"),
+        }
+    }
+}
+
+impl Side {
+    fn name(self) -> &'static str {
+        match self {
+            Side::Left => "left",
+            Side::Right => "right",
+        }
+    }
+}
+
+/// Differences between two engines, as returned by
+/// [`TransactionEngine::diff_states`](crate::TransactionEngine::diff_states): their number for the clients, the
+/// stored transactions and the totals, along with the first [`MAX_EXAMPLES`] of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub clients: usize,
+    pub transactions: usize,
+    pub totals: usize,
+    /// Client differences first, by client id, then transactions by tx id, then totals.
+    pub examples: Vec<Difference>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Number of differences, the ones left out of the examples included.
+    pub fn count(&self) -> usize {
+        self.clients + self.transactions + self.totals
+    }
+
+    pub(crate) fn push(&mut self, difference: Difference) {
+        match difference {
+            Difference::MissingClient { .. } | Difference::Client { .. } => self.clients += 1,
+            Difference::MissingTransaction { .. } | Difference::Transaction { .. } => self.transactions += 1,
+            Difference::Total { .. } => self.totals += 1,
+        }
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(difference);
+        }
+    }
+
+    // Records a difference built by `difference` when the values differ.
+    pub(crate) fn compare<T: PartialEq + fmt::Display>(
+        &mut self,
+        left: T,
+        right: T,
+        difference: impl FnOnce(String, String) -> Difference,
+    ) {
+        if left != right {
+            self.push(difference(left.to_string(), right.to_string()));
+        }
+    }
+}
+
+// A line per example, after a line with the counts.
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} differences: {} in the clients, {} in the transactions, {} in the totals",
+            self.count(), self.clients, self.transactions, self.totals)?;
+        for difference in &self.examples {
+            write!(f, "\n{difference}")?;
+        }
+        if self.examples.len() < self.count() {
+            write!(f, "\n...")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+//! Where a [`TransactionEngine`](crate::TransactionEngine) keeps the transactions that can still be disputed.
+//!
+//! The engine reads and writes them through the [`TransactionStore`] trait, so that a deployment can pick
+//! its own storage. [`InMemoryStore`] is the default one, [`BTreeStore`] keeps them ordered by tx id.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "json-io")]
+use std::collections::VecDeque;
+#[cfg(feature = "json-io")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "json-io")]
+use std::io::{Read, Seek, SeekFrom, Write};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::collections::FastHashMap;
+#[cfg(feature = "json-io")]
+use crate::memory;
+use crate::memory::TRANSACTION_BYTES;
+use crate::transaction_engine::{PersistedTransaction, TxId};
+
+// An I/O error on the spill file, only ever used by this process, is handled like a failed allocation.
+const SPILL_FAILED: &str = "cannot use the spill file of the stored transactions";
+
+/// Dispute state of a stored transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum TransactionState {
+    Disputed,
+    ChargedBack,
+    /// Not disputed, either never disputed or resolved.
+    None
+}
+
+/// A transaction kept by the engine, with its dispute state and the number of disputes opened on it.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct StoredTransaction {
+    pub transaction: PersistedTransaction,
+    pub state: TransactionState,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub disputes: u32,
+}
+
+impl StoredTransaction {
+    pub fn tx_id(&self) -> TxId {
+        self.transaction.tx_id()
+    }
+}
+
+/// Storage of the transactions of an engine, by tx id.
+pub trait TransactionStore {
+    /// Stores a transaction, replacing the one with the same tx id if any.
+    fn insert(&mut self, transaction: StoredTransaction);
+
+    fn get(&self, tx_id: TxId) -> Option<&StoredTransaction>;
+
+    /// Sets the dispute state of the transaction and the number of disputes opened on it, nothing is done
+    /// when it is not stored. The engine changes the state of its transactions through it only.
+    fn set_state(&mut self, tx_id: TxId, state: TransactionState, disputes: u32);
+
+    fn remove(&mut self, tx_id: TxId) -> Option<StoredTransaction>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains(&self, tx_id: TxId) -> bool {
+        self.get(tx_id).is_some()
+    }
+
+    /// Brings the transaction back in memory when the store moved it elsewhere, so that `get` finds it. The
+    /// engine fetches the transaction referenced by a dispute, resolve or chargeback before reading it.
+    fn fetch(&mut self, _tx_id: TxId) {}
+
+    /// Iterates over the stored transactions, in the order of the store.
+    fn iter(&self) -> impl Iterator<Item = &StoredTransaction> + '_;
+
+    /// Removes every transaction, handing them over, e.g. to merge them into another store.
+    fn drain(&mut self) -> impl Iterator<Item = StoredTransaction> + '_;
+
+    fn clear(&mut self) {
+        self.drain().for_each(drop);
+    }
+
+    /// Makes room for `additional` transactions, when the store has any use of it.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Estimated bytes of the transactions in memory, and of what the store keeps in memory of the others.
+    fn memory_bytes(&self) -> usize {
+        self.len() * TRANSACTION_BYTES
+    }
+}
+
+/// Transactions in a hash map, the store of [`TransactionEngine::new`](crate::TransactionEngine::new).
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryStore {
+    transactions: FastHashMap<TxId, StoredTransaction>,
+    spill: Option<Spill>,
+}
+
+impl InMemoryStore {
+    /// Creates a store keeping about `max_bytes` of transactions in memory at most. Past it, the oldest
+    /// transactions that are not disputed move to a temporary file, which is deleted along with the store or
+    /// when the process ends, and are read back when a dispute references them.
+    ///
+    /// Only the transactions in memory are listed by `iter` and found by `get` before being fetched, so that
+    /// [`TransactionEngine::transaction`](crate::TransactionEngine::transaction), `compact` and snapshots
+    /// miss the spilled ones. The store panics when the file cannot be written or read, as it would when
+    /// failing to allocate memory.
+    #[cfg(feature = "json-io")]
+    pub fn with_memory_limit(max_bytes: usize) -> io::Result<InMemoryStore> {
+        let spill = Spill {
+            max_bytes,
+            file: tempfile::tempfile()?,
+            end: 0,
+            index: FastHashMap::default(),
+            queue: VecDeque::new(),
+        };
+        Ok(InMemoryStore { transactions: FastHashMap::default(), spill: Some(spill) })
+    }
+
+    /// Number of transactions the store holds without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.transactions.capacity()
+    }
+
+    /// Number of transactions moved to the spill file.
+    pub fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, Spill::len)
+    }
+}
+
+impl TransactionStore for InMemoryStore {
+    fn insert(&mut self, transaction: StoredTransaction) {
+        let tx_id = transaction.tx_id();
+        self.transactions.insert(tx_id, transaction);
+        if let Some(spill) = &mut self.spill {
+            spill.enqueue(tx_id);
+            spill.spill(&mut self.transactions).expect(SPILL_FAILED);
+        }
+    }
+
+    fn get(&self, tx_id: TxId) -> Option<&StoredTransaction> {
+        self.transactions.get(&tx_id)
+    }
+
+    fn set_state(&mut self, tx_id: TxId, state: TransactionState, disputes: u32) {
+        if let Some(stored) = self.transactions.get_mut(&tx_id) {
+            stored.state = state;
+            stored.disputes = disputes;
+        }
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> Option<StoredTransaction> {
+        match &mut self.spill {
+            Some(spill) if !self.transactions.contains_key(&tx_id) => spill.read(tx_id).expect(SPILL_FAILED),
+            _ => self.transactions.remove(&tx_id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len() + self.spilled()
+    }
+
+    fn contains(&self, tx_id: TxId) -> bool {
+        self.transactions.contains_key(&tx_id) || self.spill.as_ref().is_some_and(|spill| spill.contains(tx_id))
+    }
+
+    fn fetch(&mut self, tx_id: TxId) {
+        let Some(spill) = &mut self.spill else {
+            return
+        };
+        if let Some(transaction) = spill.read(tx_id).expect(SPILL_FAILED) {
+            spill.enqueue(tx_id);
+            self.transactions.insert(tx_id, transaction);
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &StoredTransaction> + '_ {
+        self.transactions.values()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = StoredTransaction> + '_ {
+        let spilled = match &mut self.spill {
+            Some(spill) => spill.read_all().expect(SPILL_FAILED),
+            None => Vec::new(),
+        };
+        self.transactions.drain().map(|(_, transaction)| transaction).chain(spilled)
+    }
+
+    fn clear(&mut self) {
+        self.transactions.clear();
+        if let Some(spill) = &mut self.spill {
+            spill.clear().expect(SPILL_FAILED);
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.transactions.reserve(additional);
+    }
+
+    fn memory_bytes(&self) -> usize {
+        match &self.spill {
+            Some(spill) => spill.memory(self.transactions.len()),
+            None => self.transactions.len() * TRANSACTION_BYTES,
+        }
+    }
+}
+
+// The transactions of an `InMemoryStore` moved to a temporary file, as the JSON of each one after the other.
+#[cfg(feature = "json-io")]
+#[derive(Debug)]
+struct Spill {
+    max_bytes: usize,
+    // Deleted by the OS once closed, even when the process is killed.
+    file: File,
+    end: u64,
+    // Offset and length of each spilled transaction in the file.
+    index: FastHashMap<TxId, (u64, u32)>,
+    // Tx ids of the transactions in memory, oldest first, possibly along with ids removed since.
+    queue: VecDeque<TxId>,
+}
+
+#[cfg(feature = "json-io")]
+impl Spill {
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn contains(&self, tx_id: TxId) -> bool {
+        self.index.contains_key(&tx_id)
+    }
+
+    // Queues a transaction back in memory, to be spilled in turn.
+    fn enqueue(&mut self, tx_id: TxId) {
+        self.queue.push_back(tx_id);
+    }
+
+    fn memory(&self, in_memory: usize) -> usize {
+        memory::spilling_store_bytes(in_memory, self.index.len())
+    }
+
+    // Once past the limit, spills down to three quarters of it so that the file is written in large chunks.
+    // Open disputes stay in memory, they are about to be resolved or charged back.
+    fn spill(&mut self, transactions: &mut FastHashMap<TxId, StoredTransaction>) -> io::Result<()> {
+        if self.memory(transactions.len()) <= self.max_bytes {
+            return Ok(())
+        }
+        let target = self.max_bytes / 4 * 3;
+        let mut buffer = Vec::new();
+        for _ in 0..self.queue.len() {
+            if self.memory(transactions.len()) <= target {
+                break
+            }
+            let Some(tx_id) = self.queue.pop_front() else {
+                break
+            };
+            match transactions.get(&tx_id).map(|transaction| transaction.state) {
+                Some(TransactionState::None) => {},
+                Some(_) => {
+                    self.queue.push_back(tx_id);
+                    continue
+                },
+                None => continue,
+            }
+            if let Some(transaction) = transactions.remove(&tx_id) {
+                let start = buffer.len();
+                serde_json::to_writer(&mut buffer, &transaction)?;
+                self.index.insert(tx_id, (self.end + start as u64, (buffer.len() - start) as u32));
+            }
+        }
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&buffer)?;
+        self.end += buffer.len() as u64;
+        Ok(())
+    }
+
+    fn read(&mut self, tx_id: TxId) -> io::Result<Option<StoredTransaction>> {
+        let Some((offset, len)) = self.index.remove(&tx_id) else {
+            return Ok(None)
+        };
+        let mut record = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut record)?;
+        Ok(Some(serde_json::from_slice(&record)?))
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<StoredTransaction>> {
+        let tx_ids: Vec<TxId> = self.index.keys().copied().collect();
+        let transactions = tx_ids.into_iter().filter_map(|tx_id| self.read(tx_id).transpose()).collect();
+        self.clear()?;
+        transactions
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.index.clear();
+        self.queue.clear();
+        self.end = 0;
+        self.file.set_len(0)
+    }
+}
+
+// A clone spills to its own copy of the file.
+#[cfg(feature = "json-io")]
+impl Clone for Spill {
+    fn clone(&self) -> Spill {
+        let copy = || -> io::Result<File> {
+            let mut file = tempfile::tempfile()?;
+            let mut source = &self.file;
+            source.seek(SeekFrom::Start(0))?;
+            io::copy(&mut source.take(self.end), &mut file)?;
+            Ok(file)
+        };
+        Spill {
+            max_bytes: self.max_bytes,
+            file: copy().expect(SPILL_FAILED),
+            end: self.end,
+            index: self.index.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+// Without the `json-io` feature there is no spill file, and no store spills.
+#[cfg(not(feature = "json-io"))]
+#[derive(Clone, Debug)]
+enum Spill {}
+
+#[cfg(not(feature = "json-io"))]
+impl Spill {
+    fn len(&self) -> usize {
+        match *self {}
+    }
+
+    fn contains(&self, _: TxId) -> bool {
+        match *self {}
+    }
+
+    fn enqueue(&mut self, _: TxId) {
+        match *self {}
+    }
+
+    fn memory(&self, _: usize) -> usize {
+        match *self {}
+    }
+
+    fn spill(&mut self, _: &mut FastHashMap<TxId, StoredTransaction>) -> io::Result<()> {
+        match *self {}
+    }
+
+    fn read(&mut self, _: TxId) -> io::Result<Option<StoredTransaction>> {
+        match *self {}
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<StoredTransaction>> {
+        match *self {}
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+/// Transactions in a B-tree, iterated by increasing tx id, so that the disputed transactions are listed in
+/// the same order on every run.
+#[derive(Clone, Debug, Default)]
+pub struct BTreeStore {
+    transactions: BTreeMap<TxId, StoredTransaction>,
+}
+
+impl TransactionStore for BTreeStore {
+    fn insert(&mut self, transaction: StoredTransaction) {
+        self.transactions.insert(transaction.tx_id(), transaction);
+    }
+
+    fn get(&self, tx_id: TxId) -> Option<&StoredTransaction> {
+        self.transactions.get(&tx_id)
+    }
+
+    fn set_state(&mut self, tx_id: TxId, state: TransactionState, disputes: u32) {
+        if let Some(stored) = self.transactions.get_mut(&tx_id) {
+            stored.state = state;
+            stored.disputes = disputes;
+        }
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> Option<StoredTransaction> {
+        self.transactions.remove(&tx_id)
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &StoredTransaction> + '_ {
+        self.transactions.values()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = StoredTransaction> + '_ {
+        std::mem::take(&mut self.transactions).into_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Currency;
+
+    fn deposit(tx_id: TxId) -> StoredTransaction {
+        let transaction = PersistedTransaction::Deposit { client_id: 1, tx_id, amount: "1.0".parse().unwrap(), ts: None, currency: Currency::USD };
+        StoredTransaction { transaction, state: TransactionState::None, disputes: 0 }
+    }
+
+    fn check_store<S: TransactionStore + Default>() {
+        let mut store = S::default();
+        for tx_id in [3, 1, 2] {
+            store.insert(deposit(tx_id));
+        }
+        store.set_state(2, TransactionState::Disputed, 1);
+        store.set_state(4, TransactionState::Disputed, 1);
+
+        assert_eq!(store.len(), 3);
+        assert!(!store.contains(4));
+        assert_eq!(store.get(2).map(|stored| (stored.state, stored.disputes)), Some((TransactionState::Disputed, 1)));
+        assert_eq!(store.remove(3).map(|stored| stored.tx_id()), Some(3));
+        let mut drained: Vec<TxId> = store.drain().map(|stored| stored.tx_id()).collect();
+        drained.sort_unstable();
+        assert_eq!(drained, vec![1, 2]);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn in_memory_store_should_keep_the_transactions_by_tx_id() {
+        check_store::<InMemoryStore>();
+    }
+
+    #[test]
+    fn btree_store_should_keep_the_transactions_by_tx_id() {
+        check_store::<BTreeStore>();
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn spilling_store_should_keep_the_transactions_by_tx_id() {
+        let mut store = InMemoryStore::with_memory_limit(0).unwrap();
+        for tx_id in [3, 1, 2] {
+            store.insert(deposit(tx_id));
+        }
+
+        assert_eq!((store.len(), store.spilled()), (3, 3));
+        assert_eq!(store.memory_bytes(), memory::spilling_store_bytes(0, 3));
+        assert!(store.contains(1) && store.get(1).is_none());
+        store.fetch(1);
+        assert_eq!(store.get(1).map(StoredTransaction::tx_id), Some(1));
+        assert_eq!(store.remove(3).map(|stored| stored.tx_id()), Some(3));
+        assert_eq!(store.clone().len(), 2);
+        let mut drained: Vec<TxId> = store.drain().map(|stored| stored.tx_id()).collect();
+        drained.sort_unstable();
+        assert_eq!(drained, vec![1, 2]);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "json-io")]
+    fn when_spilled_a_deposit_should_still_be_disputed() {
+        use crate::{Amount, EngineConfig, Transaction, TransactionEngine, TxStatus};
+
+        let store = InMemoryStore::with_memory_limit(memory::spilling_store_bytes(10, 0)).unwrap();
+        let mut engine = TransactionEngine::with_store(store, EngineConfig::default());
+        for tx_id in 1..=100 {
+            engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount: "1.0".parse().unwrap() }).unwrap();
+        }
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+        for tx_id in 101..=200 {
+            engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id, amount: "1.0".parse().unwrap() }).unwrap();
+        }
+
+        assert_eq!(engine.metrics().stored_transactions, 200);
+        assert_eq!(engine.transaction_state(1), TxStatus::Unknown);
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 2 }).unwrap();
+        assert_eq!(engine.transaction_state(1), TxStatus::ChargedBack);
+        let client = engine.get_client(1).unwrap();
+        assert_eq!((client.available(), client.held(), client.total()), ("99".parse().unwrap(), Amount::ZERO, "99".parse().unwrap()));
+        assert!(client.is_locked());
+        assert_eq!(
+            engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 3, amount: "1.0".parse().unwrap() }),
+            Err(crate::TransactionError::DuplicateTransaction { tx_id: 3 })
+        );
+    }
+
+    #[test]
+    fn btree_store_should_iterate_by_increasing_tx_id() {
+        let mut store = BTreeStore::default();
+        for tx_id in [3, 1, 2] {
+            store.insert(deposit(tx_id));
+        }
+
+        assert_eq!(store.iter().map(StoredTransaction::tx_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+}
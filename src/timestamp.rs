@@ -0,0 +1,260 @@
+//! Time of an input row, from its optional `ts` column.
+
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::de::{self, Visitor};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// A point in time stored as milliseconds since the Unix epoch.
+///
+/// Parses from epoch milliseconds (`1700000000000`) or an RFC 3339 date time
+/// (`2023-11-14T22:13:20.5Z`, `2023-11-14T23:13:20+01:00`), digits beyond the millisecond being
+/// truncated. Serializes to an RFC 3339 UTC date time with milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const fn from_millis(millis: i64) -> Timestamp {
+        Timestamp(millis)
+    }
+
+    /// Milliseconds since the Unix epoch.
+    pub const fn millis(self) -> i64 {
+        self.0
+    }
+
+    /// Time from `earlier` to this one, zero when `earlier` is later, e.g. after the system clock went back.
+    pub fn duration_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(u64::try_from(self.0.saturating_sub(earlier.0)).unwrap_or(0))
+    }
+
+    /// Days since the Unix epoch, the same for every time of a UTC date.
+    pub const fn day(self) -> i64 {
+        self.0.div_euclid(MILLIS_PER_DAY)
+    }
+
+    /// The UTC date, as `YYYY-MM-DD`.
+    pub fn date(self) -> String {
+        let (year, month, day) = civil_from_days(self.day());
+        format!("{year:04}-{month:02}-{day:02}")
+    }
+}
+
+/// Error returned when parsing a [`Timestamp`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTimestampError(String);
+
+impl fmt::Display for ParseTimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid timestamp {:?}, expected epoch milliseconds or an RFC 3339 date time", self.0)
+    }
+}
+
+impl std::error::Error for ParseTimestampError {}
+
+impl FromStr for Timestamp {
+    type Err = ParseTimestampError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseTimestampError(s.to_string());
+        if !s.is_empty() && s.trim_start_matches('-').bytes().all(|b| b.is_ascii_digit()) {
+            return s.parse().map(Timestamp).map_err(|_| invalid())
+        }
+        parse_rfc3339(s).ok_or_else(invalid)
+    }
+}
+
+// `YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)`, the `T` may also be a space.
+fn parse_rfc3339(s: &str) -> Option<Timestamp> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+        return None
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') {
+        return None
+    }
+    let (year, month, day) = (number(s, 0..4)?, number(s, 5..7)?, number(s, 8..10)?);
+    let (hour, minute, second) = (number(s, 11..13)?, number(s, 14..16)?, number(s, 17..19)?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+        return None
+    }
+
+    let mut rest = &s[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None
+        }
+        let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+        millis = padded.parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+
+    let offset_minutes = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (hours, minutes) = (number(rest, 1..3)?, number(rest, 4..6)?);
+            if hours > 23 || minutes > 59 {
+                return None
+            }
+            let offset = hours * 60 + minutes;
+            if *sign == b'-' { -offset } else { offset }
+        },
+        _ => return None,
+    };
+
+    let seconds = ((days_from_civil(year, month, day) * 24 + hour) * 60 + minute - offset_minutes) * 60 + second;
+    Some(Timestamp(seconds * 1000 + millis))
+}
+
+// Digits of `s` in `range`, without sign.
+fn number(s: &str, range: Range<usize>) -> Option<i64> {
+    let digits = s.get(range)?;
+    digits.bytes().all(|b| b.is_ascii_digit()).then(|| digits.parse().ok())?
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date, after Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.0.div_euclid(MILLIS_PER_DAY));
+        let millis_of_day = self.0.rem_euclid(MILLIS_PER_DAY);
+        let (seconds, millis) = (millis_of_day / 1000, millis_of_day % 1000);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+            seconds / 3600, seconds / 60 % 60, seconds % 60
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Timestamp {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Timestamp".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({ "type": "string", "format": "date-time" })
+    }
+}
+
+#[cfg(feature = "serde")]
+struct TimestampVisitor;
+
+#[cfg(feature = "serde")]
+impl Visitor<'_> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "epoch milliseconds or an RFC 3339 date time")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Timestamp, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn should_parse_epoch_millis_and_rfc3339() {
+        assert_eq!(ts("1700000000000"), Timestamp::from_millis(1_700_000_000_000));
+        assert_eq!(ts("-1"), Timestamp::from_millis(-1));
+        assert_eq!(ts("2023-11-14T22:13:20Z"), Timestamp::from_millis(1_700_000_000_000));
+        assert_eq!(ts("2023-11-14 22:13:20.5z"), Timestamp::from_millis(1_700_000_000_500));
+        assert_eq!(ts("2023-11-14T22:13:20.123456Z"), Timestamp::from_millis(1_700_000_000_123));
+        assert_eq!(ts("2023-11-14T23:43:20+01:30"), Timestamp::from_millis(1_700_000_000_000));
+        assert_eq!(ts("2023-11-14T21:13:20-01:00"), Timestamp::from_millis(1_700_000_000_000));
+        assert_eq!(ts("1970-01-01T00:00:00Z"), Timestamp::from_millis(0));
+        assert_eq!(ts("2024-02-29T00:00:00Z"), Timestamp::from_millis(1_709_164_800_000));
+    }
+
+    #[test]
+    fn when_not_a_timestamp_should_reject() {
+        for s in [
+            "", "-", "12a", "2023-11-14", "2023-11-14T22:13:20", "2023-13-14T22:13:20Z", "2023-02-29T00:00:00Z",
+            "2023-11-14T24:00:00Z", "2023-11-14T22:13:20.Z", "2023-11-14T22:13:20+0100", "2023-11-14X22:13:20Z",
+        ] {
+            assert!(s.parse::<Timestamp>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn should_display_as_rfc3339_utc() {
+        assert_eq!(Timestamp::from_millis(1_700_000_000_500).to_string(), "2023-11-14T22:13:20.500Z");
+        assert_eq!(Timestamp::from_millis(-1).to_string(), "1969-12-31T23:59:59.999Z");
+        assert_eq!(ts(&Timestamp::from_millis(1_709_164_800_000).to_string()), Timestamp::from_millis(1_709_164_800_000));
+    }
+
+    #[test]
+    fn should_give_the_utc_date() {
+        assert_eq!(ts("2024-02-29T00:00:00Z").date(), "2024-02-29");
+        assert_eq!(ts("2024-02-29T23:59:59.999Z").day(), ts("2024-02-29T00:00:00Z").day());
+        assert_eq!(ts("2024-03-01T00:30:00+01:00").date(), "2024-02-29");
+        assert_eq!(Timestamp::from_millis(-1).date(), "1969-12-31");
+    }
+}
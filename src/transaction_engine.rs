@@ -1,524 +1,1915 @@
-use std::{ collections::HashMap};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+#[cfg(feature = "json-io")]
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::amount::Amount;
+use crate::batch::BatchSummary;
+use crate::changes::{ChangeTracker, Changes};
+use crate::clock::{Clock, SharedClock};
+use crate::collections::{FastHashMap, FastHashSet};
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::events::{EngineEvent, EngineObserver, Observer};
+use crate::eviction::{ArchivedClient, ClientArchive, EvictAfter, Eviction};
+use crate::memory::{self, MemoryEstimate};
+use crate::state_diff::{Difference, Side, StateDiff};
+use crate::metrics::{DisputeCounters, Metrics};
+use crate::retry::{ParkedTransaction, RetryQueue, RetryQueueConfig};
+use crate::store::{InMemoryStore, StoredTransaction, TransactionState, TransactionStore};
+use crate::timestamp::Timestamp;
+
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Id of a transaction, unique across the clients. 64 bits wide, so that the ids of an upstream system
+/// numbering its transactions past 2^32 are kept as is.
+pub type TxId = u64;
+
+/// An operation applied to a client account by [`TransactionEngine::compute_transaction`].
+///
+/// Disputes, resolves and chargebacks reference a previous deposit by its `tx_id`. An unlock
+/// reinstates an account locked by a chargeback. A transfer moves funds from the `from` client to the
+/// `to` client, it can be disputed by the `from` client. An adjustment is a signed correction of
+/// the balances that cannot be disputed. A fee is charged like a withdrawal but may leave the
+/// available funds negative.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transaction {
+    Deposit{client_id: u16, tx_id : TxId, amount: Amount},
+    Withdrawal{client_id: u16, tx_id : TxId, amount: Amount},
+    Dispute{client_id: u16, tx_id : TxId},
+    Resolve{client_id: u16, tx_id : TxId},
+    Chargeback{client_id: u16, tx_id : TxId},
+    Unlock{client_id: u16, tx_id : TxId},
+    Transfer{from: u16, to: u16, tx_id : TxId, amount: Amount},
+    Adjustment{client_id: u16, tx_id : TxId, amount: Amount},
+    Fee{client_id: u16, tx_id : TxId, amount: Amount},
+} 
 
-use serde::Deserialize;
-use serde::Serialize;
+impl Transaction {
+    /// Client the transaction applies to, the source of a transfer.
+    pub fn client_id(&self) -> u16 {
+        match self {
+            Transaction::Transfer { from, .. } => *from,
+            Transaction::Deposit { client_id, .. }
+            | Transaction::Withdrawal { client_id, .. }
+            | Transaction::Dispute { client_id, .. }
+            | Transaction::Resolve { client_id, .. }
+            | Transaction::Chargeback { client_id, .. }
+            | Transaction::Unlock { client_id, .. }
+            | Transaction::Adjustment { client_id, .. }
+            | Transaction::Fee { client_id, .. } => *client_id,
+        }
+    }
 
+    /// Id of the transaction, or of the referenced one for disputes, resolves and chargebacks.
+    pub fn tx_id(&self) -> TxId {
+        match self {
+            Transaction::Deposit { tx_id, .. }
+            | Transaction::Withdrawal { tx_id, .. }
+            | Transaction::Dispute { tx_id, .. }
+            | Transaction::Resolve { tx_id, .. }
+            | Transaction::Chargeback { tx_id, .. }
+            | Transaction::Unlock { tx_id, .. }
+            | Transaction::Transfer { tx_id, .. }
+            | Transaction::Adjustment { tx_id, .. }
+            | Transaction::Fee { tx_id, .. } => *tx_id,
+        }
+    }
 
-pub enum Transaction {
-    Deposit{client_id: u16, tx_id : u32, amount: f64},
-    Withdrawal{client_id: u16, tx_id : u32, amount: f64},
-    Dispute{client_id: u16, tx_id : u32},
-    Resolve{client_id: u16, tx_id : u32},
-    Chargeback{client_id: u16, tx_id : u32},
-} 
+    /// Amount of the transaction, none for disputes, resolves, chargebacks and unlocks.
+    pub fn amount(&self) -> Option<Amount> {
+        match self {
+            Transaction::Deposit { amount, .. }
+            | Transaction::Withdrawal { amount, .. }
+            | Transaction::Transfer { amount, .. }
+            | Transaction::Adjustment { amount, .. }
+            | Transaction::Fee { amount, .. } => Some(*amount),
+            Transaction::Dispute { .. } | Transaction::Resolve { .. } | Transaction::Chargeback { .. } | Transaction::Unlock { .. } => None,
+        }
+    }
 
-#[derive(Clone)]
+    /// Snake case name of the transaction type, as in the `type` column of an input.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit { .. } => "deposit",
+            Transaction::Withdrawal { .. } => "withdrawal",
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolve { .. } => "resolve",
+            Transaction::Chargeback { .. } => "chargeback",
+            Transaction::Unlock { .. } => "unlock",
+            Transaction::Transfer { .. } => "transfer",
+            Transaction::Adjustment { .. } => "adjustment",
+            Transaction::Fee { .. } => "fee",
+        }
+    }
+}
+
+/// Serialized as the row of an input: its `type`, `client`, `tx`, `amount` and `to_client` columns, the last two
+/// being none when the transaction has none. With the `csv-io` feature, the row is deserialized back into the
+/// transaction by the impl of `csv_model`.
+#[cfg(feature = "serde")]
+impl Serialize for Transaction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let to_client = match self {
+            Transaction::Transfer { to, .. } => Some(*to),
+            _ => None,
+        };
+        let mut row = serializer.serialize_struct("Transaction", 5)?;
+        row.serialize_field("type", self.name())?;
+        row.serialize_field("client", &self.client_id())?;
+        row.serialize_field("tx", &self.tx_id())?;
+        row.serialize_field("amount", &self.amount())?;
+        row.serialize_field("to_client", &to_client)?;
+        row.end()
+    }
+}
+
+// Records the amount of the disputed transaction on the `transaction` span of a dispute, resolve or chargeback.
+fn record_disputed_amount(amount: Amount) {
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        tracing::Span::current().record("amount", tracing::field::display(amount));
+    }
+}
+
+/// Reason a transaction was rejected by [`TransactionEngine::compute_transaction`].
+///
+/// A rejected transaction leaves balances and stored transactions untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// Deposits, withdrawals and fees on a locked account are rejected, as well as adjustments unless allowed
+    /// by [`EngineConfig::allow_adjustment_on_locked`] and disputes, resolves and chargebacks unless allowed
+    /// by [`EngineConfig::allow_dispute_on_locked`]. Deposits can be allowed by
+    /// [`EngineConfig::reject_deposits_when_locked`].
+    AccountLocked { client_id: u16 },
+    /// The withdrawal exceeds the available funds.
+    InsufficientFunds { client_id: u16, requested: Amount, available: Amount },
+    /// The deposit, withdrawal or fee amount is zero or negative, or the adjustment amount is zero.
+    InvalidAmount { tx_id: TxId, amount: Amount },
+    /// The referenced tx is not stored by the engine.
+    UnknownTransaction { tx_id: TxId },
+    /// A dispute, resolve or chargeback comes from another client than the referenced tx's owner.
+    ClientMismatch { client_id: u16, tx_id: TxId },
+    /// A resolve or chargeback references a tx that is not disputed.
+    NotDisputed { tx_id: TxId },
+    /// A dispute references a tx that is already disputed.
+    AlreadyDisputed { tx_id: TxId },
+    /// The referenced tx has been charged back and can no longer change state.
+    AlreadyChargedBack { tx_id: TxId },
+    /// A deposit, withdrawal or transfer reuses the tx id of a stored transaction.
+    DuplicateTransaction { tx_id: TxId },
+    /// A transfer from a client to itself.
+    SelfTransfer { tx_id: TxId },
+    /// A dispute references an adjustment.
+    NotDisputable { tx_id: TxId },
+    /// A dispute would hold more than the available funds while
+    /// [`EngineConfig::allow_negative_available_on_dispute`] is disabled. The same dispute can be
+    /// submitted again once the funds are back.
+    DisputeExceedsAvailable { client_id: u16, tx_id: TxId, amount: Amount, available: Amount },
+    /// A dispute references a tx already disputed [`EngineConfig::max_disputes_per_tx`] times.
+    DisputeLimitReached { tx_id: TxId, max: u32 },
+    /// The referenced tx was dropped by [`TransactionEngine::compact`] or by the compaction of a locked account.
+    Compacted { tx_id: TxId },
+    /// An unlock references a client without account.
+    UnknownClient { client_id: u16 },
+    /// An unlock references an account that is not locked.
+    NotLocked { client_id: u16 },
+    /// A dispute, resolve or chargeback in `currency` references a tx in the `expected` one.
+    CurrencyMismatch { tx_id: TxId, currency: Currency, expected: Currency },
+    /// A dispute, resolve or chargeback references a tx not stored yet, and waits for it in the retry queue of
+    /// [`TransactionEngine::set_retry_queue`].
+    Parked { tx_id: TxId },
+    /// A balance of the account would go beyond the range of an [`Amount`].
+    AmountOverflow { client_id: u16, tx_id: TxId },
+}
+
+impl TransactionError {
+    /// Stable snake case name of the error kind, e.g. `insufficient_funds`, for counting rejections.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::AccountLocked { .. } => "account_locked",
+            TransactionError::InsufficientFunds { .. } => "insufficient_funds",
+            TransactionError::InvalidAmount { .. } => "invalid_amount",
+            TransactionError::UnknownTransaction { .. } => "unknown_transaction",
+            TransactionError::ClientMismatch { .. } => "client_mismatch",
+            TransactionError::NotDisputed { .. } => "not_disputed",
+            TransactionError::AlreadyDisputed { .. } => "already_disputed",
+            TransactionError::AlreadyChargedBack { .. } => "already_charged_back",
+            TransactionError::DuplicateTransaction { .. } => "duplicate_transaction",
+            TransactionError::SelfTransfer { .. } => "self_transfer",
+            TransactionError::NotDisputable { .. } => "not_disputable",
+            TransactionError::DisputeExceedsAvailable { .. } => "dispute_exceeds_available",
+            TransactionError::DisputeLimitReached { .. } => "dispute_limit_reached",
+            TransactionError::Compacted { .. } => "compacted",
+            TransactionError::UnknownClient { .. } => "unknown_client",
+            TransactionError::NotLocked { .. } => "not_locked",
+            TransactionError::CurrencyMismatch { .. } => "currency_mismatch",
+            TransactionError::Parked { .. } => "parked",
+            TransactionError::AmountOverflow { .. } => "amount_overflow",
+        }
+    }
+
+    /// Whether the transaction references a tx that cannot be disputed, resolved or charged back, or
+    /// an account that cannot be unlocked, in which case it is ignored as an error on the partner's side
+    /// rather than rejected.
+    pub fn is_ignored(&self) -> bool {
+        matches!(
+            self,
+            TransactionError::UnknownTransaction { .. }
+                | TransactionError::ClientMismatch { .. }
+                | TransactionError::NotDisputed { .. }
+                | TransactionError::AlreadyDisputed { .. }
+                | TransactionError::AlreadyChargedBack { .. }
+                | TransactionError::NotDisputable { .. }
+                | TransactionError::Compacted { .. }
+                | TransactionError::UnknownClient { .. }
+                | TransactionError::NotLocked { .. }
+                | TransactionError::Parked { .. }
+        )
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::AccountLocked { client_id } => write!(f, "account {client_id} is locked"),
+            TransactionError::InsufficientFunds { client_id, requested, available } => {
+                write!(f, "insufficient funds on account {client_id}: requested {requested}, available {available}")
+            },
+            TransactionError::InvalidAmount { tx_id, amount } => write!(f, "tx {tx_id} has a non-positive amount {amount}"),
+            TransactionError::UnknownTransaction { tx_id } => write!(f, "tx {tx_id} is unknown"),
+            TransactionError::ClientMismatch { client_id, tx_id } => write!(f, "tx {tx_id} does not belong to client {client_id}"),
+            TransactionError::NotDisputed { tx_id } => write!(f, "tx {tx_id} is not disputed"),
+            TransactionError::AlreadyDisputed { tx_id } => write!(f, "tx {tx_id} is already disputed"),
+            TransactionError::AlreadyChargedBack { tx_id } => write!(f, "tx {tx_id} has been charged back"),
+            TransactionError::DuplicateTransaction { tx_id } => write!(f, "tx {tx_id} already exists"),
+            TransactionError::SelfTransfer { tx_id } => write!(f, "tx {tx_id} transfers to its own source"),
+            TransactionError::NotDisputable { tx_id } => write!(f, "tx {tx_id} is an adjustment and cannot be disputed"),
+            TransactionError::DisputeExceedsAvailable { client_id, tx_id, amount, available } => {
+                write!(f, "dispute of tx {tx_id} would hold {amount} on account {client_id} with {available} available")
+            },
+            TransactionError::DisputeLimitReached { tx_id, max } => write!(f, "tx {tx_id} has already been disputed {max} times"),
+            TransactionError::Compacted { tx_id } => write!(f, "tx {tx_id} can no longer be disputed and was compacted"),
+            TransactionError::UnknownClient { client_id } => write!(f, "client {client_id} has no account"),
+            TransactionError::NotLocked { client_id } => write!(f, "account {client_id} is not locked"),
+            TransactionError::CurrencyMismatch { tx_id, currency, expected } => {
+                write!(f, "tx {tx_id} is in {expected}, not in {currency}")
+            },
+            TransactionError::Parked { tx_id } => write!(f, "tx {tx_id} is unknown yet, parked until it comes"),
+            TransactionError::AmountOverflow { client_id, tx_id } => {
+                write!(f, "tx {tx_id} would overflow a balance of account {client_id}")
+            },
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// A client whose balances break the ledger invariants, as returned by
+/// [`TransactionEngine::check_invariants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// Available plus held funds differ from the total.
+    Unbalanced { client_id: u16, available: Amount, held: Amount, total: Amount },
+    /// Held funds are negative.
+    NegativeHeld { client_id: u16, held: Amount },
+}
+
+impl InvariantViolation {
+    pub fn client_id(&self) -> u16 {
+        match self {
+            InvariantViolation::Unbalanced { client_id, .. }
+            | InvariantViolation::NegativeHeld { client_id, .. } => *client_id,
+        }
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::Unbalanced { client_id, available, held, total } => {
+                write!(f, "client {client_id}: available {available} + held {held} != total {total}")
+            },
+            InvariantViolation::NegativeHeld { client_id, held } => write!(f, "client {client_id}: held {held} is negative"),
+        }
+    }
+}
+
+/// A transaction kept by the engine so it can later be disputed, with the timestamp of its row if any and
+/// the currency of its account, USD for the transactions saved before currencies were supported.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum PersistedTransaction{
-    Deposit{client_id: u16, tx_id : u32, amount: f64},
+    Deposit{client_id: u16, tx_id : TxId, amount: Amount, #[cfg_attr(feature = "serde", serde(default))] ts: Option<Timestamp>, #[cfg_attr(feature = "serde", serde(default))] currency: Currency},
+    Withdrawal{client_id: u16, tx_id : TxId, amount: Amount, #[cfg_attr(feature = "serde", serde(default))] ts: Option<Timestamp>, #[cfg_attr(feature = "serde", serde(default))] currency: Currency},
+    Transfer{from: u16, to: u16, tx_id : TxId, amount: Amount, #[cfg_attr(feature = "serde", serde(default))] ts: Option<Timestamp>, #[cfg_attr(feature = "serde", serde(default))] currency: Currency},
+    /// Stored for the record and to keep its tx id unique, an adjustment cannot be disputed.
+    Adjustment{client_id: u16, tx_id : TxId, amount: Amount, #[cfg_attr(feature = "serde", serde(default))] ts: Option<Timestamp>, #[cfg_attr(feature = "serde", serde(default))] currency: Currency},
+    /// Disputed like a withdrawal.
+    Fee{client_id: u16, tx_id : TxId, amount: Amount, #[cfg_attr(feature = "serde", serde(default))] ts: Option<Timestamp>, #[cfg_attr(feature = "serde", serde(default))] currency: Currency},
+}
+
+impl PersistedTransaction {
+    /// Client owning the transaction, who can dispute it: the source of a transfer.
+    pub fn client_id(&self) -> u16 {
+        match self {
+            PersistedTransaction::Deposit { client_id, .. }
+            | PersistedTransaction::Withdrawal { client_id, .. }
+            | PersistedTransaction::Transfer { from: client_id, .. }
+            | PersistedTransaction::Adjustment { client_id, .. }
+            | PersistedTransaction::Fee { client_id, .. } => *client_id,
+        }
+    }
+
+    /// Client whose funds are held while the transaction is disputed: the recipient of a transfer.
+    pub fn held_client_id(&self) -> u16 {
+        match self {
+            PersistedTransaction::Transfer { to, .. } => *to,
+            _ => self.client_id(),
+        }
+    }
+
+    /// Id of the stored transaction.
+    pub fn tx_id(&self) -> TxId {
+        match self {
+            PersistedTransaction::Deposit { tx_id, .. }
+            | PersistedTransaction::Withdrawal { tx_id, .. }
+            | PersistedTransaction::Transfer { tx_id, .. }
+            | PersistedTransaction::Adjustment { tx_id, .. }
+            | PersistedTransaction::Fee { tx_id, .. } => *tx_id,
+        }
+    }
+
+    /// Time of the row the transaction was read from, when given.
+    pub fn ts(&self) -> Option<Timestamp> {
+        match self {
+            PersistedTransaction::Deposit { ts, .. }
+            | PersistedTransaction::Withdrawal { ts, .. }
+            | PersistedTransaction::Transfer { ts, .. }
+            | PersistedTransaction::Adjustment { ts, .. }
+            | PersistedTransaction::Fee { ts, .. } => *ts,
+        }
+    }
+
+    /// Currency of the accounts the transaction applies to.
+    pub fn currency(&self) -> Currency {
+        match self {
+            PersistedTransaction::Deposit { currency, .. }
+            | PersistedTransaction::Withdrawal { currency, .. }
+            | PersistedTransaction::Transfer { currency, .. }
+            | PersistedTransaction::Adjustment { currency, .. }
+            | PersistedTransaction::Fee { currency, .. } => *currency,
+        }
+    }
+
+    /// Amount deposited, withdrawn, transferred or charged, signed amount of an adjustment.
+    pub fn amount(&self) -> Amount {
+        match self {
+            PersistedTransaction::Deposit { amount, .. }
+            | PersistedTransaction::Withdrawal { amount, .. }
+            | PersistedTransaction::Transfer { amount, .. }
+            | PersistedTransaction::Adjustment { amount, .. }
+            | PersistedTransaction::Fee { amount, .. } => *amount,
+        }
+    }
+}
+
+/// Dispute status of a transaction, as returned by [`TransactionEngine::transaction_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The engine stores no deposit or withdrawal with this tx id.
+    Unknown,
+    /// Stored and not disputed, either never disputed or resolved.
+    Settled,
+    Disputed,
+    ChargedBack,
 }
 
-#[derive(Clone,Copy,Debug, Deserialize, Serialize)]
+/// Balances of a client account in one currency, a client having an account per currency it transacts in.
+#[derive(Clone,Copy,Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Client {
     client: u16,
-    available: f64,
-    held: f64,
-    total: f64,
-    locked: bool
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    currency: Currency,
+}
+
+impl Client {
+    /// Builds a client with the given balances in USD, mostly useful to compare against engine output.
+    pub fn new(client: u16, available: Amount, held: Amount, total: Amount, locked: bool) -> Client {
+        Client { client, available, held, total, locked, currency: Currency::default() }
+    }
+
+    /// The same balances in `currency`.
+    pub fn in_currency(self, currency: Currency) -> Client {
+        Client { currency, ..self }
+    }
+
+    /// Client id.
+    pub fn id(&self) -> u16 {
+        self.client
+    }
+
+    /// Currency of the account.
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Funds available for withdrawal.
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    /// Funds held by open disputes.
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    /// Available plus held funds.
+    pub fn total(&self) -> Amount {
+        self.total
+    }
+
+    /// Whether a chargeback locked the account.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // Without funds, held ones or lock, as an account that can be evicted.
+    pub(crate) fn is_empty(&self) -> bool {
+        [self.available, self.held, self.total] == [Amount::ZERO; 3] && !self.locked
+    }
+
+    // The client with the signed amounts added to its balances, `None` when one of them overflows.
+    fn with_changes(&self, available: Amount, held: Amount, total: Amount) -> Option<Client> {
+        Some(Client {
+            available: self.available.checked_add(available)?,
+            held: self.held.checked_add(held)?,
+            total: self.total.checked_add(total)?,
+            ..*self
+        })
+    }
+
+    fn violations(&self) -> impl Iterator<Item = InvariantViolation> {
+        let unbalanced = (self.available + self.held != self.total).then_some(InvariantViolation::Unbalanced {
+            client_id: self.client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+        });
+        let negative_held = self.held.is_negative()
+            .then_some(InvariantViolation::NegativeHeld { client_id: self.client, held: self.held });
+        unbalanced.into_iter().chain(negative_held)
+    }
+}
+
+// A client with the tx ids of its stored transactions, in insertion order, kept in the same entry so that
+// applying a transaction looks the client up once.
+#[derive(Clone, Debug)]
+pub(crate) struct Account {
+    client: Client,
+    history: Vec<TxId>,
+}
+
+impl Account {
+    // Keeps a transaction of this client so that it can be disputed, unless the store is full, in which case only
+    // its tx id is kept in `unstored`.
+    fn store<S: TransactionStore>(
+        &mut self,
+        transactions: &mut S,
+        unstored: &mut FastHashSet<TxId>,
+        max_stored: Option<usize>,
+        transaction: PersistedTransaction,
+    ) {
+        if max_stored.is_some_and(|max| transactions.len() >= max) {
+            unstored.insert(transaction.tx_id());
+            return
+        }
+        self.history.push(transaction.tx_id());
+        transactions.insert(StoredTransaction { transaction, state: TransactionState::None, disputes: 0 });
+    }
 }
 
+// Accounts keyed by client id and currency.
+#[derive(Clone, Debug, Default)]
 struct ClientList{
-    clients: HashMap<u16,Client>
+    clients: FastHashMap<(u16, Currency),Account>
 }
 
 impl ClientList {
     fn new() -> ClientList {
-        ClientList { clients: HashMap::new() }
+        ClientList { clients: FastHashMap::default() }
     }
 
-    fn get_mut(&mut self,id: u16) -> &mut Client {
+    fn account_mut(&mut self, id: u16, currency: Currency) -> &mut Account {
         self.clients
-            .entry(id)
-            .or_insert_with(|| Client{
-                client: id,
-                held: 0.0,
-                total: 0.0,
-                available: 0.0,
-                locked: false
+            .entry((id, currency))
+            .or_insert_with(|| Account {
+                client: Client{
+                    client: id,
+                    held: Amount::ZERO,
+                    total: Amount::ZERO,
+                    available: Amount::ZERO,
+                    locked: false,
+                    currency,
+                },
+                history: Vec::new(),
         })
     }
 
-    fn get_all(&self) -> Vec<Client> {
-        (&self.clients).into_iter()
-            .map(|(_,client)| client )
-            .copied()
-            .collect()
+    fn get_mut(&mut self,id: u16, currency: Currency) -> &mut Client {
+        &mut self.account_mut(id, currency).client
+    }
+
+    fn get(&self, id: u16, currency: Currency) -> Option<&Client> {
+        self.clients.get(&(id, currency)).map(|account| &account.client)
+    }
+
+    #[cfg(any(feature = "json-io", feature = "sqlite"))]
+    fn insert(&mut self, client: Client) {
+        self.account_mut(client.client, client.currency).client = client;
+    }
+
+    fn history(&self, id: u16, currency: Currency) -> &[TxId] {
+        self.clients.get(&(id, currency)).map_or(&[], |account| &account.history)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Client> + '_ {
+        self.clients.values().map(|account| &account.client)
+    }
+
+    // A client of both lists ends up with the balances it has in `other`, and the histories of both.
+    fn merge(&mut self, other: ClientList) {
+        for ((id, currency), account) in other.clients {
+            let merged = self.account_mut(id, currency);
+            merged.client = account.client;
+            merged.history.extend(account.history);
+        }
+    }
+
+    fn clear_histories(&mut self) {
+        for account in self.clients.values_mut() {
+            account.history.clear();
+        }
+    }
+
+    // Rejects a dispute, resolve or chargeback of `disputed` changing the balances of one of `client_ids` when it
+    // is locked and disputes on locked accounts are disallowed. Adjustments are left to their own rejection.
+    fn check_locked_dispute(&self, allow_dispute_on_locked: bool, disputed: &PersistedTransaction, client_ids: &[u16]) -> Result<(), TransactionError> {
+        if allow_dispute_on_locked || matches!(disputed, PersistedTransaction::Adjustment { .. }) {
+            return Ok(())
+        }
+        match client_ids.iter().find(|id| self.get(**id, disputed.currency()).is_some_and(Client::is_locked)) {
+            Some(&client_id) => Err(TransactionError::AccountLocked { client_id }),
+            None => Ok(()),
+        }
     }
 }
 
-enum TransactionState {
-    Disputed,
-    None
+/// A client together with the tx ids of its open disputes, as returned by
+/// [`TransactionEngine::top_by_held`].
+pub struct HeldExposure {
+    pub client: Client,
+    pub disputed_tx: Vec<TxId>,
+}
+
+struct ByHeld(Client);
+
+impl Ord for ByHeld {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.held.cmp(&other.0.held)
+            .then_with(|| other.0.client.cmp(&self.0.client))
+    }
 }
 
-pub struct TransactionEngine {
+impl PartialOrd for ByHeld {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ByHeld {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByHeld {}
+
+/// First line of a snapshot, followed by the JSON state. The version changes whenever the state format does.
+#[cfg(feature = "json-io")]
+const SNAPSHOT_HEADER: &str = "transaction_engine snapshot 1";
+
+/// Reason a snapshot could not be written or loaded by [`TransactionEngine::save_snapshot`]
+/// and [`TransactionEngine::load_snapshot`].
+#[cfg(feature = "json-io")]
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The snapshot does not start with the header of this version.
+    Incompatible { header: String },
+    /// The state after the header is not valid.
+    Corrupt(serde_json::Error),
+}
+
+#[cfg(feature = "json-io")]
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{e}"),
+            SnapshotError::Incompatible { header } => {
+                write!(f, "not a compatible snapshot, expected the header {SNAPSHOT_HEADER:?} but found {header:?}")
+            },
+            SnapshotError::Corrupt(e) => write!(f, "corrupt snapshot: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "json-io")]
+impl std::error::Error for SnapshotError {}
+
+#[cfg(feature = "json-io")]
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+// Transactions are listed client by client in the order they were applied, which is enough to
+// rebuild the history of each client.
+#[cfg(any(feature = "json-io", feature = "sqlite"))]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub(crate) struct Snapshot {
+    pub(crate) clients: Vec<Client>,
+    pub(crate) transactions: Vec<StoredTransaction>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) dispute_counters: Vec<(u16,DisputeCounters)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) compacted: Vec<TxId>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) unstored: Vec<TxId>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) parked: Vec<ParkedTransaction>,
+}
+
+/// Applies transactions to client accounts.
+///
+/// Clients are created on their first transaction. Deposits are kept so they can be
+/// disputed, resolved and charged back later on, in the [`TransactionStore`] `S`.
+///
+/// ```
+/// use transaction_engine::{Amount, Transaction, TransactionEngine};
+///
+/// let mut engine = TransactionEngine::new();
+/// let amount: Amount = "10.5".parse().unwrap();
+/// engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount }).unwrap();
+///
+/// let client = engine.get_client(1).unwrap();
+/// assert_eq!(client.available(), amount);
+/// assert_eq!(client.total(), amount);
+/// assert!(!client.is_locked());
+/// ```
+#[derive(Clone, Debug)]
+pub struct TransactionEngine<S = InMemoryStore> {
     client_list: ClientList,
-    transactions: HashMap<u32,(PersistedTransaction,TransactionState)>,
+    transactions: S,
+    // Tx ids of the transactions dropped by compaction, still known so that they are told apart from unknown ones.
+    compacted: FastHashSet<TxId>,
+    // Tx ids of the transactions applied once the store held EngineConfig::max_stored_transactions, still known so
+    // that they cannot be reused.
+    unstored: FastHashSet<TxId>,
+    metrics: Metrics,
+    dispute_counters: FastHashMap<u16,DisputeCounters>,
+    // Credit limits of the clients that do not have the default one of the config.
+    credit_limits: FastHashMap<u16, Amount>,
+    observer: Option<Observer>,
+    eviction: Option<Eviction>,
+    retry: RetryQueue,
+    changes: Option<ChangeTracker>,
+    clock: SharedClock,
+    config: EngineConfig,
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        TransactionEngine::new()
+    }
 }
 
 impl TransactionEngine {
     pub fn new() -> TransactionEngine {
+        TransactionEngine::with_config(EngineConfig::default())
+    }
+
+    /// Creates an engine with room for `clients` clients and `transactions` stored transactions, so that
+    /// callers knowing the rough size of their input avoid rehashing as it grows.
+    pub fn with_capacity(clients: usize, transactions: usize) -> TransactionEngine {
+        let mut engine = TransactionEngine::new();
+        engine.client_list.clients.reserve(clients);
+        engine.transactions.reserve(transactions);
+        engine
+    }
+
+    pub fn with_config(config: EngineConfig) -> TransactionEngine {
+        TransactionEngine::with_store(InMemoryStore::default(), config)
+    }
+
+    /// Restores an engine saved by [`TransactionEngine::save_snapshot`]. The metrics counters start over.
+    #[cfg(feature = "json-io")]
+    pub fn load_snapshot<R: Read>(input: R) -> Result<TransactionEngine, SnapshotError> {
+        let mut input = BufReader::new(input);
+        let mut header = String::new();
+        input.read_line(&mut header)?;
+        if header.trim_end() != SNAPSHOT_HEADER {
+            return Err(SnapshotError::Incompatible { header: header.trim_end().to_string() })
+        }
+        let snapshot: Snapshot = serde_json::from_reader(input).map_err(SnapshotError::Corrupt)?;
+        Ok(TransactionEngine::from_snapshot(snapshot))
+    }
+
+    #[cfg(feature = "json-io")]
+    pub(crate) fn from_snapshot(snapshot: Snapshot) -> TransactionEngine {
+        TransactionEngine::restore(InMemoryStore::default(), EngineConfig::default(), snapshot)
+    }
+
+}
+
+impl<S: TransactionStore> TransactionEngine<S> {
+    /// Creates an engine keeping its transactions in `store`, which is expected to be empty.
+    ///
+    /// ```
+    /// use transaction_engine::{BTreeStore, EngineConfig, Transaction, TransactionEngine, TxId};
+    ///
+    /// let mut engine = TransactionEngine::with_store(BTreeStore::default(), EngineConfig::default());
+    /// for tx_id in [3, 1, 2] {
+    ///     let amount = "1.0".parse().unwrap();
+    ///     engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount }).unwrap();
+    ///     engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id }).unwrap();
+    /// }
+    ///
+    /// let disputed: Vec<TxId> = engine.disputed_transactions().map(|(tx_id, _)| tx_id).collect();
+    /// assert_eq!(disputed, vec![1, 2, 3]);
+    /// ```
+    pub fn with_store(store: S, config: EngineConfig) -> TransactionEngine<S> {
         TransactionEngine{
             client_list: ClientList::new(),
-            transactions: HashMap::new()
+            transactions: store,
+            compacted: FastHashSet::default(),
+            unstored: FastHashSet::default(),
+            metrics: Metrics::default(),
+            dispute_counters: FastHashMap::default(),
+            credit_limits: FastHashMap::default(),
+            observer: None,
+            eviction: None,
+            retry: RetryQueue::default(),
+            changes: None,
+            clock: SharedClock::default(),
+            config,
         }
     }
 
-    pub fn compute_transaction(&mut self, transaction: Transaction) {
-        match transaction {
-            Transaction::Deposit{client_id,tx_id,amount} => self.handle_deposit(client_id,tx_id,amount),
-            Transaction::Withdrawal{client_id,tx_id,amount} => self.handle_withdrawal(client_id,tx_id,amount),
-            Transaction::Dispute{client_id,tx_id} => self.handle_dispute(client_id,tx_id),
-            Transaction::Resolve{client_id,tx_id} => self.handle_resolve(client_id,tx_id),
-            Transaction::Chargeback{client_id,tx_id} => self.handle_chargeback(client_id,tx_id),
+    // Restores the state of `snapshot` around `store`, the transactions of the snapshot that the store does not
+    // hold yet being inserted into it. The open disputes are counted from those of the snapshot.
+    #[cfg(any(feature = "json-io", feature = "sqlite"))]
+    pub(crate) fn restore(store: S, config: EngineConfig, snapshot: Snapshot) -> TransactionEngine<S> {
+        let mut engine = TransactionEngine::with_store(store, config);
+        for client in snapshot.clients {
+            engine.client_list.insert(client);
         }
+        for stored in snapshot.transactions {
+            let tx_id = stored.tx_id();
+            if matches!(stored.state, TransactionState::Disputed) {
+                engine.metrics.open_disputes += 1;
+            }
+            engine.client_list.account_mut(stored.transaction.client_id(), stored.transaction.currency()).history.push(tx_id);
+            if !engine.transactions.contains(tx_id) {
+                engine.transactions.insert(stored);
+            }
+        }
+        engine.dispute_counters.extend(snapshot.dispute_counters);
+        engine.compacted.extend(snapshot.compacted);
+        engine.unstored.extend(snapshot.unstored);
+        engine.retry.extend(snapshot.parked, engine.clock.now());
+        engine
     }
 
-    pub fn get_client_list(&self) -> Vec<Client> {
-        (&self.client_list).get_all().clone()
+    // The store of the transactions, for a store that also persists the rest of the engine.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn store_mut(&mut self) -> &mut S {
+        &mut self.transactions
     }
 
-    fn handle_deposit(&mut self, client_id: u16, tx_id : u32, amount: f64) {
-        let client = self.client_list.get_mut(client_id);
+    /// Sends the events of the following transactions to `observer`. The clones of the engine share it.
+    pub fn set_observer<O: EngineObserver + 'static>(&mut self, observer: O) {
+        self.observer = Some(Observer(Arc::new(observer)));
+    }
 
-        if client.locked {
-            return
-        }
-    
-        client.total += amount;
-        client.available += amount;
+    /// Lets [`TransactionEngine::evict_idle`] move the accounts idle for `after` to `archive`, an account being
+    /// restored from the archive by the next transaction naming it: as its client, the recipient of a transfer,
+    /// or the client of a disputed, resolved or charged back transaction. Only the accounts without funds,
+    /// held ones or lock are evicted, their stored transactions staying in the engine so that they can still
+    /// be disputed. The evicted accounts are left out of [`TransactionEngine::clients`] until restored.
+    pub fn set_eviction<A: ClientArchive + 'static>(&mut self, after: EvictAfter, archive: A) {
+        self.eviction = Some(Eviction::new(after, Arc::new(Mutex::new(archive)), self.clock.now()));
+    }
 
-        self.transactions.insert(tx_id, 
-            (PersistedTransaction::Deposit { client_id, tx_id,  amount },TransactionState::None));
+    /// Reads the time of [`EvictAfter::Duration`] and [`crate::ExpireAfter::Duration`] from `clock` rather than
+    /// from the [`SystemClock`](crate::clock::SystemClock), e.g. a [`ManualClock`](crate::clock::ManualClock)
+    /// moved by a test. Set before the eviction and the retry queue, the times they already recorded being those
+    /// of the previous clock. The clones of the engine share it.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = SharedClock(Arc::new(clock));
     }
 
-    fn handle_withdrawal(&mut self, client_id: u16, _ : u32, amount: f64) {
-        let client = self.client_list.get_mut(client_id);
+    /// Moves the accounts idle for the [`EvictAfter`] of [`TransactionEngine::set_eviction`] to its archive, an
+    /// account the archive fails to take being kept. Returns the number of evicted accounts, 0 without
+    /// eviction.
+    pub fn evict_idle(&mut self) -> usize {
+        let Some(eviction) = &mut self.eviction else {
+            return 0
+        };
+        let now = self.clock.now();
+        let idle: Vec<(u16, Currency)> = self.client_list.clients.iter()
+            .filter(|(key, account)| account.client.is_empty() && eviction.is_idle(key, now))
+            .map(|(key, _)| *key)
+            .collect();
+        let mut archive = eviction.archive.lock().expect("a thread panicked while archiving an account");
+        let mut evicted = 0;
+        for key in idle {
+            let Account { client, history } = self.client_list.clients.remove(&key).expect("idle account");
+            let account = ArchivedClient { client, history };
+            match archive.archive(&account) {
+                Ok(()) => {
+                    eviction.activity.remove(&key);
+                    evicted += 1;
+                },
+                Err(e) => {
+                    tracing::warn!("cannot archive client {} in {}: {e}", key.0, key.1);
+                    self.client_list.clients.insert(key, Account { client: account.client, history: account.history });
+                },
+            }
+        }
+        self.metrics.evicted_clients += evicted as u64;
+        evicted
+    }
 
-        if client.locked {
+    // Brings back the evicted accounts that `transaction` may change, and counts it as their activity. An
+    // account the archive fails to give back is opened anew, with zero balances like the evicted one.
+    fn restore_evicted(&mut self, transaction: &Transaction, currency: Currency) {
+        let client_ids = self.touched_client_ids(transaction);
+        let Some(eviction) = &mut self.eviction else {
             return
+        };
+        eviction.sequence += 1;
+        let now = self.clock.now();
+        for client_id in client_ids.into_iter().flatten() {
+            let key = (client_id, currency);
+            eviction.touch(key, now);
+            if self.client_list.clients.contains_key(&key) {
+                continue
+            }
+            let restored = eviction.archive.lock().expect("a thread panicked while archiving an account")
+                .restore(client_id, currency);
+            match restored {
+                Ok(Some(ArchivedClient { client, history })) => {
+                    self.client_list.clients.insert(key, Account { client, history });
+                    self.metrics.restored_clients += 1;
+                },
+                Ok(None) => {},
+                Err(e) => tracing::warn!("cannot restore client {client_id} in {currency}: {e}"),
+            }
         }
-        
-        if client.total >= amount || client.available >= amount {
-            client.total -= amount;
-            client.available -= amount;
+    }
+
+    // Clients whose accounts `transaction` may change: its client, the recipient of a transfer, and the owner and
+    // holder of the referenced tx.
+    fn touched_client_ids(&self, transaction: &Transaction) -> [Option<u16>; 4] {
+        let referenced = match *transaction {
+            Transaction::Dispute { tx_id, .. } | Transaction::Resolve { tx_id, .. } | Transaction::Chargeback { tx_id, .. } => {
+                self.transactions.get(tx_id).map(|stored| &stored.transaction)
+            },
+            _ => None,
+        };
+        let recipient = match *transaction {
+            Transaction::Transfer { to, .. } => Some(to),
+            _ => None,
+        };
+        [
+            Some(transaction.client_id()),
+            recipient,
+            referenced.map(PersistedTransaction::client_id),
+            referenced.map(PersistedTransaction::held_client_id),
+        ]
+    }
+
+    /// Records the accounts changed by the following transactions, to be taken by
+    /// [`TransactionEngine::take_changes`], e.g. to write only the balances changed since the previous output.
+    pub fn track_changes(&mut self) {
+        self.changes.get_or_insert_with(ChangeTracker::default);
+    }
+
+    /// The accounts changed since the previous call, or since [`TransactionEngine::track_changes`], which are
+    /// then cleared. An account counts as changed once a transaction touching it is applied, or opened it before
+    /// being rejected. An account evicted since it changed comes with the zero balances it was evicted with.
+    /// `None` when the changes are not tracked.
+    pub fn take_changes(&mut self) -> Option<Changes> {
+        let tracker = self.changes.as_mut()?;
+        let mut clients: Vec<Client> = tracker.changed.drain()
+            .map(|(id, currency)| match self.client_list.get(id, currency) {
+                Some(client) => *client,
+                None => Client::new(id, Amount::ZERO, Amount::ZERO, Amount::ZERO, false).in_currency(currency),
+            })
+            .collect();
+        clients.sort_unstable_by_key(|client| (client.client, client.currency));
+        let changes = Changes { clients, first_seq: tracker.taken + 1, last_seq: tracker.sequence };
+        tracker.taken = tracker.sequence;
+        Some(changes)
+    }
+
+    /// Parks the disputes, resolves and chargebacks of a tx not stored yet, rejecting them as
+    /// [`TransactionError::Parked`], instead of ignoring them as [`TransactionError::UnknownTransaction`]. They are
+    /// applied in the order they came right after the transaction with their tx id, e.g. when two producers race,
+    /// or left to [`TransactionEngine::expire_parked`]. The parked transactions are saved in snapshots, and wait
+    /// anew from the time the snapshot is loaded.
+    pub fn set_retry_queue(&mut self, config: RetryQueueConfig) {
+        self.retry.config = Some(config);
+    }
+
+    /// Removes the parked transactions that waited longer than the [`RetryQueueConfig`] allows, for the caller
+    /// to report them as rejected for their unknown tx.
+    pub fn expire_parked(&mut self) -> Vec<ParkedTransaction> {
+        let expired = self.retry.expire(self.clock.now());
+        self.metrics.parked_expired += expired.len() as u64;
+        expired
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Applies `config` to the following transactions, e.g. on an engine restored from a snapshot.
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    /// Lets the withdrawals of `client_id` take its available funds in the base currency down to `-limit`,
+    /// instead of the [`EngineConfig::credit_limit`] of the other clients. Like the config, credit limits are
+    /// not saved in snapshots.
+    pub fn set_credit_limit(&mut self, client_id: u16, limit: Amount) {
+        self.credit_limits.insert(client_id, limit);
+    }
 
+    /// The amount `client_id` can withdraw beyond its available funds in the base currency.
+    pub fn credit_limit(&self, client_id: u16) -> Amount {
+        self.credit_limits.get(&client_id).copied().unwrap_or(self.config.credit_limit)
+    }
+
+    fn emit(&self, event: EngineEvent) {
+        if let Some(Observer(observer)) = &self.observer {
+            observer.on_event(&event);
         }
     }
 
-    fn handle_dispute(&mut self, _: u16, tx_id : u32) {
-        let (disputed,state) = match self.transactions.get(&tx_id){
-            Some(tx) => tx,
-            None => return,
-        };
+    /// Applies a transaction, or returns why it was rejected (locked account,
+    /// insufficient funds, unknown or wrongly disputed tx...). A rejected transaction has no effect.
+    pub fn compute_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        self.compute_transaction_at(transaction, None)
+    }
 
-        if !matches!(state, &TransactionState::None) {
-            return
+    /// Like [`TransactionEngine::compute_transaction`], with the time of the row the transaction was
+    /// read from, which is kept with the stored transaction.
+    pub fn compute_transaction_at(&mut self, transaction: Transaction, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        self.compute_transaction_in(transaction, self.config.base_currency, ts)
+    }
+
+    /// Like [`TransactionEngine::compute_transaction_at`], on the accounts in `currency` rather than in the base
+    /// currency of the [`EngineConfig`]. Balances in different currencies are never added up: a transfer moves
+    /// funds between the accounts of both clients in `currency`, and a dispute, resolve or chargeback of a
+    /// transaction in another currency is rejected as [`TransactionError::CurrencyMismatch`].
+    ///
+    /// Logs the decision taken at debug level with [`tracing`], in a `transaction` span naming its type, client,
+    /// tx, amount and currency, the amount of the referenced transaction for disputes, resolves and chargebacks.
+    pub fn compute_transaction_in(&mut self, transaction: Transaction, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if self.eviction.is_some() {
+            self.restore_evicted(&transaction, currency);
         }
-        
-        match disputed {
-            PersistedTransaction::Deposit { client_id, tx_id: _, amount } => {
-                let client = self.client_list.get_mut(*client_id);
-                client.available -= amount;
-                client.held += amount;
+        let touched = match &mut self.changes {
+            Some(tracker) => {
+                tracker.sequence += 1;
+                Some(self.touched_client_ids(&transaction))
+            },
+            None => None,
+        };
+        if self.retry.config.is_some() {
+            self.retry.tick();
+            if let Some(parked) = ParkedTransaction::new(transaction, currency, ts) {
+                if !self.is_tx_id_used(parked.tx_id) {
+                    if self.retry.park(parked, self.clock.now()) {
+                        return Err(TransactionError::Parked { tx_id: parked.tx_id })
+                    }
+                    self.metrics.parked_overflow += 1;
+                }
             }
         }
+        let client_id = transaction.client_id();
+        let new_client = (self.observer.is_some() || touched.is_some()) && self.client_list.get(client_id, currency).is_none();
+        // Checked first: without subscriber, the macros of `tracing` still evaluate their fields for the `log` crate.
+        let result = match tracing::enabled!(tracing::Level::DEBUG) {
+            true => self.apply_logged(transaction, currency, ts),
+            false => self.apply(transaction, currency, ts),
+        };
+        if let (Some(touched), Some(tracker)) = (touched, &mut self.changes) {
+            match result {
+                Ok(()) => tracker.changed.extend(touched.into_iter().flatten().map(|id| (id, currency))),
+                Err(_) if new_client && self.client_list.get(client_id, currency).is_some() => {
+                    tracker.changed.insert((client_id, currency));
+                },
+                Err(_) => {},
+            }
+        }
+        // An ignored transaction changes nothing the observer would have to know about.
+        match result {
+            Err(error) if !error.is_ignored() => {
+                // Checks come after the account is looked up, leaving it opened with zero balances.
+                if new_client && self.client_list.get(client_id, currency).is_some() {
+                    self.emit(EngineEvent::AccountOpened { client_id, tx_id: transaction.tx_id() });
+                }
+                self.emit(EngineEvent::TransactionRejected { client_id, tx_id: transaction.tx_id(), error });
+            },
+            // The transactions waiting for this one are applied right after it.
+            Ok(()) if self.retry.is_waiting_for(transaction.tx_id()) && self.transactions.contains(transaction.tx_id()) => {
+                for parked in self.retry.take(transaction.tx_id(), self.clock.now()) {
+                    self.metrics.parked_retried += 1;
+                    let _ = self.compute_transaction_in(parked.transaction(), parked.currency, parked.ts);
+                }
+            },
+            _ => {},
+        }
+        result
+    }
 
-        self.transactions.insert(tx_id, (disputed.clone(),TransactionState::Disputed));
+    fn apply_logged(&mut self, transaction: Transaction, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        let _span = tracing::debug_span!(
+            "transaction",
+            r#type = transaction.name(),
+            client_id = transaction.client_id(),
+            tx_id = transaction.tx_id(),
+            amount = transaction.amount().map(tracing::field::display),
+            currency = tracing::field::display(currency),
+        ).entered();
+        let result = self.apply(transaction, currency, ts);
+        match &result {
+            Ok(()) => tracing::debug!(decision = "applied", "transaction applied"),
+            Err(error) => {
+                let decision = if error.is_ignored() { "ignored" } else { "rejected" };
+                tracing::debug!(decision, reason = error.code(), "transaction {decision}: {error}");
+            },
+        }
+        result
     }
 
-    fn handle_resolve(&mut self, _: u16, tx_id : u32) {
-        let (disputed,state) = match self.transactions.get(&tx_id){
-            Some(tx) => tx,
-            None => return,
-        };
+    /// Applies every transaction in turn, a rejected one being counted and skipped.
+    pub fn compute_transactions<I: IntoIterator<Item = Transaction>>(&mut self, transactions: I) -> BatchSummary {
+        let mut summary = BatchSummary::new();
+        for transaction in transactions {
+            match self.compute_transaction(transaction) {
+                Ok(()) => summary.applied(),
+                Err(e) => summary.rejected_tx(transaction.tx_id(), e.code()),
+            }
+        }
+        summary
+    }
 
-        if !matches!(state, &TransactionState::Disputed) {
-            return
+    fn apply(&mut self, transaction: Transaction, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        match transaction {
+            Transaction::Deposit{client_id,tx_id,amount} => {
+                let result = self.handle_deposit(client_id,tx_id,amount,currency,ts);
+                self.metrics.deposit.record(&result);
+                result
+            },
+            Transaction::Withdrawal{client_id,tx_id,amount} => {
+                let result = self.handle_withdrawal(client_id,tx_id,amount,currency,ts);
+                self.metrics.withdrawal.record(&result);
+                result
+            },
+            Transaction::Dispute{client_id,tx_id} => {
+                let result = self.handle_dispute(client_id,tx_id,currency);
+                self.metrics.dispute.record(&result);
+                result
+            },
+            Transaction::Resolve{client_id,tx_id} => {
+                let result = self.handle_resolve(client_id,tx_id,currency);
+                self.metrics.resolve.record(&result);
+                result
+            },
+            Transaction::Chargeback{client_id,tx_id} => {
+                let result = self.handle_chargeback(client_id,tx_id,currency);
+                self.metrics.chargeback.record(&result);
+                result
+            },
+            Transaction::Unlock{client_id,tx_id} => {
+                let result = self.handle_unlock(client_id,tx_id,currency);
+                self.metrics.unlock.record(&result);
+                result
+            },
+            Transaction::Transfer{from,to,tx_id,amount} => {
+                let result = self.handle_transfer(from,to,tx_id,amount,currency,ts);
+                self.metrics.transfer.record(&result);
+                result
+            },
+            Transaction::Adjustment{client_id,tx_id,amount} => {
+                let result = self.handle_adjustment(client_id,tx_id,amount,currency,ts);
+                self.metrics.adjustment.record(&result);
+                result
+            },
+            Transaction::Fee{client_id,tx_id,amount} => {
+                let result = self.handle_fee(client_id,tx_id,amount,currency,ts);
+                self.metrics.fee.record(&result);
+                result
+            },
         }
+    }
 
-        match disputed {
-            PersistedTransaction::Deposit { client_id, tx_id: _, amount } => {
-                let client = self.client_list.get_mut(*client_id);
-                client.available += amount;
-                client.held -= amount;
-            }
+    /// Moves the clients, stored transactions and counters of `other` into this engine, e.g. to gather the
+    /// shards of a [`crate::shard::ShardedEngine`]. The engines are expected to have no client nor tx id in
+    /// common, a client of both ends up with the balances it has in `other`.
+    pub fn merge<T: TransactionStore>(&mut self, mut other: TransactionEngine<T>) {
+        if let Some(tracker) = &mut self.changes {
+            tracker.changed.extend(other.client_list.clients.keys().copied());
+        }
+        self.client_list.merge(other.client_list);
+        for transaction in other.transactions.drain() {
+            self.transactions.insert(transaction);
+        }
+        self.compacted.extend(other.compacted);
+        self.unstored.extend(other.unstored);
+        self.retry.extend(other.retry.parked().copied().collect::<Vec<_>>(), self.clock.now());
+        self.credit_limits.extend(other.credit_limits);
+        self.metrics.add(&other.metrics);
+        for (client_id, counters) in other.dispute_counters {
+            let merged = self.dispute_counters.entry(client_id).or_default();
+            merged.disputes += counters.disputes;
+            merged.chargebacks += counters.chargebacks;
+            merged.charged_back += counters.charged_back;
+        }
+    }
+
+    /// Drops every client and stored transaction, as well as the metrics, leaving the engine as new
+    /// apart from its observer and settings.
+    pub fn reset(&mut self) {
+        self.client_list = ClientList::new();
+        self.transactions.clear();
+        self.compacted.clear();
+        self.unstored.clear();
+        self.metrics = Metrics::default();
+        self.dispute_counters.clear();
+        self.retry.clear();
+        if let Some(tracker) = &mut self.changes {
+            tracker.changed.clear();
         }
+        if let Some(eviction) = &mut self.eviction {
+            eviction.activity.clear();
+        }
+    }
 
-        self.transactions.insert(tx_id, (disputed.clone(),TransactionState::None));
+    /// Drops the stored transactions while keeping the client balances, so that past transactions
+    /// can no longer be disputed. Funds held by open disputes stay held.
+    pub fn clear_transactions(&mut self) {
+        self.transactions.clear();
+        self.client_list.clear_histories();
+        self.compacted.clear();
+        self.unstored.clear();
+        self.metrics.open_disputes = 0;
     }
 
-    fn handle_chargeback(&mut self, _: u16, tx_id : u32) {
-        let (disputed,state) = match self.transactions.get(&tx_id){
-            Some(tx) => tx,
-            None => return,
-        };
+    /// Drops the stored transactions that can no longer be meaningfully disputed: the charged back ones and
+    /// the settled ones whose disputed funds would be held on a locked account. Open disputes are kept so
+    /// that they can still be resolved or charged back. Returns the number of dropped transactions.
+    ///
+    /// A dispute, resolve or chargeback referencing a dropped transaction is then ignored as
+    /// [`TransactionError::Compacted`], and its tx id cannot be reused.
+    pub fn compact(&mut self) -> usize {
+        let tx_ids: Vec<TxId> = self.transactions.iter().map(StoredTransaction::tx_id).collect();
+        self.compact_transactions(tx_ids)
+    }
 
-        if !matches!(state, &TransactionState::Disputed) {
-            return
+    fn compact_transactions<I: IntoIterator<Item = TxId>>(&mut self, tx_ids: I) -> usize {
+        let mut accounts = FastHashSet::default();
+        let mut compacted = 0;
+        for tx_id in tx_ids {
+            self.transactions.fetch(tx_id);
+            let compactable = match self.transactions.get(tx_id) {
+                Some(StoredTransaction { state: TransactionState::ChargedBack, .. }) => true,
+                Some(StoredTransaction { transaction, state: TransactionState::None, .. }) => {
+                    self.client_list.get(transaction.held_client_id(), transaction.currency()).is_some_and(Client::is_locked)
+                },
+                Some(StoredTransaction { state: TransactionState::Disputed, .. }) | None => false,
+            };
+            if let Some(stored) = compactable.then(|| self.transactions.remove(tx_id)).flatten() {
+                accounts.insert((stored.transaction.client_id(), stored.transaction.currency()));
+                self.compacted.insert(tx_id);
+                compacted += 1;
+            }
         }
 
-        match disputed {
-            PersistedTransaction::Deposit { client_id, tx_id: _, amount } => {
-                let client = self.client_list.get_mut(*client_id);
-                client.total -= amount;
-                client.held -= amount;
-                client.locked = true;
+        let transactions = &self.transactions;
+        for key in &accounts {
+            if let Some(account) = self.client_list.clients.get_mut(key) {
+                account.history.retain(|tx_id| transactions.contains(*tx_id));
             }
         }
+        compacted
+    }
 
-        self.transactions.insert(tx_id, (disputed.clone(),TransactionState::None));
+    /// Whether `tx_id` is taken by a stored or compacted transaction, or by one applied without being stored past
+    /// [`EngineConfig::max_stored_transactions`], a new transaction with this id being rejected as a duplicate.
+    pub fn is_tx_id_used(&self, tx_id: TxId) -> bool {
+        self.transactions.contains(tx_id) || self.compacted.contains(&tx_id) || self.unstored.contains(&tx_id)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn missing_transaction(&self, tx_id: TxId) -> TransactionError {
+        match self.compacted.contains(&tx_id) {
+            true => TransactionError::Compacted { tx_id },
+            false => TransactionError::UnknownTransaction { tx_id },
+        }
+    }
 
-    #[test]
-    fn when_deposit_should_increase_total_and_available() {
-        let mut engine = TransactionEngine::new();
+    /// Returns whether the transaction is stored, disputed or charged back.
+    pub fn transaction_state(&self, tx_id: TxId) -> TxStatus {
+        match self.transactions.get(tx_id).map(|stored| stored.state) {
+            None => TxStatus::Unknown,
+            Some(TransactionState::None) => TxStatus::Settled,
+            Some(TransactionState::Disputed) => TxStatus::Disputed,
+            Some(TransactionState::ChargedBack) => TxStatus::ChargedBack,
+        }
+    }
 
-        engine.compute_transaction(Transaction::Deposit { 
-            client_id: 1, 
-            tx_id: 1, 
-            amount: 10.0 
-        });
-        let clients = engine.get_client_list();
+    /// Returns the stored deposit or withdrawal with this tx id.
+    pub fn transaction(&self, tx_id: TxId) -> Option<&PersistedTransaction> {
+        self.transactions.get(tx_id).map(|stored| &stored.transaction)
+    }
 
-        assert_eq!(clients.len(),1);
-        let client = clients.get(0).unwrap();
-        assert_eq!(client.available,10.0);
-        assert_eq!(client.total,10.0);
-        assert_eq!(client.client,1);
+    /// Iterates over the stored deposits and withdrawals of a client in the base currency, in the order they
+    /// were applied.
+    pub fn client_transactions(&self, client_id: u16) -> impl Iterator<Item = &PersistedTransaction> + '_ {
+        self.client_list.history(client_id, self.config.base_currency)
+            .iter()
+            .filter_map(|tx_id| self.transaction(*tx_id))
     }
 
-    #[test]
-    fn when_deposit_on_client_locked_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    /// Iterates over the transactions currently disputed, in the order of the store.
+    pub fn disputed_transactions(&self) -> impl Iterator<Item = (TxId, &PersistedTransaction)> + '_ {
+        self.transactions.iter()
+            .filter(|stored| matches!(stored.state, TransactionState::Disputed))
+            .map(|stored| (stored.tx_id(), &stored.transaction))
+    }
 
-        let locked = engine.client_list.get_mut(1);
-        locked.locked = true;
+    /// Sum of the amounts held by the open disputes in the base currency.
+    pub fn held_total(&self) -> Amount {
+        self.disputed_transactions()
+            .filter(|(_, transaction)| transaction.currency() == self.config.base_currency)
+            .map(|(_, transaction)| transaction.amount())
+            .sum()
+    }
 
-        engine.compute_transaction(Transaction::Deposit { 
-            client_id: 1, 
-            tx_id: 1, 
-            amount: 10.0 
-        });
-        let clients = engine.get_client_list();
+    /// Returns the disputes and chargebacks on the funds of a client, `None` when it never had any dispute.
+    pub fn dispute_counters(&self, client_id: u16) -> Option<&DisputeCounters> {
+        self.dispute_counters.get(&client_id)
+    }
 
-        assert_eq!(clients.len(),1);
-        let client = clients.get(0).unwrap();
-        assert_eq!(client.available,0.0);
-        assert_eq!(client.total,0.0);
-        assert_eq!(client.client,1);
+    /// Clients with at least `min_chargebacks` chargebacks, to be reviewed for fraud, by increasing id.
+    pub fn flagged_clients(&self, min_chargebacks: u32) -> Vec<u16> {
+        let mut flagged: Vec<u16> = self.dispute_counters.iter()
+            .filter(|(_, counters)| counters.chargebacks >= min_chargebacks)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        flagged.sort_unstable();
+        flagged
     }
 
-    #[test]
-    fn when_withdrawal_and_fund_available_should_decrease_total_and_available() {
-        let mut engine = TransactionEngine::new();
+    /// Checks that every client has `available + held == total` and no negative held funds.
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations: Vec<InvariantViolation> = self.clients().flat_map(Client::violations).collect();
+        if violations.is_empty() {
+            return Ok(())
+        }
+        violations.sort_by_key(InvariantViolation::client_id);
+        Err(violations)
+    }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 30.0
-        });
+    // Called by the handlers on the account they changed, so that tests fail on the faulty transaction.
+    fn debug_check_invariants(&self, client_id: u16, currency: Currency) {
+        if let Some(client) = self.client_list.get(client_id, currency) {
+            debug_assert_eq!(client.violations().collect::<Vec<_>>(), vec![]);
+        }
+    }
 
-        let locked = engine.client_list.get_mut(1);
-        locked.locked = true;
+    /// Writes the clients and the stored transactions with their dispute state, so that the engine
+    /// can be restored by [`TransactionEngine::load_snapshot`].
+    #[cfg(feature = "json-io")]
+    pub fn save_snapshot<W: Write>(&self, mut output: W) -> Result<(), SnapshotError> {
+        writeln!(output, "{SNAPSHOT_HEADER}")?;
+        serde_json::to_writer(&mut output, &self.snapshot()).map_err(SnapshotError::Corrupt)?;
+        writeln!(output)?;
+        output.flush()?;
+        Ok(())
+    }
 
-        engine.compute_transaction(Transaction::Withdrawal{
-            client_id: 1,
-            tx_id: 2,
-            amount: 20.0
-        });
-        let clients = engine.get_client_list();
+    // The state saved by snapshots.
+    #[cfg(feature = "json-io")]
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let transactions = self.client_list.clients.values()
+            .flat_map(|account| &account.history)
+            .filter_map(|tx_id| self.transactions.get(*tx_id))
+            .cloned()
+            .collect();
+        Snapshot { transactions, ..self.account_snapshot() }
+    }
 
-        assert_eq!(clients.len(),1);
-        let client = clients.get(0).unwrap();
-        assert_eq!(client.available,30.0);
-        assert_eq!(client.total,30.0);
-        assert_eq!(client.client,1);
+    // The state of a snapshot but the transactions, which the SQLite store saves on its own.
+    #[cfg(any(feature = "json-io", feature = "sqlite"))]
+    pub(crate) fn account_snapshot(&self) -> Snapshot {
+        let mut dispute_counters: Vec<_> = self.dispute_counters.iter().map(|(client_id, counters)| (*client_id, *counters)).collect();
+        dispute_counters.sort_unstable_by_key(|(client_id, _)| *client_id);
+        let mut compacted: Vec<TxId> = self.compacted.iter().copied().collect();
+        compacted.sort_unstable();
+        let mut unstored: Vec<TxId> = self.unstored.iter().copied().collect();
+        unstored.sort_unstable();
+        let parked = self.retry.parked().copied().collect();
+        Snapshot { clients: self.get_client_list(), transactions: Vec::new(), dispute_counters, compacted, unstored, parked }
     }
 
-    #[test]
-    fn when_withdrawal_on_locked_client_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    /// Compares this engine, the left one, with `other`: the accounts field by field, the stored transactions
+    /// with their dispute state, and the totals. The engines of the parallel modes are expected to end up with
+    /// the state of a sequential one, which an empty diff checks. Only the transactions `iter` lists are compared.
+    ///
+    /// ```
+    /// use transaction_engine::state_diff::Difference;
+    /// use transaction_engine::{Transaction, TransactionEngine};
+    ///
+    /// let mut left = TransactionEngine::new();
+    /// let mut right = TransactionEngine::new();
+    /// for engine in [&mut left, &mut right] {
+    ///     engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: "2.0".parse().unwrap() }).unwrap();
+    /// }
+    /// assert!(left.diff_states(&right).is_empty());
+    ///
+    /// right.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    /// let diff = left.diff_states(&right);
+    /// assert_eq!((diff.clients, diff.transactions, diff.totals), (2, 2, 2));
+    /// assert_eq!(diff.examples[2], Difference::Transaction { tx_id: 1, field: "state", left: "None".into(), right: "Disputed".into() });
+    /// ```
+    pub fn diff_states<T: TransactionStore>(&self, other: &TransactionEngine<T>) -> StateDiff {
+        let mut diff = StateDiff::default();
+
+        let mut accounts: Vec<(u16, Currency)> = self.client_list.clients.keys()
+            .chain(other.client_list.clients.keys())
+            .copied()
+            .collect();
+        accounts.sort_unstable();
+        accounts.dedup();
+        for (client_id, currency) in accounts {
+            let (left, right) = match (self.client_list.get(client_id, currency), other.client_list.get(client_id, currency)) {
+                (Some(left), Some(right)) => (left, right),
+                (left, _) => {
+                    let missing_from = if left.is_none() { Side::Left } else { Side::Right };
+                    diff.push(Difference::MissingClient { client_id, currency, missing_from });
+                    continue
+                },
+            };
+            let client = |field| move |left, right| Difference::Client { client_id, currency, field, left, right };
+            diff.compare(left.available, right.available, client("available"));
+            diff.compare(left.held, right.held, client("held"));
+            diff.compare(left.total, right.total, client("total"));
+            diff.compare(left.locked, right.locked, client("locked"));
+        }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 30.0
-        });
-        engine.compute_transaction(Transaction::Withdrawal{
-            client_id: 1,
-            tx_id: 2,
-            amount: 20.0
-        });
-        let clients = engine.get_client_list();
+        let mut tx_ids: Vec<TxId> = self.transactions.iter()
+            .chain(other.transactions.iter())
+            .map(StoredTransaction::tx_id)
+            .collect();
+        tx_ids.sort_unstable();
+        tx_ids.dedup();
+        for tx_id in tx_ids {
+            let (left, right) = match (self.transactions.get(tx_id), other.transactions.get(tx_id)) {
+                (Some(left), Some(right)) => (left, right),
+                (left, _) => {
+                    let missing_from = if left.is_none() { Side::Left } else { Side::Right };
+                    diff.push(Difference::MissingTransaction { tx_id, missing_from });
+                    continue
+                },
+            };
+            let transaction = |field| move |left, right| Difference::Transaction { tx_id, field, left, right };
+            let type_name = |stored: &StoredTransaction| match stored.transaction {
+                PersistedTransaction::Deposit { .. } => "deposit",
+                PersistedTransaction::Withdrawal { .. } => "withdrawal",
+                PersistedTransaction::Transfer { .. } => "transfer",
+                PersistedTransaction::Adjustment { .. } => "adjustment",
+                PersistedTransaction::Fee { .. } => "fee",
+            };
+            diff.compare(type_name(left), type_name(right), transaction("type"));
+            diff.compare(left.transaction.client_id(), right.transaction.client_id(), transaction("client"));
+            diff.compare(left.transaction.held_client_id(), right.transaction.held_client_id(), transaction("to"));
+            diff.compare(left.transaction.amount(), right.transaction.amount(), transaction("amount"));
+            diff.compare(left.transaction.currency(), right.transaction.currency(), transaction("currency"));
+            diff.compare(format!("{:?}", left.state), format!("{:?}", right.state), transaction("state"));
+            diff.compare(left.disputes, right.disputes, transaction("disputes"));
+        }
 
-        assert_eq!(clients.len(),1);
-        let client = clients.get(0).unwrap();
-        assert_eq!(client.available,10.0);
-        assert_eq!(client.total,10.0);
-        assert_eq!(client.client,1);
+        let total = |field| move |left, right| Difference::Total { field, left, right };
+        diff.compare(self.total_funds(), other.total_funds(), total("total_funds"));
+        diff.compare(self.total_held(), other.total_held(), total("total_held"));
+        diff.compare(self.client_count(), other.client_count(), total("clients"));
+        diff.compare(self.transactions.len(), other.transactions.len(), total("stored_transactions"));
+        diff.compare(self.metrics.open_disputes, other.metrics.open_disputes, total("open_disputes"));
+        diff
     }
 
-    #[test]
-    fn when_withdrawal_and_fund_not_available_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    /// Estimates the memory taken by the clients, the stored transactions and the rest of the state, from their
+    /// numbers times the size of their entries. The estimate of the transactions is the one of the store, which
+    /// may move some of them out of memory.
+    pub fn memory_estimate(&self) -> MemoryEstimate {
+        let clients = self.client_list.clients.len();
+        let histories: usize = self.client_list.clients.values().map(|account| account.history.len()).sum();
+        let aux_bytes = histories * memory::TX_ID_BYTES
+            + (self.compacted.len() + self.unstored.len()) * memory::COMPACTED_BYTES
+            + self.dispute_counters.len() * memory::DISPUTE_COUNTERS_BYTES
+            + self.credit_limits.len() * memory::CREDIT_LIMIT_BYTES;
+        MemoryEstimate {
+            clients: clients as u64,
+            client_bytes: (clients * memory::CLIENT_BYTES) as u64,
+            transactions: self.transactions.len() as u64,
+            transaction_bytes: self.transactions.memory_bytes() as u64,
+            aux_bytes: aux_bytes as u64,
+        }
+    }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 50.0
-        });
-        engine.compute_transaction(Transaction::Withdrawal{
-            client_id: 1,
-            tx_id: 2,
-            amount: 60.0
-        });
-        let clients = engine.get_client_list();
+    /// Returns the counters of applied and rejected transactions by type, along with the
+    /// number of open disputes, stored transactions and clients.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            stored_transactions: self.transactions.len() as u64,
+            clients: self.client_count() as u64,
+            parked_transactions: self.retry.len() as u64,
+            ..self.metrics
+        }
+    }
 
-        assert_eq!(clients.len(),1);
-        let client = clients.get(0).unwrap();
-        assert_eq!(client.available,50.0);
-        assert_eq!(client.total,50.0);
-        assert_eq!(client.client,1);
+    /// Returns the account of the client in the base currency, without creating it when it has none.
+    pub fn get_client(&self, id: u16) -> Option<&Client> {
+        self.client_list.get(id, self.config.base_currency)
     }
 
-    #[test]
-    fn when_deposit_should_copy_it_with_state_none() {
-        let mut engine = TransactionEngine::new();
-        let transaction = Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        };
+    /// Returns the account of the client in `currency`, without creating it when it has none.
+    pub fn get_account(&self, id: u16, currency: Currency) -> Option<&Client> {
+        self.client_list.get(id, currency)
+    }
 
-        engine.compute_transaction(transaction);
-        
-        assert_eq!(engine.transactions.len(),1);
-        let (tx,state) = engine.transactions.get(&1).unwrap();
-
-        if let PersistedTransaction::Deposit { client_id, tx_id, amount } = tx {
-            assert!(matches!(state,TransactionState::None));
-            assert_eq!(*tx_id,1);
-            assert_eq!(*client_id,1);
-            assert_eq!(*amount,10.0);
-        } else {
-            panic!()
-        }   
-        
+    /// Iterates over every account, in no particular order, without copying them.
+    pub fn clients(&self) -> impl Iterator<Item = &Client> + '_ {
+        self.client_list.iter()
     }
 
+    /// Whether an account is in another currency than the base one.
+    pub fn is_multi_currency(&self) -> bool {
+        self.clients().any(|client| client.currency != self.config.base_currency)
+    }
 
-    #[test]
-    fn when_dispute_on_deposit_should_decrease_available_increase_held() {
-        let mut engine = TransactionEngine::new();
+    /// Number of accounts, a client having one per currency.
+    pub fn client_count(&self) -> usize {
+        self.client_list.clients.len()
+    }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1,
-        });
+    /// Number of accounts locked by a chargeback.
+    pub fn locked_client_count(&self) -> usize {
+        self.clients().filter(|client| client.locked).count()
+    }
 
-        let (_, state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::Disputed));
+    // The accounts whose balances can be added up.
+    fn base_currency_clients(&self) -> impl Iterator<Item = &Client> + '_ {
+        self.clients().filter(|client| client.currency == self.config.base_currency)
+    }
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.held,10.0);
-        assert_eq!(client.available,0.0);
-        assert_eq!(client.total,10.0);
+    /// Sum of the totals of every account in the base currency.
+    pub fn total_funds(&self) -> Amount {
+        self.base_currency_clients().map(|client| client.total).sum()
     }
-    
-    #[test]
-    fn when_dispute_on_already_disputed_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1
-        });
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1
-        });
+    /// Sum of the held funds of every account in the base currency.
+    pub fn total_held(&self) -> Amount {
+        self.base_currency_clients().map(|client| client.held).sum()
+    }
+
+    /// Returns a copy of every account, in no particular order.
+    pub fn get_client_list(&self) -> Vec<Client> {
+        self.clients().copied().collect()
+    }
+
+    /// Returns the `n` clients with the largest held amount in the base currency, largest first, ties broken by
+    /// lowest client id. Uses a bounded heap so the cost stays proportional to `n`.
+    pub fn top_by_held(&self, n: usize) -> Vec<HeldExposure> {
+        if n == 0 {
+            return Vec::new()
+        }
 
-        let (_, state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::Disputed));
+        // `n` may be any count, e.g. usize::MAX for every client, the heap never holds more than the clients.
+        let mut heap = BinaryHeap::with_capacity(n.min(self.client_count()).saturating_add(1));
+        for client in self.base_currency_clients() {
+            heap.push(Reverse(ByHeld(*client)));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.held,10.0);
-        assert_eq!(client.available,0.0);
-        assert_eq!(client.total,10.0);
+        let mut disputed: HashMap<u16, Vec<TxId>> = heap.iter()
+            .map(|Reverse(ByHeld(client))| (client.client, Vec::new()))
+            .collect();
+        for (tx_id, tx) in self.disputed_transactions().filter(|(_, tx)| tx.currency() == self.config.base_currency) {
+            if let Some(tx_ids) = disputed.get_mut(&tx.held_client_id()) {
+                tx_ids.push(tx_id);
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ByHeld(client))| {
+                let mut disputed_tx = disputed.remove(&client.client).unwrap_or_default();
+                disputed_tx.sort_unstable();
+                HeldExposure { client, disputed_tx }
+            })
+            .collect()
     }
 
-    #[test]
-    fn when_dispute_on_missing_tx_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    fn handle_deposit(&mut self, client_id: u16, tx_id : TxId, amount: Amount, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if !amount.is_positive() {
+            return Err(TransactionError::InvalidAmount { tx_id, amount })
+        }
+        if self.is_tx_id_used(tx_id) {
+            return Err(TransactionError::DuplicateTransaction { tx_id })
+        }
 
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1,
-        });
+        let account = self.client_list.account_mut(client_id, currency);
+        let client = &mut account.client;
+
+        if client.locked && self.config.reject_deposits_when_locked {
+            return Err(TransactionError::AccountLocked { client_id })
+        }
+    
+        *client = client.with_changes(amount, Amount::ZERO, amount).ok_or(TransactionError::AmountOverflow { client_id, tx_id })?;
 
-        assert_eq!(0,engine.transactions.len());
-        assert_eq!(0,engine.client_list.get_all().len())
+        account.store(&mut self.transactions, &mut self.unstored, self.config.max_stored_transactions, PersistedTransaction::Deposit { client_id, tx_id, amount, ts, currency });
+        self.debug_check_invariants(client_id, currency);
+        self.emit(EngineEvent::AccountCredited { client_id, tx_id, amount });
+        Ok(())
     }
-    #[test]
-    fn when_resolve_on_missing_tx_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
 
-        engine.compute_transaction(Transaction::Resolve {
-            client_id: 1,
-            tx_id: 1,
-        });
+    fn handle_withdrawal(&mut self, client_id: u16, tx_id : TxId, amount: Amount, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if !amount.is_positive() {
+            return Err(TransactionError::InvalidAmount { tx_id, amount })
+        }
+        if self.is_tx_id_used(tx_id) {
+            return Err(TransactionError::DuplicateTransaction { tx_id })
+        }
+
+        // Accounts in other currencies have no credit.
+        let credit_limit = if currency == self.config.base_currency { self.credit_limit(client_id) } else { Amount::ZERO };
+        let account = self.client_list.account_mut(client_id, currency);
+        let client = &mut account.client;
+
+        if client.locked {
+            return Err(TransactionError::AccountLocked { client_id })
+        }
+        
+        // Funds beyond the range of an amount cover any withdrawal.
+        if client.available.checked_add(credit_limit).is_some_and(|funds| funds < amount) {
+            return Err(TransactionError::InsufficientFunds { client_id, requested: amount, available: client.available })
+        }
+
+        *client = client.with_changes(-amount, Amount::ZERO, -amount).ok_or(TransactionError::AmountOverflow { client_id, tx_id })?;
 
-        assert_eq!(0,engine.transactions.len());
-        assert_eq!(0,engine.client_list.get_all().len())
+        account.store(&mut self.transactions, &mut self.unstored, self.config.max_stored_transactions, PersistedTransaction::Withdrawal { client_id, tx_id, amount, ts, currency });
+        self.debug_check_invariants(client_id, currency);
+        self.emit(EngineEvent::AccountDebited { client_id, tx_id, amount });
+        Ok(())
     }
 
-    #[test]
-    fn when_resolve_on_not_disputed_tx_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    fn handle_dispute(&mut self, client_id: u16, tx_id : TxId, currency: Currency) -> Result<(), TransactionError> {
+        self.transactions.fetch(tx_id);
+        let Some(stored) = self.transactions.get(tx_id) else {
+            return Err(self.missing_transaction(tx_id))
+        };
+        let disputed = &stored.transaction;
+        record_disputed_amount(disputed.amount());
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Resolve {
-            client_id: 1,
-            tx_id: 1,
-        });
+        if disputed.client_id() != client_id {
+            return Err(TransactionError::ClientMismatch { client_id, tx_id })
+        }
+        if disputed.currency() != currency {
+            return Err(TransactionError::CurrencyMismatch { tx_id, currency, expected: disputed.currency() })
+        }
 
-        assert_eq!(1,engine.transactions.len());
+        match stored.state {
+            TransactionState::None => {},
+            TransactionState::Disputed => return Err(TransactionError::AlreadyDisputed { tx_id }),
+            TransactionState::ChargedBack => return Err(TransactionError::AlreadyChargedBack { tx_id }),
+        }
 
-        let (_,state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::None));
+        if let Some(max) = self.config.max_disputes_per_tx.filter(|max| stored.disputes >= *max) {
+            return Err(TransactionError::DisputeLimitReached { tx_id, max })
+        }
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.total,10.0);
-        assert_eq!(client.available,10.0);
-        assert_eq!(client.held,0.0)
+        let (held_client_id, amount) = (disputed.held_client_id(), disputed.amount());
+        self.client_list.check_locked_dispute(self.config.allow_dispute_on_locked, disputed, &[held_client_id])?;
+
+        // The account of a stored transaction exists, looking it up does not create it.
+        let held = self.client_list.get_mut(held_client_id, currency);
+        let (changed, event) = match disputed {
+            // Disputes of deposits and transfers hold funds taken from the available ones.
+            PersistedTransaction::Deposit { .. } | PersistedTransaction::Transfer { .. } => {
+                if !self.config.allow_negative_available_on_dispute && held.available < amount {
+                    return Err(TransactionError::DisputeExceedsAvailable { client_id: held_client_id, tx_id, amount, available: held.available })
+                }
+                (held.with_changes(-amount, amount, Amount::ZERO), EngineEvent::FundsHeld { client_id: held_client_id, tx_id, amount })
+            },
+            PersistedTransaction::Withdrawal { .. } | PersistedTransaction::Fee { .. } => {
+                (held.with_changes(Amount::ZERO, amount, amount), EngineEvent::HeldCredited { client_id: held_client_id, tx_id, amount })
+            },
+            PersistedTransaction::Adjustment { .. } => return Err(TransactionError::NotDisputable { tx_id }),
+        };
+        *held = changed.ok_or(TransactionError::AmountOverflow { client_id: held_client_id, tx_id })?;
+
+        let disputes = stored.disputes + 1;
+        self.transactions.set_state(tx_id, TransactionState::Disputed, disputes);
+        self.metrics.open_disputes += 1;
+        self.dispute_counters.entry(held_client_id).or_default().disputes += 1;
+        self.debug_check_invariants(held_client_id, currency);
+        self.emit(event);
+        Ok(())
     }
 
-    #[test]
-    fn when_resolve_should_revert_dispute() {
-        let mut engine = TransactionEngine::new();
+    fn handle_resolve(&mut self, client_id: u16, tx_id : TxId, currency: Currency) -> Result<(), TransactionError> {
+        self.transactions.fetch(tx_id);
+        let Some(stored) = self.transactions.get(tx_id) else {
+            return Err(self.missing_transaction(tx_id))
+        };
+        let disputed = &stored.transaction;
+        record_disputed_amount(disputed.amount());
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1,
-        });
-        engine.compute_transaction(Transaction::Resolve {
-            client_id: 1,
-            tx_id: 1,
-        });
+        if disputed.client_id() != client_id {
+            return Err(TransactionError::ClientMismatch { client_id, tx_id })
+        }
+        if disputed.currency() != currency {
+            return Err(TransactionError::CurrencyMismatch { tx_id, currency, expected: disputed.currency() })
+        }
+
+        match stored.state {
+            TransactionState::Disputed => {},
+            TransactionState::None => return Err(TransactionError::NotDisputed { tx_id }),
+            TransactionState::ChargedBack => return Err(TransactionError::AlreadyChargedBack { tx_id }),
+        }
+
+        let (held_client_id, amount) = (disputed.held_client_id(), disputed.amount());
+        self.client_list.check_locked_dispute(self.config.allow_dispute_on_locked, disputed, &[held_client_id])?;
+
+        let held = self.client_list.get_mut(held_client_id, currency);
+        let (changed, event) = match disputed {
+            PersistedTransaction::Deposit { .. } | PersistedTransaction::Transfer { .. } => {
+                (held.with_changes(amount, -amount, Amount::ZERO), EngineEvent::FundsReleased { client_id: held_client_id, tx_id, amount })
+            },
+            PersistedTransaction::Withdrawal { .. } | PersistedTransaction::Fee { .. } => {
+                (held.with_changes(Amount::ZERO, -amount, -amount), EngineEvent::HeldDebited { client_id: held_client_id, tx_id, amount })
+            },
+            // Adjustments are never disputed.
+            PersistedTransaction::Adjustment { .. } => return Err(TransactionError::NotDisputed { tx_id }),
+        };
+        *held = changed.ok_or(TransactionError::AmountOverflow { client_id: held_client_id, tx_id })?;
+
+        let disputes = stored.disputes;
+        self.transactions.set_state(tx_id, TransactionState::None, disputes);
+        self.metrics.open_disputes -= 1;
+        self.debug_check_invariants(held_client_id, currency);
+        self.emit(event);
+        Ok(())
+    }
+
+    fn handle_chargeback(&mut self, client_id: u16, tx_id : TxId, currency: Currency) -> Result<(), TransactionError> {
+        self.transactions.fetch(tx_id);
+        let Some(stored) = self.transactions.get(tx_id) else {
+            return Err(self.missing_transaction(tx_id))
+        };
+        let disputed = &stored.transaction;
+        record_disputed_amount(disputed.amount());
+
+        if disputed.client_id() != client_id {
+            return Err(TransactionError::ClientMismatch { client_id, tx_id })
+        }
+        if disputed.currency() != currency {
+            return Err(TransactionError::CurrencyMismatch { tx_id, currency, expected: disputed.currency() })
+        }
 
-        assert_eq!(1,engine.transactions.len());
+        match stored.state {
+            TransactionState::Disputed => {},
+            TransactionState::None => return Err(TransactionError::NotDisputed { tx_id }),
+            TransactionState::ChargedBack => return Err(TransactionError::AlreadyChargedBack { tx_id }),
+        }
 
-        let (_,state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::None));
+        // The account losing the disputed funds is locked: the recipient of a transfer, which also refunds the source.
+        let (locked_client_id, amount) = (disputed.held_client_id(), disputed.amount());
+        self.client_list.check_locked_dispute(self.config.allow_dispute_on_locked, disputed, &[locked_client_id, client_id])?;
+
+        // The funds go back to the source of a transfer, whose refund is checked before the first change.
+        let refunded = match disputed {
+            PersistedTransaction::Transfer { .. } => {
+                let source = self.client_list.get_mut(client_id, currency);
+                Some(source.with_changes(amount, Amount::ZERO, amount).ok_or(TransactionError::AmountOverflow { client_id, tx_id })?)
+            },
+            _ => None,
+        };
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.total,10.0);
-        assert_eq!(client.available,10.0);
-        assert_eq!(client.held,0.0)
+        let locked = self.client_list.get_mut(locked_client_id, currency);
+        let was_locked = locked.locked;
+        let (changed, event) = match disputed {
+            PersistedTransaction::Deposit { .. } => {
+                (locked.with_changes(Amount::ZERO, -amount, -amount), EngineEvent::HeldDebited { client_id, tx_id, amount })
+            },
+            PersistedTransaction::Withdrawal { .. } | PersistedTransaction::Fee { .. } => {
+                (locked.with_changes(amount, -amount, Amount::ZERO), EngineEvent::FundsReleased { client_id, tx_id, amount })
+            },
+            PersistedTransaction::Transfer { .. } => {
+                (locked.with_changes(Amount::ZERO, -amount, -amount), EngineEvent::HeldDebited { client_id: locked_client_id, tx_id, amount })
+            },
+            PersistedTransaction::Adjustment { .. } => return Err(TransactionError::NotDisputed { tx_id }),
+        };
+        let changed = changed.ok_or(TransactionError::AmountOverflow { client_id: locked_client_id, tx_id })?;
+        *locked = Client { locked: true, ..changed };
+        let mut refund = None;
+        if let Some(refunded) = refunded {
+            *self.client_list.get_mut(client_id, currency) = refunded;
+            refund = Some(EngineEvent::AccountCredited { client_id, tx_id, amount });
+        }
+
+        let disputes = stored.disputes;
+        self.transactions.set_state(tx_id, TransactionState::ChargedBack, disputes);
+        let counters = self.dispute_counters.entry(locked_client_id).or_default();
+        counters.chargebacks += 1;
+        if currency == self.config.base_currency {
+            counters.charged_back = counters.charged_back.saturating_add(amount);
+        }
+        self.metrics.open_disputes -= 1;
+        self.debug_check_invariants(client_id, currency);
+        self.debug_check_invariants(locked_client_id, currency);
+        self.emit(event);
+        if let Some(refund) = refund {
+            self.emit(refund);
+        }
+        if !was_locked {
+            self.emit(EngineEvent::AccountLocked { client_id: locked_client_id, tx_id });
+        }
+        if self.config.compact_locked {
+            // Only the history of the locked account is scanned, transfers it received are left to `compact`.
+            let mut tx_ids = self.client_list.history(locked_client_id, currency).to_vec();
+            tx_ids.push(tx_id);
+            self.compact_transactions(tx_ids);
+        }
+        Ok(())
     }
 
-    #[test]
-    fn when_chargeback_on_missing_tx_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    // Every check is done before the first change, a rejected transfer changes neither account.
+    fn handle_transfer(&mut self, from: u16, to: u16, tx_id : TxId, amount: Amount, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if !amount.is_positive() {
+            return Err(TransactionError::InvalidAmount { tx_id, amount })
+        }
+        if from == to {
+            return Err(TransactionError::SelfTransfer { tx_id })
+        }
+        if self.is_tx_id_used(tx_id) {
+            return Err(TransactionError::DuplicateTransaction { tx_id })
+        }
 
-        engine.compute_transaction(Transaction::Chargeback{
-            client_id: 1,
-            tx_id: 1,
-        });
+        let recipient = self.client_list.get(to, currency);
+        let recipient_locked = recipient.is_some_and(Client::is_locked);
+        let recipient_overflows = recipient.is_some_and(|recipient| recipient.with_changes(amount, Amount::ZERO, amount).is_none());
+        let account = self.client_list.account_mut(from, currency);
+        let source = &mut account.client;
+
+        if source.locked {
+            return Err(TransactionError::AccountLocked { client_id: from })
+        }
+
+        if source.available < amount {
+            return Err(TransactionError::InsufficientFunds { client_id: from, requested: amount, available: source.available })
+        }
 
-        assert_eq!(0,engine.transactions.len());
-        assert_eq!(0,engine.client_list.get_all().len())
+        if recipient_locked {
+            return Err(TransactionError::AccountLocked { client_id: to })
+        }
+
+        if recipient_overflows {
+            return Err(TransactionError::AmountOverflow { client_id: to, tx_id })
+        }
+
+        *source = source.with_changes(-amount, Amount::ZERO, -amount).ok_or(TransactionError::AmountOverflow { client_id: from, tx_id })?;
+        account.store(&mut self.transactions, &mut self.unstored, self.config.max_stored_transactions, PersistedTransaction::Transfer { from, to, tx_id, amount, ts, currency });
+        let recipient = self.client_list.get_mut(to, currency);
+        recipient.total += amount;
+        recipient.available += amount;
+
+        self.debug_check_invariants(from, currency);
+        self.debug_check_invariants(to, currency);
+        self.emit(EngineEvent::AccountDebited { client_id: from, tx_id, amount });
+        self.emit(EngineEvent::AccountCredited { client_id: to, tx_id, amount });
+        Ok(())
     }
 
-    #[test]
-    fn when_chargeback_on_not_disputed_tx_should_do_nothing() {
-        let mut engine = TransactionEngine::new();
+    // Fees are owed regardless of the available funds, which may turn negative.
+    fn handle_fee(&mut self, client_id: u16, tx_id : TxId, amount: Amount, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if !amount.is_positive() {
+            return Err(TransactionError::InvalidAmount { tx_id, amount })
+        }
+        if self.is_tx_id_used(tx_id) {
+            return Err(TransactionError::DuplicateTransaction { tx_id })
+        }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Chargeback {
-            client_id: 1,
-            tx_id: 1,
-        });
+        let account = self.client_list.account_mut(client_id, currency);
+        let client = &mut account.client;
 
-        assert_eq!(1,engine.transactions.len());
+        if client.locked {
+            return Err(TransactionError::AccountLocked { client_id })
+        }
 
-        let (_,state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::None));
+        client.total -= amount;
+        client.available -= amount;
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.total,10.0);
-        assert_eq!(client.available,10.0);
-        assert_eq!(client.held,0.0)
+        account.store(&mut self.transactions, &mut self.unstored, self.config.max_stored_transactions, PersistedTransaction::Fee { client_id, tx_id, amount, ts, currency });
+        self.debug_check_invariants(client_id, currency);
+        self.emit(EngineEvent::AccountDebited { client_id, tx_id, amount });
+        Ok(())
     }
 
-    #[test]
-    fn when_chargeback_should_freeze_and_withdraw() {
-        let mut engine = TransactionEngine::new();
+    // Unlike a withdrawal, a negative adjustment may leave the available funds negative.
+    fn handle_adjustment(&mut self, client_id: u16, tx_id : TxId, amount: Amount, currency: Currency, ts: Option<Timestamp>) -> Result<(), TransactionError> {
+        if amount == Amount::ZERO {
+            return Err(TransactionError::InvalidAmount { tx_id, amount })
+        }
+        if self.is_tx_id_used(tx_id) {
+            return Err(TransactionError::DuplicateTransaction { tx_id })
+        }
 
-        engine.compute_transaction(Transaction::Deposit{
-            client_id: 1,
-            tx_id: 1,
-            amount: 10.0
-        });
-        engine.compute_transaction(Transaction::Dispute{
-            client_id: 1,
-            tx_id: 1,
-        });
-        engine.compute_transaction(Transaction::Chargeback {
-            client_id: 1,
-            tx_id: 1,
-        });
+        let locked_adjustments = self.config.allow_adjustment_on_locked;
+        let account = self.client_list.account_mut(client_id, currency);
+        let client = &mut account.client;
+
+        if client.locked && !locked_adjustments {
+            return Err(TransactionError::AccountLocked { client_id })
+        }
+
+        client.total += amount;
+        client.available += amount;
 
-        assert_eq!(1,engine.transactions.len());
+        account.store(&mut self.transactions, &mut self.unstored, self.config.max_stored_transactions, PersistedTransaction::Adjustment { client_id, tx_id, amount, ts, currency });
+        self.debug_check_invariants(client_id, currency);
+        self.emit(match amount.is_positive() {
+            true => EngineEvent::AccountCredited { client_id, tx_id, amount },
+            false => EngineEvent::AccountDebited { client_id, tx_id, amount: -amount },
+        });
+        Ok(())
+    }
 
-        let (_,state) = engine.transactions.get(&1).unwrap();
-        assert!(matches!(state,TransactionState::None));
+    // The unlock itself is not stored, it cannot be disputed.
+    fn handle_unlock(&mut self, client_id: u16, tx_id : TxId, currency: Currency) -> Result<(), TransactionError> {
+        match self.client_list.get(client_id, currency) {
+            None => return Err(TransactionError::UnknownClient { client_id }),
+            Some(client) if !client.is_locked() => return Err(TransactionError::NotLocked { client_id }),
+            Some(_) => {},
+        }
 
-        let client = engine.client_list.get_mut(1);
-        assert_eq!(client.total,0.0);
-        assert_eq!(client.available,0.0);
-        assert_eq!(client.held,0.0);
-        assert_eq!(client.locked,true);
+        self.client_list.get_mut(client_id, currency).locked = false;
+        self.emit(EngineEvent::AccountUnlocked { client_id, tx_id });
+        Ok(())
     }
 }
 
+#[cfg(test)]
+// The baseline tests keep the style they were written in.
+#[allow(clippy::get_first, clippy::bool_assert_comparison)]
+mod tests {
+    // The tests of `transaction_engine/tests.rs` run against each store, `Store` being the one of their module.
 
+    mod in_memory {
+        type Store = crate::store::InMemoryStore;
+        include!("transaction_engine/tests.rs");
+    }
 
+    mod btree {
+        type Store = crate::store::BTreeStore;
+        include!("transaction_engine/tests.rs");
+    }
+}
@@ -0,0 +1,2120 @@
+use std::io::{self, Write};
+
+use crate::transaction_engine::*;
+use crate::metrics::TypeCounters;
+use crate::retry::ExpireAfter;
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+fn new_engine() -> TransactionEngine<Store> {
+    engine_with_config(EngineConfig::default())
+}
+
+fn engine_with_config(config: EngineConfig) -> TransactionEngine<Store> {
+    TransactionEngine::with_store(Store::default(), config)
+}
+
+#[test]
+fn metrics_should_count_transactions_by_type() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") }).unwrap_err();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 3, amount: amount("4.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 2, tx_id: 4, amount: amount("50.0") }).unwrap_err();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 99 }).unwrap_err();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 2, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap_err();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 3 }).unwrap();
+
+    let metrics = engine.metrics();
+
+    assert_eq!(metrics.deposit, TypeCounters { applied: 2, rejected: 1 });
+    assert_eq!(metrics.withdrawal, TypeCounters { applied: 1, rejected: 1 });
+    assert_eq!(metrics.dispute, TypeCounters { applied: 3, rejected: 1 });
+    assert_eq!(metrics.resolve, TypeCounters { applied: 1, rejected: 0 });
+    assert_eq!(metrics.chargeback, TypeCounters { applied: 1, rejected: 1 });
+    assert_eq!(metrics.unlock, TypeCounters::default());
+    assert_eq!(metrics.open_disputes, 1);
+    assert_eq!(metrics.stored_transactions, 3);
+    assert_eq!(metrics.clients, 2);
+    assert_eq!((metrics.applied(), metrics.rejected()), (8, 4));
+}
+
+#[test]
+fn is_ignored_should_only_hold_for_dispute_references() {
+    assert!(TransactionError::UnknownTransaction { tx_id: 1 }.is_ignored());
+    assert!(TransactionError::NotDisputed { tx_id: 1 }.is_ignored());
+    assert!(!TransactionError::InsufficientFunds { client_id: 1, requested: amount("2.0"), available: amount("1.0") }.is_ignored());
+    assert!(!TransactionError::AccountLocked { client_id: 1 }.is_ignored());
+}
+
+#[test]
+fn error_code_should_name_the_error_kind() {
+    assert_eq!(TransactionError::AccountLocked { client_id: 1 }.code(), "account_locked");
+    assert_eq!(TransactionError::InsufficientFunds { client_id: 1, requested: amount("2.0"), available: amount("1.0") }.code(), "insufficient_funds");
+    assert_eq!(TransactionError::AlreadyChargedBack { tx_id: 1 }.code(), "already_charged_back");
+    let exceeding = TransactionError::DisputeExceedsAvailable { client_id: 1, tx_id: 2, amount: amount("3.0"), available: amount("1.0") };
+    assert_eq!(exceeding.code(), "dispute_exceeds_available");
+    assert!(!exceeding.is_ignored());
+    assert_eq!(TransactionError::DisputeLimitReached { tx_id: 1, max: 3 }.code(), "dispute_limit_reached");
+}
+
+#[test]
+fn when_deposit_should_increase_total_and_available() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit { 
+        client_id: 1, 
+        tx_id: 1, 
+        amount: amount("10.0") 
+    }).unwrap();
+    let clients = engine.get_client_list();
+
+    assert_eq!(clients.len(),1);
+    let client = clients.get(0).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.id(),1);
+}
+
+#[test]
+fn get_client_should_return_the_client_balances() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("3.0") }).unwrap();
+
+    let client = engine.get_client(2).unwrap();
+    assert_eq!(client.id(), 2);
+    assert_eq!(client.available(), amount("3.0"));
+}
+
+#[test]
+fn when_client_unknown_get_client_should_not_create_it() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    assert!(engine.get_client(7).is_none());
+    assert!(engine.get_client(7).is_none());
+    assert_eq!(engine.get_client_list().len(), 1);
+}
+
+#[test]
+fn clients_should_yield_the_same_clients_as_the_list() {
+    let mut engine = new_engine();
+    for tx_id in 1..=20 {
+        engine.compute_transaction(Transaction::Deposit { client_id: (tx_id % 7) as u16, tx_id, amount: amount("1.5") }).unwrap();
+    }
+
+    let mut iterated: Vec<Client> = engine.clients().copied().collect();
+    let mut listed = engine.get_client_list();
+    iterated.sort_by_key(Client::id);
+    listed.sort_by_key(Client::id);
+
+    assert_eq!(iterated.len(), 7);
+    assert_eq!(iterated, listed);
+}
+
+#[test]
+fn clients_should_borrow_instead_of_copying() {
+    let mut engine = new_engine();
+    for tx_id in 1..=1_000 {
+        engine.compute_transaction(Transaction::Deposit { client_id: tx_id as u16, tx_id, amount: amount("1.0") }).unwrap();
+    }
+
+    // Every snapshot yields references to the clients stored by the engine, never new copies.
+    let first: Vec<*const Client> = engine.clients().map(|client| client as *const Client).collect();
+    for _ in 0..100 {
+        assert!(engine.clients().zip(&first).all(|(client, stored)| std::ptr::eq(client, *stored)));
+    }
+}
+
+#[test]
+fn when_cloned_should_evolve_independently() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") }).unwrap();
+
+    let mut snapshot = engine.clone();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+    snapshot.compute_transaction(Transaction::Withdrawal { client_id: 2, tx_id: 3, amount: amount("2.0") }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert!(client.is_locked());
+    assert_eq!(client.total(), amount("0.0"));
+    assert_eq!(engine.get_client(2).unwrap().available(), amount("5.0"));
+    assert!(!engine.transactions.contains(3));
+
+    let client = snapshot.get_client(1).unwrap();
+    assert!(!client.is_locked());
+    assert_eq!(client.total(), amount("10.0"));
+    assert_eq!(snapshot.get_client(2).unwrap().available(), amount("3.0"));
+    assert!(matches!(snapshot.transactions.get(1), Some(StoredTransaction { state: TransactionState::None, .. })));
+}
+
+#[test]
+fn when_reset_should_behave_as_a_new_engine() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+
+    engine.reset();
+
+    assert_eq!(engine.clients().count(), 0);
+    assert_eq!(engine.metrics(), Metrics::default());
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("4.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert!(!client.is_locked());
+    assert_eq!(client.held(), amount("4.0"));
+    assert_eq!(client.total(), amount("4.0"));
+}
+
+#[test]
+fn with_capacity_should_preallocate_and_behave_as_a_new_engine() {
+    let mut engine = TransactionEngine::with_capacity(100, 1000);
+    // The B-trees without `std-collections` allocate node by node.
+    #[cfg(feature = "std-collections")]
+    assert!(engine.client_list.clients.capacity() >= 100);
+    #[cfg(feature = "std-collections")]
+    assert!(engine.transactions.capacity() >= 1000);
+    assert_eq!(engine.config(), &EngineConfig::default());
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    assert_eq!(engine.get_client(1).unwrap().held(), amount("10.0"));
+}
+
+#[test]
+fn when_reset_should_keep_the_config() {
+    let config = EngineConfig::default().max_stored_transactions(Some(1));
+    let mut engine = engine_with_config(config);
+
+    engine.reset();
+
+    assert_eq!(engine.config(), &config);
+    assert_eq!(new_engine().config(), &EngineConfig::default());
+}
+
+fn locked_client(config: EngineConfig) -> TransactionEngine<Store> {
+    let mut engine = engine_with_config(config);
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+    engine
+}
+
+#[test]
+fn deposit_on_locked_account_should_depend_on_the_config() {
+    let mut engine = locked_client(EngineConfig::default());
+    assert_eq!(engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3.0") }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("0.0"));
+
+    let mut engine = locked_client(EngineConfig::default().reject_deposits_when_locked(false));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 3, amount: amount("1.0") }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.total(), client.is_locked()), (amount("3.0"), amount("3.0"), true));
+}
+
+fn withdrawn_deposit(config: EngineConfig) -> TransactionEngine<Store> {
+    let mut engine = engine_with_config(config);
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("8.0") }).unwrap();
+    engine
+}
+
+#[test]
+fn dispute_exceeding_available_funds_should_depend_on_the_config() {
+    let mut engine = withdrawn_deposit(EngineConfig::default());
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held()), (amount("-8.0"), amount("10.0")));
+
+    let mut engine = withdrawn_deposit(EngineConfig::default().allow_negative_available_on_dispute(false));
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }),
+        Err(TransactionError::DisputeExceedsAvailable { client_id: 1, tx_id: 1, amount: amount("10.0"), available: amount("2.0") }));
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+    // A disputed withdrawal does not take from the available funds.
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held()), (amount("2.0"), amount("8.0")));
+}
+
+#[test]
+fn when_negative_available_disallowed_dispute_should_apply_once_the_funds_are_back() {
+    let mut engine = withdrawn_deposit(EngineConfig::default().allow_negative_available_on_dispute(false));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("2.0") }).unwrap();
+
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 4, amount: amount("2.0") }).unwrap();
+
+    // 2.0 available, the dispute of tx 3 fits exactly.
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 3 }).unwrap();
+
+    let rejected = engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 });
+    assert!(matches!(rejected, Err(TransactionError::DisputeExceedsAvailable { available, .. }) if available == amount("2.0")));
+    assert_eq!(engine.get_client(1).unwrap().held(), amount("0.0"));
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 5, amount: amount("8.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("0.0"), amount("10.0"), amount("10.0")));
+    assert_eq!(engine.transaction_state(1), TxStatus::Disputed);
+}
+
+#[test]
+fn when_dispute_limit_reached_should_reject_further_disputes() {
+    let mut engine = engine_with_config(EngineConfig::default().max_disputes_per_tx(Some(2)));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    // The second dispute, opened before the limit was reached, can still be resolved.
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }),
+        Err(TransactionError::DisputeLimitReached { tx_id: 1, max: 2 }));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held()), (amount("10.0"), amount("0.0")));
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+}
+
+#[test]
+fn dispute_opened_before_the_limit_should_still_be_charged_back() {
+    let mut engine = engine_with_config(EngineConfig::default().max_disputes_per_tx(Some(1)));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.total(), client.is_locked()), (amount("0.0"), true));
+}
+
+#[test]
+fn by_default_a_tx_should_be_disputed_any_number_of_times() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    for _ in 0..100 {
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+    }
+    assert_eq!(engine.transactions.get(1).map(|stored| stored.disputes), Some(100));
+}
+
+#[test]
+fn max_stored_transactions_should_stop_storing_but_keep_applying() {
+    let mut engine = engine_with_config(EngineConfig::default().max_stored_transactions(Some(2)));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("1.0") }).unwrap();
+
+    assert_eq!(engine.metrics().stored_transactions, 2);
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("16.0"));
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 3 }),
+        Err(TransactionError::UnknownTransaction { tx_id: 3 }));
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+
+    let mut engine = new_engine();
+    for tx_id in 1..=3 {
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount: amount("1.0") }).unwrap();
+    }
+    assert_eq!(engine.metrics().stored_transactions, 3);
+}
+
+#[test]
+fn max_stored_transactions_should_still_reject_the_reused_tx_ids() {
+    let mut engine = engine_with_config(EngineConfig::default().max_stored_transactions(Some(1)));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") }).unwrap();
+
+    for replayed in [
+        Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") },
+        Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("1.0") },
+        Transaction::Transfer { from: 1, to: 2, tx_id: 2, amount: amount("1.0") },
+    ] {
+        assert_eq!(engine.compute_transaction(replayed), Err(TransactionError::DuplicateTransaction { tx_id: 2 }));
+    }
+
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("15.0"));
+    assert_eq!(engine.metrics().stored_transactions, 1);
+    assert!(engine.is_tx_id_used(2));
+    // Still unknown to a dispute, which is not parked for a tx that will not come.
+    engine.set_retry_queue(RetryQueueConfig { capacity: 10, expire_after: ExpireAfter::Sequences(10) });
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }),
+        Err(TransactionError::UnknownTransaction { tx_id: 2 }));
+}
+
+#[cfg(feature = "json-io")]
+#[test]
+fn the_unstored_tx_ids_should_be_saved_in_snapshots() {
+    let mut engine = engine_with_config(EngineConfig::default().max_stored_transactions(Some(1)));
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") }).unwrap();
+    let mut snapshot = vec![];
+    engine.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(restored.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("5.0") }),
+        Err(TransactionError::DuplicateTransaction { tx_id: 2 }));
+    assert_eq!(restored.memory_estimate().aux_bytes, engine.memory_estimate().aux_bytes);
+}
+
+#[test]
+fn when_transactions_cleared_should_keep_balances_but_reject_disputes() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("3.0") }).unwrap();
+
+    engine.clear_transactions();
+
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }),
+        Err(TransactionError::UnknownTransaction { tx_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }),
+        Err(TransactionError::UnknownTransaction { tx_id: 2 }));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(), amount("7.0"));
+    assert_eq!(client.total(), amount("7.0"));
+    assert_eq!(engine.metrics().stored_transactions, 0);
+}
+
+#[test]
+fn transaction_state_should_follow_the_dispute_lifecycle() {
+    let mut engine = new_engine();
+    assert_eq!(engine.transaction_state(1), TxStatus::Unknown);
+    assert!(engine.transaction(1).is_none());
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 3, tx_id: 1, amount: amount("10.0") }).unwrap();
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+    engine.compute_transaction(Transaction::Dispute { client_id: 3, tx_id: 1 }).unwrap();
+    assert_eq!(engine.transaction_state(1), TxStatus::Disputed);
+    engine.compute_transaction(Transaction::Resolve { client_id: 3, tx_id: 1 }).unwrap();
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+    engine.compute_transaction(Transaction::Dispute { client_id: 3, tx_id: 1 }).unwrap();
+    assert_eq!(engine.transaction_state(1), TxStatus::Disputed);
+    engine.compute_transaction(Transaction::Chargeback { client_id: 3, tx_id: 1 }).unwrap();
+    assert_eq!(engine.transaction_state(1), TxStatus::ChargedBack);
+
+    let transaction = engine.transaction(1).unwrap();
+    assert_eq!(transaction.client_id(), 3);
+    assert_eq!(transaction.amount(), amount("10.0"));
+    assert_eq!(engine.transaction_state(2), TxStatus::Unknown);
+}
+
+#[test]
+fn disputed_transactions_should_only_list_open_disputes() {
+    let mut engine = new_engine();
+    for (client_id, tx_id, value) in [(1, 1, "10.0"), (1, 2, "2.5"), (2, 3, "7.25"), (2, 4, "1.0"), (3, 5, "4.0")] {
+        engine.compute_transaction(Transaction::Deposit { client_id, tx_id, amount: amount(value) }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 6, amount: amount("1.5") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 4 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 2, tx_id: 4 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 3, tx_id: 5 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 3, tx_id: 5 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 6 }).unwrap();
+
+    let mut disputed: Vec<(TxId, u16)> = engine.disputed_transactions()
+        .map(|(tx_id, transaction)| (tx_id, transaction.client_id()))
+        .collect();
+    disputed.sort_unstable();
+
+    assert_eq!(disputed, vec![(1, 1), (3, 2), (6, 1)]);
+    assert_eq!(engine.held_total(), amount("18.75"));
+}
+
+#[test]
+fn client_transactions_should_list_the_client_history_in_order() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 30, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 10, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 20, amount: amount("4.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 2, tx_id: 11, amount: amount("50.0") }).unwrap_err();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 30 }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 5, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 2, tx_id: 12, amount: amount("2.0") }).unwrap();
+
+    let tx_ids = |client_id| -> Vec<(TxId, u16)> {
+        engine.client_transactions(client_id)
+            .map(|transaction| (transaction.tx_id(), transaction.client_id()))
+            .collect()
+    };
+
+    assert_eq!(tx_ids(1), vec![(30, 1), (20, 1), (5, 1)]);
+    assert_eq!(tx_ids(2), vec![(10, 2), (12, 2)]);
+    assert_eq!(tx_ids(3), vec![]);
+}
+
+#[test]
+fn merge_should_keep_the_history_of_a_client_of_both_engines() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    let mut other = new_engine();
+    other.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("3.0") }).unwrap();
+
+    engine.merge(other);
+
+    let tx_ids: Vec<TxId> = engine.client_transactions(1).map(PersistedTransaction::tx_id).collect();
+    assert_eq!(tx_ids, vec![1, 2]);
+    assert_eq!(engine.get_client(1).map(Client::total), Some(amount("3.0")));
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+}
+
+#[test]
+fn aggregates_should_sum_over_every_client() {
+    let mut engine = new_engine();
+    assert_eq!((engine.client_count(), engine.locked_client_count()), (0, 0));
+    assert_eq!(engine.total_funds(), amount("0.0"));
+
+    for (client_id, tx_id, value) in [(1, 1, "10.0"), (2, 2, "5.5"), (3, 3, "2.25"), (3, 4, "1.0")] {
+        engine.compute_transaction(Transaction::Deposit { client_id, tx_id, amount: amount(value) }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 5, amount: amount("3.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 3, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 3, tx_id: 3 }).unwrap();
+
+    assert_eq!(engine.client_count(), 3);
+    assert_eq!(engine.locked_client_count(), 1);
+    assert_eq!(engine.total_funds(), amount("13.5"));
+    assert_eq!(engine.total_held(), amount("5.5"));
+    assert_eq!(engine.total_held(), engine.held_total());
+}
+
+#[test]
+fn check_invariants_should_pass_after_disputes_and_chargebacks() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("8.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+
+    assert_eq!(engine.check_invariants(), Ok(()));
+}
+
+#[test]
+fn when_client_corrupted_check_invariants_should_report_it() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") }).unwrap();
+
+    let client = engine.client_list.get_mut(2, Currency::USD);
+    client.held = amount("-1.0");
+    client.available = amount("6.0");
+    engine.client_list.get_mut(1, Currency::USD).total = amount("11.0");
+
+    assert_eq!(engine.check_invariants(), Err(vec![
+        InvariantViolation::Unbalanced { client_id: 1, available: amount("10.0"), held: amount("0.0"), total: amount("11.0") },
+        InvariantViolation::NegativeHeld { client_id: 2, held: amount("-1.0") },
+    ]));
+}
+
+#[cfg(feature = "json-io")]
+fn sorted_clients<S: TransactionStore>(engine: &TransactionEngine<S>) -> Vec<Client> {
+    let mut clients = engine.get_client_list();
+    clients.sort_by_key(Client::id);
+    clients
+}
+
+#[test]
+#[cfg(feature = "json-io")]
+fn when_restored_from_snapshot_should_process_like_the_original() {
+    let mut engine = new_engine();
+    for (client_id, tx_id, value) in [(1, 1, "10.0"), (2, 2, "5.5"), (1, 3, "2.25"), (3, 4, "7.0")] {
+        engine.compute_transaction(Transaction::Deposit { client_id, tx_id, amount: amount(value) }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 5, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 3, tx_id: 4 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 3, tx_id: 4 }).unwrap();
+
+    let mut snapshot = vec![];
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let mut restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(sorted_clients(&restored), sorted_clients(&engine));
+    assert_eq!(restored.metrics().open_disputes, 1);
+    assert_eq!(restored.client_transactions(1).map(PersistedTransaction::amount).collect::<Vec<_>>(),
+        vec![amount("10.0"), amount("2.25"), amount("1.0")]);
+
+    let batch = [
+        Transaction::Dispute { client_id: 1, tx_id: 1 },
+        Transaction::Resolve { client_id: 2, tx_id: 2 },
+        Transaction::Dispute { client_id: 1, tx_id: 5 },
+        Transaction::Chargeback { client_id: 1, tx_id: 1 },
+        Transaction::Dispute { client_id: 2, tx_id: 2 },
+        Transaction::Deposit { client_id: 3, tx_id: 6, amount: amount("1.0") },
+        Transaction::Deposit { client_id: 2, tx_id: 3, amount: amount("1.0") },
+        Transaction::Dispute { client_id: 3, tx_id: 4 },
+    ];
+    for transaction in batch {
+        assert_eq!(restored.compute_transaction(transaction), engine.compute_transaction(transaction));
+    }
+
+    assert_eq!(sorted_clients(&restored), sorted_clients(&engine));
+    for tx_id in 1..=6 {
+        assert_eq!(restored.transaction_state(tx_id), engine.transaction_state(tx_id));
+    }
+    for client_id in 1..=3 {
+        assert_eq!(restored.dispute_counters(client_id), engine.dispute_counters(client_id));
+    }
+}
+
+fn with_locked_client(config: EngineConfig) -> TransactionEngine<Store> {
+    let mut engine = engine_with_config(config);
+    for tx_id in 1..=4 {
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount: amount("10.0") }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 5, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+    engine
+}
+
+#[test]
+fn compact_should_drop_the_transactions_that_cannot_be_disputed() {
+    let mut engine = with_locked_client(EngineConfig::default());
+    assert_eq!(engine.transactions.len(), 5);
+
+    // Tx 1 is charged back and txs 3 and 4 belong to the locked client, tx 2 is still disputed.
+    assert_eq!(engine.compact(), 3);
+
+    assert_eq!(engine.transactions.len(), 2);
+    assert_eq!(engine.client_transactions(1).map(PersistedTransaction::tx_id).collect::<Vec<_>>(), vec![2]);
+    let dispute = engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 3 });
+    assert_eq!(dispute, Err(TransactionError::Compacted { tx_id: 3 }));
+    assert!(dispute.unwrap_err().is_ignored());
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }),
+        Err(TransactionError::Compacted { tx_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 4, amount: amount("1.0") }),
+        Err(TransactionError::DuplicateTransaction { tx_id: 4 }));
+
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 2 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("20.0"), amount("0.0"), amount("20.0")));
+    assert_eq!(engine.compact(), 1);
+    assert_eq!(engine.transactions.len(), 1);
+}
+
+#[test]
+fn compact_locked_should_compact_on_chargeback() {
+    let engine = with_locked_client(EngineConfig::default().compact_locked(true));
+    assert_eq!(engine.transactions.len(), 2);
+    assert_eq!(engine.transaction_state(2), TxStatus::Disputed);
+    assert_eq!(engine.transaction_state(5), TxStatus::Settled);
+
+    #[cfg(feature = "json-io")]
+    {
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        let mut restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 4 }),
+            Err(TransactionError::Compacted { tx_id: 4 }));
+    }
+}
+
+#[test]
+fn flagged_clients_should_only_list_repeated_chargebacks() {
+    let mut engine = new_engine();
+    for tx_id in 1..=3 {
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id, amount: amount("10.0") }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: tx_id + 10, amount: amount("2.5") }).unwrap();
+    }
+    // The benign client disputes twice, one dispute being resolved.
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 2 }).unwrap();
+    for tx_id in 11..=13 {
+        engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id }).unwrap();
+    }
+    for tx_id in 11..=13 {
+        engine.compute_transaction(Transaction::Chargeback { client_id: 2, tx_id }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Chargeback { client_id: 2, tx_id: 13 }).unwrap_err();
+
+    assert_eq!(engine.flagged_clients(3), vec![2]);
+    assert_eq!(engine.flagged_clients(1), vec![1, 2]);
+    assert_eq!(engine.dispute_counters(1), Some(&DisputeCounters { disputes: 2, chargebacks: 1, charged_back: amount("10.0") }));
+    assert_eq!(engine.dispute_counters(2), Some(&DisputeCounters { disputes: 3, chargebacks: 3, charged_back: amount("7.5") }));
+    assert_eq!(engine.dispute_counters(3), None);
+}
+
+#[test]
+#[cfg(feature = "json-io")]
+fn when_snapshot_has_another_header_should_reject_it() {
+    let loaded = TransactionEngine::load_snapshot("transaction_engine snapshot 0\n{}\n".as_bytes());
+
+    assert!(matches!(loaded, Err(SnapshotError::Incompatible { header }) if header == "transaction_engine snapshot 0"));
+}
+
+#[test]
+#[cfg(feature = "json-io")]
+fn when_snapshot_truncated_should_report_it_corrupt() {
+    let mut engine = new_engine();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    let mut snapshot = vec![];
+    engine.save_snapshot(&mut snapshot).unwrap();
+    snapshot.truncate(snapshot.len() - 10);
+
+    assert!(matches!(TransactionEngine::load_snapshot(snapshot.as_slice()), Err(SnapshotError::Corrupt(_))));
+}
+
+#[test]
+fn observer_should_receive_the_events_of_state_changes() {
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut engine = new_engine();
+    let recorded = Arc::clone(&events);
+    engine.set_observer(move |event: &EngineEvent| recorded.lock().unwrap().push(*event));
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("3.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 9 }).unwrap_err();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 3, amount: amount("50.0") }).unwrap_err();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 2, tx_id: 4, amount: amount("1.0") }).unwrap_err();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap_err();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap();
+
+    assert_eq!(*events.lock().unwrap(), vec![
+        EngineEvent::AccountCredited { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        EngineEvent::AccountDebited { client_id: 1, tx_id: 2, amount: amount("3.0") },
+        EngineEvent::TransactionRejected {
+            client_id: 1,
+            tx_id: 3,
+            error: TransactionError::InsufficientFunds { client_id: 1, requested: amount("50.0"), available: amount("7.0") },
+        },
+        EngineEvent::AccountOpened { client_id: 2, tx_id: 4 },
+        EngineEvent::TransactionRejected {
+            client_id: 2,
+            tx_id: 4,
+            error: TransactionError::InsufficientFunds { client_id: 2, requested: amount("1.0"), available: amount("0.0") },
+        },
+        EngineEvent::FundsHeld { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        EngineEvent::FundsReleased { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        EngineEvent::HeldCredited { client_id: 1, tx_id: 2, amount: amount("3.0") },
+        EngineEvent::FundsReleased { client_id: 1, tx_id: 2, amount: amount("3.0") },
+        EngineEvent::AccountLocked { client_id: 1, tx_id: 2 },
+        EngineEvent::FundsHeld { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        EngineEvent::HeldDebited { client_id: 1, tx_id: 1, amount: amount("10.0") },
+    ]);
+}
+
+// Buffer the JSON logs of a test are written to.
+#[derive(Clone, Default)]
+struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl tracing_subscriber::fmt::MakeWriter<'_> for Logs {
+    type Writer = Logs;
+
+    fn make_writer(&self) -> Logs {
+        self.clone()
+    }
+}
+
+#[test]
+fn decisions_should_be_logged_with_the_transaction() {
+    let logs = Logs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(logs.clone())
+        .finish();
+    let mut engine = new_engine();
+
+    tracing::subscriber::with_default(subscriber, || {
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 1 }).unwrap_err();
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap_err();
+        engine.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+        engine.compute_transaction(Transaction::Chargeback { client_id: 1, tx_id: 1 }).unwrap_err();
+        engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("50.0") }).unwrap_err();
+    });
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let decisions: Vec<(String, String, String)> = logs.lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|log| {
+            let span = &log["span"];
+            (
+                format!("{} {} {} {}", span["type"], span["client_id"], span["tx_id"], span["amount"]),
+                log["fields"]["decision"].as_str().unwrap().to_string(),
+                log["fields"]["reason"].as_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+    let decision = |transaction: &str, decision: &str, reason: &str| (transaction.to_string(), decision.to_string(), reason.to_string());
+
+    // Disputes, resolves and chargebacks log the amount of the disputed deposit once it is found.
+    assert_eq!(decisions, vec![
+        decision(r#""deposit" 1 1 "10.0""#, "applied", ""),
+        decision(r#""dispute" 2 1 "10.0""#, "ignored", "client_mismatch"),
+        decision(r#""dispute" 1 1 "10.0""#, "applied", ""),
+        decision(r#""dispute" 1 1 "10.0""#, "ignored", "already_disputed"),
+        decision(r#""resolve" 1 1 "10.0""#, "applied", ""),
+        decision(r#""chargeback" 1 1 "10.0""#, "ignored", "not_disputed"),
+        decision(r#""withdrawal" 1 2 "50.0""#, "rejected", "insufficient_funds"),
+    ]);
+}
+
+#[test]
+fn compute_transactions_should_apply_a_batch_and_count_rejections() {
+    let mut engine = new_engine();
+
+    let summary = engine.compute_transactions(vec![
+        Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("20.0") },
+        Transaction::Dispute { client_id: 1, tx_id: 9 },
+        Transaction::Deposit { client_id: 2, tx_id: 3, amount: amount("5.0") },
+        Transaction::Dispute { client_id: 1, tx_id: 1 },
+        Transaction::Chargeback { client_id: 1, tx_id: 1 },
+        Transaction::Deposit { client_id: 1, tx_id: 4, amount: amount("1.0") },
+        Transaction::Withdrawal { client_id: 2, tx_id: 5, amount: amount("6.0") },
+    ]);
+
+    assert_eq!(summary.read_count(), 8);
+    assert_eq!(summary.applied_count(), 4);
+    assert_eq!(summary.rejected_count(), 4);
+    assert_eq!(summary.count("insufficient_funds"), 2);
+    assert_eq!(summary.count("unknown_transaction"), 1);
+    assert_eq!(summary.count("account_locked"), 1);
+    assert_eq!(summary.rejected_tx_ids(), &[2, 9, 4, 5]);
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("0.0"));
+    assert_eq!(engine.get_client(2).unwrap().total(), amount("5.0"));
+}
+
+#[test]
+fn when_many_small_transactions_should_keep_exact_balances() {
+    let mut engine = new_engine();
+
+    for tx_id in 0..10_000 {
+        engine.compute_transaction(Transaction::Deposit {
+            client_id: 1,
+            tx_id,
+            amount: amount("0.1")
+        }).unwrap();
+    }
+    for tx_id in 10_000..13_000 {
+        engine.compute_transaction(Transaction::Withdrawal {
+            client_id: 1,
+            tx_id,
+            amount: amount("0.0001")
+        }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 42 }).unwrap();
+
+    let client = engine.get_client_list()[0];
+    assert_eq!(client.total(), amount("999.7"));
+    assert_eq!(client.held(), amount("0.1"));
+    assert_eq!(client.available(), amount("999.6"));
+    assert_eq!(client.available() + client.held(), client.total());
+}
+
+#[test]
+fn when_deposit_on_client_locked_should_do_nothing() {
+    let mut engine = new_engine();
+
+    let locked = engine.client_list.get_mut(1, Currency::USD);
+    locked.locked = true;
+
+    assert_eq!(engine.compute_transaction(Transaction::Deposit { 
+        client_id: 1, 
+        tx_id: 1, 
+        amount: amount("10.0") 
+    }), Err(TransactionError::AccountLocked { client_id: 1 }));
+    let clients = engine.get_client_list();
+
+    assert_eq!(clients.len(),1);
+    let client = clients.get(0).unwrap();
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.total(),amount("0.0"));
+    assert_eq!(client.id(),1);
+}
+
+#[test]
+fn when_withdrawal_and_fund_available_should_decrease_total_and_available() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("30.0")
+    }).unwrap();
+
+    let locked = engine.client_list.get_mut(1, Currency::USD);
+    locked.locked = true;
+
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }), Err(TransactionError::AccountLocked { client_id: 1 }));
+    let clients = engine.get_client_list();
+
+    assert_eq!(clients.len(),1);
+    let client = clients.get(0).unwrap();
+    assert_eq!(client.available(),amount("30.0"));
+    assert_eq!(client.total(),amount("30.0"));
+    assert_eq!(client.id(),1);
+}
+
+#[test]
+fn when_withdrawal_on_locked_client_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("30.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }).unwrap();
+    let clients = engine.get_client_list();
+
+    assert_eq!(clients.len(),1);
+    let client = clients.get(0).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.id(),1);
+}
+
+#[test]
+fn when_withdrawal_and_fund_not_available_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("50.0")
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("60.0")
+    }), Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("60.0"), available: amount("50.0") }));
+    let clients = engine.get_client_list();
+
+    assert_eq!(clients.len(),1);
+    let client = clients.get(0).unwrap();
+    assert_eq!(client.available(),amount("50.0"));
+    assert_eq!(client.total(),amount("50.0"));
+    assert_eq!(client.id(),1);
+}
+
+#[test]
+fn top_by_held_should_return_largest_held_with_ties_by_client_id() {
+    let mut engine = new_engine();
+
+    for client_id in 1..=100u16 {
+        let tx_id = TxId::from(client_id);
+        engine.compute_transaction(Transaction::Deposit {
+            client_id,
+            tx_id,
+            amount: Amount::from_units(i64::from(client_id % 10 + 1) * 100_000)
+        }).unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id, tx_id }).unwrap();
+    }
+    engine.compute_transaction(Transaction::Deposit { client_id: 9, tx_id: 1000, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 9, tx_id: 1000 }).unwrap();
+
+    let top = engine.top_by_held(3);
+
+    let ids: Vec<u16> = top.iter().map(|exposure| exposure.client.id()).collect();
+    assert_eq!(ids, vec![9, 19, 29]);
+    assert_eq!(top[0].client.held(), amount("105.0"));
+    assert_eq!(top[0].disputed_tx, vec![9, 1000]);
+    assert_eq!(top[1].client.held(), amount("100.0"));
+    assert_eq!(top[1].disputed_tx, vec![19]);
+}
+
+#[test]
+fn top_by_held_should_handle_small_populations() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    assert_eq!(engine.top_by_held(0).len(), 0);
+    let top = engine.top_by_held(5);
+    assert_eq!(top.len(), 1);
+    assert_eq!(top[0].client.held(), amount("0.0"));
+    assert!(top[0].disputed_tx.is_empty());
+    assert_eq!(engine.top_by_held(usize::MAX).len(), 1);
+}
+
+#[test]
+fn when_deposit_should_copy_it_with_state_none() {
+    let mut engine = new_engine();
+    let transaction = Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    };
+
+    engine.compute_transaction(transaction).unwrap();
+    
+    assert_eq!(engine.transactions.len(),1);
+    let StoredTransaction { transaction: tx, state, .. } = engine.transactions.get(1).unwrap();
+
+    if let PersistedTransaction::Deposit { client_id, tx_id, amount: deposited, .. } = tx {
+        assert!(matches!(state,TransactionState::None));
+        assert_eq!(*tx_id,1);
+        assert_eq!(*client_id,1);
+        assert_eq!(*deposited,amount("10.0"));
+    } else {
+        panic!()
+    }
+    
+}
+
+
+#[test]
+fn when_funds_held_by_dispute_withdrawal_above_available_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("100.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("20.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 3, amount: amount("100.0") }), Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("100.0"), available: amount("20.0") }));
+
+    let client = *engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("20.0"));
+    assert_eq!(client.held(),amount("100.0"));
+    assert_eq!(client.total(),amount("120.0"));
+    assert!(!engine.transactions.contains(3));
+
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 4, amount: amount("20.0") }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.held(),amount("100.0"));
+    assert_eq!(client.total(),amount("100.0"));
+}
+
+#[test]
+fn when_deposit_amount_not_positive_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("-50.0") }), Err(TransactionError::InvalidAmount { tx_id: 2, amount: amount("-50.0") }));
+    assert_eq!(engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 3, amount: amount("0.0") }), Err(TransactionError::InvalidAmount { tx_id: 3, amount: amount("0.0") }));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(engine.transactions.len(),1);
+}
+
+#[test]
+fn when_deposit_overflows_the_balance_should_do_nothing() {
+    let mut engine = new_engine();
+    let near_max = Amount::from_units(i64::MAX - 10_000);
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: near_max }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("1.0001") }),
+        Err(TransactionError::AmountOverflow { client_id: 1, tx_id: 2 }));
+    assert_eq!(engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("900000000000000") }),
+        Err(TransactionError::AmountOverflow { client_id: 1, tx_id: 3 }));
+
+    assert_eq!(engine.get_client(1), Some(&Client::new(1, near_max, Amount::ZERO, near_max, false)));
+    assert_eq!(engine.transactions.len(), 1);
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 4, amount: amount("1.0") }).unwrap();
+    assert_eq!(engine.get_client(1).map(Client::total), Some(Amount::from_units(i64::MAX)));
+}
+
+#[test]
+fn when_transfer_or_dispute_overflows_the_balance_should_change_no_account() {
+    let mut engine = new_engine();
+    let near_max = amount("900000000000000");
+
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("100000000000000") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 2, amount: amount("100000000000000") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 3, amount: near_max }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 4, amount: near_max }).unwrap();
+
+    assert_eq!(engine.compute_transaction(Transaction::Transfer { from: 1, to: 2, tx_id: 5, amount: amount("100000000000000") }),
+        Err(TransactionError::AmountOverflow { client_id: 2, tx_id: 5 }));
+    // The disputed withdrawal is credited back as held funds.
+    assert_eq!(engine.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 2 }),
+        Err(TransactionError::AmountOverflow { client_id: 1, tx_id: 2 }));
+
+    assert_eq!(engine.get_client(1), Some(&Client::new(1, near_max, Amount::ZERO, near_max, false)));
+    assert_eq!(engine.get_client(2), Some(&Client::new(2, near_max, Amount::ZERO, near_max, false)));
+    assert_eq!(TransactionError::AmountOverflow { client_id: 1, tx_id: 2 }.code(), "amount_overflow");
+}
+
+#[test]
+fn when_withdrawal_amount_not_positive_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("-50.0") }), Err(TransactionError::InvalidAmount { tx_id: 2, amount: amount("-50.0") }));
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 3, amount: amount("0.0") }), Err(TransactionError::InvalidAmount { tx_id: 3, amount: amount("0.0") }));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(engine.transactions.len(),1);
+}
+
+#[test]
+fn when_deposit_reuses_tx_id_should_keep_original() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 5, amount: amount("10.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 5, amount: amount("99.0") }), Err(TransactionError::DuplicateTransaction { tx_id: 5 }));
+
+    let client = *engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 5 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.held(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+}
+
+#[test]
+fn when_withdrawal_reuses_tx_id_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 5, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 6, amount: amount("2.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 5, amount: amount("3.0") }), Err(TransactionError::DuplicateTransaction { tx_id: 5 }));
+    assert_eq!(engine.compute_transaction(Transaction::Deposit{ client_id: 2, tx_id: 6, amount: amount("7.0") }), Err(TransactionError::DuplicateTransaction { tx_id: 6 }));
+
+    let client = *engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("8.0"));
+    assert_eq!(client.total(),amount("8.0"));
+    assert!(matches!(engine.transactions.get(5), Some(StoredTransaction { transaction: PersistedTransaction::Deposit { .. }, .. })));
+    assert!(matches!(engine.transactions.get(6), Some(StoredTransaction { transaction: PersistedTransaction::Withdrawal { .. }, .. })));
+    assert_eq!(engine.client_list.get_mut(2, Currency::USD).total(),amount("0.0"));
+}
+
+#[test]
+fn when_withdrawal_should_copy_it_with_state_none() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("30.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }).unwrap();
+
+    assert_eq!(engine.transactions.len(),2);
+    let StoredTransaction { transaction: tx, state, .. } = engine.transactions.get(2).unwrap();
+
+    if let PersistedTransaction::Withdrawal { client_id, tx_id, amount: withdrawn, .. } = tx {
+        assert!(matches!(state,TransactionState::None));
+        assert_eq!(*tx_id,2);
+        assert_eq!(*client_id,1);
+        assert_eq!(*withdrawn,amount("20.0"));
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn when_withdrawal_rejected_should_not_copy_it() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }), Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("20.0"), available: amount("10.0") }));
+    engine.client_list.get_mut(1, Currency::USD).locked = true;
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 3,
+        amount: amount("5.0")
+    }), Err(TransactionError::AccountLocked { client_id: 1 }));
+
+    assert_eq!(engine.transactions.len(),1);
+    assert!(!engine.transactions.contains(2));
+    assert!(!engine.transactions.contains(3));
+}
+
+#[test]
+fn when_dispute_on_withdrawal_should_find_it() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("30.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 2,
+    }).unwrap();
+
+    let StoredTransaction { state, .. } = engine.transactions.get(2).unwrap();
+    assert!(matches!(state,TransactionState::Disputed));
+}
+
+#[test]
+fn when_dispute_on_withdrawal_should_hold_withdrawn_amount() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("30.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("20.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("20.0"));
+    assert_eq!(client.total(),amount("30.0"));
+}
+
+#[test]
+fn when_resolve_on_withdrawal_should_release_hold_without_net_change() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("30.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("20.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let StoredTransaction { state, .. } = engine.transactions.get(2).unwrap();
+    assert!(matches!(state,TransactionState::None));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("0.0"));
+    assert_eq!(client.total(),amount("10.0"));
+    assert!(!client.is_locked());
+}
+
+#[test]
+fn when_chargeback_on_withdrawal_should_credit_back_and_freeze() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("30.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("20.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("30.0"));
+    assert_eq!(client.held(),amount("0.0"));
+    assert_eq!(client.total(),amount("30.0"));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn when_withdrawal_disputed_with_negative_available_should_keep_balances_consistent() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("50.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("40.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let client = *engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("-40.0"));
+    assert_eq!(client.held(),amount("90.0"));
+    assert_eq!(client.total(),amount("50.0"));
+
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.held(),amount("0.0"));
+    assert_eq!(client.total(),amount("0.0"));
+    assert_eq!(client.available() + client.held(),client.total());
+    assert!(client.is_locked());
+}
+
+#[test]
+fn when_dispute_on_rejected_withdrawal_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{
+        client_id: 1,
+        tx_id: 2,
+        amount: amount("20.0")
+    }), Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("20.0"), available: amount("10.0") }));
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 2,
+    }), Err(TransactionError::UnknownTransaction { tx_id: 2 }));
+
+    assert!(!engine.transactions.contains(2));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.held(),amount("0.0"));
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.total(),amount("10.0"));
+}
+
+#[test]
+fn when_dispute_on_deposit_should_decrease_available_increase_held() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1,
+    }).unwrap();
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::Disputed));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.held(),amount("10.0"));
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.total(),amount("10.0"));
+}
+
+#[test]
+fn when_dispute_on_already_disputed_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1
+    }), Err(TransactionError::AlreadyDisputed { tx_id: 1 }));
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::Disputed));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.held(),amount("10.0"));
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.total(),amount("10.0"));
+}
+
+#[test]
+fn when_dispute_on_missing_tx_should_do_nothing() {
+    let mut engine = new_engine();
+
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1,
+    }), Err(TransactionError::UnknownTransaction { tx_id: 1 }));
+
+    assert_eq!(0,engine.transactions.len());
+    assert_eq!(0,engine.clients().count())
+}
+#[test]
+fn when_resolve_on_missing_tx_should_do_nothing() {
+    let mut engine = new_engine();
+
+    assert_eq!(engine.compute_transaction(Transaction::Resolve {
+        client_id: 1,
+        tx_id: 1,
+    }), Err(TransactionError::UnknownTransaction { tx_id: 1 }));
+
+    assert_eq!(0,engine.transactions.len());
+    assert_eq!(0,engine.clients().count())
+}
+
+#[test]
+fn when_resolve_on_not_disputed_tx_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Resolve {
+        client_id: 1,
+        tx_id: 1,
+    }), Err(TransactionError::NotDisputed { tx_id: 1 }));
+
+    assert_eq!(1,engine.transactions.len());
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::None));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("0.0"))
+}
+
+#[test]
+fn when_resolve_should_revert_dispute() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1,
+    }).unwrap();
+    engine.compute_transaction(Transaction::Resolve {
+        client_id: 1,
+        tx_id: 1,
+    }).unwrap();
+
+    assert_eq!(1,engine.transactions.len());
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::None));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("0.0"))
+}
+
+#[test]
+fn when_chargeback_on_missing_tx_should_do_nothing() {
+    let mut engine = new_engine();
+
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{
+        client_id: 1,
+        tx_id: 1,
+    }), Err(TransactionError::UnknownTransaction { tx_id: 1 }));
+
+    assert_eq!(0,engine.transactions.len());
+    assert_eq!(0,engine.clients().count())
+}
+
+#[test]
+fn when_chargeback_on_not_disputed_tx_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback {
+        client_id: 1,
+        tx_id: 1,
+    }), Err(TransactionError::NotDisputed { tx_id: 1 }));
+
+    assert_eq!(1,engine.transactions.len());
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::None));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("0.0"))
+}
+
+#[test]
+fn when_chargeback_should_freeze_and_withdraw() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{
+        client_id: 1,
+        tx_id: 1,
+        amount: amount("10.0")
+    }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{
+        client_id: 1,
+        tx_id: 1,
+    }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback {
+        client_id: 1,
+        tx_id: 1,
+    }).unwrap();
+
+    assert_eq!(1,engine.transactions.len());
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::ChargedBack));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("0.0"));
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.held(),amount("0.0"));
+    assert_eq!(client.is_locked(),true);
+}
+
+#[test]
+fn when_tx_id_above_32_bits_should_dispute_and_charge_back() {
+    let mut engine = new_engine();
+    let tx_id: TxId = 5_000_000_000;
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id, amount: amount("10.0") }).unwrap();
+    // The same id truncated to 32 bits is another transaction.
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: tx_id % (1 << 32), amount: amount("3.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id }).unwrap();
+    assert_eq!(engine.transaction_state(tx_id), TxStatus::Disputed);
+    assert_eq!(engine.transaction_state(tx_id % (1 << 32)), TxStatus::Settled);
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id }).unwrap();
+
+    assert_eq!(engine.transaction_state(tx_id), TxStatus::ChargedBack);
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(), amount("3.0"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn when_charged_back_should_not_be_disputed_or_charged_back_again() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("15.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }), Err(TransactionError::AlreadyChargedBack { tx_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }), Err(TransactionError::AlreadyChargedBack { tx_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 1 }), Err(TransactionError::AlreadyChargedBack { tx_id: 1 }));
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::ChargedBack));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("15.0"));
+    assert_eq!(client.available(),amount("15.0"));
+    assert_eq!(client.held(),amount("0.0"));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn when_dispute_from_another_client_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{ client_id: 2, tx_id: 1 }),
+        Err(TransactionError::ClientMismatch { client_id: 2, tx_id: 1 }));
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::None));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("10.0"));
+    assert_eq!(client.held(),amount("0.0"));
+}
+
+#[test]
+fn when_resolve_from_another_client_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Resolve{ client_id: 2, tx_id: 1 }),
+        Err(TransactionError::ClientMismatch { client_id: 2, tx_id: 1 }));
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::Disputed));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.available(),amount("0.0"));
+    assert_eq!(client.held(),amount("10.0"));
+}
+
+#[test]
+fn when_chargeback_from_another_client_should_do_nothing() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 2, tx_id: 1 }),
+        Err(TransactionError::ClientMismatch { client_id: 2, tx_id: 1 }));
+
+    let StoredTransaction { state, .. } = engine.transactions.get(1).unwrap();
+    assert!(matches!(state,TransactionState::Disputed));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.total(),amount("10.0"));
+    assert_eq!(client.held(),amount("10.0"));
+    assert!(!client.is_locked());
+}
+
+#[test]
+fn when_unlock_after_chargeback_should_reinstate_the_account() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Unlock{ client_id: 1, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 4, amount: amount("2.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 5, amount: amount("1.0") }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert!(!client.is_locked());
+    assert_eq!(client.available(),amount("6.0"));
+    assert_eq!(client.total(),amount("6.0"));
+    assert_eq!(engine.transaction_state(3), TxStatus::Unknown);
+    assert_eq!(engine.metrics().unlock, TypeCounters { applied: 1, rejected: 0 });
+}
+
+#[test]
+fn when_unlock_unknown_or_unlocked_client_should_be_ignored() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+
+    let unknown = engine.compute_transaction(Transaction::Unlock{ client_id: 2, tx_id: 2 });
+    assert_eq!(unknown, Err(TransactionError::UnknownClient { client_id: 2 }));
+    let unlocked = engine.compute_transaction(Transaction::Unlock{ client_id: 1, tx_id: 3 });
+    assert_eq!(unlocked, Err(TransactionError::NotLocked { client_id: 1 }));
+    assert!(unknown.unwrap_err().is_ignored() && unlocked.unwrap_err().is_ignored());
+    assert!(engine.get_client(2).is_none());
+    assert_eq!(engine.metrics().unlock, TypeCounters { applied: 0, rejected: 2 });
+}
+
+#[test]
+fn transfer_should_move_funds_and_create_the_recipient() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Transfer{ from: 1, to: 2, tx_id: 2, amount: amount("4.0") }).unwrap();
+
+    let source = engine.get_client(1).unwrap();
+    assert_eq!((source.available(), source.total()), (amount("6.0"), amount("6.0")));
+    let recipient = engine.get_client(2).unwrap();
+    assert_eq!((recipient.available(), recipient.total()), (amount("4.0"), amount("4.0")));
+    assert_eq!(engine.transaction_state(2), TxStatus::Settled);
+    assert_eq!(engine.metrics().transfer, TypeCounters { applied: 1, rejected: 0 });
+}
+
+#[test]
+fn when_transfer_exceeds_available_should_change_neither_account() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    assert_eq!(
+        engine.compute_transaction(Transaction::Transfer{ from: 1, to: 2, tx_id: 2, amount: amount("15.0") }),
+        Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("15.0"), available: amount("10.0") })
+    );
+
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("10.0"));
+    assert!(engine.get_client(2).is_none());
+    assert_eq!(engine.transaction_state(2), TxStatus::Unknown);
+}
+
+#[test]
+fn when_transfer_source_or_recipient_locked_should_be_rejected() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 2, tx_id: 2, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 2, tx_id: 3, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 2, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 2, tx_id: 3 }).unwrap();
+
+    assert_eq!(engine.compute_transaction(Transaction::Transfer{ from: 2, to: 1, tx_id: 4, amount: amount("1.0") }),
+        Err(TransactionError::AccountLocked { client_id: 2 }));
+    assert_eq!(engine.compute_transaction(Transaction::Transfer{ from: 1, to: 2, tx_id: 5, amount: amount("1.0") }),
+        Err(TransactionError::AccountLocked { client_id: 2 }));
+    assert_eq!(engine.compute_transaction(Transaction::Transfer{ from: 1, to: 1, tx_id: 6, amount: amount("1.0") }),
+        Err(TransactionError::SelfTransfer { tx_id: 6 }));
+
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("10.0"));
+    assert_eq!(engine.get_client(2).unwrap().total(), amount("10.0"));
+}
+
+#[test]
+fn transfer_dispute_should_hold_the_recipient_funds_until_chargeback() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Transfer{ from: 1, to: 2, tx_id: 2, amount: amount("4.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{ client_id: 2, tx_id: 2 }),
+        Err(TransactionError::ClientMismatch { client_id: 2, tx_id: 2 }));
+
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    let recipient = engine.get_client(2).unwrap();
+    assert_eq!((recipient.available(), recipient.held(), recipient.total()), (amount("0.0"), amount("4.0"), amount("4.0")));
+    assert_eq!(engine.top_by_held(1)[0].disputed_tx, vec![2]);
+
+    engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 2 }).unwrap();
+    assert_eq!(engine.get_client(2).unwrap().available(), amount("4.0"));
+
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+
+    let source = engine.get_client(1).unwrap();
+    assert_eq!((source.available(), source.total(), source.is_locked()), (amount("10.0"), amount("10.0"), false));
+    let recipient = engine.get_client(2).unwrap();
+    assert_eq!((recipient.held(), recipient.total(), recipient.is_locked()), (amount("0.0"), amount("0.0"), true));
+    assert_eq!(engine.check_invariants(), Ok(()));
+}
+
+#[test]
+fn adjustment_should_change_available_and_total_without_funds_check() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 5, tx_id: 1, amount: amount("2.0") }).unwrap();
+    engine.compute_transaction(Transaction::Adjustment{ client_id: 5, tx_id: 900, amount: amount("-3.5") }).unwrap();
+
+    let client = engine.get_client(5).unwrap();
+    assert_eq!((client.available(), client.total()), (amount("-1.5"), amount("-1.5")));
+
+    engine.compute_transaction(Transaction::Adjustment{ client_id: 5, tx_id: 901, amount: amount("4.0") }).unwrap();
+    let client = engine.get_client(5).unwrap();
+    assert_eq!((client.available(), client.total()), (amount("2.5"), amount("2.5")));
+    assert!(matches!(engine.transaction(900), Some(PersistedTransaction::Adjustment { .. })));
+    assert_eq!(engine.compute_transaction(Transaction::Adjustment{ client_id: 5, tx_id: 902, amount: amount("0.0") }),
+        Err(TransactionError::InvalidAmount { tx_id: 902, amount: amount("0.0") }));
+    assert_eq!(engine.compute_transaction(Transaction::Adjustment{ client_id: 5, tx_id: 1, amount: amount("1.0") }),
+        Err(TransactionError::DuplicateTransaction { tx_id: 1 }));
+}
+
+#[test]
+fn adjustment_on_locked_account_should_depend_on_the_setting() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+
+    engine.compute_transaction(Transaction::Adjustment{ client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+    engine.set_config(EngineConfig::default().allow_adjustment_on_locked(false));
+    assert_eq!(engine.compute_transaction(Transaction::Adjustment{ client_id: 1, tx_id: 3, amount: amount("1.0") }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.total(), client.is_locked()), (amount("1.0"), true));
+}
+
+fn locked_by_a_second_open_dispute() -> TransactionEngine<Store> {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("4.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 3, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+    engine
+}
+
+#[test]
+fn by_default_disputes_should_apply_to_locked_accounts() {
+    let mut engine = locked_by_a_second_open_dispute();
+
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 3 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("1.0"), amount("0.0"), amount("1.0")));
+    assert!(client.is_locked());
+    assert_eq!(engine.transaction_state(2), TxStatus::ChargedBack);
+}
+
+#[test]
+fn when_locked_disputes_disallowed_should_reject_any_change_of_a_locked_account() {
+    let mut engine = locked_by_a_second_open_dispute();
+    engine.set_config(EngineConfig::default().allow_dispute_on_locked(false));
+
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 2 }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    assert_eq!(engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 3 }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("1.0"), amount("4.0"), amount("5.0")));
+    assert_eq!(engine.transaction_state(2), TxStatus::Disputed);
+    assert_eq!(engine.transaction_state(3), TxStatus::Settled);
+
+    engine.compute_transaction(Transaction::Unlock{ client_id: 1, tx_id: 4 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("1.0"));
+}
+
+#[test]
+fn when_locked_disputes_disallowed_transfer_chargeback_should_check_both_accounts() {
+    let mut engine = engine_with_config(EngineConfig::default().allow_dispute_on_locked(false));
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Transfer{ from: 1, to: 2, tx_id: 3, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 3 }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 3 }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    assert_eq!(engine.get_client(2).unwrap().held(), amount("5.0"));
+}
+
+#[test]
+fn dispute_of_an_adjustment_should_be_ignored() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Adjustment{ client_id: 1, tx_id: 1, amount: amount("5.0") }).unwrap();
+    let dispute = engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 });
+    assert_eq!(dispute, Err(TransactionError::NotDisputable { tx_id: 1 }));
+    assert!(dispute.unwrap_err().is_ignored());
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }),
+        Err(TransactionError::NotDisputed { tx_id: 1 }));
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held()), (amount("5.0"), amount("0.0")));
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+}
+
+#[test]
+fn fee_should_be_charged_even_beyond_the_available_funds() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Fee{ client_id: 1, tx_id: 2, amount: amount("1.5") }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.total()), (amount("-0.5"), amount("-0.5")));
+    assert!(matches!(engine.transaction(2), Some(PersistedTransaction::Fee { .. })));
+    assert_eq!(engine.metrics().fee, TypeCounters { applied: 1, rejected: 0 });
+    assert_eq!(engine.compute_transaction(Transaction::Fee{ client_id: 1, tx_id: 3, amount: amount("-1.0") }),
+        Err(TransactionError::InvalidAmount { tx_id: 3, amount: amount("-1.0") }));
+}
+
+#[test]
+fn when_fee_on_locked_account_should_be_skipped() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("5.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+
+    assert_eq!(engine.compute_transaction(Transaction::Fee{ client_id: 1, tx_id: 3, amount: amount("1.0") }),
+        Err(TransactionError::AccountLocked { client_id: 1 }));
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("10.0"));
+}
+
+#[test]
+fn fee_dispute_lifecycle_should_hold_then_refund_the_fee() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Fee{ client_id: 1, tx_id: 2, amount: amount("2.0") }).unwrap();
+
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("8.0"), amount("2.0"), amount("10.0")));
+
+    engine.compute_transaction(Transaction::Resolve{ client_id: 1, tx_id: 2 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("8.0"), amount("0.0"), amount("8.0")));
+
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 2 }).unwrap();
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 2 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("10.0"), amount("0.0"), amount("10.0")));
+    assert!(client.is_locked());
+    assert_eq!(engine.transaction_state(2), TxStatus::ChargedBack);
+}
+
+#[test]
+fn compute_transaction_at_should_keep_the_timestamp_with_the_stored_transaction() {
+    let mut engine = new_engine();
+    let ts = Timestamp::from_millis(1_700_000_000_000);
+
+    engine.compute_transaction_at(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }, Some(ts)).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction_at(Transaction::Dispute{ client_id: 1, tx_id: 1 }, Some(Timestamp::from_millis(0))).unwrap();
+
+    let history: Vec<Option<Timestamp>> = engine.client_transactions(1).map(PersistedTransaction::ts).collect();
+    assert_eq!(history, vec![Some(ts), None]);
+
+    #[cfg(feature = "json-io")]
+    {
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        let restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.transaction(1).and_then(PersistedTransaction::ts), Some(ts));
+    }
+}
+
+fn eur() -> Currency {
+    "EUR".parse().unwrap()
+}
+
+#[test]
+fn accounts_in_each_currency_should_keep_their_own_balance() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction_in(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("3.0") }, eur(), None).unwrap();
+    assert_eq!(engine.compute_transaction_in(Transaction::Withdrawal{ client_id: 1, tx_id: 3, amount: amount("5.0") }, eur(), None),
+        Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("5.0"), available: amount("3.0") }));
+    engine.compute_transaction_in(Transaction::Transfer{ from: 1, to: 2, tx_id: 4, amount: amount("1.0") }, eur(), None).unwrap();
+
+    assert!(engine.is_multi_currency());
+    assert_eq!(engine.client_count(), 3);
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("10.0"));
+    assert_eq!(engine.get_account(1, eur()).map(|client| (client.total(), client.currency())), Some((amount("2.0"), eur())));
+    assert_eq!(engine.get_account(2, eur()).unwrap().available(), amount("1.0"));
+    assert_eq!(engine.get_client(2), None);
+    assert_eq!(engine.total_funds(), amount("10.0"));
+}
+
+#[test]
+fn when_dispute_in_another_currency_should_be_rejected() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction_in(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("4.0") }, eur(), None).unwrap();
+    let dispute = engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 });
+    assert_eq!(dispute, Err(TransactionError::CurrencyMismatch { tx_id: 1, currency: Currency::USD, expected: eur() }));
+    assert_eq!(dispute.unwrap_err().to_string(), "tx 1 is in EUR, not in USD");
+    assert_eq!(engine.transaction_state(1), TxStatus::Settled);
+
+    engine.compute_transaction_in(Transaction::Dispute{ client_id: 1, tx_id: 1 }, eur(), None).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }),
+        Err(TransactionError::CurrencyMismatch { tx_id: 1, currency: Currency::USD, expected: eur() }));
+    engine.compute_transaction_in(Transaction::Chargeback{ client_id: 1, tx_id: 1 }, eur(), None).unwrap();
+
+    assert!(engine.get_account(1, eur()).unwrap().is_locked());
+    assert_eq!(engine.dispute_counters(1), Some(&DisputeCounters { disputes: 1, chargebacks: 1, charged_back: amount("0.0") }));
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+}
+
+#[test]
+#[cfg(feature = "json-io")]
+fn when_restored_from_snapshot_should_keep_the_currencies() {
+    let mut engine = new_engine();
+
+    engine.compute_transaction_in(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("4.0") }, eur(), None).unwrap();
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+
+    let mut snapshot = vec![];
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let mut restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.transaction(1).map(PersistedTransaction::currency), Some(eur()));
+    assert_eq!(restored.get_account(1, eur()).unwrap().total(), amount("4.0"));
+    restored.compute_transaction_in(Transaction::Dispute{ client_id: 1, tx_id: 1 }, eur(), None).unwrap();
+    assert_eq!(restored.get_account(1, eur()).unwrap().held(), amount("4.0"));
+}
+
+#[test]
+fn withdrawal_should_overdraw_up_to_the_credit_limit() {
+    let mut engine = engine_with_config(EngineConfig::default().credit_limit(amount("5.0")));
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("12.0") }).unwrap();
+    assert_eq!(engine.get_client(1).map(|client| (client.available(), client.total())), Some((amount("-2.0"), amount("-2.0"))));
+
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 3, amount: amount("3.0") }).unwrap();
+    assert_eq!(engine.get_client(1).unwrap().available(), amount("-5.0"));
+
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 4, amount: amount("0.0001") }),
+        Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("0.0001"), available: amount("-5.0") }));
+    assert_eq!(engine.get_client(1).unwrap().total(), amount("-5.0"));
+}
+
+#[test]
+fn set_credit_limit_should_override_the_default_of_a_single_client() {
+    let mut engine = engine_with_config(EngineConfig::default().credit_limit(amount("1.0")));
+    engine.set_credit_limit(2, amount("100.0"));
+    engine.set_credit_limit(3, amount("0.0"));
+
+    assert_eq!((engine.credit_limit(1), engine.credit_limit(2), engine.credit_limit(3)), (amount("1.0"), amount("100.0"), amount("0.0")));
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 1, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 2, tx_id: 2, amount: amount("100.0") }).unwrap();
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 3, tx_id: 3, amount: amount("1.0") }),
+        Err(TransactionError::InsufficientFunds { client_id: 3, requested: amount("1.0"), available: amount("0.0") }));
+    // Accounts in other currencies have no credit.
+    assert_eq!(engine.compute_transaction_in(Transaction::Withdrawal{ client_id: 2, tx_id: 4, amount: amount("1.0") }, eur(), None),
+        Err(TransactionError::InsufficientFunds { client_id: 2, requested: amount("1.0"), available: amount("0.0") }));
+    assert_eq!(engine.total_funds(), amount("-101.0"));
+}
+
+#[test]
+fn dispute_of_the_deposit_backing_an_overdraft_should_ignore_the_credit_limit() {
+    let mut engine = engine_with_config(EngineConfig::default().credit_limit(amount("5.0")));
+
+    engine.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("13.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("-13.0"), amount("10.0"), amount("-3.0")));
+    assert_eq!(engine.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 3, amount: amount("1.0") }),
+        Err(TransactionError::InsufficientFunds { client_id: 1, requested: amount("1.0"), available: amount("-13.0") }));
+
+    engine.compute_transaction(Transaction::Chargeback{ client_id: 1, tx_id: 1 }).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (amount("-13.0"), amount("0.0"), amount("-13.0")));
+
+    let mut strict = engine_with_config(EngineConfig::default().credit_limit(amount("5.0")).allow_negative_available_on_dispute(false));
+    strict.compute_transaction(Transaction::Deposit{ client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    strict.compute_transaction(Transaction::Withdrawal{ client_id: 1, tx_id: 2, amount: amount("13.0") }).unwrap();
+    assert_eq!(strict.compute_transaction(Transaction::Dispute{ client_id: 1, tx_id: 1 }),
+        Err(TransactionError::DisputeExceedsAvailable { client_id: 1, tx_id: 1, amount: amount("10.0"), available: amount("-3.0") }));
+}
+
+#[test]
+fn when_engines_applied_the_same_transactions_should_have_no_state_diff() {
+    let mut left = new_engine();
+    let mut right = TransactionEngine::new();
+    for transaction in [
+        Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") },
+        Transaction::Deposit { client_id: 2, tx_id: 2, amount: amount("5.0") },
+        Transaction::Dispute { client_id: 1, tx_id: 1 },
+    ] {
+        left.compute_transaction(transaction).unwrap();
+        right.compute_transaction(transaction).unwrap();
+    }
+
+    let diff = left.diff_states(&right);
+
+    assert!(diff.is_empty(), "{diff}");
+    assert_eq!(diff.examples, vec![]);
+}
+
+#[test]
+fn when_a_client_differs_state_diff_should_pinpoint_its_fields() {
+    use crate::state_diff::{Difference, Side};
+
+    let mut left = new_engine();
+    let mut right = new_engine();
+    left.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    right.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    left.compute_transaction(Transaction::Adjustment { client_id: 1, tx_id: 2, amount: amount("-4.0") }).unwrap();
+    right.compute_transaction(Transaction::Adjustment { client_id: 1, tx_id: 2, amount: amount("-4.0") }).unwrap();
+    right.compute_transaction_in(Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("1.0") }, eur(), None).unwrap();
+    left.client_list.get_mut(1, Currency::USD).locked = true;
+
+    let diff = left.diff_states(&right);
+
+    assert_eq!((diff.clients, diff.transactions, diff.totals), (2, 1, 2));
+    assert_eq!(diff.examples[..2], [
+        Difference::MissingClient { client_id: 1, currency: eur(), missing_from: Side::Left },
+        Difference::Client { client_id: 1, currency: Currency::USD, field: "locked", left: "true".into(), right: "false".into() },
+    ]);
+}
+
+#[test]
+fn when_balances_differ_state_diff_should_pinpoint_the_fields_and_totals() {
+    use crate::state_diff::Difference;
+
+    let mut left = new_engine();
+    let mut right = new_engine();
+    left.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    right.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    right.compute_transaction(Transaction::Adjustment { client_id: 1, tx_id: 2, amount: amount("2.5") }).unwrap();
+
+    let diff = left.diff_states(&right);
+
+    assert_eq!((diff.clients, diff.transactions, diff.totals), (2, 1, 2));
+    assert_eq!(diff.examples[0], Difference::Client {
+        client_id: 1, currency: Currency::USD, field: "available", left: "10.0".into(), right: "12.5".into(),
+    });
+    assert_eq!(diff.examples[3], Difference::Total { field: "total_funds", left: "10.0".into(), right: "12.5".into() });
+    assert_eq!(diff.examples[4], Difference::Total { field: "stored_transactions", left: "1".into(), right: "2".into() });
+}
+
+#[test]
+fn when_transactions_differ_state_diff_should_pinpoint_them() {
+    use crate::state_diff::{Difference, Side};
+
+    let mut left = new_engine();
+    let mut right = new_engine();
+    left.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    right.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("10.0") }).unwrap();
+    left.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("1.0") }).unwrap();
+    right.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("2.0") }).unwrap();
+    left.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 3, amount: amount("1.0") }).unwrap();
+    right.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 4, amount: amount("1.0") }).unwrap();
+    left.compute_transaction(Transaction::Dispute { client_id: 1, tx_id: 1 }).unwrap();
+    left.compute_transaction(Transaction::Resolve { client_id: 1, tx_id: 1 }).unwrap();
+
+    let diff = left.diff_states(&right);
+
+    assert_eq!(diff.transactions, 4);
+    assert_eq!(diff.examples.iter().filter(|difference| matches!(difference, Difference::Transaction { .. } | Difference::MissingTransaction { .. })).cloned().collect::<Vec<_>>(), vec![
+        Difference::Transaction { tx_id: 1, field: "disputes", left: "1".into(), right: "0".into() },
+        Difference::Transaction { tx_id: 2, field: "amount", left: "1.0".into(), right: "2.0".into() },
+        Difference::MissingTransaction { tx_id: 3, missing_from: Side::Right },
+        Difference::MissingTransaction { tx_id: 4, missing_from: Side::Left },
+    ]);
+}
+
+#[test]
+fn when_many_differences_state_diff_should_count_them_all_but_keep_a_few_examples() {
+    use crate::state_diff::MAX_EXAMPLES;
+
+    let mut left = new_engine();
+    let right = new_engine();
+    for client_id in 1..=100 {
+        left.compute_transaction(Transaction::Deposit { client_id, tx_id: client_id.into(), amount: amount("1.0") }).unwrap();
+    }
+
+    let diff = left.diff_states(&right);
+
+    assert_eq!((diff.clients, diff.transactions, diff.totals), (100, 100, 3));
+    assert_eq!(diff.examples.len(), MAX_EXAMPLES);
+    assert!(diff.to_string().starts_with("203 differences: 100 in the clients, 100 in the transactions, 3 in the totals\n"));
+    assert!(diff.to_string().ends_with("\n..."));
+}
@@ -0,0 +1,168 @@
+//! Alias table mapping partner specific type names onto the canonical transaction types.
+
+use std::collections::HashMap;
+
+use csv::StringRecord;
+
+use crate::csv_model::TransactionType;
+
+/// Canonical values of the `type` column.
+pub const TRANSACTION_TYPES: [&str; 9] = [
+    "deposit", "withdrawal", "dispute", "resolve", "chargeback", "unlock", "transfer", "adjustment", "fee",
+];
+
+/// Runtime alias table applied to the `type` column before deserialization. Aliases match in any case, like the
+/// transaction types.
+#[derive(Debug, Default)]
+pub struct TypeAliases {
+    // Keyed by the lowercase alias.
+    aliases: HashMap<String, String>,
+}
+
+impl TypeAliases {
+    pub fn new() -> TypeAliases {
+        TypeAliases::default()
+    }
+
+    /// Adds an `alias=type` mapping. Aliases already naming another type, in any case or as one of the
+    /// [`crate::csv_model::BUILTIN_TYPE_ALIASES`], targeting an unknown type or already mapped elsewhere are rejected.
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let (alias, target) = spec.split_once('=')
+            .ok_or_else(|| format!("type alias {spec} should be of the form alias=type"))?;
+
+        if alias.is_empty() {
+            return Err(format!("type alias {spec} has an empty alias"))
+        }
+        if let Some(existing) = TransactionType::from_name(alias).filter(|existing| existing.name() != target) {
+            return Err(format!("type alias {alias} collides with the transaction type {}", existing.name()))
+        }
+        if !TRANSACTION_TYPES.contains(&target) {
+            return Err(format!("type alias {alias} targets unknown transaction type {target}"))
+        }
+        if let Some(previous) = self.get(alias) {
+            if previous != target {
+                return Err(format!("type alias {alias} is mapped to both {previous} and {target}"))
+            }
+        }
+
+        self.aliases.insert(alias.to_ascii_lowercase(), target.to_string());
+        Ok(())
+    }
+
+    // The type `name` is an alias of, in any case. Most names are lowercase already and are looked up as is.
+    fn get(&self, name: &str) -> Option<&str> {
+        let target = match name.bytes().any(|b| b.is_ascii_uppercase()) {
+            true => self.aliases.get(&name.to_ascii_lowercase()),
+            false => self.aliases.get(name),
+        };
+        target.map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Canonical name for `name`, or `name` itself when it is not an alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.get(name).unwrap_or(name)
+    }
+
+    /// Returns a copy of `record` with its type column resolved, or `None` when there
+    /// is nothing to rewrite.
+    pub fn apply(&self, record: &StringRecord, type_column: usize) -> Option<StringRecord> {
+        let target = self.get(record.get(type_column)?)?;
+
+        Some(record.iter()
+            .enumerate()
+            .map(|(i, field)| if i == type_column { target } else { field })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_alias_added_should_resolve_to_canonical_type() {
+        let mut aliases = TypeAliases::new();
+
+        aliases.add("withdraw=withdrawal").unwrap();
+        aliases.add("credit=deposit").unwrap();
+
+        assert_eq!(aliases.resolve("withdraw"), "withdrawal");
+        assert_eq!(aliases.resolve("credit"), "deposit");
+        assert_eq!(aliases.resolve("deposit"), "deposit");
+        assert_eq!(aliases.resolve("payout"), "payout");
+    }
+
+    #[test]
+    fn when_alias_collides_with_canonical_type_should_reject() {
+        let mut aliases = TypeAliases::new();
+
+        let err = aliases.add("deposit=withdrawal").unwrap_err();
+
+        assert!(err.contains("collides"));
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn when_alias_names_another_type_in_any_case_should_reject() {
+        let mut aliases = TypeAliases::new();
+
+        assert!(aliases.add("DEPOSIT=withdrawal").unwrap_err().contains("collides with the transaction type deposit"));
+        assert!(aliases.add("charge_back=deposit").unwrap_err().contains("collides with the transaction type chargeback"));
+        // Naming the type it already names is harmless.
+        aliases.add("withdraw=withdrawal").unwrap();
+        assert_eq!(aliases.resolve("withdraw"), "withdrawal");
+    }
+
+    #[test]
+    fn when_alias_targets_unknown_type_should_reject() {
+        let mut aliases = TypeAliases::new();
+
+        assert!(aliases.add("payout=settlement").is_err());
+        assert!(aliases.add("payout").is_err());
+        assert!(aliases.add("=deposit").is_err());
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn when_alias_mapped_twice_to_different_types_should_reject() {
+        let mut aliases = TypeAliases::new();
+
+        aliases.add("payout=withdrawal").unwrap();
+        aliases.add("payout=withdrawal").unwrap();
+
+        assert!(aliases.add("payout=deposit").is_err());
+        assert_eq!(aliases.resolve("payout"), "withdrawal");
+    }
+
+    #[test]
+    fn aliases_should_match_in_any_case_like_the_types() {
+        let mut aliases = TypeAliases::new();
+        aliases.add("Payout=withdrawal").unwrap();
+
+        assert!(aliases.add("PAYOUT=deposit").unwrap_err().contains("mapped to both withdrawal and deposit"));
+        for name in ["payout", "Payout", "PAYOUT"] {
+            assert_eq!(TransactionType::from_name(aliases.resolve(name)), Some(TransactionType::Withdrawal), "{name}");
+        }
+        let record = StringRecord::from(vec!["Payout", "1", "2", "3.0"]);
+        assert_eq!(aliases.apply(&record, 0).unwrap(), StringRecord::from(vec!["withdrawal", "1", "2", "3.0"]));
+        // The types themselves are parsed in any case without alias.
+        assert_eq!(TransactionType::from_name(aliases.resolve("Deposit")), Some(TransactionType::Deposit));
+    }
+
+    #[test]
+    fn apply_should_rewrite_only_aliased_type_column() {
+        let mut aliases = TypeAliases::new();
+        aliases.add("withdraw=withdrawal").unwrap();
+
+        let record = StringRecord::from(vec!["withdraw", "1", "2", "3.0"]);
+        let rewritten = aliases.apply(&record, 0).unwrap();
+        assert_eq!(rewritten, StringRecord::from(vec!["withdrawal", "1", "2", "3.0"]));
+
+        let canonical = StringRecord::from(vec!["deposit", "1", "2", "3.0"]);
+        assert!(aliases.apply(&canonical, 0).is_none());
+    }
+}
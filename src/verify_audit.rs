@@ -0,0 +1,103 @@
+//! The `verify-audit` command: the accounts rebuilt from the events of an audit log, compared with the balances
+//! the log and the run report.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use serde::Deserialize;
+use transaction_engine::{Amount, AuditReplay, Client, Currency};
+
+use crate::audit::LoggedRow;
+use crate::cli::VerifyAuditArgs;
+use crate::diff::{EXIT_DIFFERENT, EXIT_TROUBLE};
+
+// A row of the balances, as written by the CSV report.
+#[derive(Deserialize)]
+struct CsvAccount {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    currency: Option<Currency>,
+}
+
+/// Prints the first divergence, nothing when the audit log and the balances agree. Returns the exit code.
+pub fn run(args: VerifyAuditArgs) -> i32 {
+    match verify(&args) {
+        Ok(None) => 0,
+        Ok(Some(divergence)) => {
+            println!("{divergence}");
+            EXIT_DIFFERENT
+        },
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            EXIT_TROUBLE
+        },
+    }
+}
+
+// The events are replayed line by line, the account of each line being checked against its balance once its
+// events are applied. The sequence number of a divergence is that of the last event of the account, or the last
+// one read when the account has none.
+fn verify(args: &VerifyAuditArgs) -> Result<Option<String>, String> {
+    let audit = File::open(&args.audit).map_err(|e| format!("cannot read {}: {e}", args.audit))?;
+    let mut replay = AuditReplay::new();
+    let mut last_seq = 0;
+    for (index, line) in BufReader::new(audit).lines().enumerate() {
+        let number = index + 1;
+        let invalid = |e: String| format!("invalid line {number} of {}: {e}", args.audit);
+        let line = line.map_err(|e| format!("cannot read {}: {e}", args.audit))?;
+        let logged: LoggedRow = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+        for event in &logged.events {
+            let event = event.event().map_err(invalid)?;
+            replay.apply(&event);
+            last_seq = event.seq;
+        }
+        if let Some(balance) = logged.balance {
+            let logged = balance.client().map_err(invalid)?;
+            let (client_id, currency) = (logged.id(), logged.currency());
+            let replayed = replay.get(client_id, currency);
+            if replayed != Some(&logged) {
+                let seq = replay.last_seq(client_id, currency).unwrap_or(last_seq);
+                return Ok(Some(format!(
+                    "divergence at seq {seq}, line {number} of {}: client {client_id} in {currency}: {} after the events, {} in the log",
+                    args.audit, describe(replayed), describe(Some(&logged)),
+                )))
+            }
+        }
+    }
+
+    let mut reader = csv::Reader::from_path(&args.accounts).map_err(|e| format!("cannot read {}: {e}", args.accounts))?;
+    let mut expected = BTreeMap::new();
+    for account in reader.deserialize() {
+        let account: CsvAccount = account.map_err(|e| format!("invalid balances in {}: {e}", args.accounts))?;
+        let currency = account.currency.unwrap_or(args.base_currency);
+        let client = Client::new(account.client, account.available, account.held, account.total, account.locked);
+        expected.insert((account.client, currency), client.in_currency(currency));
+    }
+    let replayed: BTreeMap<(u16, Currency), &Client> = replay.clients()
+        .map(|client| ((client.id(), client.currency()), client))
+        .collect();
+
+    let seq = |&(client_id, currency): &(u16, Currency)| replay.last_seq(client_id, currency).unwrap_or(last_seq);
+    let divergence = expected.keys().chain(replayed.keys())
+        .filter(|key| expected.get(key) != replayed.get(key).copied())
+        .min_by_key(|key| seq(key));
+    Ok(divergence.map(|key @ (client_id, currency)| format!(
+        "divergence at seq {}: client {client_id} in {currency}: {} after the events, {} in {}",
+        seq(key), describe(replayed.get(key).copied()), describe(expected.get(key)), args.accounts,
+    )))
+}
+
+fn describe(client: Option<&Client>) -> String {
+    match client {
+        None => "no account".to_string(),
+        Some(client) => format!(
+            "available {}, held {}, total {}{}",
+            client.available(), client.held(), client.total(), if client.is_locked() { ", locked" } else { "" },
+        ),
+    }
+}
@@ -0,0 +1,412 @@
+//! The JSON payloads written by the commands and the services: the `--stats` line, the balances of
+//! `--format json`, the metadata row of the incremental outputs, the lines of the audit log and the outcomes
+//! and errors of `serve-http`. With the `schema` feature, their JSON Schemas are written by the hidden
+//! `dump-schemas` command to `schemas/`, so that a change to them shows up there.
+
+use std::fmt;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+use transaction_engine::csv_model::RowError;
+use transaction_engine::{
+    AuditEvent, Client, Currency, EngineEvent, MemoryEstimate, Metrics, Timestamp, TransactionEngine, TransactionError,
+    TransactionStore, TxId,
+};
+
+use crate::output;
+
+/// Engine counters of a run along with its throughput, printed with `--stats`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Stats {
+    #[serde(flatten)]
+    metrics: Metrics,
+    locked_clients: usize,
+    total_funds: Number,
+    total_held: Number,
+    memory: MemoryEstimate,
+    rows: u64,
+    elapsed_seconds: f64,
+    rows_per_second: f64,
+}
+
+impl Stats {
+    /// `rows` counts every row read, including the rejected ones.
+    pub fn new<S: TransactionStore>(engine: &TransactionEngine<S>, rows: u64, elapsed: Duration) -> Stats {
+        let elapsed_seconds = elapsed.as_secs_f64();
+        let rows_per_second = if elapsed_seconds > 0.0 { rows as f64 / elapsed_seconds } else { 0.0 };
+        Stats {
+            metrics: engine.metrics(),
+            locked_clients: engine.locked_client_count(),
+            total_funds: output::number(engine.total_funds()),
+            total_held: output::number(engine.total_held()),
+            memory: engine.memory_estimate(),
+            rows,
+            elapsed_seconds,
+            rows_per_second,
+        }
+    }
+}
+
+// A single line of JSON, so that it can be told apart from the other messages on stderr.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{json}")
+    }
+}
+
+// Same fields as the CSV header, with the amounts written as JSON numbers.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct JsonClient {
+    client: u16,
+    available: Number,
+    held: Number,
+    total: Number,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+}
+
+impl JsonClient {
+    pub fn new(client: &Client, with_currency: bool) -> JsonClient {
+        JsonClient { currency: with_currency.then(|| client.currency()), ..JsonClient::from(client) }
+    }
+}
+
+impl From<&Client> for JsonClient {
+    fn from(client: &Client) -> JsonClient {
+        JsonClient {
+            client: client.id(),
+            available: output::number(client.available()),
+            held: output::number(client.held()),
+            total: output::number(client.total()),
+            locked: client.is_locked(),
+            currency: None,
+        }
+    }
+}
+
+/// The metadata row heading an incremental output, after a `#`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Manifest<'a> {
+    pub run_id: &'a str,
+    pub first_seq: u64,
+    pub last_seq: u64,
+    /// Clients listed by the output.
+    pub count: usize,
+}
+
+/// Line of the audit log, for a single input row.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AuditEntry<'a> {
+    pub line: Option<u64>,
+    pub row: Vec<&'a str>,
+    pub ts: Option<Timestamp>,
+    /// `applied`, `ignored` or `rejected`.
+    pub action: &'static str,
+    pub reason: Option<&'static str>,
+    /// Balances of the row's client in the row's currency once the row is handled, if the client has an account.
+    pub balance: Option<JsonClient>,
+    /// Changes made by the engine to the accounts, rejections aside.
+    pub events: Vec<JsonEvent>,
+}
+
+/// An [`AuditEvent`] of the audit log, the amount being written as a JSON number.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct JsonEvent {
+    seq: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    client: u16,
+    tx: TxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<Number>,
+    currency: Currency,
+}
+
+impl JsonEvent {
+    /// `None` for a rejection, which changes no account.
+    pub fn new(event: &AuditEvent) -> Option<JsonEvent> {
+        let (kind, client, tx, amount) = match event.event {
+            EngineEvent::AccountOpened { client_id, tx_id } => ("account_opened", client_id, tx_id, None),
+            EngineEvent::AccountCredited { client_id, tx_id, amount } => ("account_credited", client_id, tx_id, Some(amount)),
+            EngineEvent::AccountDebited { client_id, tx_id, amount } => ("account_debited", client_id, tx_id, Some(amount)),
+            EngineEvent::FundsHeld { client_id, tx_id, amount } => ("funds_held", client_id, tx_id, Some(amount)),
+            EngineEvent::FundsReleased { client_id, tx_id, amount } => ("funds_released", client_id, tx_id, Some(amount)),
+            EngineEvent::HeldCredited { client_id, tx_id, amount } => ("held_credited", client_id, tx_id, Some(amount)),
+            EngineEvent::HeldDebited { client_id, tx_id, amount } => ("held_debited", client_id, tx_id, Some(amount)),
+            EngineEvent::AccountLocked { client_id, tx_id } => ("account_locked", client_id, tx_id, None),
+            EngineEvent::AccountUnlocked { client_id, tx_id } => ("account_unlocked", client_id, tx_id, None),
+            EngineEvent::TransactionRejected { .. } => return None,
+        };
+        Some(JsonEvent {
+            seq: event.seq,
+            kind: kind.to_string(),
+            client,
+            tx,
+            amount: amount.map(output::number),
+            currency: event.currency,
+        })
+    }
+
+    /// The event read back from the audit log.
+    pub fn event(&self) -> Result<AuditEvent, String> {
+        let (client_id, tx_id) = (self.client, self.tx);
+        let amount = || {
+            let amount = self.amount.as_ref().ok_or(format!("{} event without amount", self.kind))?;
+            amount.as_str().parse().map_err(|e| format!("invalid amount {amount}: {e}"))
+        };
+        let event = match self.kind.as_str() {
+            "account_opened" => EngineEvent::AccountOpened { client_id, tx_id },
+            "account_credited" => EngineEvent::AccountCredited { client_id, tx_id, amount: amount()? },
+            "account_debited" => EngineEvent::AccountDebited { client_id, tx_id, amount: amount()? },
+            "funds_held" => EngineEvent::FundsHeld { client_id, tx_id, amount: amount()? },
+            "funds_released" => EngineEvent::FundsReleased { client_id, tx_id, amount: amount()? },
+            "held_credited" => EngineEvent::HeldCredited { client_id, tx_id, amount: amount()? },
+            "held_debited" => EngineEvent::HeldDebited { client_id, tx_id, amount: amount()? },
+            "account_locked" => EngineEvent::AccountLocked { client_id, tx_id },
+            "account_unlocked" => EngineEvent::AccountUnlocked { client_id, tx_id },
+            kind => return Err(format!("unknown event type {kind:?}")),
+        };
+        Ok(AuditEvent { seq: self.seq, currency: self.currency, event })
+    }
+}
+
+/// Why a request to `serve-http` failed, with the same codes as the rejection summary: the body of a rejected
+/// [`Outcome`] and of a 404.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Reason {
+    code: &'static str,
+    message: String,
+}
+
+impl From<TransactionError> for Reason {
+    fn from(e: TransactionError) -> Reason {
+        Reason { code: e.code(), message: e.to_string() }
+    }
+}
+
+/// The outcome of a transaction posted to `serve-http`: 400 for a malformed transaction, 422 for one the engine
+/// rejected, 202 for one parked by the retry queue until its tx comes.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Accepted,
+    Parked,
+    Rejected {
+        #[serde(skip)]
+        status: StatusCode,
+        #[serde(flatten)]
+        reason: Reason,
+    },
+}
+
+impl Outcome {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Outcome::Accepted => StatusCode::OK,
+            Outcome::Parked => StatusCode::ACCEPTED,
+            Outcome::Rejected { status, .. } => *status,
+        }
+    }
+}
+
+impl From<RowError> for Outcome {
+    fn from(e: RowError) -> Outcome {
+        let reason = Reason { code: e.code(), message: e.to_string() };
+        Outcome::Rejected { status: StatusCode::BAD_REQUEST, reason }
+    }
+}
+
+impl From<TransactionError> for Outcome {
+    fn from(e: TransactionError) -> Outcome {
+        Outcome::Rejected { status: StatusCode::UNPROCESSABLE_ENTITY, reason: Reason::from(e) }
+    }
+}
+
+/// The schema of each payload with the name of its file, `GET /stats` of `serve-http` answering the
+/// [`Metrics`] of the engine.
+#[cfg(feature = "schema")]
+pub fn schemas() -> Vec<(&'static str, schemars::Schema)> {
+    vec![
+        ("stats", schemars::schema_for!(Stats)),
+        ("metrics", schemars::schema_for!(Metrics)),
+        ("client", schemars::schema_for!(JsonClient)),
+        ("manifest", schemars::schema_for!(Manifest<'static>)),
+        ("audit_entry", schemars::schema_for!(AuditEntry<'static>)),
+        ("outcome", schemars::schema_for!(Outcome)),
+        ("error", schemars::schema_for!(Reason)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction_engine::Transaction;
+
+    #[test]
+    fn stats_should_write_the_counters_and_throughput_as_json() {
+        let mut engine = TransactionEngine::new();
+        engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: "10.5".parse().unwrap() }).unwrap();
+        engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 2, amount: "2".parse().unwrap() }).unwrap();
+        engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 2 }).unwrap();
+        let stats = Stats::new(&engine, 10, Duration::from_millis(500));
+        let json: serde_json::Value = serde_json::from_str(&stats.to_string()).unwrap();
+
+        assert_eq!(json["deposit"]["applied"], 2);
+        assert_eq!(json["open_disputes"], 1);
+        assert_eq!(json["clients"], 2);
+        assert_eq!(json["locked_clients"], 0);
+        assert_eq!(json["total_funds"].to_string(), "12.5");
+        assert_eq!(json["total_held"].to_string(), "2.0");
+        assert_eq!(json["memory"]["transactions"], 2);
+        assert_eq!(json["memory"]["transaction_bytes"], engine.memory_estimate().transaction_bytes);
+        assert_eq!(json["rows"], 10);
+        assert_eq!(json["rows_per_second"].to_string(), "20.0");
+    }
+
+    #[test]
+    fn when_nothing_was_timed_stats_should_report_no_throughput() {
+        let stats = Stats::new(&TransactionEngine::new(), 10, Duration::ZERO);
+
+        assert_eq!(stats.rows_per_second, 0.0);
+    }
+
+    #[cfg(feature = "schema")]
+    mod schema {
+        use std::collections::HashSet;
+
+        use csv::StringRecord;
+        use serde_json::{json, Value};
+        use transaction_engine::Changes;
+
+        use super::*;
+        use crate::audit::Audit;
+        use crate::rejection::Rejection;
+
+        // Compiles `schema`, its patterns included, to validate the payloads of the 2020-12 draft it declares.
+        fn validator(schema: &Value) -> jsonschema::Validator {
+            jsonschema::draft202012::new(schema).unwrap_or_else(|e| panic!("{schema} does not compile: {e}"))
+        }
+
+        fn schema(name: &str) -> Value {
+            let (_, schema) = schemas().into_iter().find(|(schema, _)| *schema == name).unwrap();
+            schema.to_value()
+        }
+
+        fn assert_valid(name: &str, payload: impl Serialize) {
+            let validator = validator(&schema(name));
+            let payload = serde_json::to_value(payload).unwrap();
+            if let Err(e) = validator.validate(&payload) {
+                panic!("{payload} is not a valid {name}: {e}");
+            }
+        }
+
+        // A few transactions of every kind, a client being locked and another one having a disputed deposit.
+        fn engine() -> TransactionEngine {
+            let mut engine = TransactionEngine::new();
+            engine.track_changes();
+            let amount = "1.5".parse().unwrap();
+            for transaction in [
+                Transaction::Deposit { client_id: 1, tx_id: 1, amount },
+                Transaction::Deposit { client_id: 2, tx_id: 2, amount },
+                Transaction::Dispute { client_id: 2, tx_id: 2 },
+                Transaction::Deposit { client_id: 3, tx_id: 3, amount },
+                Transaction::Dispute { client_id: 3, tx_id: 3 },
+                Transaction::Chargeback { client_id: 3, tx_id: 3 },
+            ] {
+                engine.compute_transaction(transaction).unwrap();
+            }
+            let _ = engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 4, amount: "9".parse().unwrap() });
+            engine
+        }
+
+        #[test]
+        fn the_schemas_should_be_valid() {
+            let names: Vec<&str> = schemas().iter().map(|(name, _)| *name).collect();
+            assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+            for (name, schema) in schemas() {
+                let schema = schema.to_value();
+                assert_eq!(schema["$schema"], "https://json-schema.org/draft/2020-12/schema", "{name}");
+                jsonschema::draft202012::meta::validate(&schema).unwrap_or_else(|e| panic!("{name}: {e}"));
+                validator(&schema);
+            }
+        }
+
+        #[test]
+        fn the_validation_should_reject_what_the_schemas_forbid() {
+            let client = validator(&schema("client"));
+            let valid = json!({"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false});
+            assert!(client.is_valid(&valid));
+
+            for invalid in [
+                json!({"client": 1, "available": 1.5, "held": 0.0, "total": 1.5}),
+                json!({"client": 70000, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false}),
+                json!({"client": 1, "available": "1.5", "held": 0.0, "total": 1.5, "locked": false}),
+                json!({"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false, "currency": 1}),
+                json!({"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false, "currency": "usd"}),
+            ] {
+                assert!(!client.is_valid(&invalid), "{invalid}");
+            }
+            let outcome = validator(&schema("outcome"));
+            assert!(!outcome.is_valid(&json!({"status": "refused"})));
+        }
+
+        #[test]
+        fn the_payloads_should_match_their_schemas() {
+            let mut engine = engine();
+
+            assert_valid("stats", Stats::new(&engine, 7, Duration::from_millis(3)));
+            assert_valid("metrics", engine.metrics());
+            for client in engine.clients() {
+                assert_valid("client", JsonClient::from(client));
+                assert_valid("client", JsonClient::new(client, true));
+            }
+            let Changes { clients, first_seq, last_seq } = engine.take_changes().unwrap();
+            assert_valid("manifest", Manifest { run_id: "run", first_seq, last_seq, count: clients.len() });
+            assert_valid("outcome", Outcome::Accepted);
+            assert_valid("outcome", Outcome::Parked);
+            assert_valid("outcome", Outcome::from(TransactionError::UnknownTransaction { tx_id: 9 }));
+            assert_valid("outcome", Outcome::from(RowError::Parse("bad".to_string())));
+            assert_valid("error", Reason::from(TransactionError::UnknownClient { client_id: 9 }));
+        }
+
+        #[test]
+        fn the_audit_log_should_match_its_schema() {
+            let mut output = vec![];
+            let mut audit = Audit::from_writer(&mut output);
+            let mut engine = TransactionEngine::new();
+            engine.set_observer(audit.observer());
+            let rows = [
+                (Transaction::Deposit { client_id: 1, tx_id: 1, amount: "1.5".parse().unwrap() }, "deposit,1,1,1.5"),
+                (Transaction::Dispute { client_id: 1, tx_id: 1 }, "dispute,1,1,"),
+                (Transaction::Chargeback { client_id: 1, tx_id: 1 }, "chargeback,1,1,"),
+                (Transaction::Withdrawal { client_id: 2, tx_id: 2, amount: "1".parse().unwrap() }, "withdrawal,2,2,1"),
+            ];
+            for (transaction, row) in rows {
+                let rejection = engine.compute_transaction(transaction).err().map(Rejection::Engine);
+                let record = StringRecord::from(row.split(',').collect::<Vec<_>>());
+                let ts = Some(Timestamp::from_millis(1_700_000_000_000));
+                audit.write(&record, ts, rejection.as_ref(), engine.get_client(transaction.client_id()), Currency::USD).unwrap();
+            }
+            audit.write(&StringRecord::from(vec!["deposit", "x"]), None, Some(&Rejection::Row(RowError::Parse("bad".to_string()))), None, Currency::USD).unwrap();
+            audit.flush().unwrap();
+            drop(audit);
+
+            let lines = String::from_utf8(output).unwrap();
+            assert_eq!(lines.lines().count(), 5);
+            for line in lines.lines() {
+                assert_valid("audit_entry", serde_json::from_str::<Value>(line).unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+#![cfg(all(feature = "arrow", feature = "cli"))]
+
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Decimal128Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt16Array, UInt64Array};
+use arrow::ipc::writer::FileWriter;
+use assert_cmd::prelude::*;
+use transaction_engine::{Amount, Timestamp};
+
+const BASE_PATH: &str = "/resources/tests";
+
+fn get_base_path () -> String {
+    let path = env!("CARGO_MANIFEST_DIR").to_string();
+    path + BASE_PATH
+}
+
+// Fixtures whose every field converts to its Arrow type.
+const FIXTURES: [&str; 22] = [
+    "adjustment", "chargeback", "cross_client", "deposit", "dispute", "fraud", "large_tx", "locked_dispute",
+    "multi_currency", "multi_day1", "non_positive", "out_of_order", "policies", "redispute", "resolve", "sentinel", "timestamp",
+    "transfer", "type_case", "unlock", "withdrawal_dispute", "withdrawal_held",
+];
+
+// Writes the rows of a CSV fixture to an Arrow file with typed columns: integers for the ids, decimals with four
+// decimal places for the amounts and millisecond timestamps. An empty field is a null.
+fn write_arrow(csv: &Path, arrow: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(csv)?;
+    let headers = reader.headers()?.clone();
+    let rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+    let field = |name: &str| {
+        let column = headers.iter().position(|header| header == name);
+        rows.iter().map(move |row| column.and_then(|column| row.get(column)).filter(|field| !field.is_empty()))
+    };
+
+    let mut columns: Vec<(&str, ArrayRef)> = vec![
+        ("type", Arc::new(field("type").collect::<StringArray>())),
+        ("client", Arc::new(field("client").map(|id| id.map(str::parse).transpose()).collect::<Result<UInt16Array, _>>()?)),
+        ("tx", Arc::new(field("tx").map(|id| id.map(str::parse).transpose()).collect::<Result<UInt64Array, _>>()?)),
+        ("amount", Arc::new(
+            field("amount")
+                .map(|amount| amount.map(|amount| amount.parse::<Amount>().map(|amount| i128::from(amount.units()))).transpose())
+                .collect::<Result<Decimal128Array, _>>()?
+                .with_precision_and_scale(38, 4)?,
+        )),
+    ];
+    if headers.iter().any(|header| header == "to_client") {
+        let to_clients = field("to_client").map(|id| id.map(str::parse).transpose()).collect::<Result<UInt16Array, _>>()?;
+        columns.push(("to_client", Arc::new(to_clients)));
+    }
+    if headers.iter().any(|header| header == "ts") {
+        let ts = field("ts")
+            .map(|ts| ts.map(|ts| ts.parse::<Timestamp>().map(Timestamp::millis)).transpose())
+            .collect::<Result<TimestampMillisecondArray, _>>()?;
+        columns.push(("ts", Arc::new(ts)));
+    }
+    if headers.iter().any(|header| header == "currency") {
+        columns.push(("currency", Arc::new(field("currency").collect::<StringArray>())));
+    }
+
+    let batch = RecordBatch::try_from_iter_with_nullable(columns.into_iter().map(|(name, column)| (name, column, true)))?;
+    let mut writer = FileWriter::try_new(std::fs::File::create(arrow)?, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn run(path: &Path, args: &[&str]) -> Result<Output, Box<dyn std::error::Error>> {
+    Ok(Command::cargo_bin("transaction_engine")?.arg(path).args(args).output()?)
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output).lines().map(str::to_string).collect();
+    lines.sort();
+    lines
+}
+
+// The run summary, without the messages of the rejected rows that differ between the formats.
+fn summary(stderr: &[u8]) -> Vec<String> {
+    sorted_lines(stderr).into_iter().filter(|line| line.starts_with("read:") || line.starts_with("rejected:")).collect()
+}
+
+#[test]
+fn arrow_input_should_match_the_csv_run() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    for fixture in FIXTURES {
+        let csv = Path::new(&get_base_path()).join(format!("{fixture}.csv"));
+        let arrow = dir.path().join(format!("{fixture}.arrow"));
+        write_arrow(&csv, &arrow)?;
+        // Checks the order of the rows as well, on the fixtures having timestamps.
+        let args: &[&str] = match fixture {
+            "out_of_order" | "timestamp" => &["--detect-out-of-order"],
+            _ => &[],
+        };
+
+        let (expected, actual) = (run(&csv, args)?, run(&arrow, args)?);
+        assert_eq!(actual.status.code(), expected.status.code(), "{fixture}");
+        assert_eq!(sorted_lines(&actual.stdout), sorted_lines(&expected.stdout), "{fixture}");
+        assert_eq!(summary(&actual.stderr), summary(&expected.stderr), "{fixture}");
+
+        let sharded = run(&arrow, &[args, &["--threads", "2"]].concat())?;
+        assert_eq!(sorted_lines(&sharded.stdout), sorted_lines(&expected.stdout), "{fixture} with --threads");
+    }
+    Ok(())
+}
+
+#[test]
+fn arrow_null_amount_should_be_rejected_as_missing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let csv = dir.path().join("missing_amount.csv");
+    std::fs::write(&csv, "type,client,tx,amount\ndeposit,1,1,2.5\ndeposit,1,2,\nwithdrawal,1,3,\ndispute,1,1,\n")?;
+    let arrow = dir.path().join("missing_amount.arrow");
+    write_arrow(&csv, &arrow)?;
+    let rejected = dir.path().join("rejected.csv");
+
+    let output = run(&arrow, &["--rejected", rejected.to_str().ok_or("path")?])?;
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(&output.stdout), vec!["1,0.0,2.5,2.5,false", "client,available,held,total,locked"]);
+    assert_eq!(summary(&output.stderr), vec!["read: 4, applied: 2", "rejected: 2 (missing_amount: 2)"]);
+    let rejected = std::fs::read_to_string(rejected)?;
+    assert!(rejected.contains("deposit,1,2,,missing amount"), "{rejected}");
+
+    let strict = run(&arrow, &["--strict"])?;
+    assert_eq!(strict.status.code(), Some(3));
+    assert!(String::from_utf8(strict.stderr)?.contains("malformed row at line 2 of"));
+    Ok(())
+}
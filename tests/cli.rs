@@ -1,8 +1,11 @@
+#![cfg(feature = "cli")]
+// The baseline helpers below keep the style they were written in.
+#![allow(clippy::needless_return, clippy::result_filter_map)]
+
 use assert_cmd::prelude::*; // Add methods on commands
 use predicates::prelude::*;
-use serde::{Deserialize, Serialize}; // Used for writing assertions
 use std::process::Command; // Run programs
-use std::cmp::Ordering;
+use transaction_engine::Client;
 
 const BASE_PATH: &str = "/resources/tests";
 
@@ -18,10 +21,343 @@ fn deposit() -> Result<(), Box<dyn std::error::Error>> {
     cmd.arg(get_base_path() + "/deposit.csv");
 
     let mut expected = vec![
-        Client{client: 2,available: 2.0,held: 0.0,total: 2.0,locked: false},
-        Client{client: 1,available: 3.0,held: 0.0,total: 3.0,locked: false},
+        client(2, "2.0", "0.0", "2.0", false),
+        client(1, "3.0", "0.0", "3.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn deposit_tsv() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.tsv");
+
+    let mut expected = vec![
+        client(2, "2.0", "0.0", "2.0", false),
+        client(1, "3.0", "0.0", "3.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn delimiter() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit_semicolon.csv")
+        .args(["--delimiter", ";", "--output-delimiter", "\\t"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("client\tavailable\theld\ttotal\tlocked\n"))
+        .stdout(predicate::str::contains("1\t3.0\t0.0\t3.0\tfalse\n"))
+        .stdout(predicate::str::contains("2\t2.0\t0.0\t2.0\tfalse\n"))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn compressed() -> Result<(), Box<dyn std::error::Error>> {
+    for file in ["/dispute.csv.gz", "/dispute.csv.zst"] {
+        let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+        cmd.arg(get_base_path() + file);
+
+        let mut expected = vec![
+            client(2, "-40.0", "50.0", "10.0", false),
+            client(3, "0.0", "50.0", "50.0", false),
+            client(1, "5.0", "0.0", "5.0", false),
+        ];
+        expected.sort_by_key(Client::id);
+
+        cmd.assert()
+            .success()
+            .stdout(predicate::function(compare_stdout(expected)))
+            .stderr(predicate::str::diff("read: 9, applied: 8\nrejected: 1 (insufficient_funds: 1)\n"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn compressed_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg("-")
+        .write_stdin(std::fs::read(get_base_path() + "/dispute.csv.gz")?);
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn corrupt_compressed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/corrupt.csv.gz");
+
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot read").and(predicate::str::contains("corrupt.csv.gz")));
+
+    Ok(())
+}
+
+#[test]
+fn padded_fields() -> Result<(), Box<dyn std::error::Error>> {
+    for file in ["/padded.csv", "/padded_clean.csv"] {
+        let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+        cmd.arg(get_base_path() + file);
+
+        let mut expected = vec![
+            client(1, "40.0", "2.5", "42.5", false),
+            client(2, "20.0", "0.0", "20.0", false),
+        ];
+        expected.sort_by_key(Client::id);
+
+        cmd.assert()
+            .success()
+            .stdout(predicate::function(compare_stdout(expected)))
+            .stderr(predicate::str::is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn reordered_columns() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/reordered.csv");
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::diff("read: 9, applied: 8\nrejected: 1 (insufficient_funds: 1)\n"));
+
+    Ok(())
+}
+
+#[test]
+fn missing_column() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/missing_column.csv");
+
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("missing_column.csv has no client column"));
+
+    Ok(())
+}
+
+#[test]
+fn stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg("-")
+        .write_stdin(std::fs::read(get_base_path() + "/dispute.csv")?);
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn multiple_files() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/multi_day2.csv");
+
+    let mut expected = vec![
+        client(1, "-10.0", "0.0", "-10.0", true),
+        client(2, "5.0", "20.0", "25.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn multiple_files_missing_one() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/missing.csv");
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot open").and(predicate::str::contains("missing.csv")));
+
+    Ok(())
+}
+
+#[test]
+fn output_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("clients.csv");
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv")
+        .arg("-o")
+        .arg(&output);
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    assert!(compare_stdout(expected)(&std::fs::read(&output)?));
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn output_file_in_missing_dir() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv")
+        .arg("--output")
+        .arg(dir.path().join("missing").join("clients.csv"));
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot create"));
+
+    Ok(())
+}
+
+#[test]
+fn json_format() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv")
+        .args(["--format", "json"]);
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let clients: Vec<serde_json::Value> = serde_json::from_slice(&output)?;
+    let mut clients: Vec<Client> = clients.iter().map(json_client).collect();
+    clients.sort_by_key(Client::id);
+
+    assert_eq!(clients, expected);
+
+    Ok(())
+}
+
+#[test]
+fn ndjson_format() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/precision.csv")
+        .args(["--format", "ndjson"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("{\"client\":1,\"available\":12345.6789,\"held\":0.0,\"total\":12345.6789,\"locked\":false}\n"));
+
+    Ok(())
+}
+
+#[test]
+fn ndjson_input() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.ndjson");
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("NDJSON error: line 5"));
+
+    Ok(())
+}
+
+#[test]
+fn ndjson_input_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("transaction_engine")?;
+
+    cmd.args(["-", "--input-format", "ndjson"])
+        .write_stdin(std::fs::read(get_base_path() + "/dispute.ndjson")?);
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
     ];
-    expected.sort();
+    expected.sort_by_key(Client::id);
 
     cmd.assert()
         .success()
@@ -37,10 +373,10 @@ fn withdrawal() -> Result<(), Box<dyn std::error::Error>> {
     cmd.arg(get_base_path() + "/withdrawal.csv");
 
     let mut expected = vec![
-        Client{client: 2,available: 10.0,held: 0.0,total: 10.0,locked: false},
-        Client{client: 1,available: 5.0,held: 0.0,total: 5.0,locked: false},
+        client(2, "10.0", "0.0", "10.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
     ];
-    expected.sort();
+    expected.sort_by_key(Client::id);
 
     cmd.assert()
         .success()
@@ -55,11 +391,11 @@ fn dispute() -> Result<(), Box<dyn std::error::Error>> {
     cmd.arg(get_base_path() + "/dispute.csv");
 
     let mut expected = vec![
-        Client{client: 2,available: -40.0,held: 50.0,total: 10.0,locked: false},
-        Client{client: 3,available: 0.0,held: 50.0,total: 50.0,locked: false},
-        Client{client: 1,available:5.0,held: 0.0,total: 5.0,locked: false},
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
     ];
-    expected.sort();
+    expected.sort_by_key(Client::id);
 
     cmd.assert()
         .success()
@@ -68,6 +404,43 @@ fn dispute() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn reject_disputes_exceeding_available() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv").arg("--reject-disputes-exceeding-available");
+
+    let mut expected = vec![
+        client(2, "10.0", "0.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("dispute_exceeds_available: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn max_disputes_per_tx() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/redispute.csv").args(["--max-disputes-per-tx", "2"]);
+
+    let expected = vec![client(1, "10.0", "0.0", "10.0", false)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("dispute_limit_reached: 1"));
+
+    Ok(())
+}
+
 #[test]
 fn resolve() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("transaction_engine")?;
@@ -75,9 +448,9 @@ fn resolve() -> Result<(), Box<dyn std::error::Error>> {
     cmd.arg(get_base_path() + "/resolve.csv");
 
     let mut expected = vec![
-        Client{client: 1,available:75.0,held: 50.0,total: 125.0,locked: false},
+        client(1, "75.0", "50.0", "125.0", false),
     ];
-    expected.sort();
+    expected.sort_by_key(Client::id);
 
     cmd.assert()
         .success()
@@ -92,9 +465,9 @@ fn chargeback() -> Result<(), Box<dyn std::error::Error>> {
     cmd.arg(get_base_path() + "/chargeback.csv");
 
     let mut expected = vec![
-        Client{client: 1,available:35.0,held: 50.0,total: 85.0,locked: true},
+        client(1, "35.0", "50.0", "85.0", true),
     ];
-    expected.sort();
+    expected.sort_by_key(Client::id);
 
     cmd.assert()
         .success()
@@ -104,57 +477,2184 @@ fn chargeback() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
-fn missing_arg() -> Result<(), Box<dyn std::error::Error>> {
+fn large_tx() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("transaction_engine")?;
 
+    // Tx 705032704 is 5000000000 truncated to 32 bits, a separate deposit that stays undisputed.
+    cmd.arg(get_base_path() + "/large_tx.csv");
+
+    let mut expected = vec![
+        client(1, "3.0", "0.0", "3.0", true),
+    ];
+    expected.sort_by_key(Client::id);
+
     cmd.assert()
-        .failure();
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
 
     Ok(())
 }
 
-fn compare_stdout(expected: Vec<Client>) -> impl Fn(&[u8]) -> bool {
-    move |x: &[u8]| {
-        let mut rdr = csv::Reader::from_reader(x);
-        let mut clients : Vec<Client> = rdr.deserialize()
-            .filter(|client: &Result<Client, csv::Error>| client.is_ok())
-            .map(|client|{
-                client.unwrap()
-            })
-            .collect();
-        clients.sort();
+#[test]
+fn unlock() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
 
-        expected == clients
-    }
-}
+    cmd.arg(get_base_path() + "/unlock.csv");
 
-#[derive(Clone,Copy,Debug, Deserialize, Serialize, PartialOrd)]
-struct Client {
-    client: u32,
-    available: f64,
-    held: f64,
-    total: f64,
-    locked: bool
-}
+    let mut expected = vec![
+        client(1, "55.0", "0.0", "55.0", false),
+        client(2, "3.0", "0.0", "3.0", false),
+    ];
+    expected.sort_by_key(Client::id);
 
-impl std::cmp::Ord for Client {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        if self.client > other.client {
-            Ordering::Greater
-        } else {
-            Ordering::Less
-        }
-    }
-}
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("account_locked: 1"))
+        .stderr(predicate::str::contains("not_locked: 1"))
+        .stderr(predicate::str::contains("unknown_client: 1"));
 
-impl PartialEq for Client {
-    fn eq(&self, other: &Self) -> bool {
-        self.client == other.client &&
-        self.available == other.available &&
-        self.locked == other.locked &&
-        self.held == other.held &&
-        self.total == other.total
-    }
+    Ok(())
 }
 
-impl Eq for Client {}
\ No newline at end of file
+#[test]
+fn transfer() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/transfer.csv");
+
+    let mut expected = vec![
+        client(1, "30.0", "0.0", "30.0", false),
+        client(2, "30.0", "0.0", "30.0", false),
+        client(3, "0.0", "0.0", "0.0", true),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("insufficient_funds: 1"))
+        .stderr(predicate::str::contains("account_locked: 1"))
+        .stderr(predicate::str::contains("missing_to_client: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn multi_currency() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/multi_currency.csv");
+
+    // The dispute without a currency is of the USD account, not of the EUR deposit.
+    cmd.assert()
+        .success()
+        .stdout(concat!(
+            "client,available,held,total,locked,currency\n",
+            "1,0.0,0.0,0.0,true,EUR\n",
+            "1,9.0,0.0,9.0,false,USD\n",
+            "2,3.0,0.0,3.0,false,GBP\n",
+        ))
+        .stderr(predicate::str::contains("currency_mismatch: 1"))
+        .stderr(predicate::str::contains("account_locked: 1"));
+
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/multi_currency.csv").args(["--base-currency", "eur", "--quiet"]);
+
+    cmd.assert()
+        .success()
+        .stdout(concat!(
+            "client,available,held,total,locked,currency\n",
+            "1,10.0,0.0,10.0,true,EUR\n",
+            "1,0.0,0.0,0.0,false,USD\n",
+            "2,3.0,0.0,3.0,false,GBP\n",
+        ));
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_currency.csv")
+        .args(["--base-currency", "EURO"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid currency \"EURO\", expected a 3-letter code"));
+
+    Ok(())
+}
+
+#[test]
+fn credit_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/overdraft.csv").args(["--credit-limit", "5.0"]);
+
+    // The second withdrawal of client 1 would take it past its limit.
+    let expected = vec![
+        client(1, "-5.0", "0.0", "-5.0", false),
+        client(2, "-0.5", "0.0", "-0.5", false),
+    ];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("insufficient_funds: 1"));
+
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/overdraft.csv");
+
+    let expected = vec![
+        client(1, "9.0", "0.0", "9.0", false),
+        client(2, "1.0", "0.0", "1.0", false),
+    ];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("insufficient_funds: 2"));
+
+    Ok(())
+}
+
+#[test]
+fn adjustment() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/adjustment.csv");
+
+    let mut expected = vec![
+        client(1, "2.5", "0.0", "2.5", true),
+        client(5, "-2.5", "0.0", "-2.5", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("not_disputable: 1"))
+        .stderr(predicate::str::contains("missing_amount: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn reject_locked_adjustments() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/adjustment.csv").arg("--reject-locked-adjustments");
+
+    let mut expected = vec![
+        client(1, "0.0", "0.0", "0.0", true),
+        client(5, "-2.5", "0.0", "-2.5", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("account_locked: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn locked_disputes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/locked_dispute.csv");
+
+    let expected = vec![client(1, "1.0", "0.0", "1.0", true)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn reject_locked_disputes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/locked_dispute.csv").arg("--reject-locked-disputes");
+
+    let expected = vec![client(1, "1.0", "4.0", "5.0", true)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("account_locked: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn allow_locked_deposits() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/policies.csv").arg("--allow-locked-deposits");
+
+    let expected = vec![
+        client(1, "3.0", "0.0", "3.0", true),
+        client(2, "0.0", "5.0", "5.0", false),
+    ];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn max_stored_transactions() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/policies.csv").args(["--max-stored-transactions", "1"]);
+
+    let expected = vec![
+        client(1, "0.0", "0.0", "0.0", true),
+        client(2, "5.0", "0.0", "5.0", false),
+    ];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("unknown_transaction: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn non_positive_amounts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/non_positive.csv");
+
+    let mut expected = vec![
+        client(1, "50.0", "0.0", "50.0", false),
+        client(2, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn withdrawal_held() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/withdrawal_held.csv");
+
+    let mut expected = vec![
+        client(1, "0.0", "100.0", "100.0", false),
+        client(2, "0.0", "50.0", "50.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn withdrawal_dispute() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/withdrawal_dispute.csv");
+
+    let mut expected = vec![
+        client(1, "100.0", "0.0", "100.0", true),
+        client(2, "10.0", "10.0", "20.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn cross_client() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/cross_client.csv");
+
+    let mut expected = vec![
+        client(1, "0.0", "50.0", "50.0", false),
+        client(2, "30.0", "0.0", "30.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn client_out_of_range() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let rejected = dir.path().join("rejected.csv");
+    let output = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/client_out_of_range.csv")
+        .arg("--rejected")
+        .arg(&rejected)
+        .output()?;
+
+    // Client 4464 is 70000 truncated to 16 bits, and 34464 would be 100000: no row reaches them.
+    let mut expected = vec![
+        client(1, "10.0", "0.0", "10.0", false),
+        client(4464, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+    assert!(output.status.success());
+    assert!(compare_stdout(expected)(&output.stdout));
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("Application error: client id 70000 is out of range, the maximum is 65535 at line 3 of"), "{stderr}");
+    assert!(stderr.contains("Application error: client id 100000 is out of range, the maximum is 65535 at line 6 of"), "{stderr}");
+    assert!(stderr.contains("read: 5, applied: 2\nrejected: 3 (client_out_of_range: 3)"), "{stderr}");
+    let rejected = std::fs::read_to_string(rejected)?;
+    assert!(rejected.contains("deposit,100000,4,7.0,\"client id 100000 is out of range"), "{rejected}");
+
+    Ok(())
+}
+
+#[test]
+fn precision() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/precision.csv");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("client,available,held,total,locked\n1,12345.6789,0.0,12345.6789,false\n"))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn strict_precision() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/precision.csv")
+        .arg("--strict-precision");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("client,available,held,total,locked\n1,12345.679,0.0,12345.679,false\n"))
+        .stderr(predicate::str::contains("amount 0.00005 has more than 4 decimal places on tx 3 at line 4 of "));
+
+    Ok(())
+}
+
+#[test]
+fn rejection_summary() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/rejected.csv");
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::ends_with(
+            "read: 8, applied: 3\nrejected: 5 (parse_error: 2, insufficient_funds: 1, missing_amount: 1, unknown_transaction: 1)\n"
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn no_rejection_summary_when_everything_applied() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.csv");
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn stats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv").arg("--stats");
+
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    let line = stderr.lines().last().unwrap();
+    let stats: serde_json::Value = serde_json::from_str(line)?;
+
+    assert_eq!(stats["deposit"], serde_json::json!({"applied": 3, "rejected": 0}));
+    assert_eq!(stats["withdrawal"], serde_json::json!({"applied": 3, "rejected": 1}));
+    assert_eq!(stats["dispute"], serde_json::json!({"applied": 2, "rejected": 0}));
+    assert_eq!(stats["open_disputes"], 2);
+    assert_eq!(stats["stored_transactions"], 6);
+    assert_eq!(stats["clients"], 3);
+    assert_eq!(stats["locked_clients"], 0);
+    assert_eq!(stats["memory"]["transactions"], 6);
+    assert_eq!(stats["total_funds"].to_string(), "65.0");
+    assert_eq!(stats["total_held"].to_string(), "100.0");
+    assert_eq!(stats["rows"], 9);
+    assert!(stats["elapsed_seconds"].is_number());
+    assert!(stats["rows_per_second"].is_number());
+
+    Ok(())
+}
+
+#[test]
+fn progress_keeps_stdout_unchanged() -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_base_path() + "/dispute.csv";
+    let without = Command::cargo_bin("transaction_engine")?.arg(&path).output()?;
+    let with = Command::cargo_bin("transaction_engine")?.arg(&path).arg("--progress").output()?;
+
+    // Clients are written in no particular order.
+    let sorted = |stdout: Vec<u8>| -> Result<Vec<String>, std::string::FromUtf8Error> {
+        let mut lines: Vec<String> = String::from_utf8(stdout)?.lines().map(str::to_string).collect();
+        lines.sort();
+        Ok(lines)
+    };
+
+    assert!(with.status.success());
+    assert_eq!(sorted(with.stdout)?, sorted(without.stdout)?);
+    // stderr is not a terminal here, so no progress line is written.
+    assert_eq!(with.stderr, without.stderr);
+
+    Ok(())
+}
+
+#[test]
+fn verbose_json_logs() -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_base_path() + "/dispute.csv";
+    let without = Command::cargo_bin("transaction_engine")?.arg(&path).output()?;
+    let with = Command::cargo_bin("transaction_engine")?.arg(&path).args(["-vv", "--log-format", "json"]).output()?;
+
+    assert!(with.status.success());
+    assert_eq!(sorted_lines(&with.stdout), sorted_lines(&without.stdout));
+    // The logs are JSON lines on stderr, followed by the summary.
+    let stderr = String::from_utf8(with.stderr)?;
+    let logs = stderr.lines()
+        .take_while(|line| line.starts_with('{'))
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+    assert_eq!(logs.len(), 10, "{stderr}");
+    assert!(stderr.ends_with("read: 9, applied: 8\nrejected: 1 (insufficient_funds: 1)\n"), "{stderr}");
+
+    fn text(value: &serde_json::Value) -> &str {
+        value.as_str().unwrap_or_default()
+    }
+    let decisions: Vec<(&str, &str, &str)> = logs.iter()
+        .filter(|log| log["span"]["name"] == "transaction")
+        .map(|log| (text(&log["span"]["type"]), text(&log["span"]["amount"]), text(&log["fields"]["decision"])))
+        .collect();
+    assert_eq!(decisions[4], ("withdrawal", "60.0", "rejected"));
+    assert_eq!(decisions[8], ("dispute", "50.0", "applied"));
+    assert_eq!(logs[9]["fields"]["message"], "input processed");
+    assert_eq!((&logs[9]["fields"]["read"], &logs[9]["fields"]["applied"]), (&serde_json::json!(9), &serde_json::json!(8)));
+
+    // Only the summary of the input with a single -v, nothing by default.
+    let info = Command::cargo_bin("transaction_engine")?.arg(&path).arg("-v").output()?;
+    let info = String::from_utf8(info.stderr)?;
+    assert!(info.lines().next().is_some_and(|line| line.contains("INFO") && line.contains("input processed")), "{info}");
+    assert_eq!(without.stderr, b"read: 9, applied: 8\nrejected: 1 (insufficient_funds: 1)\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    // Large enough to still be processing when the signal arrives.
+    let mut input = tempfile::NamedTempFile::new()?;
+    {
+        let mut writer = std::io::BufWriter::new(input.as_file_mut());
+        writeln!(writer, "type,client,tx,amount")?;
+        for tx in 1..=5_000_000 {
+            writeln!(writer, "deposit,{},{tx},1.0", tx % 100)?;
+        }
+    }
+
+    let child = Command::cargo_bin("transaction_engine")?
+        .arg(input.path())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    Command::new("kill").arg("-INT").arg(child.id().to_string()).status()?;
+    let output = child.wait_with_output()?;
+
+    assert_eq!(output.status.code(), Some(130));
+    assert!(String::from_utf8(output.stderr)?.contains("Interrupted after "));
+    let mut rdr = csv::Reader::from_reader(output.stdout.as_slice());
+    let clients: Vec<Client> = rdr.deserialize().collect::<Result<_, _>>()?;
+    assert!(!clients.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn disputes_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let disputes = dir.path().join("disputes.csv");
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv")
+        .arg("--disputes")
+        .arg(&disputes);
+
+    cmd.assert().success();
+
+    assert_eq!(std::fs::read_to_string(&disputes)?, "tx,client,amount\n4,2,50.0\n7,3,50.0\n");
+
+    Ok(())
+}
+
+#[test]
+fn flag_report() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let report = dir.path().join("flagged.csv");
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/fraud.csv")
+        .arg("--flag-report")
+        .arg(&report)
+        .args(["--flag-min-chargebacks", "3"]);
+
+    cmd.assert().success();
+
+    assert_eq!(std::fs::read_to_string(&report)?, "client,disputes,chargebacks,charged_back\n2,3,3,7.5\n");
+
+    Ok(())
+}
+
+#[test]
+fn compact() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/compact.csv").arg("--compact");
+
+    // The dispute of tx 3 is ignored once the locked account is compacted.
+    let expected = vec![client(1, "1.0", "0.0", "1.0", true)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("compacted: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn verify() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv").arg("--verify");
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Invariant").not());
+
+    Ok(())
+}
+
+#[test]
+fn resume_from_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let snapshot = dir.path().join("day1.snapshot");
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .assert()
+        .success();
+
+    let resumed = Command::cargo_bin("transaction_engine")?
+        .arg("--resume")
+        .arg(&snapshot)
+        .arg(get_base_path() + "/multi_day2.csv")
+        .output()?;
+    let single = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/multi_day2.csv")
+        .output()?;
+
+    let mut expected = vec![
+        client(1, "-10.0", "0.0", "-10.0", true),
+        client(2, "5.0", "20.0", "25.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    assert!(resumed.status.success());
+    assert!(compare_stdout(expected.clone())(&resumed.stdout));
+    assert!(compare_stdout(expected)(&single.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn resume_from_invalid_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg("--resume")
+        .arg(get_base_path() + "/deposit.csv")
+        .arg(get_base_path() + "/multi_day2.csv");
+
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot resume from").and(predicate::str::contains("not a compatible snapshot")));
+
+    Ok(())
+}
+
+#[test]
+fn recover_from_checkpoint() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = get_base_path() + "/checkpoint.csv";
+
+    // --strict stops the run on the malformed row, after the checkpoint of the first 6 rows.
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .args(["--strict", "--checkpoint-every", "2", "--checkpoint-dir"])
+        .arg(dir.path())
+        .assert()
+        .code(3);
+    let checkpoints: Vec<_> = std::fs::read_dir(dir.path())?.collect::<Result<_, _>>()?;
+    assert_eq!(checkpoints.len(), 1);
+
+    let recovered = Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--recover")
+        .arg(dir.path())
+        .output()?;
+
+    let mut expected = vec![
+        client(1, "-2.5", "0.0", "-2.5", true),
+        client(2, "23.0", "0.0", "23.0", false),
+        client(3, "7.0", "0.0", "7.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    assert!(recovered.status.success());
+    assert!(compare_stdout(expected.clone())(&recovered.stdout));
+    // Only the rows after the checkpoint are read again.
+    assert!(String::from_utf8(recovered.stderr)?.contains("read: 5, applied: 4\n"));
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn checkpoint_needs_a_single_csv_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv.gz")
+        .args(["--checkpoint-every", "2", "--checkpoint-dir"])
+        .arg(dir.path());
+
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("checkpoints need a single uncompressed CSV file"));
+
+    Ok(())
+}
+
+#[test]
+fn audit() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let audit = dir.path().join("audit.jsonl");
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/audit.csv")
+        .arg("--audit")
+        .arg(&audit);
+
+    cmd.assert().success();
+
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(&audit)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let actions: Vec<(u64, String, Option<String>)> = lines.iter()
+        .map(|line| (
+            line["line"].as_u64().unwrap(),
+            line["action"].as_str().unwrap().to_string(),
+            line["reason"].as_str().map(str::to_string),
+        ))
+        .collect();
+    let applied = |line| (line, "applied".to_string(), None);
+    assert_eq!(actions, vec![
+        applied(2),
+        applied(3),
+        applied(4),
+        applied(5),
+        applied(6),
+        applied(7),
+        applied(8),
+        (9, "ignored".to_string(), Some("unknown_transaction".to_string())),
+        (10, "rejected".to_string(), Some("insufficient_funds".to_string())),
+        (11, "rejected".to_string(), Some("parse_error".to_string())),
+    ]);
+
+    assert_eq!(lines[6]["row"], serde_json::json!(["chargeback", "1", "2", ""]));
+    assert_eq!(lines[6]["balance"].to_string(), r#"{"available":7.0,"client":1,"currency":"USD","held":0.0,"locked":true,"total":7.0}"#);
+    assert_eq!(lines[6]["events"].to_string(), concat!(
+        r#"[{"amount":5.0,"client":1,"currency":"USD","seq":7,"tx":2,"type":"held_debited"},"#,
+        r#"{"client":1,"currency":"USD","seq":8,"tx":2,"type":"account_locked"}]"#,
+    ));
+    assert_eq!(lines[8]["events"].to_string(), r#"[{"client":2,"currency":"USD","seq":9,"tx":4,"type":"account_opened"}]"#);
+    assert_eq!(lines[9]["row"], serde_json::json!(["deposit", "2", "oops", "1.0"]));
+
+    Ok(())
+}
+
+#[test]
+fn timestamp_column() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/timestamp.csv").arg("--detect-out-of-order");
+
+    let mut expected = vec![
+        client(1, "8.0", "0.0", "8.0", false),
+        client(2, "0.0", "5.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn out_of_order_warns() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/out_of_order.csv").arg("--detect-out-of-order");
+
+    let expected = vec![client(1, "17.0", "0.0", "17.0", false)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains(
+            "Application warning: timestamp 2023-11-14T22:13:19.500Z is 500 ms earlier than the previous row's 2023-11-14T22:13:20.000Z"
+        ))
+        .stderr(predicate::str::contains("is 1500 ms earlier"));
+
+    Ok(())
+}
+
+#[test]
+fn out_of_order_strict_rejects() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/out_of_order.csv")
+        .arg("--detect-out-of-order")
+        .arg("--out-of-order-tolerance")
+        .arg("1000")
+        .arg("--strict");
+
+    let expected = vec![client(1, "16.0", "0.0", "16.0", false)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("is 1500 ms earlier than the previous row's 2023-11-14T22:13:19.500Z"))
+        .stderr(predicate::str::contains("out_of_order: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn malformed_row_lenient() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/malformed.csv");
+
+    let expected = vec![client(1, "17.0", "0.0", "17.0", false)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("line: 4"));
+
+    Ok(())
+}
+
+#[test]
+fn malformed_row_strict() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/malformed.csv")
+        .arg("--strict");
+
+    cmd.assert()
+        .code(3)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("malformed row at line 4"))
+        .stderr(predicate::str::contains("\ndeposit,two,3,1.0\n"))
+        .stderr(predicate::str::contains("invalid digit"));
+
+    Ok(())
+}
+
+#[test]
+fn missing_amount_strict() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/rejected.csv")
+        .arg("--strict");
+
+    cmd.assert()
+        .code(3)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("malformed row at line 4"))
+        .stderr(predicate::str::contains("missing amount on deposit tx 3 of client 1\ndeposit,1,3,\n"));
+
+    Ok(())
+}
+
+#[test]
+fn quiet() -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_base_path() + "/rejected.csv";
+    let loud = Command::cargo_bin("transaction_engine")?.arg(&path).output()?;
+    let quiet = Command::cargo_bin("transaction_engine")?.arg(&path).arg("--quiet").output()?;
+
+    assert!(loud.status.success() && quiet.status.success());
+    assert_eq!(sorted_lines(&quiet.stdout), sorted_lines(&loud.stdout));
+    assert!(String::from_utf8(loud.stderr)?.contains("Application error: missing amount on deposit tx 3 of client 1"));
+    assert_eq!(String::from_utf8(quiet.stderr)?, "");
+
+    // The errors failing the run are still printed.
+    Command::cargo_bin("transaction_engine")?
+        .arg(&path)
+        .args(["--quiet", "--strict"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("malformed row at line 4"));
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/no_such_file.csv")
+        .arg("-q")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Application error: cannot open"));
+
+    Ok(())
+}
+
+#[test]
+fn missing_amount() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let rejected = dir.path().join("rejected.csv");
+    let output = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/missing_amount.csv")
+        .arg("--rejected")
+        .arg(&rejected)
+        .output()?;
+
+    // The balances are those of the rows with an amount.
+    let mut expected = vec![
+        client(1, "10.0", "0.0", "10.0", false),
+        client(2, "3.0", "0.0", "3.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+    assert!(output.status.success());
+    assert!(compare_stdout(expected)(&output.stdout));
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("Application error: missing amount on deposit tx 2 of client 1 at line 3 of"), "{stderr}");
+    assert!(stderr.contains("Application error: missing amount on withdrawal tx 4 of client 1 at line 5 of"), "{stderr}");
+    assert!(stderr.contains("Application error: missing amount on deposit tx 5 of client 2 at line 6 of"), "{stderr}");
+    assert!(stderr.contains("read: 6, applied: 3\nrejected: 3 (missing_amount: 3)"), "{stderr}");
+
+    let content = std::fs::read_to_string(&rejected)?;
+    assert_eq!(content.lines().skip(1).collect::<Vec<_>>(), vec![
+        "deposit,1,2,,missing amount on deposit tx 2 of client 1",
+        "withdrawal,1,4,,missing amount on withdrawal tx 4 of client 1",
+        "deposit,2,5,,missing amount on deposit tx 5 of client 2",
+    ]);
+
+    Ok(())
+}
+
+#[test]
+fn rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let rejected = dir.path().join("rejected.csv");
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/rejected.csv")
+        .arg("--rejected")
+        .arg(&rejected);
+
+    let mut expected = vec![
+        client(1, "50.0", "0.0", "50.0", false),
+        client(2, "6.0", "0.0", "6.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    let content = std::fs::read_to_string(&rejected)?;
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "type,client,tx,amount,reason");
+    assert_eq!(lines[1], "withdrawal,1,2,60.0,\"insufficient funds on account 1: requested 60.0, available 50.0\"");
+    assert_eq!(lines[2], "deposit,1,3,,missing amount on deposit tx 3 of client 1");
+    assert_eq!(lines[3], "dispute,1,99,,tx 99 is unknown");
+    assert!(lines[4].starts_with("deposit,two,4,1.0,\"parse error: "), "{}", lines[4]);
+    assert!(lines[4].contains("line: 6"), "{}", lines[4]);
+    assert!(lines[5].starts_with("deposit,2,6,1.0,extra,\"parse error: "), "{}", lines[5]);
+    assert!(lines[5].contains("line: 8"), "{}", lines[5]);
+
+    Ok(())
+}
+
+#[test]
+fn missing_arg() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("Usage: transaction_engine"));
+
+    Ok(())
+}
+
+#[test]
+fn type_alias() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/type_alias.csv")
+        .args(["--type-alias", "credit=deposit"])
+        .args(["--type-alias", "withdraw=withdrawal"]);
+
+    let mut expected = vec![
+        client(2, "50.0", "0.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("payout"));
+
+    Ok(())
+}
+
+#[test]
+fn type_alias_in_any_case() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/type_alias_case.csv")
+        .args(["--type-alias", "payout=withdrawal"])
+        .args(["--type-alias", "Credit=deposit"]);
+
+    let expected = vec![
+        client(1, "35.0", "0.0", "35.0", false),
+        client(2, "3.0", "0.0", "3.0", false),
+    ];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn type_case_and_builtin_aliases() -> Result<(), Box<dyn std::error::Error>> {
+    let mut expected = vec![
+        client(1, "12.0", "0.0", "12.0", false),
+        client(2, "0.0", "0.0", "0.0", true),
+        client(3, "7.0", "0.0", "7.0", false),
+        client(4, "0.0", "0.0", "0.0", true),
+    ];
+    expected.sort_by_key(Client::id);
+
+    // The same balances as the file with the canonical type names.
+    for file in ["/type_case.csv", "/type_case_canonical.csv"] {
+        Command::cargo_bin("transaction_engine")?
+            .arg(get_base_path() + file)
+            .assert()
+            .success()
+            .stdout(predicate::function(compare_stdout(expected.clone())))
+            .stderr(predicate::str::is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_type() -> Result<(), Box<dyn std::error::Error>> {
+    let mut expected = vec![
+        client(1, "6.0", "0.0", "6.0", false),
+        client(2, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    // The rows of an unsupported type are skipped even with --strict, the others are applied.
+    for strict in [false, true] {
+        let mut cmd = Command::cargo_bin("transaction_engine")?;
+        cmd.arg(get_base_path() + "/unsupported_type.csv");
+        if strict {
+            cmd.arg("--strict");
+        }
+        let output = cmd.output()?;
+
+        assert!(output.status.success());
+        assert!(compare_stdout(expected.clone())(&output.stdout));
+        let stderr = String::from_utf8(output.stderr)?;
+        // Reported once per type, at its first row.
+        assert_eq!(stderr.lines().filter(|line| line.contains("unsupported transaction type")).count(), 2, "{stderr}");
+        assert!(stderr.contains("unsupported transaction type \"refund\" at line 3 of"), "{stderr}");
+        assert!(stderr.contains("unsupported transaction type \"payout\" at line 6 of"), "{stderr}");
+        assert!(stderr.contains("read: 10, applied: 5\nrejected: 5 (unsupported_type: 5)"), "{stderr}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dispute_rows_without_amount() -> Result<(), Box<dyn std::error::Error>> {
+    let mut expected = vec![
+        client(1, "5.0", "0.0", "5.0", true),
+        client(2, "6.0", "0.0", "6.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    // No amount column, a trailing comma or a quoted empty amount: the dispute rows are applied the same way,
+    // the deposit and withdrawal without amount are still rejected.
+    for file in ["/dispute_no_amount.csv", "/dispute_trailing_comma.csv", "/dispute_quoted_amount.csv"] {
+        let output = Command::cargo_bin("transaction_engine")?.arg(get_base_path() + file).output()?;
+
+        assert!(output.status.success(), "{file}");
+        assert!(compare_stdout(expected.clone())(&output.stdout), "{file}");
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(stderr.contains("read: 10, applied: 8\nrejected: 2 (missing_amount: 2)"), "{file}: {stderr}");
+        assert!(!stderr.contains("parse_error"), "{file}: {stderr}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn type_alias_collision() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.csv")
+        .args(["--type-alias", "deposit=withdrawal"]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("collides"));
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/deposit.csv")
+        .args(["--type-alias", "Charge-Back=withdrawal"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("type alias Charge-Back collides with the transaction type chargeback"));
+
+    Ok(())
+}
+
+#[test]
+fn sentinel_default() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/sentinel.csv");
+
+    let mut expected = vec![
+        client(0, "50.0", "0.0", "50.0", false),
+        client(1, "40.0", "5.0", "45.0", false),
+        client(65534, "20.0", "0.0", "20.0", false),
+        client(65535, "10.0", "0.0", "10.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn sentinel_configured() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/sentinel.csv")
+        .args(["--sentinel-client-ids", "zero,max"])
+        .args(["--sentinel-tx-ids", "zero"]);
+
+    let mut expected = vec![
+        client(1, "40.0", "0.0", "40.0", false),
+        client(65534, "20.0", "0.0", "20.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("sentinel client id 0"))
+        .stderr(predicate::str::contains("sentinel client id 65535"))
+        .stderr(predicate::str::contains("sentinel tx id 0"))
+        .stderr(predicate::str::contains("Rejected 4 transactions with sentinel ids"));
+
+    Ok(())
+}
+
+#[test]
+fn sentinel_transfer_recipient() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/sentinel_transfer.csv")
+        .args(["--sentinel-client-ids", "zero"]);
+
+    // The transfer to client 0 neither creates nor credits its account.
+    let expected = vec![client(1, "10.0", "0.0", "10.0", false)];
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)))
+        .stderr(predicate::str::contains("sentinel client id 0"))
+        .stderr(predicate::str::contains("Rejected 1 transactions with sentinel ids"));
+
+    Ok(())
+}
+
+#[test]
+fn top_held() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/dispute.csv")
+        .arg("--top-held")
+        .arg("2");
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::ends_with("client,held,disputed_tx\n2,50.0,4\n3,50.0,7\n"));
+
+    Ok(())
+}
+
+#[test]
+fn unknown_option() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.csv")
+        .arg("--unknown");
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("unexpected argument '--unknown'"))
+        .stderr(predicate::str::contains("Usage: transaction_engine"));
+
+    Ok(())
+}
+
+#[test]
+fn mmap() -> Result<(), Box<dyn std::error::Error>> {
+    assert_same_output_on_fixtures("--mmap")
+}
+
+#[test]
+fn mmap_empty_file() -> Result<(), Box<dyn std::error::Error>> {
+    let file = tempfile::NamedTempFile::new()?;
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(file.path()).arg("--mmap");
+
+    // Reported as without --mmap, an empty file has no header.
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("has no type column"))
+        .stderr(predicate::str::contains("panicked").not());
+
+    Ok(())
+}
+
+#[test]
+fn pipeline() -> Result<(), Box<dyn std::error::Error>> {
+    assert_same_output_on_fixtures("--pipeline")
+}
+
+#[test]
+fn pipeline_many_rows() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    // Several batches of rows, with a malformed one far from the start.
+    let mut file = tempfile::NamedTempFile::new()?;
+    writeln!(file, "type,client,tx,amount")?;
+    for tx in 1..=5000 {
+        match tx {
+            3000 => writeln!(file, "deposit,x,{tx},1.0")?,
+            _ if tx % 7 == 0 => writeln!(file, "dispute,{},{},", tx % 10, tx - 1)?,
+            _ => writeln!(file, "deposit,{},{tx},1.5", tx % 10)?,
+        }
+    }
+
+    let serial = Command::cargo_bin("transaction_engine")?.arg(file.path()).output()?;
+    let pipelined = Command::cargo_bin("transaction_engine")?.arg(file.path()).arg("--pipeline").output()?;
+    assert!(serial.status.success());
+    assert_eq!(serial.stdout, pipelined.stdout);
+    assert_eq!(serial.stderr, pipelined.stderr);
+
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+    cmd.arg(file.path()).arg("--pipeline").arg("--strict");
+    cmd.assert()
+        .code(3)
+        .stderr(predicate::str::contains("malformed row at line 3001"));
+
+    Ok(())
+}
+
+#[test]
+fn threads() -> Result<(), Box<dyn std::error::Error>> {
+    assert_same_output_on_fixtures("--threads=3")
+}
+
+#[test]
+fn threads_many_rows() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    // Disputes of the client's own transactions and of other clients' ones, tx ids reused across clients
+    // and a few transfers, the first of which between clients of different shards.
+    let mut file = tempfile::NamedTempFile::new()?;
+    writeln!(file, "type,client,tx,amount,to_client")?;
+    for tx in 1..=5000 {
+        let client = tx % 13;
+        match tx % 10 {
+            0 => writeln!(file, "dispute,{client},{},,", tx - 3)?,
+            1 => writeln!(file, "dispute,{client},{},,", tx - 13)?,
+            2 => writeln!(file, "chargeback,{client},{},,", tx - 2)?,
+            3 => writeln!(file, "resolve,{client},{},,", tx - 13)?,
+            4 => writeln!(file, "deposit,{client},{},2.0,", tx + 2)?,
+            5 => writeln!(file, "withdrawal,{client},{tx},3.0,")?,
+            _ if tx == 4006 => writeln!(file, "transfer,{client},{tx},1.0,{}", client + 1)?,
+            _ => writeln!(file, "deposit,{client},{tx},1.5,")?,
+        }
+    }
+
+    let sequential = Command::cargo_bin("transaction_engine")?.arg(file.path()).arg("--stats").output()?;
+    let sharded = Command::cargo_bin("transaction_engine")?.arg(file.path()).arg("--stats").arg("--threads=4").output()?;
+    assert!(sequential.status.success());
+    assert_eq!(sorted_lines(&sequential.stdout), sorted_lines(&sharded.stdout));
+    // The summary of the rejections followed by the stats, without the timings.
+    let report = |stderr: &[u8]| -> Result<(Vec<String>, serde_json::Value), serde_json::Error> {
+        let mut lines: Vec<String> = String::from_utf8_lossy(stderr).lines().map(str::to_string).collect();
+        let mut stats: serde_json::Value = serde_json::from_str(&lines.pop().unwrap_or_default())?;
+        stats["elapsed_seconds"].take();
+        stats["rows_per_second"].take();
+        Ok((lines, stats))
+    };
+    let (summary, stats) = report(&sequential.stderr)?;
+    assert!(summary.iter().any(|line| line.contains("duplicate_transaction")), "{summary:?}");
+    assert_eq!((summary, stats), report(&sharded.stderr)?);
+
+    Ok(())
+}
+
+#[test]
+fn threads_conflicts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.csv")
+        .arg("--threads=2")
+        .arg("--audit=audit.jsonl");
+
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// Starts `serve` or `serve-http` on a free port, returning the server and its address.
+fn start_server(command: &str) -> Result<(std::process::Child, String), Box<dyn std::error::Error>> {
+    start_server_with(command, &[])
+}
+
+fn start_server_with(command: &str, args: &[&str]) -> Result<(std::process::Child, String), Box<dyn std::error::Error>> {
+    use std::io::BufRead;
+
+    let mut server = Command::cargo_bin("transaction_engine")?
+        .args([command, "--listen", "127.0.0.1:0"])
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let mut line = String::new();
+    std::io::BufReader::new(server.stderr.take().ok_or("no stderr")?).read_line(&mut line)?;
+    let address = line.trim().strip_prefix("Listening on ").ok_or(format!("unexpected line {line:?}"))?.to_string();
+    Ok((server, address))
+}
+
+// Reads a report, the balances followed by an empty line.
+fn read_report(reader: &mut impl std::io::BufRead) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut report = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\n" {
+            return Ok(report)
+        }
+        report.extend(line.into_bytes());
+    }
+}
+
+#[test]
+fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (mut server, address) = start_server("serve")?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let fixture = std::fs::read_to_string(get_base_path() + "/dispute.csv")?;
+        let mut stream = std::net::TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // The rows without their header, then a second connection to check that the engine is shared.
+        let (header, rows) = fixture.split_once('\n').ok_or("empty fixture")?;
+        assert_eq!(header.trim(), "type,client,tx,amount");
+        stream.write_all(rows.as_bytes())?;
+        stream.write_all(b"REPORT\n")?;
+        let batch = Command::cargo_bin("transaction_engine")?.arg(get_base_path() + "/dispute.csv").output()?;
+        assert_eq!(sorted_lines(&read_report(&mut reader)?), sorted_lines(&batch.stdout));
+
+        let mut other = std::net::TcpStream::connect(&address)?;
+        other.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        other.write_all(b"deposit,x,100,1.0\ndeposit, 9, 100, 1.5\nREPORT\n")?;
+        let mut other_reader = BufReader::new(other.try_clone()?);
+        let mut error = String::new();
+        other_reader.read_line(&mut error)?;
+        assert!(error.starts_with("ERROR line 1: "), "{error}");
+        let report = String::from_utf8(read_report(&mut other_reader)?)?;
+        assert!(report.lines().any(|line| line == "9,1.5,0.0,1.5,false"), "{report}");
+
+        stream.write_all(b"REPORT\n")?;
+        assert_eq!(sorted_lines(&read_report(&mut reader)?), sorted_lines(report.as_bytes()));
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+#[test]
+fn serve_should_close_the_connection_of_a_line_too_long() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (mut server, address) = start_server("serve")?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut stream = std::net::TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // A row at the limit is read, one byte more without line end is not.
+        let row_at_limit = format!("deposit,1,1,{}\n", "1".repeat(64 * 1024 - 12));
+        stream.write_all(row_at_limit.as_bytes())?;
+        let mut error = String::new();
+        reader.read_line(&mut error)?;
+        assert!(error.starts_with("ERROR line 1: "), "{error}");
+        stream.write_all(&[b'1'; 64 * 1024 + 1])?;
+        let mut rest = String::new();
+        reader.read_line(&mut rest)?;
+        assert_eq!(rest, "ERROR line too long\n");
+        assert_eq!(reader.read_line(&mut rest)?, 0);
+
+        // The server goes on with the other connections.
+        let mut other = std::net::TcpStream::connect(&address)?;
+        other.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        other.write_all(b"deposit,1,1,1.0\nREPORT\n")?;
+        let report = read_report(&mut BufReader::new(other))?;
+        assert_eq!(String::from_utf8(report)?, "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n");
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+#[test]
+fn serve_should_evict_the_idle_accounts_and_restore_them() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufReader, Write};
+
+    let dir = tempfile::tempdir()?;
+    let archive = dir.path().join("archive.jsonl");
+    let (mut server, address) = start_server_with("serve", &["--evict-after-rows", "2", "--archive", archive.to_str().ok_or("path")?])?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut stream = std::net::TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        stream.write_all(b"deposit,1,1,1.0\nwithdrawal,1,2,1.0\ndeposit,2,3,2.0\ndeposit,2,4,1.0\n")?;
+        // Long enough for an eviction to run once the rows are applied.
+        std::thread::sleep(std::time::Duration::from_millis(2_500));
+        stream.write_all(b"REPORT\n")?;
+        assert_eq!(String::from_utf8(read_report(&mut reader)?)?, "client,available,held,total,locked\n2,3.0,0.0,3.0,false\n");
+        let archived = std::fs::read_to_string(&archive)?;
+        assert!(archived.starts_with(r#"{"client":{"client":1,"#) && archived.ends_with("\"history\":[1,2]}\n"), "{archived}");
+
+        // The dispute of the deposit of the evicted client brings it back.
+        stream.write_all(b"dispute,1,1,\nREPORT\n")?;
+        assert_eq!(
+            sorted_lines(&read_report(&mut reader)?),
+            sorted_lines(b"client,available,held,total,locked\n1,-1.0,1.0,0.0,false\n2,3.0,0.0,3.0,false\n"),
+        );
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+#[test]
+fn serve_should_park_the_disputes_coming_before_their_deposit() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufReader, Write};
+
+    let dir = tempfile::tempdir()?;
+    let rejected = dir.path().join("rejected.csv");
+    let args = ["--retry-capacity", "2", "--retry-expire-after-rows", "4", "--rejected", rejected.to_str().ok_or("path")?];
+    let (mut server, address) = start_server_with("serve", &args)?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut stream = std::net::TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // The dispute of tx 1 waits for its deposit, that of tx 9 expires, that of tx 8 overflows the queue.
+        stream.write_all(b"dispute,1,1,\ndispute,2,9,\ndispute,2,8,\ndeposit,1,1,1.0\ndeposit,2,2,1.0\ndeposit,2,3,1.0\n")?;
+        stream.write_all(b"deposit,2,9,1.0\nREPORT\n")?;
+        assert_eq!(
+            sorted_lines(&read_report(&mut reader)?),
+            sorted_lines(b"client,available,held,total,locked\n1,0.0,1.0,1.0,false\n2,3.0,0.0,3.0,false\n"),
+        );
+        // Long enough for the expired transactions to be dropped.
+        std::thread::sleep(std::time::Duration::from_millis(2_500));
+        assert_eq!(std::fs::read_to_string(&rejected)?, "type,client,tx,amount,reason\ndispute,2,9,,tx 9 is unknown\n");
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+// Sends a request on its own connection, returning the status code and the body of the response.
+fn http(address: &str, method: &str, path: &str, body: &str) -> Result<(u16, String), Box<dyn std::error::Error>> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").ok_or(format!("no body in {response:?}"))?;
+    let status = head.split(' ').nth(1).ok_or(format!("no status in {head:?}"))?.parse()?;
+    Ok((status, body.to_string()))
+}
+
+#[test]
+fn serve_http() -> Result<(), Box<dyn std::error::Error>> {
+    use serde_json::{json, Value};
+
+    let (mut server, address) = start_server("serve-http")?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(http(&address, "GET", "/healthz", "")?, (200, "ok".to_string()));
+
+        // The dispute lifecycle of the fixture, one transaction per request.
+        let mut reader = csv::Reader::from_path(get_base_path() + "/chargeback.csv")?;
+        for row in reader.records() {
+            let row = row?;
+            let transaction = json!({ "type": &row[0], "client": &row[1], "tx": &row[2], "amount": &row[3] });
+            let (status, body) = http(&address, "POST", "/transactions", &transaction.to_string())?;
+            assert_eq!((status, serde_json::from_str::<Value>(&body)?), (200, json!({ "status": "accepted" })));
+        }
+        let batch = Command::cargo_bin("transaction_engine")?
+            .args([get_base_path() + "/chargeback.csv", "--format".to_string(), "json".to_string()])
+            .output()?;
+        let (status, clients) = http(&address, "GET", "/clients", "")?;
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&clients)?, serde_json::from_slice::<Value>(&batch.stdout)?);
+
+        let (status, body) = http(&address, "POST", "/transactions", r#"{"type": "deposit", "client": 1, "tx": 5, "amount": 1.0}"#)?;
+        assert_eq!(status, 422);
+        assert_eq!(serde_json::from_str::<Value>(&body)?["code"], "account_locked");
+
+        // Every transaction of an array is applied, the status is the one of the first rejection.
+        let batch = json!([
+            { "type": "deposit", "client": 2, "tx": 6, "amount": "1.5" },
+            { "type": "dispute", "client": 2, "tx": 60 },
+            { "type": "deposit", "client": 2, "tx": 7 },
+            { "type": "deposit", "client": 2, "tx": 8, "amount": 2 },
+        ]);
+        let (status, body) = http(&address, "POST", "/transactions", &batch.to_string())?;
+        assert_eq!(status, 422);
+        let outcomes: Value = serde_json::from_str(&body)?;
+        assert_eq!(outcomes[0], json!({ "status": "accepted" }));
+        assert_eq!((&outcomes[1]["status"], &outcomes[1]["code"]), (&json!("rejected"), &json!("unknown_transaction")));
+        assert_eq!(outcomes[2]["code"], "missing_amount");
+        assert_eq!(outcomes[3], json!({ "status": "accepted" }));
+
+        let (status, client) = http(&address, "GET", "/clients/2", "")?;
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&client)?, json!({ "client": 2, "available": 3.5, "held": 0.0, "total": 3.5, "locked": false }));
+        let (status, body) = http(&address, "GET", "/clients/3", "")?;
+        assert_eq!((status, serde_json::from_str::<Value>(&body)?["code"].clone()), (404, json!("unknown_client")));
+
+        let (status, body) = http(&address, "POST", "/transactions", "{\"type\": ")?;
+        assert_eq!((status, serde_json::from_str::<Value>(&body)?["code"].clone()), (400, json!("parse_error")));
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+#[test]
+fn serve_http_should_park_a_dispute_and_count_it_in_the_stats() -> Result<(), Box<dyn std::error::Error>> {
+    use serde_json::{json, Value};
+
+    let (mut server, address) = start_server_with("serve-http", &["--retry-capacity", "1"])?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let (status, body) = http(&address, "POST", "/transactions", r#"{"type": "dispute", "client": 1, "tx": 1}"#)?;
+        assert_eq!((status, serde_json::from_str::<Value>(&body)?), (202, json!({ "status": "parked" })));
+        let (status, body) = http(&address, "POST", "/transactions", r#"{"type": "dispute", "client": 1, "tx": 2}"#)?;
+        assert_eq!((status, serde_json::from_str::<Value>(&body)?["code"].clone()), (422, json!("unknown_transaction")));
+        let (_, stats) = http(&address, "GET", "/stats", "")?;
+        let stats: Value = serde_json::from_str(&stats)?;
+        assert_eq!((&stats["parked_transactions"], &stats["parked_overflow"]), (&json!(1), &json!(1)));
+
+        let (status, _) = http(&address, "POST", "/transactions", r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.0}"#)?;
+        assert_eq!(status, 200);
+        let (_, client) = http(&address, "GET", "/clients/1", "")?;
+        assert_eq!(serde_json::from_str::<Value>(&client)?, json!({ "client": 1, "available": 0.0, "held": 2.0, "total": 2.0, "locked": false }));
+        let (_, stats) = http(&address, "GET", "/stats", "")?;
+        let stats: Value = serde_json::from_str(&stats)?;
+        assert_eq!((&stats["parked_transactions"], &stats["parked_retried"]), (&json!(0), &json!(1)));
+        Ok(())
+    })();
+    server.kill()?;
+    server.wait()?;
+
+    result
+}
+
+#[test]
+fn process_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg("process")
+        .arg(get_base_path() + "/dispute.csv");
+
+    let mut expected = vec![
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+        client(1, "5.0", "0.0", "5.0", false),
+    ];
+    expected.sort_by_key(Client::id);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::function(compare_stdout(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn help_and_version() -> Result<(), Box<dyn std::error::Error>> {
+    Command::cargo_bin("transaction_engine")?
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--top-held"))
+        .stdout(predicate::str::contains("process"));
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("--version")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(env!("CARGO_PKG_VERSION")));
+
+    Ok(())
+}
+
+#[test]
+fn directory_arg() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path());
+
+    cmd.assert()
+        .code(2)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("is a directory"));
+
+    Ok(())
+}
+
+#[test]
+fn empty_path_arg() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg("");
+
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("input path is empty"));
+
+    Ok(())
+}
+
+#[test]
+fn unexpanded_glob_arg() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/*.tsv");
+
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("glob pattern"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn closed_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    // More balances than a pipe buffers, so that the engine is still writing when `head` exits.
+    let mut file = tempfile::NamedTempFile::new()?;
+    writeln!(file, "type,client,tx,amount")?;
+    for client in 1..=20_000 {
+        writeln!(file, "deposit,{client},{client},1.0")?;
+    }
+
+    let mut engine = Command::cargo_bin("transaction_engine")?
+        .arg(file.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let head = Command::new("head")
+        .args(["-n", "1"])
+        .stdin(engine.stdout.take().ok_or("stdout")?)
+        .output()?;
+    let engine = engine.wait_with_output()?;
+
+    assert_eq!(String::from_utf8(head.stdout)?, "client,available,held,total,locked\n");
+    // Like a process killed by SIGPIPE, without a message.
+    assert_eq!(engine.status.code(), Some(141));
+    assert_eq!(String::from_utf8(engine.stderr)?, "");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn full_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("transaction_engine")?;
+
+    cmd.arg(get_base_path() + "/deposit.csv")
+        .stdout(std::fs::File::create("/dev/full")?);
+
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("Application error: cannot write the balances to stdout: No space left on device"));
+
+    Ok(())
+}
+
+// The balances are written in no particular order.
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output).lines().map(str::to_string).collect();
+    lines.sort();
+    lines
+}
+
+// Runs the binary on every fixture with and without `arg`, which should not change the outcome.
+fn assert_same_output_on_fixtures(arg: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut fixtures: Vec<_> = std::fs::read_dir(get_base_path())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    fixtures.sort();
+
+    for fixture in fixtures {
+        let without = Command::cargo_bin("transaction_engine")?.arg(&fixture).output()?;
+        let with = Command::cargo_bin("transaction_engine")?.arg(&fixture).arg(arg).output()?;
+
+        assert_eq!(without.status.code(), with.status.code(), "{}", fixture.display());
+        assert_eq!(sorted_lines(&without.stdout), sorted_lines(&with.stdout), "{}", fixture.display());
+        assert_eq!(without.stderr, with.stderr, "{}", fixture.display());
+    }
+
+    Ok(())
+}
+
+fn client(id: u16, available: &str, held: &str, total: &str, locked: bool) -> Client {
+    Client::new(id, available.parse().unwrap(), held.parse().unwrap(), total.parse().unwrap(), locked)
+}
+
+// JSON amounts are numbers, they are read back through their decimal representation.
+fn json_client(value: &serde_json::Value) -> Client {
+    let amount = |field: &str| value[field].as_number().unwrap().to_string();
+    client(
+        value["client"].as_u64().unwrap() as u16,
+        &amount("available"),
+        &amount("held"),
+        &amount("total"),
+        value["locked"].as_bool().unwrap(),
+    )
+}
+
+fn compare_stdout(expected: Vec<Client>) -> impl Fn(&[u8]) -> bool {
+    move |x: &[u8]| {
+        let mut rdr = csv::Reader::from_reader(x);
+        let mut clients : Vec<Client> = rdr.deserialize()
+            .filter(|client: &Result<Client, csv::Error>| client.is_ok())
+            .map(|client|{
+                client.unwrap()
+            })
+            .collect();
+        clients.sort_by_key(Client::id);
+
+        expected == clients
+    }
+}
+
+#[test]
+fn max_memory_mb() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let tmp = tempfile::tempdir()?;
+    let input = dir.path().join("large.csv");
+    let mut rows = String::from("type,client,tx,amount\n");
+    for tx in 1..=30_000 {
+        rows += &format!("deposit,{},{tx},1.0\n", tx % 10 + 1);
+    }
+    // The first deposits are spilled to disk by then.
+    rows += "dispute,2,1,\ndispute,3,2,\nchargeback,2,1,\nresolve,3,2,\ndispute,4,3,\n";
+    std::fs::write(&input, rows)?;
+
+    let expected = Command::cargo_bin("transaction_engine")?.arg(&input).output()?;
+    let spilled = Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .args(["--max-memory-mb", "1"])
+        .env("TMPDIR", tmp.path())
+        .output()?;
+
+    assert!(spilled.status.success());
+    assert_eq!(sorted_lines(&spilled.stdout), sorted_lines(&expected.stdout));
+    assert!(String::from_utf8(spilled.stdout)?.contains("2,2999.0,0.0,2999.0,true"));
+    assert_eq!(std::fs::read_dir(tmp.path())?.count(), 0, "the spill file should be removed");
+
+    Ok(())
+}
+
+#[test]
+fn max_memory_mb_should_be_addressable() -> Result<(), Box<dyn std::error::Error>> {
+    let mb = (usize::MAX >> 19).to_string();
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/deposit.csv")
+        .args(["--max-memory-mb", &mb])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(format!("Invalid argument: --max-memory-mb {mb} is more than can be addressed\n"));
+
+    Ok(())
+}
+
+#[test]
+fn generate() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let generated = |seed: &str, name: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let out = dir.path().join(name);
+        Command::cargo_bin("transaction_engine")?
+            .args(["generate", "--rows", "20000", "--clients", "50", "--dispute-rate", "0.05", "--chargeback-rate", "0.01"])
+            .args(["--seed", seed, "--out"])
+            .arg(&out)
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+        Ok(std::fs::read(out)?)
+    };
+
+    let first = generated("42", "first.csv")?;
+    assert_eq!(generated("42", "second.csv")?, first);
+    assert_ne!(generated("43", "other.csv")?, first);
+    let rows = String::from_utf8(first)?;
+    assert_eq!(rows.lines().count(), 20_001);
+    assert!(rows.contains("\ndispute,") && rows.contains("\nchargeback,"));
+
+    // Every row parses and is applied, no rejection summary is printed.
+    Command::cargo_bin("transaction_engine")?
+        .arg(dir.path().join("first.csv"))
+        .arg("--strict")
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty())
+        .stdout(predicate::str::contains(",true"));
+
+    Ok(())
+}
+
+#[test]
+fn diff_of_sequential_and_sharded_snapshots() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("input.csv");
+    Command::cargo_bin("transaction_engine")?
+        .args(["generate", "--rows", "20000", "--clients", "50", "--dispute-rate", "0.05", "--chargeback-rate", "0.01", "--out"])
+        .arg(&input)
+        .assert()
+        .success();
+    for (snapshot, threads) in [("sequential.snapshot", "1"), ("sharded.snapshot", "4")] {
+        let mut cmd = Command::cargo_bin("transaction_engine")?;
+        cmd.arg(&input).arg("--snapshot-out").arg(dir.path().join(snapshot));
+        if threads != "1" {
+            cmd.arg("--threads").arg(threads);
+        }
+        cmd.assert().success();
+    }
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("diff")
+        .arg(dir.path().join("sequential.snapshot"))
+        .arg(dir.path().join("sharded.snapshot"))
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn diff_of_different_snapshots() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let day1 = dir.path().join("day1.snapshot");
+    let day2 = dir.path().join("day2.snapshot");
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg("--snapshot-out")
+        .arg(&day1)
+        .assert()
+        .success();
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/multi_day2.csv")
+        .arg("--snapshot-out")
+        .arg(&day2)
+        .assert()
+        .success();
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("diff")
+        .arg(&day1)
+        .arg(&day2)
+        .assert()
+        .code(1)
+        .stdout(predicate::str::starts_with("15 differences: 6 in the clients, 5 in the transactions, 4 in the totals\n"))
+        .stdout(predicate::str::contains("\nclient 1 in USD: locked false != true\n"))
+        .stdout(predicate::str::contains("\ntx 1: state None != ChargedBack\n"))
+        .stdout(predicate::str::ends_with("\n...\n"));
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("diff")
+        .arg(&day1)
+        .arg(dir.path().join("missing.snapshot"))
+        .assert()
+        .code(2)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot load"));
+
+    Ok(())
+}
+
+#[test]
+fn verify_audit_of_a_generated_run() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("input.csv");
+    let (audit, accounts) = (dir.path().join("audit.jsonl"), dir.path().join("accounts.csv"));
+    Command::cargo_bin("transaction_engine")?
+        .args(["generate", "--rows", "5000", "--clients", "30", "--dispute-rate", "0.1", "--chargeback-rate", "0.03", "--out"])
+        .arg(&input)
+        .assert()
+        .success();
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--audit")
+        .arg(&audit)
+        .arg("--output")
+        .arg(&accounts)
+        .assert()
+        .success();
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("verify-audit")
+        .arg(&audit)
+        .arg(&accounts)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn verify_audit_should_report_the_first_divergence() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let (audit, accounts) = (dir.path().join("audit.jsonl"), dir.path().join("accounts.csv"));
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/audit.csv")
+        .arg("--audit")
+        .arg(&audit)
+        .arg("--output")
+        .arg(&accounts)
+        .assert()
+        .success();
+    let verify = |audit: &std::path::Path, accounts: &std::path::Path| -> Result<_, Box<dyn std::error::Error>> {
+        Ok(Command::cargo_bin("transaction_engine")?.arg("verify-audit").arg(audit).arg(accounts).assert())
+    };
+
+    // The withdrawal of line 3 of the log loses its event.
+    let mut lines: Vec<serde_json::Value> = std::fs::read_to_string(&audit)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    lines[2]["events"] = serde_json::json!([]);
+    let tampered = dir.path().join("tampered.jsonl");
+    std::fs::write(&tampered, lines.iter().map(|line| line.to_string() + "\n").collect::<String>())?;
+    verify(&tampered, &accounts)?
+        .code(1)
+        .stdout(format!(
+            "divergence at seq 2, line 3 of {}: client 1 in USD: available 15.0, held 0.0, total 15.0 after the events, \
+             available 12.0, held 0.0, total 12.0 in the log\n",
+            tampered.display(),
+        ));
+
+    // The balances of client 2, opened by the rejected withdrawal, are not those of the run.
+    let balances = std::fs::read_to_string(&accounts)?.replace("2,0.0,0.0,0.0,false", "2,1.0,0.0,1.0,false");
+    let tampered = dir.path().join("tampered.csv");
+    std::fs::write(&tampered, balances)?;
+    verify(&audit, &tampered)?
+        .code(1)
+        .stdout(format!(
+            "divergence at seq 9: client 2 in USD: available 0.0, held 0.0, total 0.0 after the events, \
+             available 1.0, held 0.0, total 1.0 in {}\n",
+            tampered.display(),
+        ));
+
+    verify(&audit, &dir.path().join("missing.csv"))?
+        .code(2)
+        .stderr(predicate::str::contains("cannot read"));
+
+    Ok(())
+}
+
+#[test]
+fn daily_snapshots_of_a_three_day_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let generated = dir.path().join("generated.csv");
+    Command::cargo_bin("transaction_engine")?
+        .args(["generate", "--rows", "3000", "--clients", "40", "--dispute-rate", "0.1", "--chargeback-rate", "0.03", "--out"])
+        .arg(&generated)
+        .assert()
+        .success();
+    // A thousand rows a day from 2024-02-28, 86 s apart, the second day being the leap day.
+    let rows: Vec<(String, String)> = std::fs::read_to_string(&generated)?
+        .lines()
+        .skip(1)
+        .enumerate()
+        .map(|(index, row)| {
+            let seconds = index % 1000 * 86;
+            let date = ["2024-02-28", "2024-02-29", "2024-03-01"][index / 1000];
+            let time = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds % 3600 / 60, seconds % 60);
+            (date.to_string(), format!("{row},{date}T{time}Z\n"))
+        })
+        .collect();
+    let write = |path: &std::path::Path, last_date: &str| {
+        let body: String = rows.iter().filter(|(date, _)| date.as_str() <= last_date).map(|(_, row)| row.as_str()).collect();
+        std::fs::write(path, format!("type,client,tx,amount,ts\n{body}"))
+    };
+    let input = dir.path().join("input.csv");
+    write(&input, "2024-03-01")?;
+
+    let days = dir.path().join("days");
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--daily-snapshots")
+        .arg(&days)
+        .assert()
+        .success();
+
+    let sorted_lines = |text: String| {
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(std::fs::read_dir(&days)?.count(), 3);
+    for date in ["2024-02-28", "2024-02-29", "2024-03-01"] {
+        let prefix = dir.path().join(format!("prefix-{date}.csv"));
+        write(&prefix, date)?;
+        let expected = Command::cargo_bin("transaction_engine")?.arg(&prefix).output()?;
+        assert!(expected.status.success());
+
+        let snapshot = std::fs::read_to_string(days.join(format!("accounts-{date}.csv")))?;
+        assert!(snapshot.starts_with("client,available,held,total,locked\n"));
+        assert_eq!(sorted_lines(snapshot), sorted_lines(String::from_utf8(expected.stdout)?), "{date}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn incremental_daily_snapshots_should_rebuild_the_balances_on_top_of_a_full_one() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let generated = dir.path().join("generated.csv");
+    Command::cargo_bin("transaction_engine")?
+        .args(["generate", "--rows", "3000", "--clients", "40", "--dispute-rate", "0.1", "--chargeback-rate", "0.03", "--out"])
+        .arg(&generated)
+        .assert()
+        .success();
+    // A thousand rows a day from 2024-03-01, 86 s apart.
+    let rows: Vec<String> = std::fs::read_to_string(&generated)?
+        .lines()
+        .skip(1)
+        .enumerate()
+        .map(|(index, row)| {
+            let seconds = index % 1000 * 86;
+            format!("{row},2024-03-0{}T{:02}:{:02}:{:02}Z\n", index / 1000 + 1, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+        })
+        .collect();
+    let write = |name: &str, rows: &[String]| -> std::io::Result<std::path::PathBuf> {
+        let path = dir.path().join(name);
+        std::fs::write(&path, format!("type,client,tx,amount,ts\n{}", rows.concat()))?;
+        Ok(path)
+    };
+    let (first_day, next_days) = (write("day1.csv", &rows[..1000])?, write("days2-3.csv", &rows[1000..])?);
+    let all_days = write("days1-3.csv", &rows)?;
+
+    let snapshot = dir.path().join("day1.snapshot");
+    let full = Command::cargo_bin("transaction_engine")?.arg(&first_day).arg("--snapshot-out").arg(&snapshot).output()?;
+    assert!(full.status.success());
+    let (days, last) = (dir.path().join("days"), dir.path().join("last.csv"));
+    Command::cargo_bin("transaction_engine")?
+        .arg("--resume")
+        .arg(&snapshot)
+        .arg(&next_days)
+        .args(["--output-mode", "incremental", "--run-id", "nightly", "--daily-snapshots"])
+        .arg(&days)
+        .arg("--output")
+        .arg(&last)
+        .assert()
+        .success();
+    let expected = Command::cargo_bin("transaction_engine")?.arg(&all_days).output()?;
+    assert!(expected.status.success());
+
+    let mut rebuilt: std::collections::BTreeMap<String, String> = String::from_utf8(full.stdout)?
+        .lines()
+        .map(|line| (line.split(',').next().unwrap_or_default().to_string(), line.to_string()))
+        .collect();
+    let mut last_seq = 0;
+    for path in [days.join("accounts-2024-03-02.csv"), days.join("accounts-2024-03-03.csv"), last] {
+        let text = std::fs::read_to_string(&path)?;
+        let (metadata, balances) = text.split_once('\n').unwrap_or_default();
+        let metadata: serde_json::Value = serde_json::from_str(metadata.strip_prefix('#').unwrap_or_default())?;
+        let lines: Vec<&str> = balances.lines().collect();
+        // Without changes, not even the header is written.
+        assert!(lines.first().is_none_or(|header| *header == "client,available,held,total,locked"));
+        assert_eq!(metadata["run_id"], "nightly");
+        assert_eq!(metadata["first_seq"], last_seq + 1, "{}", path.display());
+        assert_eq!(metadata["count"], lines.len().saturating_sub(1));
+        last_seq = metadata["last_seq"].as_u64().unwrap_or_default();
+        rebuilt.extend(lines.iter().skip(1).map(|line| (line.split(',').next().unwrap_or_default().to_string(), line.to_string())));
+    }
+
+    // The last day is written by --daily-snapshots, leaving no change to the final output.
+    assert_eq!(last_seq, 2000);
+    let mut rebuilt: Vec<String> = rebuilt.into_values().collect();
+    rebuilt.sort();
+    assert_eq!(rebuilt, sorted_lines(&expected.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn incremental_outputs_should_be_csv() -> Result<(), Box<dyn std::error::Error>> {
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/deposit.csv")
+        .args(["--output-mode", "incremental", "--format", "json"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr("Invalid argument: --output-mode incremental writes CSV balances\n");
+
+    Ok(())
+}
+
+#[test]
+fn daily_snapshots_of_an_unsorted_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("input.csv");
+    std::fs::write(&input, "type,client,tx,amount,ts\n\
+        deposit,1,1,1.0,2024-01-01T10:00:00Z\n\
+        deposit,1,2,1.0,2024-01-02T10:00:00Z\n\
+        deposit,1,3,1.0,2024-01-01T11:00:00Z\n")?;
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--daily-snapshots")
+        .arg(dir.path().join("days"))
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(format!(
+            "Application error: --daily-snapshots needs rows sorted by time: timestamp 2024-01-01T11:00:00.000Z is \
+             82800000 ms earlier than the previous row's 2024-01-02T10:00:00.000Z, at line 4 of {}\n",
+            input.display(),
+        ));
+    // The first day was over before the unsorted row.
+    assert!(dir.path().join("days/accounts-2024-01-01.csv").exists());
+
+    Ok(())
+}
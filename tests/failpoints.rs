@@ -0,0 +1,164 @@
+#![cfg(all(feature = "failpoints", feature = "cli"))]
+
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use assert_cmd::prelude::*;
+
+const BASE_PATH: &str = "/resources/tests";
+
+fn get_base_path () -> String {
+    let path = env!("CARGO_MANIFEST_DIR").to_string();
+    path + BASE_PATH
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output).lines().map(str::to_string).collect();
+    lines.sort();
+    lines
+}
+
+// The process was killed by SIGABRT at `point`, as claimed on stderr.
+fn assert_aborted_at(output: &Output, point: &str) {
+    assert_eq!(output.status.signal(), Some(6), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), format!("Application error: failure injected at {point}\n"));
+}
+
+// Generated rows of a few clients, every one of which is applied.
+fn generate(dir: &Path, rows: u32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = dir.join("input.csv");
+    Command::cargo_bin("transaction_engine")?
+        .args(["generate", "--rows", &rows.to_string(), "--clients", "20", "--dispute-rate", "0.1", "--out"])
+        .arg(&path)
+        .assert()
+        .success();
+    Ok(path)
+}
+
+#[test]
+fn after_rows_should_abort_before_the_next_row_and_the_checkpoint_should_recover() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = generate(dir.path(), 300)?;
+    let (checkpoints, output) = (dir.path().join("checkpoints"), dir.path().join("accounts.csv"));
+    std::fs::create_dir(&checkpoints)?;
+
+    let aborted = Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .args(["--checkpoint-every", "100", "--checkpoint-dir"])
+        .arg(&checkpoints)
+        .arg("--output")
+        .arg(&output)
+        .args(["--inject-failure", "after-rows=250"])
+        .output()?;
+
+    assert_aborted_at(&aborted, "after-rows");
+    assert!(!output.exists());
+    // The checkpoint is written before applying its row, the 201st one.
+    let names: Vec<_> = std::fs::read_dir(&checkpoints)?.map(|entry| entry.map(|entry| entry.file_name())).collect::<Result<_, _>>()?;
+    assert_eq!(names, ["checkpoint-00000000000000000200"]);
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--recover")
+        .arg(&checkpoints)
+        .arg("--output")
+        .arg(&output)
+        .assert()
+        .success();
+    let expected = Command::cargo_bin("transaction_engine")?.arg(&input).output()?;
+    assert_eq!(sorted_lines(&std::fs::read(&output)?), sorted_lines(&expected.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn during_output_should_leave_the_previous_balances_and_the_snapshot_should_resume() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let (snapshot, output) = (dir.path().join("day1.snapshot"), dir.path().join("accounts.csv"));
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .arg("--output")
+        .arg(&output)
+        .assert()
+        .success();
+    let first_day = std::fs::read(&output)?;
+    let second_day = |failure: Option<&str>, output: Option<&Path>| -> std::io::Result<Output> {
+        let mut command = Command::cargo_bin("transaction_engine").map_err(std::io::Error::other)?;
+        command.arg("--resume").arg(&snapshot).arg(get_base_path() + "/multi_day2.csv");
+        if let Some(output) = output {
+            command.arg("--output").arg(output);
+        }
+        if let Some(failure) = failure {
+            command.args(["--inject-failure", failure]);
+        }
+        command.output()
+    };
+
+    let aborted = second_day(Some("during-output"), Some(&output))?;
+    assert_aborted_at(&aborted, "during-output");
+    // The half written balances are left in the temporary file, never renamed over the previous ones.
+    assert_eq!(std::fs::read(&output)?, first_day);
+    let expected = second_day(None, None)?;
+    let aborted = second_day(Some("during-output"), None)?;
+    assert_aborted_at(&aborted, "during-output");
+    assert_eq!(aborted.stdout, expected.stdout[..expected.stdout.len() / 2]);
+
+    assert!(second_day(None, Some(&output))?.status.success());
+    let single = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/multi_day2.csv")
+        .output()?;
+    assert_eq!(sorted_lines(&std::fs::read(&output)?), sorted_lines(&single.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn before_flush_should_leave_no_balances_once_the_snapshot_is_saved() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = generate(dir.path(), 100)?;
+    let (snapshot, output) = (dir.path().join("run.snapshot"), dir.path().join("accounts.csv"));
+
+    let aborted = Command::cargo_bin("transaction_engine")?
+        .arg(&input)
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .arg("--output")
+        .arg(&output)
+        .args(["--inject-failure", "before-flush"])
+        .output()?;
+
+    assert_aborted_at(&aborted, "before-flush");
+    assert!(!output.exists());
+    // The snapshot is committed before the balances, resuming from it without rows writes them.
+    let empty = dir.path().join("empty.csv");
+    std::fs::write(&empty, "type,client,tx,amount\n")?;
+    Command::cargo_bin("transaction_engine")?
+        .arg("--resume")
+        .arg(&snapshot)
+        .arg(&empty)
+        .arg("--output")
+        .arg(&output)
+        .assert()
+        .success();
+    let expected = Command::cargo_bin("transaction_engine")?.arg(&input).output()?;
+    assert_eq!(sorted_lines(&std::fs::read(&output)?), sorted_lines(&expected.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn an_unknown_failure_point_should_be_refused() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/deposit.csv")
+        .args(["--inject-failure", "during-input"])
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)?.contains("unknown failure point \"during-input\""));
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! What the library offers under each combination of its cargo features, every test checking the part of a feature
+//! that is compiled in, or that it is left out. Run with the features of a dependent crate, e.g. the embedded one:
+//! `cargo test --no-default-features --features serde --test feature_combinations`.
+
+use transaction_engine::{Amount, BTreeStore, Client, EngineConfig, Transaction, TransactionEngine};
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+// Two clients, client 2 disputing its deposit.
+fn engine() -> TransactionEngine {
+    let mut engine = TransactionEngine::new();
+    engine.compute_transaction(Transaction::Deposit { client_id: 2, tx_id: 1, amount: amount("3.0") }).unwrap();
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 2, amount: amount("2.5") }).unwrap();
+    engine.compute_transaction(Transaction::Withdrawal { client_id: 1, tx_id: 3, amount: amount("1.0") }).unwrap();
+    engine.compute_transaction(Transaction::Dispute { client_id: 2, tx_id: 1 }).unwrap();
+    engine
+}
+
+#[test]
+fn the_engine_should_be_built_by_every_combination() {
+    let engine = engine();
+    let mut ordered = TransactionEngine::with_store(BTreeStore::default(), EngineConfig::default());
+    ordered.compute_transactions([Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1.0") }]);
+
+    assert_eq!(engine.get_client(1), Some(&Client::new(1, amount("1.5"), Amount::ZERO, amount("1.5"), false)));
+    assert_eq!(engine.get_client(2).map(Client::held), Some(amount("3.0")));
+    assert_eq!(engine.total_funds(), amount("4.5"));
+    assert_eq!(ordered.client_count(), 1);
+}
+
+#[cfg(feature = "std-collections")]
+#[test]
+fn with_std_collections_the_clients_should_be_in_hash_maps() {
+    let mut engine = TransactionEngine::with_capacity(100, 1_000);
+    engine.compute_transaction(Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("1.0") }).unwrap();
+
+    assert!(engine.memory_estimate().total_bytes() > 0);
+    assert_eq!(engine.client_count(), 1);
+}
+
+#[cfg(not(feature = "std-collections"))]
+#[test]
+fn without_std_collections_the_clients_should_be_listed_in_order() {
+    let mut engine = engine();
+    for client_id in [9, 4, 7] {
+        engine.compute_transaction(Transaction::Deposit { client_id, tx_id: u64::from(client_id) + 10, amount: amount("1.0") }).unwrap();
+    }
+
+    let ids: Vec<u16> = engine.clients().map(Client::id).collect();
+    assert_eq!(ids, vec![1, 2, 4, 7, 9]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn with_serde_the_clients_and_transactions_should_serialize() {
+    let engine = engine();
+    let client = *engine.get_client(1).unwrap();
+    let json = serde_json::to_string(&client).unwrap();
+
+    assert_eq!(json, r#"{"client":1,"available":"1.5","held":"0.0","total":"1.5","locked":false,"currency":"USD"}"#);
+    assert_eq!(serde_json::from_str::<Client>(&json).unwrap(), client);
+    let transfer = Transaction::Transfer { from: 1, to: 2, tx_id: 4, amount: amount("0.5") };
+    assert_eq!(
+        serde_json::to_string(&transfer).unwrap(),
+        r#"{"type":"transfer","client":1,"tx":4,"amount":"0.5","to_client":2}"#
+    );
+    assert_eq!(serde_json::to_string(&engine.metrics().deposit).unwrap(), r#"{"applied":2,"rejected":0}"#);
+}
+
+#[cfg(feature = "csv-io")]
+#[test]
+fn with_csv_io_the_rows_should_be_read_as_transactions() {
+    use transaction_engine::csv_model::RowParser;
+
+    let data = "type,client,tx,amount\ndeposit,1,1,2.5\ndispute,1,1,\n";
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let parser = RowParser::new(reader.headers().unwrap());
+    let rows: Vec<Transaction> = reader.records().map(|row| parser.parse(&row.unwrap()).unwrap().0).collect();
+
+    assert_eq!(rows, vec![
+        Transaction::Deposit { client_id: 1, tx_id: 1, amount: amount("2.5") },
+        Transaction::Dispute { client_id: 1, tx_id: 1 },
+    ]);
+    let mut engine = TransactionEngine::new();
+    let summary = engine.process_csv(data.as_bytes()).unwrap();
+    assert_eq!((summary.applied_count(), engine.total_held()), (2, amount("2.5")));
+}
+
+#[cfg(feature = "json-io")]
+#[test]
+fn with_json_io_the_engine_should_be_restored_from_its_snapshot() {
+    let engine = engine();
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = TransactionEngine::load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(restored.get_client_list().len(), 2);
+    restored.compute_transaction(Transaction::Resolve { client_id: 2, tx_id: 1 }).unwrap();
+    assert_eq!(restored.get_client(2).map(Client::available), Some(amount("3.0")));
+    let dir = tempfile::tempdir().unwrap();
+    assert!(transaction_engine::FileArchive::create(dir.path().join("archive.jsonl")).unwrap().is_empty());
+    assert_eq!(transaction_engine::InMemoryStore::with_memory_limit(0).unwrap().spilled(), 0);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn with_cli_the_binary_should_be_built() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_transaction_engine"))
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/tests/deposit.csv"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("client,available,held,total,locked\n"));
+}
@@ -0,0 +1,131 @@
+#![cfg(feature = "grpc")]
+
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tonic::codegen::tokio_stream;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Server};
+use tonic::Code;
+use transaction_engine::grpc::proto::engine_client::EngineClient;
+use transaction_engine::grpc::proto::{self, GetClientRequest, ListClientsRequest, TransactionType};
+use transaction_engine::grpc::EngineService;
+use transaction_engine::ConcurrentTransactionEngine;
+
+const BASE_PATH: &str = "/resources/tests";
+
+fn get_base_path () -> String {
+    let path = env!("CARGO_MANIFEST_DIR").to_string();
+    path + BASE_PATH
+}
+
+// Serves a new engine on a free port for the duration of the test, returning a client of it.
+async fn start_server() -> Result<EngineClient<Channel>, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let service = EngineService::new(Arc::new(ConcurrentTransactionEngine::default()));
+    tokio::spawn(Server::builder().add_service(service.into_server()).serve_with_incoming(TcpIncoming::from(listener)));
+    Ok(EngineClient::connect(format!("http://{address}")).await?)
+}
+
+// The rows of a fixture with the `type,client,tx,amount` columns.
+fn fixture(name: &str) -> Result<Vec<proto::Transaction>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(get_base_path() + name)?;
+    reader.records()
+        .map(|row| {
+            let row = row?;
+            let transaction_type = TransactionType::from_str_name(&row[0].to_uppercase()).ok_or("unknown type")?;
+            Ok(proto::Transaction {
+                r#type: transaction_type.into(),
+                client: row[1].parse()?,
+                tx: row[2].parse()?,
+                amount: Some(row[3].to_string()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn client(id: u32, available: &str, held: &str, total: &str, locked: bool) -> proto::Client {
+    proto::Client {
+        client: id,
+        available: available.to_string(),
+        held: held.to_string(),
+        total: total.to_string(),
+        locked,
+        currency: "USD".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn submit_batch_should_apply_a_fixture() -> Result<(), Box<dyn std::error::Error>> {
+    let mut engine = start_server().await?;
+
+    let transactions = tokio_stream::iter(fixture("/dispute.csv")?);
+    let batch = engine.submit_batch(transactions).await?.into_inner();
+    assert_eq!((batch.accepted, batch.rejected), (8, 1));
+    let rejection = batch.outcomes[4].rejection.as_ref().ok_or("the withdrawal should be rejected")?;
+    assert_eq!(rejection.code, "insufficient_funds");
+
+    let clients = engine.list_clients(ListClientsRequest {}).await?.into_inner().clients;
+    assert_eq!(clients, vec![
+        client(1, "5.0", "0.0", "5.0", false),
+        client(2, "-40.0", "50.0", "10.0", false),
+        client(3, "0.0", "50.0", "50.0", false),
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn submit_transaction_should_answer_the_rejection() -> Result<(), Box<dyn std::error::Error>> {
+    let mut engine = start_server().await?;
+    let deposit = proto::Transaction {
+        r#type: TransactionType::Deposit.into(),
+        client: 1,
+        tx: 1,
+        amount: Some("2.5".to_string()),
+        ..Default::default()
+    };
+
+    assert_eq!(engine.submit_transaction(deposit.clone()).await?.into_inner().rejection, None);
+    let rejection = engine.submit_transaction(deposit.clone()).await?.into_inner().rejection.ok_or("duplicate deposit")?;
+    assert_eq!((rejection.code.as_str(), rejection.message.as_str()), ("duplicate_transaction", "tx 1 already exists"));
+    let missing_amount = proto::Transaction { tx: 2, amount: None, ..deposit.clone() };
+    let rejection = engine.submit_transaction(missing_amount).await?.into_inner().rejection.ok_or("missing amount")?;
+    assert_eq!(rejection.code, "missing_amount");
+    let out_of_range = proto::Transaction { client: 70_000, tx: 3, ..deposit };
+    let rejection = engine.submit_transaction(out_of_range).await?.into_inner().rejection.ok_or("client out of range")?;
+    assert_eq!(rejection.code, "client_out_of_range");
+
+    let found = engine.get_client(GetClientRequest { client: 1, currency: None }).await?.into_inner();
+    assert_eq!(found, client(1, "2.5", "0.0", "2.5", false));
+    let missing = engine.get_client(GetClientRequest { client: 2, currency: None }).await.unwrap_err();
+    assert_eq!((missing.code(), missing.message()), (Code::NotFound, "client 2 has no account"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn transactions_in_another_currency_should_have_their_own_account() -> Result<(), Box<dyn std::error::Error>> {
+    let mut engine = start_server().await?;
+    let deposit = proto::Transaction {
+        r#type: TransactionType::Deposit.into(),
+        client: 1,
+        tx: 1,
+        amount: Some("2.5".to_string()),
+        currency: Some("eur".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(engine.submit_transaction(deposit).await?.into_inner().rejection, None);
+
+    let dispute = proto::Transaction { r#type: TransactionType::Dispute.into(), client: 1, tx: 1, ..Default::default() };
+    let rejection = engine.submit_transaction(dispute).await?.into_inner().rejection.ok_or("dispute in USD")?;
+    assert_eq!((rejection.code.as_str(), rejection.message.as_str()), ("currency_mismatch", "tx 1 is in EUR, not in USD"));
+
+    let found = engine.get_client(GetClientRequest { client: 1, currency: Some("EUR".to_string()) }).await?.into_inner();
+    assert_eq!(found, proto::Client { currency: "EUR".to_string(), ..client(1, "2.5", "0.0", "2.5", false) });
+    let missing = engine.get_client(GetClientRequest { client: 1, currency: None }).await.unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+    let invalid = engine.get_client(GetClientRequest { client: 1, currency: Some("euro".to_string()) }).await.unwrap_err();
+    assert_eq!(invalid.code(), Code::InvalidArgument);
+    Ok(())
+}
@@ -0,0 +1,44 @@
+#![cfg(all(feature = "schema", feature = "cli"))]
+
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn dump_schemas_should_write_the_schemas_of_the_repository() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("schemas");
+
+    Command::cargo_bin("transaction_engine")?
+        .arg("dump-schemas")
+        .arg("--out")
+        .arg(&out)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    let committed = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+    let mut names: Vec<String> = std::fs::read_dir(&out)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    assert_eq!(names, ["audit_entry.json", "client.json", "error.json", "manifest.json", "metrics.json", "outcome.json", "stats.json"]);
+    // A change to the payloads should come with the schemas written by `dump-schemas`.
+    for name in names {
+        assert_eq!(std::fs::read_to_string(out.join(&name))?, std::fs::read_to_string(committed.join(&name))?, "{name} is out of date");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dump_schemas_should_be_hidden() -> Result<(), Box<dyn std::error::Error>> {
+    Command::cargo_bin("transaction_engine")?
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("verify-audit").and(predicate::str::contains("dump-schemas").not()));
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+#![cfg(all(feature = "sqlite", feature = "cli"))]
+
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use transaction_engine::sqlite_store::SqliteStore;
+use transaction_engine::{Amount, EngineConfig, TxStatus};
+
+const BASE_PATH: &str = "/resources/tests";
+
+fn get_base_path () -> String {
+    let path = env!("CARGO_MANIFEST_DIR").to_string();
+    path + BASE_PATH
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output).lines().map(str::to_string).collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn state_db_should_dispute_the_deposits_of_a_previous_run() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let state_db = dir.path().join("state.sqlite");
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg("--state-db")
+        .arg(&state_db)
+        .assert()
+        .success();
+
+    // The engine of the first run is gone, the second one only has the database to dispute its deposits.
+    let continued = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day2.csv")
+        .arg("--state-db")
+        .arg(&state_db)
+        .output()?;
+    let single = Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/multi_day1.csv")
+        .arg(get_base_path() + "/multi_day2.csv")
+        .output()?;
+
+    assert!(continued.status.success());
+    assert_eq!(sorted_lines(&continued.stdout), sorted_lines(&single.stdout));
+    assert_eq!(sorted_lines(&continued.stdout), vec![
+        "1,-10.0,0.0,-10.0,true",
+        "2,5.0,20.0,25.0,false",
+        "client,available,held,total,locked",
+    ]);
+
+    let engine = SqliteStore::open(&state_db)?.load(EngineConfig::default())?;
+    assert_eq!(engine.transaction_state(1), TxStatus::ChargedBack);
+    assert_eq!(engine.transaction_state(2), TxStatus::Disputed);
+    assert_eq!(engine.get_client(2).map(|client| client.held()), Some("20".parse::<Amount>()?));
+    Ok(())
+}
+
+#[test]
+fn state_db_should_not_open_another_database() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let state_db = dir.path().join("state.sqlite");
+    std::fs::write(&state_db, "not a database")?;
+
+    Command::cargo_bin("transaction_engine")?
+        .arg(get_base_path() + "/deposit.csv")
+        .arg("--state-db")
+        .arg(&state_db)
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot open"));
+    Ok(())
+}